@@ -0,0 +1,374 @@
+//! Checks of the extension connections before they are accepted.
+//!
+//! Any local process can connect to the listener, so the request of a new connection is peeked
+//! and its `Origin` and `User-Agent` headers checked against those of the CodinGame extension
+//! before the connection is accepted, denied or takes over another one. Rejected clients get a
+//! `403 Forbidden` answer to their handshake explaining why. `--no-origin-check` disables the
+//! check for other clients.
+//!
+//! Browser extensions are told apart by the ID in their origin, e.g. `chrome-extension://<id>`.
+//! With `--extension-id`, only the extensions of the given IDs are accepted, so another extension
+//! installed in the browser can't pass for the CodinGame one. Firefox gives every installation of
+//! an extension its own ID, which is why the IDs are given rather than built in.
+//!
+//! With `--auth-token`, the extension also gives the token, either as the percent-encoded `token`
+//! query parameter of its request (`ws://host:port/?token=...`) or as its first message once
+//! upgraded (`{"action": "auth", "payload": {"token": "..."}}`). Connections are only accepted,
+//! denied or held once authenticated.
+//!
+//! With `--allow-ip`, connections from other addresses are closed before anything is read.
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use async_std::prelude::*;
+use async_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use async_tungstenite::tungstenite::http::StatusCode;
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::WebSocketStream;
+use futures_util::sink::SinkExt;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+
+use crate::listener::{Peer, Stream};
+use crate::runtime::net::TcpStream;
+use crate::runtime::task;
+use crate::{messages, Result, ServerMessage};
+
+/// Upgraded connection of the extension
+pub type WsStream = WebSocketStream<Stream>;
+
+/// Delay for a new connection to send the headers of its request
+const REQUEST_PEEK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest request peeked, longer headers are truncated
+const REQUEST_PEEK_LIMIT: usize = 8192;
+
+/// Delay for the extension to send its token once upgraded, when its request didn't have it
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Origins of the pages and extensions the extension connects from: its own background page, or
+/// the CodinGame IDE it is injected in
+const EXTENSION_SCHEMES: &[&str] = &[
+    "chrome-extension://",
+    "moz-extension://",
+    "safari-web-extension://",
+];
+const CODINGAME_HOST: &str = "codingame.com";
+
+/// Headers and token of a WebSocket request
+#[derive(Default)]
+pub struct PeekedRequest {
+    pub origin: Option<String>,
+    pub user_agent: Option<String>,
+    /// `token` query parameter, for --auth-token, percent-decoded
+    pub token: Option<String>,
+}
+
+impl PeekedRequest {
+    /// Why the request doesn't come from the extension, `None` if it does. Any extension is
+    /// accepted without `extension_ids`.
+    pub fn rejection(&self, extension_ids: &[&str]) -> Option<String> {
+        match (&self.origin, &self.user_agent) {
+            (None, _) => Some("missing Origin header".to_owned()),
+            (Some(origin), _) if !is_extension_origin(origin, extension_ids) => {
+                Some(format!("origin {} is not the CodinGame extension", origin))
+            }
+            (_, None) => Some("missing User-Agent header".to_owned()),
+            (_, Some(user_agent)) if !user_agent.starts_with("Mozilla/") => {
+                Some(format!("user agent {} is not a browser", user_agent))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Network of allowed peers, as `address/prefix` or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Whether the network contains the address, an IPv4-mapped IPv6 address matching its IPv4
+    /// address
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+            addr => addr,
+        };
+
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address '{}' in '{}'", addr, s))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= bits => prefix,
+                _ => return Err(format!("invalid prefix length '{}' in '{}'", prefix, s)),
+            },
+            None => bits,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Whether the first `prefix` bits of both addresses are the same
+fn prefix_matches(net: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = ((prefix / 8) as usize, prefix % 8);
+    if net[..bytes] != addr[..bytes] {
+        return false;
+    }
+
+    bits == 0 || {
+        let mask = 0xffu8 << (8 - bits);
+        net[bytes] & mask == addr[bytes] & mask
+    }
+}
+
+/// Whether the peer may connect, always with no allowed networks
+pub fn is_allowed(networks: &[IpNetwork], peer: &SocketAddr) -> bool {
+    networks.is_empty() || networks.iter().any(|net| net.contains(peer.ip()))
+}
+
+/// ID of the browser extension of the origin, `None` for other origins
+pub fn extension_id(origin: &str) -> Option<&str> {
+    EXTENSION_SCHEMES
+        .iter()
+        .find_map(|scheme| origin.strip_prefix(scheme))
+        .map(|id| id.trim_end_matches('/'))
+}
+
+/// Whether the origin is a CodinGame page or a browser extension, one of `extension_ids` if any
+pub fn is_extension_origin(origin: &str, extension_ids: &[&str]) -> bool {
+    if let Some(id) = extension_id(origin) {
+        return extension_ids.is_empty() || extension_ids.contains(&id);
+    }
+
+    match origin.strip_prefix("https://") {
+        Some(host) => host == CODINGAME_HOST || host.ends_with(&format!(".{}", CODINGAME_HOST)),
+        None => false,
+    }
+}
+
+/// URL of the listener at `host`, giving the token as the query parameter of the request
+pub fn request_url(host: &str, token: Option<&str>) -> String {
+    match token {
+        Some(token) => format!(
+            "ws://{}/?token={}",
+            host,
+            utf8_percent_encode(token, NON_ALPHANUMERIC)
+        ),
+        None => format!("ws://{}/", host),
+    }
+}
+
+/// Whether the token given by the extension is the expected one, compared in constant time
+pub fn token_matches(expected: &str, token: &str) -> bool {
+    expected.len() == token.len()
+        && expected
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Headers and token of the WebSocket request of the connection. The request is peeked, so the
+/// handshake can still read it. Headers not received within the timeout are missing.
+pub async fn peek_request(stream: &TcpStream) -> PeekedRequest {
+    let deadline = Instant::now() + REQUEST_PEEK_TIMEOUT;
+    let mut buf = vec![0u8; REQUEST_PEEK_LIMIT];
+
+    // Peeking returns what arrived so far, until the end of the headers
+    let n = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let n = match async_std::future::timeout(remaining, stream.peek(&mut buf)).await {
+            Ok(Ok(n)) => n,
+            _ => 0,
+        };
+
+        if n == 0 || n == buf.len() || buf[..n].windows(4).any(|w| w == b"\r\n\r\n") {
+            break n;
+        }
+        if Instant::now() >= deadline {
+            break n;
+        }
+
+        task::sleep(Duration::from_millis(10)).await;
+    };
+
+    parse_request(&String::from_utf8_lossy(&buf[..n]))
+}
+
+fn parse_request(request: &str) -> PeekedRequest {
+    let mut lines = request.lines();
+
+    // GET /path?query HTTP/1.1
+    let token = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|target| target.split_once('?'))
+        .and_then(|(_, query)| {
+            query
+                .split('&')
+                .find_map(|param| param.strip_prefix("token="))
+        })
+        .map(|token| percent_decode_str(token).decode_utf8_lossy().into_owned());
+
+    let mut request = PeekedRequest {
+        token,
+        ..PeekedRequest::default()
+    };
+
+    for line in lines.take_while(|line| !line.is_empty()) {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = Some(value.trim().to_owned());
+            if name.eq_ignore_ascii_case("origin") {
+                request.origin = value;
+            } else if name.eq_ignore_ascii_case("user-agent") {
+                request.user_agent = value;
+            }
+        }
+    }
+
+    request
+}
+
+/// Upgrade the connection to WebSocket. With `token`, which the request didn't have, the
+/// extension must send it as its first message within [`AUTH_TIMEOUT`]. Returns `None` if it
+/// didn't, after telling it.
+pub async fn upgrade(peer: Peer, stream: Stream, token: Option<&str>) -> Result<Option<WsStream>> {
+    let mut ws_stream = async_tungstenite::accept_async(stream).await?;
+
+    let expected = match token {
+        Some(expected) => expected,
+        None => return Ok(Some(ws_stream)),
+    };
+
+    let authenticated = match async_std::future::timeout(AUTH_TIMEOUT, ws_stream.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => matches!(
+            ServerMessage::parse(&text),
+            Ok(ServerMessage::Auth { token }) if token_matches(expected, &token)
+        ),
+        _ => false,
+    };
+
+    if authenticated {
+        debug!("{} authenticated with its first message", peer);
+        return Ok(Some(ws_stream));
+    }
+
+    warn!("{}", messages::auth_failed(&peer.to_string()));
+    ws_stream
+        .send(
+            ServerMessage::Error {
+                message: messages::authentication_failed(),
+            }
+            .into(),
+        )
+        .await?;
+    ws_stream.close(None).await?;
+
+    Ok(None)
+}
+
+/// Answer the handshake of a rejected client with `403 Forbidden` and the reason
+pub async fn reject(peer: SocketAddr, stream: TcpStream, reason: String) {
+    let refuse = move |_: &Request, _: Response| -> std::result::Result<Response, ErrorResponse> {
+        let mut response = ErrorResponse::new(Some(format!(
+            "cg-local-app: connection rejected, {}\n",
+            reason
+        )));
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        Err(response)
+    };
+
+    if let Err(err) = async_tungstenite::accept_hdr_async(stream, refuse).await {
+        debug!("rejected {}: {}", peer, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins() {
+        let pinned = ["abcdefghijklmnop"];
+        // Origin, accepted with any extension, accepted with the pinned one
+        let cases = [
+            ("chrome-extension://abcdefghijklmnop", true, true),
+            ("chrome-extension://abcdefghijklmnop/", true, true),
+            ("moz-extension://abcdefghijklmnop", true, true),
+            ("chrome-extension://ponmlkjihgfedcba", true, false),
+            (
+                "moz-extension://0d2c1a6e-3f5b-4d8e-9c7a-1b2e3f4a5b6c",
+                true,
+                false,
+            ),
+            ("https://www.codingame.com", true, true),
+            ("https://codingame.com", true, true),
+            ("https://codingame.com.example.org", false, false),
+            ("http://www.codingame.com", false, false),
+            ("https://example.org", false, false),
+        ];
+
+        for (origin, any, pinned_only) in &cases {
+            assert_eq!(is_extension_origin(origin, &[]), *any, "{}", origin);
+            assert_eq!(
+                is_extension_origin(origin, &pinned),
+                *pinned_only,
+                "{} with {:?}",
+                origin,
+                pinned
+            );
+        }
+    }
+
+    #[test]
+    fn tokens_are_percent_decoded() {
+        let request = |target: &str| {
+            parse_request(&format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nOrigin: https://www.codingame.com\r\n\r\n",
+                target
+            ))
+        };
+
+        for token in &["s3cr3t", "a+b/c=", "100% sure & more", "jeton-précis"] {
+            let url = request_url("localhost:53135", Some(token));
+            let target = url.trim_start_matches("ws://localhost:53135");
+            assert_eq!(request(target).token.as_deref(), Some(*token), "{}", url);
+        }
+
+        assert_eq!(
+            request("/?observer&token=a%2Bb%3D").token.as_deref(),
+            Some("a+b=")
+        );
+        assert_eq!(request("/?token=a+b").token.as_deref(), Some("a+b"));
+        assert_eq!(request("/").token, None);
+    }
+}
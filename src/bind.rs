@@ -0,0 +1,162 @@
+//! Parsing of the `--bind` address.
+//!
+//! The address is checked before anything is started, so a typo is reported along with the
+//! offending input rather than as an I/O error of the listener once the worker runs. Host names
+//! are resolved here, and port 0 lets the system pick a port, the listener logging the actual one.
+//!
+//! When the port is already in use, like by another instance of the app, the listener tries the
+//! next `--port-fallback` ports in turn and tells which one to set in the extension options.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use crate::runtime::net::TcpListener;
+use crate::{messages, ErrorKind, Result};
+
+/// Resolve a `host:port` address to the addresses to listen on, or explain why it is invalid
+pub fn parse(bind: &str) -> std::result::Result<Vec<SocketAddr>, String> {
+    // An IP address with its port needs no resolution
+    if let Ok(addr) = bind.parse::<SocketAddr>() {
+        return Ok(vec![addr]);
+    }
+
+    let (host, port) = match bind.rsplit_once(':') {
+        Some(parts) => parts,
+        None => return Err("the port is missing".to_owned()),
+    };
+
+    if host.is_empty() {
+        return Err("the host is missing".to_owned());
+    }
+
+    if host.contains(':') && !host.starts_with('[') {
+        return Err("IPv6 addresses must be in brackets, e.g. [::1]:53135".to_owned());
+    }
+
+    if port.parse::<u16>().is_err() {
+        return Err(format!("'{}' is not a port between 0 and 65535", port));
+    }
+
+    let addrs: Vec<_> = bind
+        .to_socket_addrs()
+        .map_err(|err| format!("can't resolve {}: {}", host, err))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(format!("{} doesn't resolve to any address", host));
+    }
+
+    info!(
+        "{} resolved to {} address(es): {}",
+        host,
+        addrs.len(),
+        addrs
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    Ok(addrs)
+}
+
+/// Check the `--bind` address, before starting anything
+pub fn validate(bind: &str) -> Result<Vec<SocketAddr>> {
+    parse(bind).map_err(|reason| ErrorKind::InvalidBind(bind.to_owned(), reason).into())
+}
+
+/// Listen on the `--bind` address, trying the next `fallback` ports in turn while its port is
+/// already in use
+pub async fn listen(bind: &str, fallback: u16) -> Result<TcpListener> {
+    let addrs = validate(bind)?;
+    let first = addrs[0].port();
+    // The system picks a free port by itself
+    let last = if first == 0 {
+        first
+    } else {
+        first.saturating_add(fallback)
+    };
+
+    for port in first..=last {
+        let candidates: Vec<_> = addrs
+            .iter()
+            .map(|addr| SocketAddr::new(addr.ip(), port))
+            .collect();
+
+        match TcpListener::bind(&candidates[..]).await {
+            Ok(listener) => {
+                if port != first {
+                    warn!("{}", messages::port_fallback(first, port));
+                }
+
+                return Ok(listener);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && port < last => {
+                info!("port {} is already in use, trying {}", port, port + 1);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && fallback > 0 => {
+                return Err(ErrorKind::PortsInUse(bind.to_owned(), first, last).into());
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    unreachable!("the range of ports is never empty")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn good_inputs() {
+        let table = [
+            ("127.0.0.1:53135", "127.0.0.1:53135"),
+            ("0.0.0.0:0", "0.0.0.0:0"),
+            ("[::1]:53135", "[::1]:53135"),
+            ("[::]:65535", "[::]:65535"),
+        ];
+
+        for (bind, expected) in table {
+            assert_eq!(
+                parse(bind),
+                Ok(vec![expected.parse().unwrap()]),
+                "{:?}",
+                bind
+            );
+        }
+
+        let addrs = parse("localhost:53135").unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs
+            .iter()
+            .all(|addr| addr.ip().is_loopback() && addr.port() == 53135));
+    }
+
+    #[test]
+    fn bad_inputs() {
+        let table = [
+            ("127.0.0.1", "the port is missing"),
+            ("", "the port is missing"),
+            (":53135", "the host is missing"),
+            (
+                "::1:53135",
+                "IPv6 addresses must be in brackets, e.g. [::1]:53135",
+            ),
+            (
+                "127.0.0.1:65536",
+                "'65536' is not a port between 0 and 65535",
+            ),
+            ("localhost:port", "'port' is not a port between 0 and 65535"),
+            ("localhost:-1", "'-1' is not a port between 0 and 65535"),
+        ];
+
+        for (bind, expected) in table {
+            assert_eq!(parse(bind), Err(expected.to_owned()), "{:?}", bind);
+        }
+
+        // The resolver's message varies between systems
+        let err = parse("host.invalid:53135").unwrap_err();
+        assert!(err.starts_with("can't resolve host.invalid: "), "{}", err);
+    }
+}
@@ -0,0 +1,70 @@
+//! Concatenation of the source files of the target directory, for `--watch-all-in-dir`.
+
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobMatcher};
+
+/// Files combined into the uploaded code
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    /// File whose change caused the upload
+    pub trigger: PathBuf,
+    /// Concatenated files, in order
+    pub files: Vec<PathBuf>,
+}
+
+/// Matcher for the file names to bundle: the given pattern, or the extension of the target
+pub fn matcher(pattern: Option<&Glob>, target: &Path) -> GlobMatcher {
+    match pattern {
+        Some(pattern) => pattern.compile_matcher(),
+        None => {
+            let pattern = match target.extension() {
+                Some(ext) => format!("*.{}", globset::escape(&ext.to_string_lossy())),
+                None => globset::escape(
+                    &target
+                        .file_name()
+                        .map(|name| name.to_string_lossy())
+                        .unwrap_or_default(),
+                ),
+            };
+
+            Glob::new(&pattern)
+                .expect("escaped pattern is valid")
+                .compile_matcher()
+        }
+    }
+}
+
+/// Whether the file is a direct child of `dir` matching the bundle pattern
+pub fn is_bundled(dir: &Path, matcher: &GlobMatcher, path: &Path) -> bool {
+    path.parent() == Some(dir)
+        && path
+            .file_name()
+            .map(|name| matcher.is_match(name))
+            .unwrap_or(false)
+}
+
+/// Concatenate the matching files of `dir`, sorted by name
+pub fn read(dir: &Path, matcher: &GlobMatcher) -> std::io::Result<(String, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_bundled(dir, matcher, &path) {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    let mut code = String::new();
+    for file in &files {
+        code.push_str(&std::fs::read_to_string(file)?);
+
+        // Keep the last line of a file apart from the first line of the next one
+        if !code.is_empty() && !code.ends_with('\n') {
+            code.push('\n');
+        }
+    }
+
+    Ok((code, files))
+}
@@ -0,0 +1,226 @@
+//! Instrumented channels between the worker tasks, to diagnose queueing problems.
+//!
+//! [`bounded`] wraps a channel of the runtime so every send and receive is counted, along with the
+//! longest time a send waited for room. Counters are relaxed atomics, cheap enough to be always
+//! enabled. Every instrumented channel is registered globally and read with [`snapshot`], for the
+//! watchdog stall reports, the Prometheus metrics and the exit summary.
+
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::runtime::channel::{self, SendError, TrySendError};
+use futures::Stream;
+
+/// Counters of an instrumented channel
+#[derive(Debug)]
+pub struct ChannelStats {
+    name: &'static str,
+    sends: AtomicU64,
+    receives: AtomicU64,
+    max_send_wait_us: AtomicU64,
+}
+
+impl ChannelStats {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            sends: AtomicU64::new(0),
+            receives: AtomicU64::new(0),
+            max_send_wait_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Messages sent but not received yet
+    pub fn occupancy(&self) -> u64 {
+        // A receive can be counted before the send that allowed it
+        self.sends
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.receives.load(Ordering::Relaxed))
+    }
+
+    pub fn snapshot(&self) -> ChannelSnapshot {
+        ChannelSnapshot {
+            name: self.name,
+            sends: self.sends.load(Ordering::Relaxed),
+            receives: self.receives.load(Ordering::Relaxed),
+            occupancy: self.occupancy(),
+            max_send_wait: Duration::from_micros(self.max_send_wait_us.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn record_send(&self, wait: Duration) {
+        self.sends.fetch_add(1, Ordering::Relaxed);
+        self.max_send_wait_us
+            .fetch_max(wait.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counters of a channel at some point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelSnapshot {
+    pub name: &'static str,
+    pub sends: u64,
+    pub receives: u64,
+    pub occupancy: u64,
+    pub max_send_wait: Duration,
+}
+
+impl fmt::Display for ChannelSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} channel: {} pending, {} sent, {} received, longest send wait {:.3}s",
+            self.name,
+            self.occupancy,
+            self.sends,
+            self.receives,
+            self.max_send_wait.as_secs_f64()
+        )
+    }
+}
+
+static CHANNELS: Mutex<Vec<Arc<ChannelStats>>> = Mutex::new(Vec::new());
+
+/// Counters of every instrumented channel, in creation order
+pub fn snapshot() -> Vec<ChannelSnapshot> {
+    CHANNELS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|stats| stats.snapshot())
+        .collect()
+}
+
+/// Create an instrumented bounded channel
+pub fn bounded<T>(name: &'static str, cap: usize) -> (Sender<T>, Receiver<T>) {
+    let stats = Arc::new(ChannelStats::new(name));
+    CHANNELS.lock().unwrap().push(stats.clone());
+
+    let (tx, rx) = channel::bounded(cap);
+    (
+        Sender {
+            inner: tx,
+            stats: stats.clone(),
+        },
+        Receiver { inner: rx, stats },
+    )
+}
+
+pub struct Sender<T> {
+    inner: channel::Sender<T>,
+    stats: Arc<ChannelStats>,
+}
+
+impl<T> Sender<T> {
+    pub async fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let started = Instant::now();
+        self.inner.send(msg).await?;
+        self.stats.record_send(started.elapsed());
+        Ok(())
+    }
+
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_send(msg)?;
+        self.stats.record_send(Duration::ZERO);
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    inner: channel::Receiver<T>,
+    stats: Arc<ChannelStats>,
+}
+
+impl<T> Receiver<T> {
+    pub fn stats(&self) -> Arc<ChannelStats> {
+        self.stats.clone()
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let item = Pin::new(&mut self.inner).poll_next(cx);
+
+        if let Poll::Ready(Some(_)) = &item {
+            self.stats.receives.fetch_add(1, Ordering::Relaxed);
+        }
+
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::task;
+    use futures::StreamExt;
+
+    #[test]
+    fn counters_under_concurrent_producers() {
+        const PRODUCERS: u64 = 8;
+        const MESSAGES: u64 = 100;
+
+        let (tx, mut rx) = bounded("concurrent", 4);
+        let stats = rx.stats();
+
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    task::block_on(async {
+                        for i in 0..MESSAGES {
+                            tx.send(producer * MESSAGES + i).await.unwrap();
+                        }
+                    })
+                })
+            })
+            .collect();
+        drop(tx);
+
+        // The producers fill the channel and wait for room meanwhile
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(stats.snapshot().occupancy, 4);
+
+        let mut received: Vec<u64> = task::block_on(async {
+            let mut received = Vec::new();
+            while let Some(msg) = rx.next().await {
+                received.push(msg);
+            }
+            received
+        });
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        received.sort_unstable();
+        assert_eq!(received, (0..PRODUCERS * MESSAGES).collect::<Vec<_>>());
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.name, "concurrent");
+        assert_eq!(snapshot.sends, PRODUCERS * MESSAGES);
+        assert_eq!(snapshot.receives, PRODUCERS * MESSAGES);
+        assert_eq!(snapshot.occupancy, 0);
+        assert!(snapshot.max_send_wait >= Duration::from_millis(10));
+
+        assert!(super::snapshot().contains(&snapshot));
+    }
+}
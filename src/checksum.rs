@@ -0,0 +1,83 @@
+//! Checksums of the synchronized code, exchanged with extensions that support them.
+//!
+//! With `--upload-checksum`, uploads carry a `checksum` field. Extensions ignoring unknown fields
+//! are unaffected, and the field is omitted entirely when the option is off. An extension that
+//! echoes checksums in its `Code` messages lets the app compare code without looking at the
+//! contents.
+
+use sha1::{Digest, Sha1};
+
+/// Hex-encoded SHA-1 of the code
+pub fn checksum(code: &str) -> String {
+    Sha1::digest(code.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Whether the remote code is the same as the local code, comparing checksums when the remote
+/// provided one and contents otherwise
+pub fn same_code(local: &str, remote: &str, remote_checksum: Option<&str>) -> bool {
+    match remote_checksum {
+        Some(remote_checksum) => checksum(local) == remote_checksum,
+        None => local == remote,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerMessage;
+
+    #[test]
+    fn messages_without_checksums_are_unchanged() {
+        let json = |msg: ServerMessage| serde_json::to_string(&msg).unwrap();
+
+        // As serialized before checksums existed
+        assert_eq!(
+            json(ServerMessage::UpdateCode {
+                code: "print(1)\n".to_owned().into(),
+                play: true,
+                checksum: None,
+            }),
+            r#"{"action":"update-code","payload":{"code":"print(1)\n","play":true}}"#
+        );
+        assert_eq!(
+            json(ServerMessage::Code {
+                code: "print(1)\n".to_owned().into(),
+                checksum: None,
+            }),
+            r#"{"action":"code","payload":{"code":"print(1)\n"}}"#
+        );
+        assert!(matches!(
+            ServerMessage::parse(r#"{"action":"code","payload":{"code":"print(1)\n"}}"#),
+            Ok(ServerMessage::Code { checksum: None, .. })
+        ));
+
+        assert_eq!(
+            json(ServerMessage::UpdateCode {
+                code: "print(1)\n".to_owned().into(),
+                play: false,
+                checksum: Some(checksum("print(1)\n")),
+            }),
+            format!(
+                r#"{{"action":"update-code","payload":{{"code":"print(1)\n","play":false,"checksum":"{}"}}}}"#,
+                checksum("print(1)\n")
+            )
+        );
+    }
+
+    #[test]
+    fn code_is_compared_by_checksum_when_given() {
+        assert!(same_code("print(1)\n", "print(1)\n", None));
+        assert!(!same_code("print(1)\n", "print(2)\n", None));
+
+        // The contents aren't looked at
+        assert!(same_code("print(1)\n", "", Some(&checksum("print(1)\n"))));
+        assert!(!same_code(
+            "print(1)\n",
+            "print(1)\n",
+            Some(&checksum("print(2)\n"))
+        ));
+    }
+}
@@ -0,0 +1,83 @@
+//! Clipboard as the source of the code, for `--input-from-clipboard`.
+//!
+//! The clipboard is polled from a dedicated thread, replacing the file watcher. Changes of its
+//! text contents are written to the target when there is one, then forwarded to the controller
+//! like changes of the target file.
+
+use std::time::Duration;
+
+use async_std::sync::{Arc, Mutex};
+
+use crate::channel_stats;
+use crate::runtime::task;
+use crate::{DynamicOpts, WorkerMessage, WorkerNotification};
+
+/// Spawn the thread polling the clipboard every `period`. It stops when the controller exits,
+/// or if the clipboard can't be opened.
+pub fn spawn(
+    dynamic: Arc<Mutex<DynamicOpts>>,
+    period: Duration,
+    tx_controller: channel_stats::Sender<WorkerMessage>,
+    tx_notification: std::sync::mpsc::Sender<WorkerNotification>,
+) {
+    std::thread::spawn(move || {
+        let mut clipboard = match arboard::Clipboard::new() {
+            Ok(clipboard) => clipboard,
+            Err(err) => {
+                error!("failed to open the clipboard: {}", err);
+                return;
+            }
+        };
+
+        info!("polling the clipboard every {}ms", period.as_millis());
+
+        // Contents at startup are not a change
+        let mut last = clipboard.get_text().ok();
+
+        while !tx_controller.is_closed() {
+            std::thread::sleep(period);
+
+            // Fails when the clipboard is empty or holds something else than text
+            let code = match clipboard.get_text() {
+                Ok(code) => code,
+                Err(err) => {
+                    trace!("no text in the clipboard: {}", err);
+                    continue;
+                }
+            };
+
+            if last.as_ref() == Some(&code) {
+                continue;
+            }
+
+            last = Some(code.clone());
+            debug!("clipboard changed, {} bytes", code.len());
+
+            if let Some(target) = task::block_on(async { dynamic.lock().await.target.clone() }) {
+                if let Err(err) = std::fs::write(&target, &code) {
+                    warn!(
+                        "failed to write the clipboard to {}: {}",
+                        target.display(),
+                        err
+                    );
+                }
+            }
+
+            if tx_notification
+                .send(WorkerNotification::ClipboardChanged { bytes: code.len() })
+                .is_err()
+            {
+                break;
+            }
+
+            if task::block_on(tx_controller.send(WorkerMessage::FileChanged {
+                code: code.into(),
+                bundle: None,
+            }))
+            .is_err()
+            {
+                break;
+            }
+        }
+    });
+}
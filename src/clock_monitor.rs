@@ -0,0 +1,151 @@
+//! Detection of system sleep and wall clock jumps.
+//!
+//! The monitor compares the monotonic and wall clocks between short ticks. The monotonic clock
+//! doesn't advance while the system is suspended, so a wall clock that moved much more than the
+//! monotonic one means the system was asleep (or its clock was set forward). A tick that took
+//! much longer than expected on the monotonic clock means the whole process was frozen.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::watchdog::Heartbeat;
+
+/// Interval between two clock samples
+const TICK: Duration = Duration::from_secs(1);
+
+/// Discrepancy from the expected tick above which time is considered to have jumped
+const JUMP_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Clocks read at one tick
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    instant: Instant,
+    wall: SystemTime,
+}
+
+impl Sample {
+    fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+}
+
+/// How the clocks moved between two ticks
+#[derive(Debug, PartialEq, Eq)]
+enum Elapsed {
+    /// About a tick, give or take the threshold
+    Tick,
+    /// The wall clock was set back by this much
+    WentBack(Duration),
+    /// The process resumed after a gap this long
+    Resumed(Duration),
+}
+
+fn elapsed(last: Sample, now: Sample) -> Elapsed {
+    let monotonic = now.instant.duration_since(last.instant);
+    let wall = match now.wall.duration_since(last.wall) {
+        Ok(wall) => wall,
+        Err(err) if err.duration() > JUMP_THRESHOLD => return Elapsed::WentBack(err.duration()),
+        Err(_) => return Elapsed::Tick,
+    };
+
+    if wall > monotonic + JUMP_THRESHOLD {
+        // Monotonic time stood still: suspended, or the wall clock was set forward
+        Elapsed::Resumed(wall - monotonic)
+    } else if monotonic > TICK + JUMP_THRESHOLD {
+        // The process itself didn't run
+        Elapsed::Resumed(monotonic - TICK)
+    } else {
+        Elapsed::Tick
+    }
+}
+
+/// Spawn the monitor thread, running until the heartbeat is stopped. `on_resume` is called with
+/// the length of the gap whenever the process resumes after a sleep or a freeze.
+pub fn spawn(
+    heartbeat: Arc<Heartbeat>,
+    on_resume: impl Fn(Duration) + Send + 'static,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut last = Sample::now();
+
+        while !heartbeat.is_stopped() {
+            std::thread::sleep(TICK);
+
+            let now = Sample::now();
+            let gap = match elapsed(last, now) {
+                Elapsed::Tick => None,
+                Elapsed::WentBack(by) => {
+                    warn!("system clock jumped back by {:.0}s", by.as_secs_f32());
+                    None
+                }
+                Elapsed::Resumed(gap) => Some(gap),
+            };
+            last = now;
+
+            if let Some(gap) = gap {
+                // Time-based state measured before the gap is meaningless now
+                heartbeat.beat("Resumed");
+
+                info!(
+                    "resumed from sleep ({:.0}s gap), re-validating connection",
+                    gap.as_secs_f32()
+                );
+
+                on_resume(gap);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample taken `monotonic` and `wall` after `last`, the wall clock going back when negative
+    fn after(last: Sample, monotonic: u64, wall: i64) -> Sample {
+        let by = Duration::from_secs(wall.unsigned_abs());
+        Sample {
+            instant: last.instant + Duration::from_secs(monotonic),
+            wall: if wall < 0 {
+                last.wall - by
+            } else {
+                last.wall + by
+            },
+        }
+    }
+
+    #[test]
+    fn gaps() {
+        let last = Sample::now();
+        let secs = Duration::from_secs;
+
+        let table = [
+            (1, 1, Elapsed::Tick),
+            // Within the threshold
+            (1, 6, Elapsed::Tick),
+            (6, 6, Elapsed::Tick),
+            (1, 0, Elapsed::Tick),
+            (1, -5, Elapsed::Tick),
+            // Suspended, or the wall clock set forward
+            (1, 61, Elapsed::Resumed(secs(60))),
+            (1, 7, Elapsed::Resumed(secs(6))),
+            // Frozen
+            (31, 31, Elapsed::Resumed(secs(30))),
+            (7, 7, Elapsed::Resumed(secs(6))),
+            (1, -60, Elapsed::WentBack(secs(60))),
+        ];
+
+        for (monotonic, wall, expected) in table {
+            assert_eq!(
+                elapsed(last, after(last, monotonic, wall)),
+                expected,
+                "{}s monotonic, {}s wall",
+                monotonic,
+                wall
+            );
+        }
+    }
+}
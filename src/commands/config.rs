@@ -0,0 +1,615 @@
+//! `config check`: validation of the configuration file and the options before a session.
+//!
+//! Every problem is reported, not only the first one. Errors make the command fail, warnings
+//! point at settings that are likely mistakes but don't prevent synchronizing.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use error_chain::bail;
+use ignore::gitignore::GitignoreBuilder;
+use toml::Spanned;
+
+use crate::bind;
+use crate::config::{self, Config};
+use crate::transform_chains::Stage;
+use crate::watch_filter::IGNORE_FILE_NAME;
+use crate::{messages, Opts, Result};
+
+/// Keys of the `[hooks]` table
+const HOOK_KEYS: &[&str] = &[
+    "on_connect",
+    "on_disconnect",
+    "on_details",
+    "on_upload",
+    "on_play",
+    "on_download",
+    "on_error",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// Problem found by the check, with where it comes from: a position in the configuration file
+/// or a command-line option
+#[derive(Debug)]
+struct Finding {
+    severity: Severity,
+    source: String,
+    message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        write!(f, "{}: {}: {}", self.source, severity, self.message)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    findings: Vec<Finding>,
+}
+
+impl Report {
+    fn error(&mut self, source: impl ToString, message: impl ToString) {
+        self.push(Severity::Error, source, message);
+    }
+
+    fn warning(&mut self, source: impl ToString, message: impl ToString) {
+        self.push(Severity::Warning, source, message);
+    }
+
+    fn push(&mut self, severity: Severity, source: impl ToString, message: impl ToString) {
+        self.findings.push(Finding {
+            severity,
+            source: source.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+}
+
+/// Configuration file keys with their position, for the findings. Values are checked from the
+/// parsed `Config`, this only locates them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Located {
+    hooks: BTreeMap<String, Spanned<toml::Value>>,
+    play_policy: Vec<BTreeMap<String, Spanned<toml::Value>>>,
+    session: BTreeMap<String, BTreeMap<String, Spanned<toml::Value>>>,
+    transforms: LocatedTransforms,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LocatedTransforms {
+    target: BTreeMap<String, Spanned<toml::Value>>,
+}
+
+/// Position in the configuration file, as `path:line:column`
+struct Location<'a> {
+    path: &'a Path,
+    contents: &'a str,
+}
+
+impl Location<'_> {
+    fn at(&self, offset: usize) -> String {
+        let before = &self.contents[..offset.min(self.contents.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map(|idx| idx + 1).unwrap_or(0) + 1;
+
+        format!("{}:{}:{}", self.path.display(), line, column)
+    }
+}
+
+pub fn check(opts: &Opts, probe: bool) -> Result<()> {
+    let mut report = Report::default();
+
+    check_config_file(opts, &mut report);
+    check_bind(opts, probe, &mut report);
+    if let Some(command) = &opts.upload_progress_command {
+        if let Err(message) = check_hook_command(command) {
+            report.error("--upload-progress-command", message);
+        }
+    }
+    check_paths(opts, &mut report);
+    check_ignore_patterns(opts, &mut report);
+
+    for finding in &report.findings {
+        println!("{}", finding);
+    }
+
+    let errors = report.count(Severity::Error);
+    let warnings = report.count(Severity::Warning);
+    println!("{}", messages::config_findings(errors, warnings));
+
+    if errors > 0 {
+        bail!("the configuration has {} error(s)", errors);
+    }
+
+    Ok(())
+}
+
+fn check_config_file(opts: &Opts, report: &mut Report) {
+    let session = opts.session.as_deref();
+    let path = match config::resolve(opts.config.as_ref().map(|path| path.as_ref())) {
+        Some(path) => path,
+        None => {
+            println!("{}", messages::config_not_found());
+            if let Some(session) = session {
+                report.error(
+                    "--session",
+                    format!("no configuration file defines session '{}'", session),
+                );
+            }
+            return;
+        }
+    };
+
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            report.error(path.display(), format!("can't read the file: {}", err));
+            return;
+        }
+    };
+
+    let location = Location {
+        path: &path,
+        contents: &contents,
+    };
+
+    let config: Config = match toml::from_str(&contents) {
+        Ok(config) => config,
+        Err(err) => {
+            let source = match err.line_col() {
+                Some((line, column)) => format!("{}:{}:{}", path.display(), line + 1, column + 1),
+                None => path.display().to_string(),
+            };
+            report.error(source, err);
+            return;
+        }
+    };
+
+    println!("{}", messages::config_checking(&path.display().to_string()));
+
+    // The file parsed as a configuration, so it also parses here
+    let located: Located = toml::from_str(&contents).unwrap_or_default();
+
+    for (key, value) in &located.hooks {
+        if !HOOK_KEYS.contains(&key.as_str()) {
+            report.warning(
+                location.at(value.start()),
+                format!(
+                    "unknown hook '{}' is ignored, expected one of {}",
+                    key,
+                    HOOK_KEYS.join(", ")
+                ),
+            );
+        }
+    }
+
+    for key in HOOK_KEYS {
+        let value = match located.hooks.get(*key) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if let Some(command) = value.get_ref().as_str() {
+            if let Err(message) = check_hook_command(command) {
+                report.error(
+                    location.at(value.start()),
+                    format!("{} hook: {}", key, message),
+                );
+            }
+        }
+    }
+
+    for (rule, located) in config.play_policy.iter().zip(&located.play_policy) {
+        let start = located
+            .values()
+            .map(|value| value.start())
+            .min()
+            .unwrap_or_default();
+
+        match (&rule.question_id, &rule.title) {
+            (None, None) => report.warning(
+                location.at(start),
+                "play_policy rule without question_id nor title never matches",
+            ),
+            (Some(_), Some(_)) => report.warning(
+                location.at(located["title"].start()),
+                "title is ignored by a play_policy rule with a question_id",
+            ),
+            (None, Some(title)) if title.trim_matches('*').is_empty() => report.warning(
+                location.at(located["title"].start()),
+                "title pattern matches every question, later rules by title never apply",
+            ),
+            _ => {}
+        }
+    }
+
+    for (name, session) in &config.session {
+        let target = &located.session[name]["target"];
+        if !session.target.is_file() {
+            report.warning(
+                location.at(target.start()),
+                format!(
+                    "{} doesn't exist, switching to session '{}' fails until it is created",
+                    session.target.display(),
+                    name
+                ),
+            );
+        }
+    }
+
+    for (path, chain) in &located.transforms.target {
+        if !Path::new(path).is_file() {
+            report.warning(
+                location.at(chain.start()),
+                format!("{} doesn't exist, its transform chain never applies", path),
+            );
+        }
+    }
+
+    // Stages taking their input from the options only
+    let mut chains: Vec<_> = config.transforms.chains().flatten().collect();
+    chains.sort();
+    chains.dedup();
+    for stage in chains {
+        let missing = match stage {
+            Stage::Copyright if opts.upload_add_copyright.is_none() => "--upload-add-copyright",
+            Stage::Wrapper if opts.upload_wrapper.is_empty() => "--upload-wrapper",
+            Stage::TestInput if opts.upload_inject_test_input.is_none() => {
+                "--upload-inject-test-input"
+            }
+            Stage::CodepointLimit
+                if opts.upload_force_codepoint_limit.is_none()
+                    && config.transforms.options.codepoint_limit.limit.is_none() =>
+            {
+                "--upload-force-codepoint-limit or a limit option"
+            }
+            _ => continue,
+        };
+
+        report.warning(
+            path.display(),
+            format!(
+                "the {} transform is skipped without {}",
+                stage.name(),
+                missing
+            ),
+        );
+    }
+
+    if let Some(session) = session {
+        if let Err(err) = config.session(session) {
+            report.error("--session", err);
+        }
+    }
+}
+
+/// Check the program run by a hook command exists. Commands using shell syntax beyond simple
+/// words are not checked.
+fn check_hook_command(command: &str) -> std::result::Result<(), String> {
+    let program = command.split_whitespace().find(|word| !is_assignment(word));
+
+    let program = match program {
+        Some(program) => program,
+        None => return Err("the command is empty".to_owned()),
+    };
+
+    if program.contains(|c| "$`'\"(){};&|<>~".contains(c)) {
+        return Ok(());
+    }
+
+    if program.contains(std::path::MAIN_SEPARATOR) || program.contains('/') {
+        return if Path::new(program).is_file() {
+            Ok(())
+        } else {
+            Err(format!("{} doesn't exist", program))
+        };
+    }
+
+    if find_in_path(program).is_some() {
+        Ok(())
+    } else {
+        Err(format!("{} was not found in PATH", program))
+    }
+}
+
+/// Whether the word is a `NAME=value` environment assignment preceding the program
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(program);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+
+        if cfg!(windows) {
+            let candidate = candidate.with_extension("exe");
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+
+        None
+    })
+}
+
+fn check_bind(opts: &Opts, probe: bool, report: &mut Report) {
+    #[cfg(unix)]
+    if opts.bind_fd.is_some() || opts.bind_unix.is_some() {
+        return;
+    }
+
+    if let Err(reason) = bind::parse(&opts.bind) {
+        report.error(
+            "--bind",
+            format!("{} is not a valid address: {}", opts.bind, reason),
+        );
+        return;
+    }
+
+    if probe {
+        match std::net::TcpListener::bind(&opts.bind) {
+            // Another port of the range is tried then
+            Err(err) if err.kind() == std::io::ErrorKind::AddrInUse && opts.port_fallback > 0 => {
+                report.warning(
+                    "--bind",
+                    format!(
+                        "{} is already in use, the app will fall back to one of the next {} ports",
+                        opts.bind, opts.port_fallback
+                    ),
+                )
+            }
+            Err(err) => report.error("--bind", format!("can't listen on {}: {}", opts.bind, err)),
+            Ok(_) => {}
+        }
+    }
+}
+
+fn check_paths(opts: &Opts, report: &mut Report) {
+    if let Some(target) = &opts.target {
+        let target: &Path = target.as_ref();
+        if target.is_dir() {
+            // A directory is resolved to its main file when synchronizing
+        } else if !target.exists() {
+            report.error("--target", format!("{} doesn't exist", target.display()));
+        }
+    }
+
+    let files = vec![
+        ("--upload-add-copyright", opts.upload_add_copyright.as_ref()),
+        (
+            "--upload-inject-test-input",
+            opts.upload_inject_test_input.as_ref(),
+        ),
+    ]
+    .into_iter()
+    .chain(
+        opts.upload_wrapper
+            .iter()
+            .map(|path| ("--upload-wrapper", Some(path))),
+    );
+
+    for (option, path) in files {
+        if let Some(path) = path {
+            if let Err(err) = std::fs::read_to_string(path) {
+                report.error(option, format!("can't read {}: {}", path.display(), err));
+            }
+        }
+    }
+
+    if let Some(emit) = &opts.emit {
+        // Directories are not created, only the part of the path before {slug} can be checked
+        let dir: PathBuf = emit
+            .parent()
+            .map(|parent| {
+                parent
+                    .components()
+                    .take_while(|component| {
+                        !component
+                            .as_os_str()
+                            .to_string_lossy()
+                            .contains(crate::SLUG_PLACEHOLDER)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if !dir.as_os_str().is_empty() && !dir.is_dir() {
+            report.error(
+                "--emit",
+                format!("directory {} doesn't exist", dir.display()),
+            );
+        }
+    }
+}
+
+fn check_ignore_patterns(opts: &Opts, report: &mut Report) {
+    let root = opts
+        .target
+        .as_ref()
+        .and_then(|target| AsRef::<Path>::as_ref(target).parent())
+        .map(Path::to_owned)
+        .unwrap_or_default();
+    let mut builder = GitignoreBuilder::new(&root);
+
+    let ignore_file = root.join(IGNORE_FILE_NAME);
+    if ignore_file.is_file() {
+        if let Some(err) = builder.add(&ignore_file) {
+            report.error(ignore_file.display(), err);
+        }
+    }
+
+    for pattern in &opts.ignore {
+        if let Err(err) = builder.add_line(None, pattern) {
+            report.error("--ignore", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt;
+
+    use super::*;
+
+    /// Findings of the configuration file with the given contents, as severity, location and
+    /// message
+    fn findings(contents: &str, args: &[&str]) -> Vec<(Severity, String, String)> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cg-local.toml");
+        std::fs::write(&path, contents).unwrap();
+
+        let mut argv = vec!["cg-local-app", "--config", path.to_str().unwrap()];
+        argv.extend_from_slice(args);
+        let opts = Opts::from_iter(argv);
+
+        let mut report = Report::default();
+        check_config_file(&opts, &mut report);
+
+        let prefix = format!("{}:", path.display());
+        report
+            .findings
+            .into_iter()
+            .map(|finding| {
+                let source = match finding.source.strip_prefix(&prefix) {
+                    Some(position) => position.to_owned(),
+                    None if finding.source == path.display().to_string() => "file".to_owned(),
+                    None => finding.source,
+                };
+                (finding.severity, source, finding.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn broken_configs() {
+        use Severity::*;
+
+        // The parser doesn't locate every error, some are reported at 1:1
+        let table: &[(&str, &[&str], Severity, &str, &str)] = &[
+            // Unknown keys
+            (
+                "[hooks]\non_save = \"true\"\n",
+                &[],
+                Warning,
+                "2:11",
+                "unknown hook 'on_save' is ignored, expected one of on_connect,",
+            ),
+            (
+                "[transforms]\nlanguages = {}\n",
+                &[],
+                Error,
+                "1:1",
+                "unknown field `languages`, expected one of `target`, `question`, `language`, `options`",
+            ),
+            // Bad types
+            (
+                "[[play_policy]]\nquestion_id = \"42\"\npolicy = \"deny\"\n",
+                &[],
+                Error,
+                "2:15",
+                "invalid type: string \"42\", expected i32",
+            ),
+            (
+                "[[play_policy]]\nquestion_id = 42\npolicy = \"sometimes\"\n",
+                &[],
+                Error,
+                "1:1",
+                "unknown variant `sometimes`, expected one of `allow`, `warn`, `deny`",
+            ),
+            ("[hooks\n", &[], Error, "1:7", "expected a right bracket"),
+            // Sessions
+            (
+                "[session.contest]\ntarget = \"missing/fall.rs\"\n",
+                &[],
+                Warning,
+                "2:10",
+                "missing/fall.rs doesn't exist, switching to session 'contest' fails until it is created",
+            ),
+            (
+                "",
+                &["--session", "contest"],
+                Error,
+                "--session",
+                "no session named 'contest' in the configuration",
+            ),
+            // Transform stages
+            (
+                "[transforms.language]\nrust = [\"minify\"]\n",
+                &[],
+                Error,
+                "1:1",
+                "unknown variant `minify`, expected one of `replace`, `dead-code`, `comment-out-main`, `copyright`, `wrapper`, `metadata`, `test-input`, `final-newline`, `codepoint-limit`",
+            ),
+            (
+                "[transforms.language]\nrust = [\"copyright\"]\n",
+                &[],
+                Warning,
+                "file",
+                "the copyright transform is skipped without --upload-add-copyright",
+            ),
+            (
+                "[transforms.target]\n\"missing/fall.rs\" = [\"final-newline\"]\n",
+                &[],
+                Warning,
+                "2:21",
+                "missing/fall.rs doesn't exist, its transform chain never applies",
+            ),
+        ];
+
+        for (contents, args, severity, source, message) in table {
+            let findings = findings(contents, args);
+            assert!(
+                findings
+                    .iter()
+                    .any(|(found_severity, found_source, found_message)| {
+                        found_severity == severity
+                            && found_source == source
+                            && found_message.contains(message)
+                    }),
+                "{:?}: expected {:?} at {} containing {:?}, found {:?}",
+                contents,
+                severity,
+                source,
+                message,
+                findings
+            );
+        }
+    }
+
+    #[test]
+    fn valid_configs_have_no_findings() {
+        let contents = "[hooks]\non_connect = \"true\"\n\n[[play_policy]]\ntitle = \"*Arena*\"\npolicy = \"deny\"\n\n[transforms.language]\nrust = [\"final-newline\"]\n";
+        assert_eq!(findings(contents, &[]), vec![]);
+    }
+}
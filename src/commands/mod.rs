@@ -0,0 +1,84 @@
+//! Subcommands run instead of the synchronization.
+
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::{Opts, Result};
+
+mod config;
+mod multi;
+mod network_interfaces;
+mod replay;
+mod stats;
+mod transform;
+
+#[derive(Debug, Clone, StructOpt)]
+pub enum Command {
+    /// Print lifetime statistics per question, most active first
+    Stats {
+        /// Print the statistics as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Print the network interfaces and their addresses, to choose the address for --bind
+    NetworkInterfaces,
+    /// Inspect the configuration
+    Config(ConfigCommand),
+    /// Run the upload preprocessing on the target once and print the code that would be uploaded
+    Transform {
+        /// Write the code to this file instead of the standard output
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+        /// Question id for --upload-inject-metadata, --question-id-override by default
+        #[structopt(long)]
+        question_id: Option<i32>,
+        /// Question title for --upload-inject-metadata
+        #[structopt(long, requires = "question-id")]
+        title: Option<String>,
+    },
+    /// Run every [[instance]] of the configuration file, each with its own address and target
+    Multi,
+    /// Send the messages of a session recorded with --record to the instance listening on --bind,
+    /// which must run with --no-origin-check, and print its answers
+    Replay {
+        /// Recording to replay
+        recording: PathBuf,
+        /// Send the messages right away instead of at their recorded pace
+        #[structopt(long)]
+        fast: bool,
+    },
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub enum ConfigCommand {
+    /// Validate the configuration file and the options, reporting every problem found
+    Check {
+        /// Also check the --bind address can be listened on
+        #[structopt(long)]
+        probe: bool,
+    },
+}
+
+impl Command {
+    pub fn run(&self, opts: &Opts) -> Result<()> {
+        match self {
+            Command::Stats { json } => stats::run(*json),
+            Command::NetworkInterfaces => network_interfaces::run(),
+            Command::Config(ConfigCommand::Check { probe }) => config::check(opts, *probe),
+            Command::Transform {
+                output,
+                question_id,
+                title,
+            } => transform::run(
+                opts,
+                output.as_deref(),
+                question_id
+                    .map(|question_id| (question_id, title.clone().unwrap_or_default()))
+                    .or_else(|| opts.question_override()),
+            ),
+            Command::Multi => multi::run(opts),
+            Command::Replay { recording, fast } => replay::run(opts, recording, *fast),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Several independent instances started from one configuration file.
+//!
+//! Each `[[instance]]` table gives the address the instance listens on, its target and the
+//! other options it runs with:
+//!
+//! ```toml
+//! [[instance]]
+//! name = "fall"
+//! bind = "127.0.0.1:53135"
+//! target = "contest/fall.rs"
+//! args = ["--play"]
+//!
+//! [[instance]]
+//! name = "practice"
+//! bind = "127.0.0.1:53136"
+//! target = "practice/main.py"
+//! ```
+//!
+//! Every instance runs as a child process without the interface, sharing the configuration file,
+//! and its output is printed prefixed with its name. An instance that fails to start or exits is
+//! reported without stopping the others, and Ctrl-C stops all of them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+
+use error_chain::bail;
+
+use crate::{bind, config, ErrorKind, Opts, Result, ResultExt};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MultiConfig {
+    instance: Vec<InstanceConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceConfig {
+    /// Prefix of the output of the instance
+    name: String,
+    bind: String,
+    target: PathBuf,
+    /// Other options of the instance
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// Process ids of the running instances, by name
+type Running = Arc<Mutex<BTreeMap<String, u32>>>;
+
+pub fn run(opts: &Opts) -> Result<()> {
+    let path = match config::resolve(opts.config.as_ref().map(|path| path.as_ref())) {
+        Some(path) => path,
+        None => {
+            bail!("multi reads the instances from the configuration file, pass it with --config")
+        }
+    };
+
+    let contents = std::fs::read_to_string(&path)
+        .chain_err(|| ErrorKind::InvalidConfig(path.display().to_string()))?;
+    let multi: MultiConfig = toml::from_str(&contents)
+        .chain_err(|| ErrorKind::InvalidConfig(path.display().to_string()))?;
+
+    if multi.instance.is_empty() {
+        bail!("{} has no [[instance]] table", path.display());
+    }
+
+    // Outputs and addresses must tell the instances apart
+    let mut names = BTreeSet::new();
+    let mut binds = BTreeMap::new();
+    for instance in &multi.instance {
+        if !names.insert(&instance.name) {
+            bail!("instance '{}' is defined twice", instance.name);
+        }
+
+        if let Some(other) = binds.insert(&instance.bind, &instance.name) {
+            bail!(
+                "instances '{}' and '{}' both listen on {}",
+                other,
+                instance.name,
+                instance.bind
+            );
+        }
+    }
+
+    let exe = std::env::current_exe()?;
+    let running: Running = Default::default();
+    let (tx_exit, rx_exit) = mpsc::channel();
+    let mut failed = 0;
+    let mut started = 0;
+
+    #[cfg(unix)]
+    crate::shutdown::on_signal({
+        let running = running.clone();
+        move || {
+            for (name, pid) in running.lock().unwrap().iter() {
+                debug!("[{}] stopping process {}", name, pid);
+                // SAFETY: kill has no memory safety requirements
+                unsafe { libc::kill(*pid as libc::pid_t, libc::SIGTERM) };
+            }
+        }
+    })?;
+
+    for instance in &multi.instance {
+        if let Err(reason) = bind::parse(&instance.bind) {
+            error!(
+                "[{}] {}",
+                instance.name,
+                ErrorKind::InvalidBind(instance.bind.clone(), reason)
+            );
+            failed += 1;
+            continue;
+        }
+
+        match spawn(&exe, &path, instance) {
+            Ok(child) => {
+                info!(
+                    "[{}] synchronizing {} on {}, process {}",
+                    instance.name,
+                    instance.target.display(),
+                    instance.bind,
+                    child.id()
+                );
+                supervise(&instance.name, child, &running, &tx_exit);
+                started += 1;
+            }
+            Err(err) => {
+                error!("[{}] failed to start: {}", instance.name, err);
+                failed += 1;
+            }
+        }
+    }
+
+    drop(tx_exit);
+
+    for (name, status) in rx_exit.iter().take(started) {
+        match status {
+            Ok(status) if status.success() => info!("[{}] exited", name),
+            Ok(status) => {
+                error!("[{}] exited with {}", name, status);
+                failed += 1;
+            }
+            Err(err) => {
+                error!("[{}] failed to wait for the process: {}", name, err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} instance(s) failed", failed, multi.instance.len());
+    }
+
+    Ok(())
+}
+
+/// Start an instance without the interface
+fn spawn(exe: &Path, config: &Path, instance: &InstanceConfig) -> std::io::Result<Child> {
+    let mut command = Command::new(exe);
+    command
+        .arg("--config")
+        .arg(config)
+        .arg("--no-gui")
+        .arg("--bind")
+        .arg(&instance.bind)
+        .arg("--target")
+        .arg(&instance.target)
+        .args(&instance.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    // Ctrl-C would reach the instances along with this process, which forwards it once instead
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    command.spawn()
+}
+
+/// Print the output of an instance with its name, and report its exit on `tx_exit`
+fn supervise(
+    name: &str,
+    mut child: Child,
+    running: &Running,
+    tx_exit: &mpsc::Sender<(String, std::io::Result<ExitStatus>)>,
+) {
+    if let Some(stdout) = child.stdout.take() {
+        forward(name, stdout, |line| println!("{}", line));
+    }
+
+    if let Some(stderr) = child.stderr.take() {
+        forward(name, stderr, |line| eprintln!("{}", line));
+    }
+
+    running.lock().unwrap().insert(name.to_owned(), child.id());
+
+    let name = name.to_owned();
+    let running = running.clone();
+    let tx_exit = tx_exit.clone();
+    std::thread::spawn(move || {
+        let status = child.wait();
+        running.lock().unwrap().remove(&name);
+        tx_exit.send((name, status)).ok();
+    });
+}
+
+/// Print each line read from `output` prefixed with the name of the instance
+fn forward(name: &str, output: impl Read + Send + 'static, print: fn(&str)) {
+    let prefix = format!("[{}]", name);
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => print(&format!("{} {}", prefix, line)),
+                Err(_) => break,
+            }
+        }
+    });
+}
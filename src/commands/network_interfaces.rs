@@ -0,0 +1,72 @@
+use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+
+use crate::{messages, Result};
+
+pub fn run() -> Result<()> {
+    let mut interfaces = NetworkInterface::show().map_err(|err| err.to_string())?;
+    interfaces.sort_by_key(|interface| interface.index);
+
+    if interfaces.is_empty() {
+        println!("{}", messages::no_network_interface());
+        return Ok(());
+    }
+
+    println!("{}", messages::network_interfaces_header());
+
+    for interface in interfaces {
+        let (status, mtu) = link(&interface.name);
+        let mut addresses = interface.addr.iter().map(address);
+
+        println!(
+            "{:<16}  {:<6}  {:>5}  {}",
+            interface.name,
+            status.unwrap_or("-"),
+            mtu.map(|mtu| mtu.to_string())
+                .unwrap_or_else(|| "-".to_owned()),
+            addresses.next().unwrap_or_default()
+        );
+
+        for address in addresses {
+            println!("{:33}{}", ' ', address);
+        }
+    }
+
+    Ok(())
+}
+
+/// Address in the form expected by --bind, with the port left out
+fn address(addr: &Addr) -> String {
+    match addr {
+        Addr::V4(addr) => addr.ip.to_string(),
+        Addr::V6(addr) => format!("[{}]", addr.ip),
+    }
+}
+
+/// Status and MTU of the interface, which the interface enumeration doesn't include
+#[cfg(target_os = "linux")]
+fn link(name: &str) -> (Option<&'static str>, Option<u32>) {
+    let read = |attribute: &str| {
+        std::fs::read_to_string(format!("/sys/class/net/{}/{}", name, attribute))
+            .ok()
+            .map(|value| value.trim().to_owned())
+    };
+
+    // Loopback interfaces report an unknown operational state while being usable
+    let status = read("flags")
+        .and_then(|flags| u32::from_str_radix(flags.trim_start_matches("0x"), 16).ok())
+        .map(|flags| {
+            if flags & libc::IFF_UP as u32 != 0 {
+                "up"
+            } else {
+                "down"
+            }
+        });
+    let mtu = read("mtu").and_then(|mtu| mtu.parse().ok());
+
+    (status, mtu)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn link(_name: &str) -> (Option<&'static str>, Option<u32>) {
+    (None, None)
+}
@@ -0,0 +1,172 @@
+//! Replay of a session recorded with `--record`.
+//!
+//! The recording is replayed against a running instance, listening on `--bind`: every recorded
+//! connection is opened at the time it was accepted, and the messages it received from the
+//! extension are sent at their recorded pace, or right away with `--fast`. The messages of the
+//! instance are printed as they arrive, so they can be compared with the recorded ones.
+//! Connections are closed once the last message was replayed.
+//!
+//! The replay doesn't come from the browser extension: the instance must run with
+//! `--no-origin-check`. Its `--auth-token`, if any, is given with the same option.
+
+use std::collections::{hash_map, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_tungstenite::tungstenite::{self, Message};
+use error_chain::bail;
+use futures::stream::{SplitSink, StreamExt};
+use futures_util::sink::SinkExt;
+
+use crate::admission;
+use crate::connections::ConnectionId;
+use crate::recording::{self, Direction, Entry};
+use crate::runtime::channel::{self, Sender};
+use crate::runtime::net::TcpStream;
+use crate::runtime::task;
+use crate::{messages, Opts, Result, ServerMessage};
+
+/// Delay for the instance to answer the last replayed messages
+const ANSWER_DELAY: Duration = Duration::from_secs(1);
+
+type Client = async_tungstenite::WebSocketStream<TcpStream>;
+
+pub fn run(opts: &Opts, path: &Path, fast: bool) -> Result<()> {
+    let entries = recording::load(path)?;
+
+    if !entries.iter().any(|entry| entry.direction == Direction::In) {
+        bail!(
+            "{} has no message of the extension to replay",
+            path.display()
+        );
+    }
+
+    let url = admission::request_url(&opts.bind, opts.auth_token.as_deref());
+
+    task::block_on(replay(&opts.bind, &url, entries, fast))
+}
+
+async fn replay(addr: &str, url: &str, entries: Vec<Entry>, fast: bool) -> Result<()> {
+    let started = Instant::now();
+    let recorded = entries
+        .iter()
+        .filter(|entry| entry.direction == Direction::Out)
+        .count();
+
+    let received = Arc::new(AtomicUsize::new(0));
+    // Readers hold a sender each, the channel closing once all of them ended
+    let (tx_done, mut rx_done) = channel::bounded::<()>(1);
+    let mut clients: HashMap<ConnectionId, SplitSink<Client, Message>> = HashMap::new();
+    let mut sent = 0;
+
+    for entry in entries {
+        if !fast {
+            let at = started + Duration::from_secs_f64(entry.elapsed.max(0.0));
+            task::sleep(at.saturating_duration_since(Instant::now())).await;
+        }
+
+        let sink = match clients.entry(entry.connection) {
+            hash_map::Entry::Occupied(sink) => sink.into_mut(),
+            hash_map::Entry::Vacant(vacant) => {
+                let client = connect(addr, url).await.map_err(|err| {
+                    format!(
+                        "failed to connect to {}: {}. The instance must run with --no-origin-check.",
+                        url, err
+                    )
+                })?;
+                println!(
+                    "{:>8.3}  {}  {}",
+                    started.elapsed().as_secs_f64(),
+                    entry.connection,
+                    messages::replay_connected()
+                );
+
+                let (sink, stream) = client.split();
+                task::spawn(read(
+                    entry.connection,
+                    stream,
+                    started,
+                    received.clone(),
+                    tx_done.clone(),
+                ));
+                vacant.insert(sink)
+            }
+        };
+
+        if entry.direction == Direction::Out {
+            continue;
+        }
+
+        let text = entry.message.to_string();
+        match ServerMessage::parse(&text) {
+            Ok(msg) => println!(
+                "{:>8.3}  {}  > {:?}",
+                started.elapsed().as_secs_f64(),
+                entry.connection,
+                msg
+            ),
+            Err(err) => bail!("invalid recorded message {}: {}", text, err),
+        }
+
+        sink.send(Message::Text(text)).await?;
+        sent += 1;
+    }
+
+    task::sleep(ANSWER_DELAY).await;
+    for (_, mut sink) in clients.drain() {
+        // Closed by the instance already if it dropped the connection
+        let _ = sink.close().await;
+    }
+
+    drop(tx_done);
+    let _ = async_std::future::timeout(ANSWER_DELAY, rx_done.next()).await;
+
+    println!(
+        "{}",
+        messages::replay_summary(sent, received.load(Ordering::Relaxed), recorded)
+    );
+
+    Ok(())
+}
+
+async fn connect(addr: &str, url: &str) -> std::result::Result<Client, tungstenite::Error> {
+    let stream = TcpStream::connect(addr).await?;
+    let (client, _) = async_tungstenite::client_async(url, stream).await?;
+    Ok(client)
+}
+
+/// Print the messages of the instance on the connection until it is closed
+async fn read(
+    connection: ConnectionId,
+    mut stream: futures::stream::SplitStream<Client>,
+    started: Instant,
+    received: Arc<AtomicUsize>,
+    _done: Sender<()>,
+) {
+    while let Some(msg) = stream.next().await {
+        let text = match msg {
+            Ok(Message::Text(text)) => text,
+            Ok(_) => continue,
+            Err(err) => {
+                debug!("connection {} ended: {}", connection, err);
+                break;
+            }
+        };
+
+        let elapsed = started.elapsed().as_secs_f64();
+        match ServerMessage::parse(&text) {
+            Ok(msg) => println!("{:>8.3}  {}  < {:?}", elapsed, connection, msg),
+            Err(_) => println!("{:>8.3}  {}  < {}", elapsed, connection, text),
+        }
+        received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    println!(
+        "{:>8.3}  {}  {}",
+        started.elapsed().as_secs_f64(),
+        connection,
+        messages::replay_closed()
+    );
+}
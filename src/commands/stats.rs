@@ -0,0 +1,48 @@
+use crate::stats;
+use crate::{messages, Result};
+
+pub fn run(json: bool) -> Result<()> {
+    let stats = stats::load();
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&stats).map_err(|err| err.to_string())?
+        );
+        return Ok(());
+    }
+
+    if stats.is_empty() {
+        println!("{}", messages::no_statistics());
+        return Ok(());
+    }
+
+    let mut questions: Vec<_> = stats.iter().collect();
+    questions.sort_by_key(|(_, question)| std::cmp::Reverse(question.activity()));
+
+    println!("{}", messages::stats_header());
+
+    for (question_id, question) in questions {
+        println!(
+            "{:>10}  {:<40}  {:>7}  {:>9}  {:>5}  {:>9}",
+            question_id,
+            truncate(&question.title, 40),
+            question.uploads,
+            question.downloads,
+            question.plays,
+            question.bytes
+        );
+    }
+
+    Ok(())
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        text.to_owned()
+    } else {
+        let mut truncated: String = text.chars().take(width - 1).collect();
+        truncated.push('…');
+        truncated
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use error_chain::bail;
+
+use crate::config::Config;
+use crate::pipeline::{self, Pipeline};
+use crate::runtime::task;
+use crate::{DynamicOpts, ErrorKind, Opts, Result, ResultExt};
+
+pub fn run(opts: &Opts, output: Option<&Path>, question: Option<(i32, String)>) -> Result<()> {
+    let target = match &opts.target {
+        Some(target) => target,
+        None => bail!("--target is required to preview the uploaded code"),
+    };
+
+    let code = std::fs::read_to_string(target)
+        .chain_err(|| ErrorKind::InvalidTarget(target.display().to_string()))?;
+
+    let config = Config::load(opts.config.as_ref().map(|path| path.as_ref()))?;
+    config.transforms.check(&pipeline::flagged(opts))?;
+    let copyright = opts.copyright()?;
+    let wrapper = opts.wrapper()?;
+    let pipeline = Pipeline::new(
+        opts,
+        &DynamicOpts::new(opts),
+        &config.transforms,
+        copyright.as_ref(),
+        wrapper.as_ref(),
+    );
+    let preprocessed = task::block_on(pipeline.run(&code, question.as_ref()));
+
+    for error in &preprocessed.errors {
+        eprintln!("warning: {}", error);
+    }
+
+    match output {
+        Some(output) => std::fs::write(output, &preprocessed.code)?,
+        None => print!("{}", preprocessed.code),
+    }
+
+    let applied = if preprocessed.applied.is_empty() {
+        "none".to_owned()
+    } else {
+        preprocessed.applied.join(", ")
+    };
+    eprintln!(
+        "{}: {} -> {} chars, transforms applied: {}",
+        target.display(),
+        code.chars().count(),
+        preprocessed.code.chars().count(),
+        applied
+    );
+
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Configuration file, for settings that don't fit on the command line.
+//!
+//! The file is given with `--config`. Otherwise `cg-local.toml` in the current directory is used
+//! if it exists, then `config.toml` in the platform configuration directory. Without any file,
+//! the defaults apply.
+//!
+//! ```toml
+//! [hooks]
+//! on_disconnect = "paplay /usr/share/sounds/freedesktop/stereo/bell.oga"
+//! on_play = "curl -s -X POST https://example.com/played"
+//!
+//! [session.fall-challenge]
+//! target = "contest/fall.rs"
+//! question_id = 9999
+//!
+//! [transforms.language]
+//! rust = ["comment-out-main", "final-newline"]
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::hooks::HooksConfig;
+use crate::play_policy::PlayPolicyRule;
+use crate::sessions::SessionConfig;
+use crate::transform_chains::TransformsConfig;
+use crate::{ErrorKind, Result, ResultExt};
+
+/// Name of the configuration file looked up in the current directory
+pub const LOCAL_CONFIG_FILE_NAME: &str = "cg-local.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Commands run on application events
+    pub hooks: HooksConfig,
+    /// Auto-play rules per question
+    pub play_policy: Vec<PlayPolicyRule>,
+    /// Named sessions, by name
+    pub session: BTreeMap<String, SessionConfig>,
+    /// Chains of upload transforms
+    pub transforms: TransformsConfig,
+}
+
+impl Config {
+    /// Load the configuration from `path`, or from the default locations if not given
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match resolve(path) {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .chain_err(|| ErrorKind::InvalidConfig(path.display().to_string()))?;
+        let config = toml::from_str(&contents)
+            .chain_err(|| ErrorKind::InvalidConfig(path.display().to_string()))?;
+
+        debug!("loaded configuration from {}", path.display());
+        Ok(config)
+    }
+
+    /// Session of the given name
+    pub fn session(&self, name: &str) -> Result<&SessionConfig> {
+        self.session.get(name).ok_or_else(|| {
+            ErrorKind::UnknownSession(name.to_owned(), self.session.keys().cloned().collect())
+                .into()
+        })
+    }
+
+    /// Name of the session for the question, the first one in name order if several are
+    pub fn session_for(&self, question_id: i32) -> Option<&str> {
+        self.session
+            .iter()
+            .find(|(_, session)| session.question_id == Some(question_id))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Configuration file to load: `path` if given, else the first existing file in the default
+/// locations
+pub fn resolve(path: Option<&Path>) -> Option<PathBuf> {
+    path.map(Path::to_owned).or_else(default_path)
+}
+
+/// First existing configuration file in the default locations
+fn default_path() -> Option<PathBuf> {
+    let local = PathBuf::from(LOCAL_CONFIG_FILE_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+
+    dirs::config_dir()
+        .map(|dir| dir.join("cg-local-app").join("config.toml"))
+        .filter(|path| path.is_file())
+}
@@ -0,0 +1,145 @@
+//! Routing of the controller messages to the extension connections.
+//!
+//! Every accepted connection gets its own channel and a [`ConnectionId`], carried by what it
+//! notifies to the controller. The controller addresses its messages to the connection which
+//! identified the question being synchronized, or to all of them, and the router forwards each
+//! message to the channels it is for. A connection that ended dropped its receiver, and is
+//! forgotten on the next message or count.
+
+use std::fmt;
+
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+
+use crate::channel_stats;
+use crate::listener::Peer;
+use crate::runtime::channel::{self, Receiver, Sender};
+use crate::ConnectedMessage;
+
+/// Connection of the extension, numbered in accept order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ConnectionId(u64);
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Connection registered to the router
+pub struct Connection {
+    pub id: ConnectionId,
+    /// Messages of the controller for this connection
+    pub rx: Receiver<ConnectedMessage>,
+}
+
+/// Recipients of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    All,
+    To(ConnectionId),
+}
+
+/// Message of the controller for the connections
+#[derive(Debug, Clone)]
+pub struct Routed {
+    pub route: Route,
+    pub msg: ConnectedMessage,
+}
+
+impl Routed {
+    pub fn all(msg: ConnectedMessage) -> Self {
+        Self {
+            route: Route::All,
+            msg,
+        }
+    }
+
+    /// Message for `connection`, or for all of them if it isn't known
+    pub fn to(connection: Option<ConnectionId>, msg: ConnectedMessage) -> Self {
+        Self {
+            route: connection.map(Route::To).unwrap_or(Route::All),
+            msg,
+        }
+    }
+}
+
+/// Open connections of the extension
+#[derive(Debug, Default)]
+pub struct Router {
+    next_id: u64,
+    connections: Vec<(ConnectionId, Peer, Sender<ConnectedMessage>)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a connection from `peer`
+    pub fn register(&mut self, peer: Peer) -> Connection {
+        self.next_id += 1;
+        let id = ConnectionId(self.next_id);
+
+        let (tx, rx) = channel::unbounded();
+        self.connections.push((id, peer, tx));
+        Connection { id, rx }
+    }
+
+    /// Number of open connections
+    pub fn len(&mut self) -> usize {
+        self.prune();
+        self.connections.len()
+    }
+
+    /// Close the oldest open connection to make room for a newer one, returning it
+    pub fn take_over(&mut self) -> Option<(ConnectionId, Peer)> {
+        self.prune();
+        if self.connections.is_empty() {
+            return None;
+        }
+
+        let (id, peer, tx) = self.connections.remove(0);
+        // Already gone if it can't receive it
+        let _ = tx.try_send(ConnectedMessage::Close);
+        Some((id, peer))
+    }
+
+    /// Forward a message to its recipients
+    pub fn route(&mut self, routed: Routed) {
+        let Routed { route, msg } = routed;
+
+        // Connections that ended dropped their receiver
+        self.connections.retain(|(id, peer, tx)| {
+            if route != Route::All && route != Route::To(*id) {
+                return !tx.is_closed();
+            }
+
+            match tx.try_send(msg.clone()) {
+                Ok(()) => true,
+                Err(_) => {
+                    debug!("connection {} from {} is gone", id, peer);
+                    false
+                }
+            }
+        });
+    }
+
+    fn prune(&mut self) {
+        self.connections.retain(|(_, _, tx)| !tx.is_closed());
+    }
+}
+
+/// Forward the messages of the controller until terminated
+pub async fn run(mut rx_connected: channel_stats::Receiver<Routed>, router: Arc<Mutex<Router>>) {
+    while let Some(routed) = rx_connected.next().await {
+        let terminate =
+            routed.route == Route::All && matches!(routed.msg, ConnectedMessage::Terminate);
+        router.lock().await.route(routed);
+
+        if terminate {
+            break;
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Removal of dead code before upload using language-specific external tools.
+//!
+//! * Rust (`dead-code-rust` feature): items reported by rustc's `dead_code` lint are removed,
+//!   and the result is checked to still compile.
+//! * Python (`dead-code-python` feature): unused imports and variables are removed by
+//!   `autoflake`.
+//! * JavaScript (`dead-code-js` feature): the code is compressed by `terser`.
+//!
+//! The stripped code is only uploaded, never written back to the target file.
+
+#[cfg(any(
+    feature = "dead-code-rust",
+    feature = "dead-code-python",
+    feature = "dead-code-js"
+))]
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::language::Language;
+
+/// Parse a language supported by the dead code stripper, rejecting the ones not compiled in
+pub fn parse_language(s: &str) -> Result<Language, String> {
+    let language: Language = s.parse()?;
+
+    let enabled = match language {
+        Language::Rust => cfg!(feature = "dead-code-rust"),
+        Language::Python => cfg!(feature = "dead-code-python"),
+        Language::JavaScript => cfg!(feature = "dead-code-js"),
+        other => return Err(format!("dead code removal is not supported for {}", other)),
+    };
+
+    if enabled {
+        Ok(language)
+    } else {
+        Err(format!(
+            "dead code removal for {} is not enabled in this build",
+            language
+        ))
+    }
+}
+
+/// Remove dead code, returning the original code if the external tool fails
+pub fn strip(language: Language, code: &str) -> String {
+    let result: Result<String, String> = match language {
+        #[cfg(feature = "dead-code-rust")]
+        Language::Rust => strip_rust(code),
+        #[cfg(feature = "dead-code-python")]
+        Language::Python => pipe(
+            Command::new("autoflake").args([
+                "--remove-all-unused-imports",
+                "--remove-unused-variables",
+                "-",
+            ]),
+            code,
+        ),
+        #[cfg(feature = "dead-code-js")]
+        Language::JavaScript => pipe(
+            Command::new("terser").args(["--compress", "dead_code=true"]),
+            code,
+        ),
+        #[allow(unreachable_patterns)]
+        other => Err(format!(
+            "dead code removal for {} is not enabled in this build",
+            other
+        )),
+    };
+
+    match result {
+        Ok(stripped) => {
+            debug!(
+                "dead code removal: {} -> {} bytes",
+                code.len(),
+                stripped.len()
+            );
+            stripped
+        }
+        Err(err) => {
+            warn!("dead code removal failed, uploading original code: {}", err);
+            code.to_owned()
+        }
+    }
+}
+
+/// Run a command with the given input on stdin and collect its output
+#[cfg(any(
+    feature = "dead-code-rust",
+    feature = "dead-code-python",
+    feature = "dead-code-js"
+))]
+fn run(command: &mut Command, input: &str) -> Result<std::process::Output, String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to run {}: {}", program, err))?;
+
+    // Write from another thread so a child filling its stdout can't deadlock us
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_owned();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("failed to run {}: {}", program, err))?;
+    let _ = writer.join();
+
+    Ok(output)
+}
+
+/// Run a command with the code on stdin and return its stdout
+#[cfg(any(feature = "dead-code-python", feature = "dead-code-js"))]
+fn pipe(command: &mut Command, input: &str) -> Result<String, String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = run(command, input)?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8(output.stdout).map_err(|err| err.to_string())
+}
+
+#[cfg(feature = "dead-code-rust")]
+fn strip_rust(code: &str) -> Result<String, String> {
+    let dead_lines = rustc_dead_code_lines(code)?;
+    if dead_lines.is_empty() {
+        return Ok(code.to_owned());
+    }
+
+    let lines: Vec<&str> = code.lines().collect();
+    let mut removed = vec![false; lines.len()];
+
+    for line in dead_lines {
+        let (start, end) = item_range(&lines, line);
+        for flag in &mut removed[start..=end] {
+            *flag = true;
+        }
+    }
+
+    let mut stripped: String = lines
+        .iter()
+        .zip(removed)
+        .filter(|(_, removed)| !removed)
+        .map(|(line, _)| *line)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if code.ends_with('\n') {
+        stripped.push('\n');
+    }
+
+    // The line-based removal is a heuristic, make sure it didn't break anything
+    rustc_check(&stripped)?;
+    Ok(stripped)
+}
+
+/// Zero-based lines at which rustc reports unused items
+#[cfg(feature = "dead-code-rust")]
+fn rustc_dead_code_lines(code: &str) -> Result<Vec<usize>, String> {
+    let diagnostics = rustc_check(code)?;
+    let mut lines = Vec::new();
+
+    for diagnostic in diagnostics.lines() {
+        let diagnostic: serde_json::Value = match serde_json::from_str(diagnostic) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+
+        if diagnostic["code"]["code"] != "dead_code" {
+            continue;
+        }
+
+        if let Some(spans) = diagnostic["spans"].as_array() {
+            for span in spans {
+                if span["is_primary"] == true {
+                    if let Some(line) = span["line_start"].as_u64() {
+                        lines.push(line as usize - 1);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Type-check the code with rustc, returning its JSON diagnostics
+#[cfg(feature = "dead-code-rust")]
+fn rustc_check(code: &str) -> Result<String, String> {
+    let out_dir = std::env::temp_dir().join(format!("cg-local-app-{}", std::process::id()));
+    std::fs::create_dir_all(&out_dir).map_err(|err| err.to_string())?;
+
+    let output = run(
+        Command::new("rustc")
+            .args([
+                "--edition",
+                "2018",
+                "--crate-type",
+                "bin",
+                "--crate-name",
+                "main",
+            ])
+            .args(["--emit=metadata", "--error-format=json", "--out-dir"])
+            .arg(&out_dir)
+            .arg("-"),
+        code,
+    );
+
+    let _ = std::fs::remove_dir_all(&out_dir);
+    let output = output?;
+    let diagnostics = String::from_utf8_lossy(&output.stderr).into_owned();
+
+    if output.status.success() {
+        Ok(diagnostics)
+    } else {
+        Err("code does not compile with rustc".to_owned())
+    }
+}
+
+/// Lines spanned by the item declared at `line`, including its attributes and doc comments
+#[cfg(feature = "dead-code-rust")]
+fn item_range(lines: &[&str], line: usize) -> (usize, usize) {
+    let mut start = line;
+    while start > 0 {
+        let previous = lines[start - 1].trim_start();
+        if previous.starts_with("#[") || previous.starts_with("///") {
+            start -= 1;
+        } else {
+            break;
+        }
+    }
+
+    let mut depth = 0i32;
+    let mut opened = false;
+    for (idx, text) in lines.iter().enumerate().skip(line) {
+        for c in text.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        if (opened && depth <= 0) || (!opened && text.trim_end().ends_with(';')) {
+            return (start, idx);
+        }
+    }
+
+    (start, lines.len() - 1)
+}
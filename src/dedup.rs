@@ -0,0 +1,78 @@
+//! Suppression of uploads repeating recently sent code.
+//!
+//! An edit undone back to a previous state (A, then B, then A again) would upload the same code
+//! twice. With `--upload-dedup-window`, the hashes of the uploaded code are remembered for the
+//! window and an upload of code sent within it is skipped. The code is hashed as saved, before the
+//! preprocessing pipeline, which may add a timestamp to every upload.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Hashes of the code uploaded within the window, with the time they were last sent
+#[derive(Debug)]
+pub struct DedupWindow {
+    window: Duration,
+    sent: HashMap<u64, Instant>,
+}
+
+impl DedupWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Whether the code was uploaded within the window
+    pub fn contains(&mut self, code: &str) -> bool {
+        self.expire();
+        self.sent.contains_key(&hash(code))
+    }
+
+    /// Record the code as uploaded now
+    pub fn record(&mut self, code: &str) {
+        self.expire();
+        self.sent.insert(hash(code), Instant::now());
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let window = self.window;
+        self.sent
+            .retain(|_, sent_at| now.duration_since(*sent_at) < window);
+    }
+}
+
+fn hash(code: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    code.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_recorded_code_is_contained() {
+        let mut dedup = DedupWindow::new(Duration::from_secs(60));
+
+        assert!(!dedup.contains("a"));
+        assert!(!dedup.contains("a"), "the lookup recorded the code");
+
+        dedup.record("a");
+        assert!(dedup.contains("a"));
+        assert!(!dedup.contains("b"));
+    }
+
+    #[test]
+    fn code_expires_after_the_window() {
+        let mut dedup = DedupWindow::new(Duration::from_millis(10));
+
+        dedup.record("a");
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!dedup.contains("a"));
+    }
+}
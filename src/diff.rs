@@ -0,0 +1,335 @@
+//! Line diffs between two versions of the code, with renderers for the terminal and the text
+//! user interface.
+//!
+//! Line endings are normalized before comparing, so a file only differing by CRLF/LF line
+//! endings yields a single note instead of every line being changed. Rendering is bounded by
+//! `DiffOptions::max_lines` so a completely different file doesn't produce thousands of lines.
+
+use cursive::theme::{BaseColor, Color, Effect};
+use cursive::utils::markup::StyledString;
+use similar::{ChangeTag, TextDiff};
+
+/// Limits of a computed diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Unchanged lines shown around changes
+    pub context: usize,
+    /// Maximum number of diff lines kept, the others are only counted
+    pub max_lines: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            max_lines: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Context,
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    /// Line contents, without the line ending
+    pub text: String,
+    /// The line is the last one of its file and has no line ending
+    pub missing_newline: bool,
+}
+
+/// Changed region, with line numbers starting at 1 as in unified diffs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    pub hunks: Vec<Hunk>,
+    /// Diff lines left out because of `DiffOptions::max_lines`
+    pub omitted_lines: usize,
+    /// Both versions use different line endings
+    pub line_endings_differ: bool,
+}
+
+/// Compute the diff from `old` to `new`, or `None` if they are identical
+pub fn diff(old: &str, new: &str, options: DiffOptions) -> Option<Diff> {
+    if old == new {
+        return None;
+    }
+
+    let old_normalized = old.replace("\r\n", "\n");
+    let new_normalized = new.replace("\r\n", "\n");
+    let line_endings_differ =
+        old_normalized == new_normalized || old.contains("\r\n") != new.contains("\r\n");
+
+    let text_diff = TextDiff::from_lines(&old_normalized, &new_normalized);
+
+    let mut hunks = Vec::new();
+    let mut kept_lines = 0;
+    let mut omitted_lines = 0;
+
+    for group in text_diff.grouped_ops(options.context) {
+        let (first, last) = match (group.first(), group.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+
+        let mut hunk = Hunk {
+            old_start: first.old_range().start + 1,
+            old_len: last.old_range().end - first.old_range().start,
+            new_start: first.new_range().start + 1,
+            new_len: last.new_range().end - first.new_range().start,
+            lines: Vec::new(),
+        };
+
+        for op in &group {
+            for change in text_diff.iter_changes(op) {
+                if kept_lines >= options.max_lines {
+                    omitted_lines += 1;
+                    continue;
+                }
+
+                kept_lines += 1;
+                hunk.lines.push(DiffLine {
+                    kind: match change.tag() {
+                        ChangeTag::Equal => LineKind::Context,
+                        ChangeTag::Insert => LineKind::Insert,
+                        ChangeTag::Delete => LineKind::Delete,
+                    },
+                    text: change.value().trim_end_matches('\n').to_owned(),
+                    missing_newline: change.missing_newline(),
+                });
+            }
+        }
+
+        if !hunk.lines.is_empty() {
+            hunks.push(hunk);
+        }
+    }
+
+    Some(Diff {
+        hunks,
+        omitted_lines,
+        line_endings_differ,
+    })
+}
+
+const LINE_ENDINGS_NOTE: &str = "line endings differ (CRLF/LF)";
+const NO_NEWLINE_NOTE: &str = "\\ No newline at end of file";
+
+impl Diff {
+    /// Unified diff with ANSI colors, for terminal output
+    pub fn render_ansi(&self) -> String {
+        let mut out = String::new();
+
+        if self.line_endings_differ {
+            out.push_str(&format!("\x1b[33m{}\x1b[0m\n", LINE_ENDINGS_NOTE));
+        }
+
+        for hunk in &self.hunks {
+            out.push_str(&format!("\x1b[36m{}\x1b[0m\n", hunk.header()));
+
+            for line in &hunk.lines {
+                let (color, prefix) = match line.kind {
+                    LineKind::Context => ("", ' '),
+                    LineKind::Insert => ("\x1b[32m", '+'),
+                    LineKind::Delete => ("\x1b[31m", '-'),
+                };
+
+                if color.is_empty() {
+                    out.push_str(&format!("{}{}\n", prefix, line.text));
+                } else {
+                    out.push_str(&format!("{}{}{}\x1b[0m\n", color, prefix, line.text));
+                }
+
+                if line.missing_newline {
+                    out.push_str(NO_NEWLINE_NOTE);
+                    out.push('\n');
+                }
+            }
+        }
+
+        if self.omitted_lines > 0 {
+            out.push_str(&format!("... {} more lines\n", self.omitted_lines));
+        }
+
+        out
+    }
+
+    /// Unified diff as styled text, for text user interface views
+    pub fn render_styled(&self) -> StyledString {
+        let mut out = StyledString::new();
+
+        if self.line_endings_differ {
+            out.append_styled(
+                format!("{}\n", LINE_ENDINGS_NOTE),
+                Color::Dark(BaseColor::Yellow),
+            );
+        }
+
+        for hunk in &self.hunks {
+            out.append_styled(format!("{}\n", hunk.header()), Effect::Bold);
+
+            for line in &hunk.lines {
+                match line.kind {
+                    LineKind::Context => out.append_plain(format!(" {}\n", line.text)),
+                    LineKind::Insert => out
+                        .append_styled(format!("+{}\n", line.text), Color::Dark(BaseColor::Green)),
+                    LineKind::Delete => {
+                        out.append_styled(format!("-{}\n", line.text), Color::Dark(BaseColor::Red))
+                    }
+                }
+
+                if line.missing_newline {
+                    out.append_plain(format!("{}\n", NO_NEWLINE_NOTE));
+                }
+            }
+        }
+
+        if self.omitted_lines > 0 {
+            out.append_plain(format!("... {} more lines\n", self.omitted_lines));
+        }
+
+        out
+    }
+}
+
+impl Hunk {
+    /// `@@ -a,b +c,d @@` header line
+    fn header(&self) -> String {
+        format!(
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_len, self.new_start, self.new_len
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of the hunks, prefixed as in unified diffs
+    fn lines(diff: &Diff) -> Vec<Vec<String>> {
+        diff.hunks
+            .iter()
+            .map(|hunk| {
+                hunk.lines
+                    .iter()
+                    .map(|line| {
+                        let prefix = match line.kind {
+                            LineKind::Context => ' ',
+                            LineKind::Insert => '+',
+                            LineKind::Delete => '-',
+                        };
+                        format!("{}{}", prefix, line.text)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Numbered lines, from 1 to `count`
+    fn numbered(count: usize) -> Vec<String> {
+        (1..=count).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn identical() {
+        for code in &["", "a\n", "a\r\nb"] {
+            assert_eq!(diff(code, code, DiffOptions::default()), None, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let inserted = diff("", "a\nb\n", DiffOptions::default()).unwrap();
+        assert_eq!(lines(&inserted), vec![vec!["+a", "+b"]]);
+
+        let deleted = diff("a\nb\n", "", DiffOptions::default()).unwrap();
+        assert_eq!(lines(&deleted), vec![vec!["-a", "-b"]]);
+        assert_eq!((deleted.hunks[0].old_len, deleted.hunks[0].new_len), (2, 0));
+    }
+
+    #[test]
+    fn missing_newline() {
+        let diff = diff("a\n", "a\nb", DiffOptions::default()).unwrap();
+        let last = diff.hunks[0].lines.last().unwrap();
+        assert_eq!((last.text.as_str(), last.missing_newline), ("b", true));
+    }
+
+    #[test]
+    fn line_endings() {
+        let diff = diff("a\nb\n", "a\r\nb\r\n", DiffOptions::default()).unwrap();
+        assert!(diff.line_endings_differ);
+        assert!(diff.hunks.is_empty());
+    }
+
+    #[test]
+    fn context_merging() {
+        let old = numbered(20);
+        let options = DiffOptions {
+            context: 2,
+            ..DiffOptions::default()
+        };
+
+        // Changes on lines 5 and 9 share their context, the one on line 18 is apart
+        let mut new = old.clone();
+        for line in &[5, 9, 18] {
+            new[line - 1] = format!("{}'", line);
+        }
+
+        let diff = diff(
+            &format!("{}\n", old.join("\n")),
+            &format!("{}\n", new.join("\n")),
+            options,
+        )
+        .unwrap();
+
+        assert_eq!(
+            lines(&diff),
+            vec![
+                vec![" 3", " 4", "-5", "+5'", " 6", " 7", " 8", "-9", "+9'", " 10", " 11"],
+                vec![" 16", " 17", "-18", "+18'", " 19", " 20"],
+            ]
+        );
+        assert_eq!(diff.hunks[0].header(), "@@ -3,9 +3,9 @@");
+        assert_eq!(diff.hunks[1].header(), "@@ -16,5 +16,5 @@");
+    }
+
+    #[test]
+    fn truncation() {
+        let new = format!("{}\n", numbered(10).join("\n"));
+        let options = DiffOptions {
+            context: 0,
+            max_lines: 4,
+        };
+
+        let diff = diff("", &new, options).unwrap();
+        assert_eq!(lines(&diff), vec![vec!["+1", "+2", "+3", "+4"]]);
+        assert_eq!(diff.omitted_lines, 6);
+        assert!(diff.render_ansi().ends_with("... 6 more lines\n"));
+
+        let diff = super::diff(
+            "",
+            &new,
+            DiffOptions {
+                max_lines: 0,
+                ..options
+            },
+        )
+        .unwrap();
+        assert!(diff.hunks.is_empty());
+        assert_eq!(diff.omitted_lines, 10);
+    }
+}
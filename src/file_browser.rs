@@ -0,0 +1,157 @@
+//! Minimal file browser used by the text user interface to pick the target.
+//!
+//! Only directories and source files (see [`SOURCE_EXTENSIONS`](crate::language::SOURCE_EXTENSIONS))
+//! are listed. Hidden entries can be toggled with a checkbox.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use cursive::view::{Nameable, Resizable, Scrollable};
+use cursive::views::{Checkbox, Dialog, LinearLayout, SelectView, TextView};
+use cursive::Cursive;
+
+use crate::language::is_source_file;
+use crate::main_file::{self, MainFile};
+use crate::messages;
+
+const LIST_NAME: &str = "file_browser_list";
+const DIR_NAME: &str = "file_browser_dir";
+
+struct Browser {
+    dir: PathBuf,
+    show_hidden: bool,
+}
+
+/// Open the file browser on `dir`. `on_select` is called with the chosen file after the browser
+/// is closed.
+pub fn show(s: &mut Cursive, dir: PathBuf, on_select: impl Fn(&mut Cursive, PathBuf) + 'static) {
+    let browser = Rc::new(RefCell::new(Browser {
+        dir: PathBuf::new(),
+        show_hidden: false,
+    }));
+    let on_select = Rc::new(on_select);
+
+    let list = SelectView::<PathBuf>::new().on_submit({
+        let browser = browser.clone();
+        move |s, path: &PathBuf| {
+            if path.is_dir() {
+                navigate(s, &browser, path.clone());
+            } else {
+                s.pop_layer();
+                on_select(s, path.clone());
+            }
+        }
+    });
+
+    let hidden = Checkbox::new().on_change({
+        let browser = browser.clone();
+        move |s, checked| {
+            let dir = {
+                let mut browser = browser.borrow_mut();
+                browser.show_hidden = checked;
+                browser.dir.clone()
+            };
+
+            navigate(s, &browser, dir);
+        }
+    });
+
+    s.add_layer(
+        Dialog::around(
+            LinearLayout::vertical()
+                .child(TextView::new("").with_name(DIR_NAME))
+                .child(
+                    list.with_name(LIST_NAME)
+                        .scrollable()
+                        .fixed_height(15)
+                        .min_width(50),
+                )
+                .child(
+                    LinearLayout::horizontal()
+                        .child(hidden)
+                        .child(TextView::new(messages::label_show_hidden())),
+                ),
+        )
+        .title(messages::browse_title())
+        .button(messages::button_cancel(), |s| {
+            s.pop_layer();
+        }),
+    );
+
+    navigate(s, &browser, dir);
+}
+
+/// List `dir` in the browser. If it can't be read, an error is shown and the listing is kept.
+fn navigate(s: &mut Cursive, browser: &Rc<RefCell<Browser>>, dir: PathBuf) {
+    let show_hidden = browser.borrow().show_hidden;
+
+    let entries = match entries(&dir, show_hidden) {
+        Ok(entries) => entries,
+        Err(err) => {
+            s.add_layer(
+                Dialog::info(format!("{}: {}", dir.display(), err)).title(messages::error_title()),
+            );
+            return;
+        }
+    };
+
+    s.call_on_name(DIR_NAME, |view: &mut TextView| {
+        view.set_content(dir.display().to_string())
+    });
+
+    // Pre-select the file the main file heuristic would pick
+    let main_file = match main_file::files(&dir).map(|files| main_file::pick(&files)) {
+        Ok(MainFile::Found(path)) => Some(path),
+        _ => None,
+    };
+
+    let selected = entries
+        .iter()
+        .position(|(_, path)| Some(path) == main_file.as_ref());
+
+    s.call_on_name(LIST_NAME, |view: &mut SelectView<PathBuf>| {
+        view.clear();
+        for (label, path) in entries {
+            view.add_item(label, path);
+        }
+
+        if let Some(index) = selected {
+            view.set_selection(index);
+        }
+    });
+
+    browser.borrow_mut().dir = dir;
+}
+
+/// Entries of `dir` as labels and paths: its parent, then subdirectories, then source files
+fn entries(dir: &Path, show_hidden: bool) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !show_hidden && name.starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.is_dir() {
+            dirs.push((format!("{}/", name), path));
+        } else if is_source_file(&path) {
+            files.push((name, path));
+        }
+    }
+
+    dirs.sort();
+    files.sort();
+
+    Ok(dir
+        .parent()
+        .map(|parent| ("../".to_owned(), parent.to_owned()))
+        .into_iter()
+        .chain(dirs)
+        .chain(files)
+        .collect())
+}
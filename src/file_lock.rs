@@ -0,0 +1,108 @@
+//! Advisory lock on the target while it is synchronized, with `--target-file-lock`.
+//!
+//! A download written while the editor saves the same file can leave it corrupted. The target is
+//! locked exclusively while a download is written to it and shared while it is read for an
+//! upload, so editors honoring advisory locks wait for the operation. The lock is retried for
+//! [`LOCK_TIMEOUT`] before the operation is given up.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use fs2::FileExt;
+
+use crate::runtime::task;
+
+/// How long another process may hold the lock before the operation is skipped
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Reading the target, other readers are allowed
+    Shared,
+    /// Writing the target
+    Exclusive,
+}
+
+/// Lock on the target, released on drop. Without `--target-file-lock` the lock is not taken and
+/// the target is accessed as usual.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl FileLock {
+    /// Lock that is not held
+    pub fn none(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            file: None,
+        }
+    }
+
+    /// Lock the file, retrying for [`LOCK_TIMEOUT`]. Returns `None` if another process kept it
+    /// locked.
+    pub async fn acquire(path: &Path, access: Access) -> std::io::Result<Option<Self>> {
+        // Opening for writing doesn't truncate, the contents are replaced once locked
+        let file = match access {
+            Access::Shared => File::open(path)?,
+            Access::Exclusive => OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?,
+        };
+
+        let deadline = Instant::now() + LOCK_TIMEOUT;
+        loop {
+            let res = match access {
+                Access::Shared => FileExt::try_lock_shared(&file),
+                Access::Exclusive => FileExt::try_lock_exclusive(&file),
+            };
+
+            match res {
+                Ok(()) => {
+                    return Ok(Some(Self {
+                        path: path.to_owned(),
+                        file: Some(file),
+                    }))
+                }
+                Err(err) if err.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                    if Instant::now() >= deadline {
+                        return Ok(None);
+                    }
+
+                    task::sleep(RETRY_INTERVAL).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Replace the contents of the file. A held lock is written through, as other handles may not
+    /// write a locked file on every platform.
+    pub fn write(&mut self, contents: &str) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => {
+                file.set_len(0)?;
+                file.write_all(contents.as_bytes())?;
+                file.flush()
+            }
+            None => std::fs::write(&self.path, contents),
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            if let Err(err) = FileExt::unlock(file) {
+                warn!("failed to unlock {}: {}", self.path.display(), err);
+            }
+        }
+    }
+}
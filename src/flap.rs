@@ -0,0 +1,134 @@
+//! Detection of an extension reconnecting in a loop.
+//!
+//! Every connection of the extension asks for the question details, which prompts the user again
+//! or, without the interface, starts synchronizing again. When more than `--flap-threshold`
+//! connections end within `--flap-window`, the connection is considered flapping: prompts and
+//! starts are suspended until a connection stays up for `--flap-stabilization`.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Connection cycles and flapping state of the extension
+#[derive(Debug)]
+pub struct FlapDetector {
+    threshold: usize,
+    window: Duration,
+    stabilization: Duration,
+    /// End of the connections closed within the window
+    recent: VecDeque<Instant>,
+    /// Open connections, more than one with --max-connections
+    open: usize,
+    /// Since when a connection has been open
+    up_since: Option<Instant>,
+    flapping: bool,
+    stats: FlapStats,
+}
+
+/// Counters of the detector, for diagnostics
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FlapStats {
+    pub connections: u64,
+    pub disconnections: u64,
+    /// Connections which didn't identify the question within --details-timeout
+    pub without_details: u64,
+    /// Times the connection started flapping
+    pub episodes: u64,
+}
+
+impl fmt::Display for FlapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "connections: {} opened, {} closed, {} without question details, flapped {} time(s)",
+            self.connections, self.disconnections, self.without_details, self.episodes
+        )
+    }
+}
+
+impl FlapDetector {
+    /// Detector tolerating `threshold` connection cycles within `window`. A threshold of 0
+    /// disables the detection.
+    pub fn new(threshold: usize, window: Duration, stabilization: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            stabilization,
+            recent: VecDeque::new(),
+            open: 0,
+            up_since: None,
+            flapping: false,
+            stats: FlapStats::default(),
+        }
+    }
+
+    pub fn is_flapping(&self) -> bool {
+        self.flapping
+    }
+
+    pub fn stats(&self) -> FlapStats {
+        self.stats
+    }
+
+    pub fn connected(&mut self, now: Instant) {
+        self.stats.connections += 1;
+        self.open += 1;
+        self.up_since.get_or_insert(now);
+    }
+
+    /// Record a connection not identifying the question in time
+    pub fn without_details(&mut self) {
+        self.stats.without_details += 1;
+    }
+
+    /// Record the end of a connection. Returns true if the connection started flapping.
+    pub fn disconnected(&mut self, now: Instant) -> bool {
+        self.stats.disconnections += 1;
+        self.open = self.open.saturating_sub(1);
+        if self.open == 0 {
+            self.up_since = None;
+        }
+
+        if self.threshold == 0 {
+            return false;
+        }
+
+        self.recent.push_back(now);
+        while let Some(end) = self.recent.front() {
+            if now.duration_since(*end) < self.window {
+                break;
+            }
+
+            self.recent.pop_front();
+        }
+
+        if self.flapping || self.recent.len() <= self.threshold {
+            return false;
+        }
+
+        self.flapping = true;
+        self.stats.episodes += 1;
+        true
+    }
+
+    /// When the connection will be stable again if it stays up, while flapping
+    pub fn stable_at(&self) -> Option<Instant> {
+        if !self.flapping {
+            return None;
+        }
+
+        self.up_since.map(|since| since + self.stabilization)
+    }
+
+    /// Record the connection staying up. Returns true if it stopped flapping.
+    pub fn check_stable(&mut self, now: Instant) -> bool {
+        match self.stable_at() {
+            Some(at) if at <= now => {
+                self.flapping = false;
+                self.recent.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! User commands run on application events.
+//!
+//! Hooks are run through the platform shell in the background, so they never delay
+//! synchronization. Their output is captured and logged, and a hook still running after
+//! `HOOK_TIMEOUT` is killed. Failures are only logged.
+//!
+//! Every hook receives `CGLOCAL_EVENT` in its environment, plus the variables relevant to the
+//! event among `CGLOCAL_QUESTION_ID`, `CGLOCAL_QUESTION_TITLE`, `CGLOCAL_QUESTION_SLUG` (the
+//! title as a file name), `CGLOCAL_TARGET`, `CGLOCAL_LANGUAGE` (of the target),
+//! `CGLOCAL_NOTES` (the notes of the target, if any), `CGLOCAL_SESSION_STARTED` (when the
+//! question was identified), `CGLOCAL_BYTES`, `CGLOCAL_PEER`, `CGLOCAL_ERROR` and
+//! `CGLOCAL_SESSION` (the active session of the configuration file, if any). The question, target
+//! and session variables come from a single snapshot, so they always belong together even while
+//! the target is being switched.
+//!
+//! The download hook is the exception: it may rewrite the target, e.g. to format it, so uploads
+//! wait for it to exit. Changes of the target while it runs are uploaded once, after it.
+//!
+//! The `--upload-progress-command` is run the same way around every upload, with
+//! `CGLOCAL_STATUS` set to `uploading` then `idle`.
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::runtime::task;
+use crate::slug;
+
+/// Delay after which a running hook is killed
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events hooks can be attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Connect,
+    Disconnect,
+    Details,
+    Upload,
+    Play,
+    Download,
+    Error,
+}
+
+impl HookEvent {
+    /// Name of the event, as given in `CGLOCAL_EVENT`
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::Connect => "connect",
+            HookEvent::Disconnect => "disconnect",
+            HookEvent::Details => "details",
+            HookEvent::Upload => "upload",
+            HookEvent::Play => "play",
+            HookEvent::Download => "download",
+            HookEvent::Error => "error",
+        }
+    }
+}
+
+/// `[hooks]` table of the configuration file
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+    pub on_details: Option<String>,
+    pub on_upload: Option<String>,
+    pub on_play: Option<String>,
+    pub on_download: Option<String>,
+    pub on_error: Option<String>,
+}
+
+impl HooksConfig {
+    /// Command to run for the given event, if any
+    pub fn command(&self, event: HookEvent) -> Option<&str> {
+        match event {
+            HookEvent::Connect => &self.on_connect,
+            HookEvent::Disconnect => &self.on_disconnect,
+            HookEvent::Details => &self.on_details,
+            HookEvent::Upload => &self.on_upload,
+            HookEvent::Play => &self.on_play,
+            HookEvent::Download => &self.on_download,
+            HookEvent::Error => &self.on_error,
+        }
+        .as_deref()
+    }
+
+    /// Run the hook for the event in the background, if one is configured
+    pub fn fire(&self, event: HookEvent, env: HookEnv) {
+        self.fire_then(event, env, || {});
+    }
+
+    /// Run the hook for the event in the background like `fire`, calling `done` once it exited.
+    /// Returns false without calling `done` if no hook is configured.
+    pub fn fire_then(
+        &self,
+        event: HookEvent,
+        env: HookEnv,
+        done: impl FnOnce() + Send + 'static,
+    ) -> bool {
+        match self.command(event) {
+            Some(command) => {
+                spawn(
+                    event.name(),
+                    command,
+                    env.var("CGLOCAL_EVENT", event.name()),
+                    done,
+                );
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Environment variables given to a hook
+#[derive(Debug, Default, Clone)]
+pub struct HookEnv {
+    vars: Vec<(&'static str, String)>,
+}
+
+impl HookEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn var(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.vars.push((name, value.to_string()));
+        self
+    }
+
+    /// Add the question variables, if the question is known
+    pub fn question(self, question: Option<&(i32, String)>, slug_max_length: usize) -> Self {
+        match question {
+            Some((question_id, title)) => self
+                .var("CGLOCAL_QUESTION_ID", question_id)
+                .var("CGLOCAL_QUESTION_TITLE", title)
+                .var("CGLOCAL_QUESTION_SLUG", slug::slug(title, slug_max_length)),
+            None => self,
+        }
+    }
+}
+
+/// Sequence number of the last progress command started
+static PROGRESS_STARTED: AtomicU64 = AtomicU64::new(0);
+/// Sequence number of the last progress command run, held while one runs
+static PROGRESS_RUN: Mutex<u64> = Mutex::new(0);
+
+/// Run the --upload-progress-command in the background with the given status. Runs are
+/// serialized, and a run overtaken by a later status is skipped so the indicator never ends on
+/// a stale status.
+fn progress(command: &str, status: &'static str, env: HookEnv) {
+    let command = command.to_owned();
+    let env = env.var("CGLOCAL_STATUS", status);
+    let seq = PROGRESS_STARTED.fetch_add(1, Ordering::Relaxed) + 1;
+
+    task::spawn_blocking(move || {
+        let mut last = PROGRESS_RUN.lock().unwrap_or_else(|err| err.into_inner());
+        if *last > seq {
+            debug!("skipping stale upload progress status {}", status);
+            return;
+        }
+
+        *last = seq;
+        if let Err(err) = run("upload progress", &command, &env) {
+            warn!("upload progress command failed: {}", err);
+        }
+    });
+}
+
+/// --upload-progress-command of an upload in progress
+#[derive(Debug, Clone)]
+pub struct UploadProgress {
+    command: String,
+    env: HookEnv,
+}
+
+impl UploadProgress {
+    /// Run the command with the `uploading` status
+    pub fn start(command: &str, env: HookEnv) -> Self {
+        progress(command, "uploading", env.clone());
+
+        Self {
+            command: command.to_owned(),
+            env,
+        }
+    }
+
+    /// Run the command with the `idle` status, once the upload was sent or timed out
+    pub fn idle(self) {
+        progress(&self.command, "idle", self.env);
+    }
+}
+
+/// Run a command in the background through the shell, with the given environment, calling `done`
+/// once it exited or was killed
+fn spawn(name: &'static str, command: &str, env: HookEnv, done: impl FnOnce() + Send + 'static) {
+    let command = command.to_owned();
+
+    task::spawn_blocking(move || {
+        if let Err(err) = run(name, &command, &env) {
+            warn!("{} hook failed: {}", name, err);
+        }
+
+        done();
+    });
+}
+
+fn run(name: &str, command: &str, env: &HookEnv) -> std::io::Result<()> {
+    let mut child = shell(command)
+        .envs(env.vars.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Read the output while waiting so a chatty hook can't fill the pipes and block
+    let stdout = read_lines(child.stdout.take());
+    let stderr = read_lines(child.stderr.take());
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+
+        if started.elapsed() >= HOOK_TIMEOUT {
+            child.kill()?;
+            child.wait()?;
+            warn!(
+                "{} hook killed after {}s: {}",
+                name,
+                HOOK_TIMEOUT.as_secs(),
+                command
+            );
+            break None;
+        }
+
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    // Processes left behind by a killed hook may still hold the pipes, don't wait for them
+    let status = match status {
+        Some(status) => status,
+        None => return Ok(()),
+    };
+
+    for line in stdout.join().unwrap_or_default() {
+        debug!("{} hook: {}", name, line);
+    }
+    for line in stderr.join().unwrap_or_default() {
+        debug!("{} hook (stderr): {}", name, line);
+    }
+
+    if !status.success() {
+        warn!("{} hook exited with {}: {}", name, status, command);
+    }
+
+    Ok(())
+}
+
+fn read_lines(pipe: Option<impl Read + Send + 'static>) -> std::thread::JoinHandle<Vec<String>> {
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+
+        String::from_utf8_lossy(&output)
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    })
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
@@ -0,0 +1,185 @@
+//! Languages of the synchronized code.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Extensions of source files for the languages available on CodinGame
+pub const SOURCE_EXTENSIONS: &[&str] = &[
+    "bash", "c", "cc", "clj", "cpp", "cs", "d", "dart", "fs", "go", "groovy", "h", "hpp", "hs",
+    "java", "js", "kt", "lua", "m", "ml", "pas", "php", "pl", "py", "rb", "rs", "scala", "sh",
+    "swift", "ts", "vb",
+];
+
+/// Whether the path looks like a source file, according to its extension
+pub fn is_source_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| SOURCE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Names of the CodinGame languages, by source file extension. Covers every extension of
+/// [`SOURCE_EXTENSIONS`].
+fn codingame_languages() -> &'static HashMap<&'static str, &'static str> {
+    static LANGUAGES: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
+
+    LANGUAGES.get_or_init(|| {
+        [
+            ("bash", "Bash"),
+            ("sh", "Bash"),
+            ("c", "C"),
+            ("h", "C"),
+            ("cs", "C#"),
+            ("cpp", "C++"),
+            ("cc", "C++"),
+            ("hpp", "C++"),
+            ("clj", "Clojure"),
+            ("d", "D"),
+            ("dart", "Dart"),
+            ("fs", "F#"),
+            ("go", "Go"),
+            ("groovy", "Groovy"),
+            ("hs", "Haskell"),
+            ("java", "Java"),
+            ("js", "Javascript"),
+            ("kt", "Kotlin"),
+            ("lua", "Lua"),
+            ("m", "ObjectiveC"),
+            ("ml", "OCaml"),
+            ("pas", "Pascal"),
+            ("pl", "Perl"),
+            ("php", "PHP"),
+            ("py", "Python3"),
+            ("rb", "Ruby"),
+            ("rs", "Rust"),
+            ("scala", "Scala"),
+            ("swift", "Swift"),
+            ("ts", "TypeScript"),
+            ("vb", "VB.NET"),
+        ]
+        .iter()
+        .copied()
+        .collect()
+    })
+}
+
+/// Name of the CodinGame language of a source file, according to its extension
+pub fn codingame_language(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    codingame_languages().get(ext.as_str()).copied()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    C,
+    Cpp,
+    CSharp,
+    Java,
+    Kotlin,
+    Scala,
+    Go,
+    Swift,
+    Php,
+    Ruby,
+    Perl,
+    Bash,
+    Lua,
+    Haskell,
+}
+
+impl Language {
+    /// Language of a source file, according to its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        Some(match ext.as_str() {
+            "rs" => Language::Rust,
+            "py" => Language::Python,
+            "js" => Language::JavaScript,
+            "ts" => Language::TypeScript,
+            "c" | "h" => Language::C,
+            "cpp" | "cc" | "hpp" => Language::Cpp,
+            "cs" => Language::CSharp,
+            "java" => Language::Java,
+            "kt" => Language::Kotlin,
+            "scala" => Language::Scala,
+            "go" => Language::Go,
+            "swift" => Language::Swift,
+            "php" => Language::Php,
+            "rb" => Language::Ruby,
+            "pl" => Language::Perl,
+            "sh" | "bash" => Language::Bash,
+            "lua" => Language::Lua,
+            "hs" => Language::Haskell,
+            _ => return None,
+        })
+    }
+}
+
+/// Prefix of single-line comments in the language
+pub fn language_comment(language: Language) -> &'static str {
+    match language {
+        Language::Python | Language::Ruby | Language::Perl | Language::Bash => "#",
+        Language::Lua | Language::Haskell => "--",
+        _ => "//",
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rust" | "rs" => Ok(Language::Rust),
+            "python" | "py" => Ok(Language::Python),
+            "javascript" | "js" => Ok(Language::JavaScript),
+            "typescript" | "ts" => Ok(Language::TypeScript),
+            "c" => Ok(Language::C),
+            "c++" | "cpp" => Ok(Language::Cpp),
+            "c#" | "csharp" | "cs" => Ok(Language::CSharp),
+            "java" => Ok(Language::Java),
+            "kotlin" | "kt" => Ok(Language::Kotlin),
+            "scala" => Ok(Language::Scala),
+            "go" => Ok(Language::Go),
+            "swift" => Ok(Language::Swift),
+            "php" => Ok(Language::Php),
+            "ruby" | "rb" => Ok(Language::Ruby),
+            "perl" | "pl" => Ok(Language::Perl),
+            "bash" | "sh" => Ok(Language::Bash),
+            "lua" => Ok(Language::Lua),
+            "haskell" | "hs" => Ok(Language::Haskell),
+            other => Err(format!("unknown language '{}'", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Language::Rust => "rust",
+            Language::Python => "python",
+            Language::JavaScript => "javascript",
+            Language::TypeScript => "typescript",
+            Language::C => "c",
+            Language::Cpp => "c++",
+            Language::CSharp => "c#",
+            Language::Java => "java",
+            Language::Kotlin => "kotlin",
+            Language::Scala => "scala",
+            Language::Go => "go",
+            Language::Swift => "swift",
+            Language::Php => "php",
+            Language::Ruby => "ruby",
+            Language::Perl => "perl",
+            Language::Bash => "bash",
+            Language::Lua => "lua",
+            Language::Haskell => "haskell",
+        })
+    }
+}
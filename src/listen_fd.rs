@@ -0,0 +1,65 @@
+//! Listening socket inherited from the parent process, for systemd socket activation with
+//! `--bind-fd`.
+
+use std::os::unix::io::{FromRawFd, RawFd};
+
+use crate::runtime::net::TcpListener;
+use crate::{ErrorKind, Result};
+
+/// Check the file descriptor is an open, listening stream socket
+pub fn validate(fd: RawFd) -> Result<()> {
+    let invalid =
+        |reason: String| -> Result<()> { Err(ErrorKind::InvalidBindFd(fd, reason).into()) };
+
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } != 0 {
+        return invalid(std::io::Error::last_os_error().to_string());
+    }
+
+    let stat = unsafe { stat.assume_init() };
+    if stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return invalid("not a socket".to_owned());
+    }
+
+    if socket_option(fd, libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return invalid("not a stream socket".to_owned());
+    }
+
+    if socket_option(fd, libc::SO_ACCEPTCONN)? == 0 {
+        return invalid("socket is not listening".to_owned());
+    }
+
+    Ok(())
+}
+
+/// Take ownership of the listening socket behind the file descriptor
+pub fn listener(fd: RawFd) -> Result<TcpListener> {
+    validate(fd)?;
+
+    // The descriptor was checked to be a listening stream socket, and nothing else owns it
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    Ok(TcpListener::from(listener))
+}
+
+fn socket_option(fd: RawFd, option: libc::c_int) -> Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if res != 0 {
+        return Err(
+            ErrorKind::InvalidBindFd(fd, std::io::Error::last_os_error().to_string()).into(),
+        );
+    }
+
+    Ok(value)
+}
@@ -0,0 +1,199 @@
+//! Listener of the extension connections, on a TCP socket or, with `--bind-unix`, a Unix domain
+//! socket.
+//!
+//! The extension only connects over TCP: a Unix domain socket sits behind a proxy forwarding the
+//! connections, like websocat or a reverse proxy. The socket file is only accessible to its
+//! owner, unlike a TCP port on localhost which every user of the machine can reach. It is
+//! removed when the listener is dropped.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(unix)]
+use async_std::path::Path;
+use async_std::path::PathBuf;
+use futures_util::io::{AsyncRead, AsyncWrite};
+
+use crate::runtime::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use crate::runtime::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use crate::{ErrorKind, Result};
+
+/// Client of the listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    /// Client of the Unix domain socket, numbered in accept order as it has no address
+    #[cfg(unix)]
+    Unix(u64),
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Peer::Unix(n) => write!(f, "local socket client {}", n),
+        }
+    }
+}
+
+/// Connection accepted by the listener
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
+
+/// Listening socket for the extension
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+        accepted: u64,
+    },
+}
+
+impl Listener {
+    /// Listen on the Unix domain socket, only accessible to the current user
+    #[cfg(unix)]
+    pub async fn bind_unix(path: &Path) -> Result<Self> {
+        use std::os::unix::fs::PermissionsExt;
+
+        validate_unix(path)?;
+        let listener = UnixListener::bind(path).await?;
+        async_std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+
+        Ok(Listener::Unix {
+            listener,
+            path: path.to_owned(),
+            accepted: 0,
+        })
+    }
+
+    pub async fn accept(&mut self) -> io::Result<(Stream, Peer)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let peer = stream.peer_addr()?;
+                Ok((Stream::Tcp(stream), Peer::Tcp(peer)))
+            }
+            #[cfg(unix)]
+            Listener::Unix {
+                listener, accepted, ..
+            } => {
+                let (stream, _) = listener.accept().await?;
+                *accepted += 1;
+                Ok((Stream::Unix(stream), Peer::Unix(*accepted)))
+            }
+        }
+    }
+
+    /// Address of the TCP listener, `None` for a Unix domain socket
+    pub fn local_addr(&self) -> io::Result<Option<SocketAddr>> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Some),
+            #[cfg(unix)]
+            Listener::Unix { .. } => Ok(None),
+        }
+    }
+
+    /// Path of the Unix domain socket, `None` for a TCP listener
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        match self {
+            Listener::Tcp(_) => None,
+            #[cfg(unix)]
+            Listener::Unix { path, .. } => Some(path.clone()),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix { path, .. } = self {
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!("failed to remove socket {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+/// Check a Unix domain socket can be created at the path, removing a socket left behind by a
+/// process that is no longer listening on it
+#[cfg(unix)]
+pub fn validate_unix(path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let path: &std::path::Path = path.as_ref();
+    let invalid = |reason: String| -> Result<()> {
+        Err(ErrorKind::InvalidBindUnix(path.display().to_string(), reason).into())
+    };
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return invalid(err.to_string()),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return invalid("the file exists and is not a socket".to_owned());
+    }
+
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return invalid("another process is listening on it".to_owned());
+    }
+
+    info!("removing stale socket {}", path.display());
+    std::fs::remove_file(path).or_else(|err| invalid(err.to_string()))
+}
@@ -31,18 +31,266 @@
 //! Rust application for CG Local
 //!
 //! USAGE:
-//!     cg-local-app [FLAGS] [OPTIONS] --target <target>
+//!     cg-local-app [FLAGS] [OPTIONS] [SUBCOMMAND]
 //!
 //! FLAGS:
-//!     -d, --download    Download the file from the IDE before synchronizing
-//!     -h, --help        Prints help information
-//!         --no-gui      Disable text user interface
-//!     -p, --play        Auto-play questions on upload
-//!     -V, --version     Prints version information
+//!         --allow-shared-target            Run even if another instance is already synchronizing the same target
+//!         --allow-unknown-question         Start synchronizing without asking when the extension doesn't identify the
+//!                                          question within --details-timeout
+//!         --auto-detect-language           Infer the CodinGame language from the extension of the target (.rs is Rust, .py
+//!                                          is Python3...) and show it in the title of the interface
+//!     -d, --download                       Download the file from the IDE before synchronizing
+//!         --follow-renames                 Switch to the new path when the target is renamed. Implies --watch-on-rename
+//!         --force-download                 Write downloaded code even when the target was saved after the download was
+//!                                          requested. Without the interface such downloads are refused otherwise, the
+//!                                          interface asks
+//!         --force-tui                      Use the text user interface even where it doesn't look supported, instead of
+//!                                          falling back to --no-gui
+//!     -h, --help                           Prints help information
+//!         --input-from-clipboard           Upload the clipboard contents when they change, instead of watching the target.
+//!                                          With --target, the clipboard contents are also written to it
+//!         --lock-ide                       Make the IDE editor read-only while synchronizing, so accidental edits in the
+//!                                          browser can't diverge from the target
+//!         --log-include-code               Log the full code carried by messages at debug and trace level, instead of only
+//!                                          its length and fingerprint, and write it to the --trace-protocol file
+//!         --mock                           Connect a fake extension to the listener, which identifies --mock-question-id,
+//!                                          keeps the uploaded code and sends it back when the IDE code is requested, to
+//!                                          try the synchronization without a browser
+//!         --no-gui                         Disable text user interface
+//!         --no-origin-check                Accept connections whatever their Origin and User-Agent headers, e.g. from
+//!                                          other clients than the browser extension. Otherwise only the CodinGame
+//!                                          extension is accepted
+//!         --no-semaphore                   Accept any number of extension connections, as with an unlimited --max-
+//!                                          connections
+//!         --no-server                      Don't listen for the extension, only write the preprocessed code to the --emit
+//!                                          file. Runs without the text user interface
+//!         --no-stats                       Don't record per-question statistics
+//!     -p, --play                           Auto-play questions on upload
+//!         --print-extension-settings       Print the settings to enter in the browser extension once the listener is
+//!                                          bound. The text user interface always shows them on the waiting screen
+//!         --recreate-target-on-delete      Re-create the target file with the last uploaded contents when it is deleted.
+//!                                          Implies --watch-delete
+//!         --restore-main-on-download       Uncomment the entry point commented out by --upload-comment-out-main in code
+//!                                          downloaded from the IDE
+//!         --show-diff-in-tui               Show a diff of the target against the last uploaded code in the text user
+//!                                          interface
+//!         --strip-metadata-on-download     Remove the metadata comment from code downloaded from the IDE
+//!         --strip-test-input               Remove the test input block from code downloaded from the IDE
+//!         --takeover                       Close the oldest extension connection instead of denying a new one, e.g. when a
+//!                                          refreshed browser tab connects before the old socket is gone. Without it, the
+//!                                          text user interface asks
+//!         --target-file-lock               Hold an advisory lock on the target while reading it for an upload (shared) and
+//!                                          while writing a download to it (exclusive), so editors honoring the lock don't
+//!                                          write it at the same time. The operation is skipped if the lock can't be taken
+//!                                          within 2 seconds
+//!         --tui-confirm-upload             Ask for confirmation in the text user interface before every upload, showing
+//!                                          what changed
+//!         --tui-show-code-preview          Show the first lines of the target file in the text user interface while
+//!                                          synchronizing
+//!         --two-way                        Also pull the edits made in the IDE into the target while synchronizing. The
+//!                                          IDE code is requested every --two-way-interval seconds
+//!         --ui-status-bar                  Show a status bar at the bottom of the text user interface with the connection
+//!                                          state, current question and transfer counters
+//!         --upload-before-quit             When quitting the text user interface, offer to upload the target if it changed
+//!                                          since the last upload
+//!         --upload-checksum                Send a checksum of the code with each upload, for extensions able to verify it
+//!         --upload-comment-out-main        Comment out the entry point before uploading, for puzzles expecting a module
+//!                                          without one: fn main in Rust, the if __name__ == "__main__": block in Python
+//!         --upload-ensure-final-newline    End the uploaded code with exactly one newline
+//!         --upload-inject-metadata         Prepend a comment with the question, upload time and app version to the
+//!                                          uploaded code
+//!         --upload-on-ide-request          Only upload when the extension asks for the code with a request-code message,
+//!                                          instead of on every change of the target
+//!         --upload-strip-final-newline     Remove all trailing newlines from the uploaded code
+//!         --upload-validate-json           Refuse to upload .json targets which are not valid JSON
+//!     -V, --version                        Prints version information
+//!         --watch-all-in-dir               Upload when any file of the target directory matching --watch-pattern changes,
+//!                                          sending all matching files concatenated in name order
+//!         --watch-delete                   Report the deletion of the target file, and stop uploading until it reappears
+//!         --watch-on-rename                Handle saves done by renaming a new file over the target, and report renames of
+//!                                          the target
+//!         --watchdog-abort                 Exit with code 70 when the watchdog detects a stall, so a supervisor can
+//!                                          restart the app
 //!
 //! OPTIONS:
-//!     -b, --bind <bind>        Address to bind to for the extension. Shouldn't need to be changed [default: 127.0.0.1:53135]
-//!     -t, --target <target>    Path to the target file to synchronize with the IDE
+//!         --allow-ip <allow-ip>...
+//!             Accept connections only from this address or network, e.g. 192.168.1.10 or 192.168.1.0/24, when binding
+//!             beyond localhost. Can be repeated
+//!         --auth-token <auth-token>
+//!             Accept only the connections giving this token, as the `token` query parameter of their request or as their
+//!             first message. For a listener exposed beyond localhost
+//!     -b, --bind <bind>
+//!             Address to bind to for the extension. Shouldn't need to be changed [default: 127.0.0.1:53135]
+//!
+//!         --bind-fd <bind-fd>
+//!             Listen on this already bound socket file descriptor instead of --bind, for systemd socket activation
+//!
+//!         --bind-unix <bind-unix>
+//!             Listen on this Unix domain socket instead of --bind, behind a proxy forwarding the extension connections.
+//!             Only the current user can connect to it, unlike a TCP port on localhost
+//!         --clipboard-poll-ms <clipboard-poll-ms>
+//!             Interval in milliseconds at which the clipboard is polled with --input-from-clipboard [default: 500]
+//!
+//!         --code-max-lines <code-max-lines>
+//!             Refuse to upload code with more than this number of lines. 0 means unlimited [default: 0]
+//!
+//!         --config <config>
+//!             Configuration file. Defaults to cg-local.toml in the current directory, then config.toml in the cg-local-app
+//!             directory of the platform configuration directory
+//!         --conflict-policy <conflict-policy>
+//!             Side kept with --two-way when both the target and the IDE code changed: prefer-local uploads the target,
+//!             prefer-ide writes the IDE code to it, ask lets the interface choose and keeps the local changes without it
+//!             [default: ask]
+//!         --copyright-year-override <copyright-year-override>
+//!             Year substituted in the --upload-add-copyright template instead of the current year, for reproducible
+//!             uploads
+//!         --details-timeout <details-timeout>
+//!             Seconds to wait for the extension to identify the question once connected, before offering to synchronize
+//!             anyway. 0 waits forever [default: 10]
+//!         --emit <emit>
+//!             Write the preprocessed code to this file on every change, in addition to uploading it. {slug} in the path is
+//!             replaced with a file name derived from the question title
+//!         --expect-question <expect-question>...
+//!             Only synchronize this question, refusing to start with a warning when the extension identifies another one,
+//!             e.g. to never upload an arena bot to a clash. Can be repeated
+//!         --extension-id <extension-id>...
+//!             Accept only the browser extension of this ID, the host of its chrome-extension:// or moz-extension:// origin
+//!             as logged when it connects. Can be repeated. Without it, any browser extension is accepted
+//!         --flap-stabilization <flap-stabilization>
+//!             Seconds a connection must stay up for a flapping connection to be considered stable again [default: 30]
+//!
+//!         --flap-threshold <flap-threshold>
+//!             Consider the extension connection flapping when it closes more than this number of times within --flap-
+//!             window seconds. Prompts and automatic starts are then suspended until a connection stays up for
+//!             --flap-stabilization seconds. 0 disables the detection [default: 5]
+//!         --flap-window <flap-window>
+//!             Seconds over which connection cycles are counted for --flap-threshold [default: 60]
+//!
+//!         --ide-poll-interval <ide-poll-interval>
+//!             Request the IDE code every this number of seconds while synchronizing, warning when it changed since it was
+//!             last synchronized, e.g. after edits made in the browser which the next save would overwrite. --two-way pulls
+//!             these edits instead
+//!         --ignore <ignore>...
+//!             Gitignore-style pattern for files to ignore in the watched directory, in addition to the contents of .cg-
+//!             localignore. Can be repeated
+//!         --keepalive-interval <keepalive-interval>
+//!             Ping the extension after this number of seconds without messages, closing the connection when it doesn't
+//!             answer, so half-open connections left by a sleeping laptop or a crashed browser free their slot. 0 disables
+//!             the pings [default: 30]
+//!         --map <map>...
+//!             Synchronize this question with its own target, as QUESTION_ID=FILE, e.g. 1234=bot.rs. The target switches to
+//!             the file when a tab identifies the question, before synchronization starts. Can be repeated
+//!         --max-connections <max-connections>
+//!             Accept up to this number of extension connections at once, e.g. one per browser tab, denying the others. The
+//!             messages for the extension go to the connection which identified the question last [default: 1]
+//!         --max-file-size <max-file-size>
+//!             Don't read nor upload the target when it is larger than this number of bytes. 0 means unlimited [default:
+//!             4194304]
+//!         --metadata-author <metadata-author>
+//!             Author name added to the metadata comment of --upload-inject-metadata
+//!
+//!         --mock-question-id <mock-question-id>
+//!             Question id identified by the --mock extension [default: 1]
+//!
+//!         --notes-pattern <notes-pattern>
+//!             Name of the notes file shown next to the target, {stem} being the target name without extension and {name}
+//!             the full target name [default: {stem}.notes.md]
+//!         --observers <observers>
+//!             Accept up to this number of read-only observers alongside the extension, connecting to --bind with an
+//!             observer query parameter (ws://host:port/?observer). They receive the question details and every upload, and
+//!             anything they send is ignored [default: 0]
+//!         --port-fallback <port-fallback>
+//!             Number of ports after the one of --bind to try in turn when it is already in use, like by another instance
+//!             of the app. The port chosen is the one to set in the extension options. 0 only tries the port of --bind
+//!             [default: 10]
+//!         --question-id-override <question-id-override>
+//!             Start on this question as if the extension had identified it, for workflows without the extension. Details
+//!             sent by the extension later take over
+//!         --question-title-override <question-title-override>              Title of the question of --question-id-override
+//!         --rebroadcast-port <rebroadcast-port>
+//!             Send a copy of the question details and every upload to the monitoring clients connected to this port, on
+//!             the same host as --bind. Monitoring clients only receive messages
+//!         --record <record>
+//!             Record every message exchanged with the extension to this file, as lines of JSON with their time, to
+//!             reproduce the session with the replay subcommand. The file is overwritten. It holds the code in full,
+//!             whatever --log-include-code says
+//!         --retain-max-bytes <retain-max-bytes>
+//!             Maximum total size in bytes of the code payloads kept in memory during the session. The most recent payload
+//!             is always kept [default: 4194304]
+//!         --retain-max-payloads <retain-max-payloads>
+//!             Maximum number of code payloads kept in memory during the session [default: 10]
+//!
+//!         --session <session>
+//!             Start in this session of the configuration file, taking its target and auto-play setting. Other sessions are
+//!             switched to from the interface
+//!         --session-timeout <session-timeout>
+//!             Close the connection when the extension sends no message for this number of seconds, to detect frozen
+//!             browsers. 0 disables the timeout [default: 0]
+//!         --shutdown-timeout <shutdown-timeout>
+//!             Seconds given to the worker to stop when exiting, after which the app exits anyway [default: 5]
+//!
+//!         --slug-max-length <slug-max-length>
+//!             Maximum length of the file names derived from question titles [default: 64]
+//!
+//!         --status-file <status-file>
+//!             Keep the synchronization state in this file as a line of JSON, for shell prompts and status bars. The file
+//!             is removed on exit
+//!     -t, --target <target>
+//!             Path to the target file to synchronize with the IDE
+//!
+//!         --trace-protocol <trace-protocol>
+//!             Write every WebSocket text frame exchanged with the extension to this file, with its time and direction,
+//!             whatever the log level. The file is overwritten. The code is redacted unless --log-include-code is given
+//!         --tui-preview-lines <tui-preview-lines>
+//!             Number of lines shown by --tui-show-code-preview [default: 10]
+//!
+//!         --tui-refresh-rate <tui-refresh-rate>
+//!             Interval in milliseconds at which the text user interface polls for updates [default: 50]
+//!
+//!         --two-way-interval <two-way-interval>
+//!             Seconds between requests of the IDE code with --two-way [default: 2]
+//!
+//!         --upload-add-copyright <upload-add-copyright>
+//!             Prepend the contents of this template file to the uploaded code, unless the code already starts with it.
+//!             {YEAR} in the template is replaced with the current year
+//!         --upload-dedup-window <upload-dedup-window>
+//!             Skip uploads of code already uploaded within this number of seconds, e.g. after undoing an edit. 0 disables
+//!             the check [default: 0]
+//!         --upload-force-codepoint-limit <upload-force-codepoint-limit>
+//!             Truncate the uploaded code to this number of characters (Unicode codepoints), the limit of the IDE, marking
+//!             the cut with a "// TRUNCATED" line
+//!         --upload-inject-test-input <upload-inject-test-input>
+//!             Append the contents of this file to the uploaded code as a commented-out test input block, which the judge
+//!             ignores
+//!         --upload-progress-command <upload-progress-command>
+//!             Command run through the shell when an upload starts and once it is sent or stalls, for status bar
+//!             indicators. It gets CGLOCAL_STATUS (uploading or idle), CGLOCAL_BYTES and the question in
+//!             CGLOCAL_QUESTION_ID and CGLOCAL_QUESTION_TITLE
+//!         --upload-remove-dead-code <upload-remove-dead-code>
+//!             Remove dead code before uploading, using a language-specific tool (rust, python or js). The local file is
+//!             left untouched
+//!         --upload-replace <upload-replace>...
+//!             Text substitution applied before uploading, as FIND:REPLACE. Can be repeated, rules are applied in order
+//!
+//!         --upload-wrapper <before-path> <after-path>
+//!             Surround the uploaded code with the contents of these two files, for puzzles expecting the code in a class
+//!             or a function. The wrapper is removed from downloaded code
+//!         --watch-pattern <watch-pattern>
+//!             Glob pattern of the file names uploaded with --watch-all-in-dir. Defaults to the files with the extension of
+//!             the target
+//!         --watchdog-timeout <watchdog-timeout>
+//!             Seconds without controller progress while messages are pending before the watchdog reports a stall. 0
+//!             disables the watchdog [default: 10]
+//!
+//! SUBCOMMANDS:
+//!     config                Inspect the configuration
+//!     help                  Prints this message or the help of the given subcommand(s)
+//!     multi                 Run every [[instance]] of the configuration file, each with its own address and target
+//!     network-interfaces    Print the network interfaces and their addresses, to choose the address for --bind
+//!     replay                Send the messages of a session recorded with --record to the instance listening on --bind,
+//!                           which must run with --no-origin-check, and print its answers
+//!     stats                 Print lifetime statistics per question, most active first
+//!     transform             Run the upload preprocessing on the target once and print the code that would be uploaded
 //! ```
 //!
 //! ## Examples
@@ -50,682 +298,5527 @@
 //! ```bash
 //! # Synchronize main.rs with the IDE, enable auto-play by default
 //! cg-local-app -p -t main.rs
+//!
+//! # Print the code that would be uploaded with these options, without connecting
+//! cg-local-app -t main.rs --upload-replace 'DEBUG:false' transform
+//!
+//! # Also pull the edits made in the IDE, keeping the IDE code when both sides changed
+//! cg-local-app -t main.rs --two-way --conflict-policy prefer-ide
+//! ```
+//!
+//! ## Configuration
+//!
+//! Settings that don't fit on the command line are read from `cg-local.toml` in the current
+//! directory, or from the file given with `--config`. The `[hooks]` table runs commands on
+//! events: `on_connect`, `on_disconnect`, `on_details`, `on_upload`, `on_play`, `on_download`
+//! and `on_error`. Hooks get details about the event in `CGLOCAL_*` environment variables.
+//!
+//! ```toml
+//! [hooks]
+//! on_disconnect = "paplay /usr/share/sounds/freedesktop/stereo/bell.oga"
+//!
+//! # Never auto-play on arena questions
+//! [[play_policy]]
+//! title = "*Arena*"
+//! policy = "deny"
+//!
+//! # Puzzles of a contest, switched with the s key
+//! [session.fall-challenge]
+//! target = "contest/fall.rs"
+//! question_id = 9999
+//! play = true
+//!
+//! # Upload transforms run for Rust targets, in this order
+//! [transforms.language]
+//! rust = ["comment-out-main", "final-newline", "codepoint-limit"]
+//!
+//! [transforms.options.codepoint-limit]
+//! limit = 100000
+//!
+//! # Started together with `cg-local-app multi`
+//! [[instance]]
+//! name = "practice"
+//! bind = "127.0.0.1:53136"
+//! target = "practice/main.py"
+//! args = ["--play"]
+//! ```
+//!
+//! `[[play_policy]]` rules set `policy` to `allow`, `warn` or `deny` for questions matched by
+//! `question_id`, or by `title` where `*` matches any text.
+//!
+//! `[session.<name>]` tables name a `target`, optionally with the `question_id` it is for and the
+//! `play` setting. `--session <name>` starts in a session, and the Sessions menu of the interface
+//! (`s`) switches to another one: synchronization stops, the target of the session is watched and
+//! its question is waited for, unless the extension is already on it. Hooks get the active session
+//! in `CGLOCAL_SESSION`.
+//!
+//! When the extension moves to another question in the same tab, synchronization stops. The
+//! session of the new question is switched to if there is one; otherwise the target isn't
+//! synchronized with the new question unless chosen on the question screen, or, without the
+//! interface, until the extension is back on the question of the target.
+//!
+//! `--map QUESTION_ID=FILE` gives a question its own target without a session, e.g.
+//! `--map 1234=bot.rs --map 5678=puzzle.py`. When a tab identifies a mapped question, the target
+//! switches to its file before synchronization starts. One target is synchronized at a time, the
+//! one of the question identified last, along with the tab which identified it.
+//!
+//! The `[transforms]` table chains the upload transforms per target (`[transforms.target]`),
+//! question id (`[transforms.question]`) or language (`[transforms.language]`), in that order of
+//! precedence. The stages are `replace`, `dead-code`, `comment-out-main`, `copyright`, `wrapper`,
+//! `metadata`, `test-input`, `final-newline` and `codepoint-limit`, run in the order listed.
+//! Transforms enabled by `--upload-*` flags but missing from the chain run at their default place,
+//! and flags override the `[transforms.options.<stage>]` settings: `rules` for `replace`,
+//! `language` for `dead-code`, `author` for `metadata`, `mode` for `final-newline` and `limit` for
+//! `codepoint-limit`.
+//!
+//! `[[instance]]` tables describe independent instances started together by `cg-local-app multi`,
+//! each with a `name`, the `bind` address, the `target` and its other options in `args`. Every
+//! instance runs as its own process without the interface, sharing the configuration file, and its
+//! output is printed prefixed with its name. An instance that fails doesn't stop the others, and
+//! Ctrl-C stops them all.
+//!
+//! `cg-local-app config check` validates the configuration file and the options without
+//! synchronizing, and fails if any error is found. With `--probe`, it also checks `--bind` can be
+//! listened on.
+//!
+//! ## Socket activation
+//!
+//! With systemd socket activation, the listening socket is passed with `--bind-fd` instead of
+//! being bound with `--bind`. systemd passes the first socket as file descriptor 3:
+//!
+//! ```ini
+//! # ~/.config/systemd/user/cg-local.socket
+//! [Socket]
+//! ListenStream=127.0.0.1:53135
+//!
+//! [Install]
+//! WantedBy=sockets.target
+//!
+//! # ~/.config/systemd/user/cg-local.service
+//! [Service]
+//! ExecStart=/usr/bin/cg-local-app --no-gui --bind-fd 3 -t %h/cg/main.rs
+//! ```
+//!
+//! ## Unix domain socket
+//!
+//! With `--bind-unix`, the app listens on a Unix domain socket only its owner can connect to,
+//! instead of a TCP port any user of the machine can reach. The extension only connects over TCP,
+//! so a proxy forwards its connections to the socket, like an SSH tunnel from the machine running
+//! the browser to the one running the app:
+//!
+//! ```sh
+//! # on the remote machine
+//! cg-local-app --no-gui --bind-unix /run/user/1000/cg-local.sock -t main.rs
+//! # on the machine running the browser
+//! ssh -N -L 127.0.0.1:53135:/run/user/1000/cg-local.sock remote
+//! ```
+//!
+//! A socket left behind by a previous run is removed, and the socket is removed on exit.
+//!
+//! ## Two-way synchronization
+//!
+//! With `--two-way`, edits made in the CodinGame IDE are also written to the target. While
+//! synchronizing, the app requests the IDE code every `--two-way-interval` seconds, and writes it
+//! to the target when it changed since the two sides were last synchronized, as a download would.
+//! Local saves are still uploaded right away. If the target was also changed without being
+//! uploaded, e.g. refused by `--code-max-lines`, `--conflict-policy` decides: `prefer-local`
+//! uploads the target, `prefer-ide` overwrites it, and `ask` lets the interface choose or keeps
+//! the local changes without it.
+//!
+//! To only be warned about edits made in the IDE, `--ide-poll-interval <seconds>` requests the
+//! IDE code as often and reports when it changed since it was last synchronized, once per change,
+//! leaving the target as it is.
+//!
+//! ## Without a browser
+//!
+//! With `--record <file>`, every message exchanged with the extension is written to the file as a
+//! line of JSON, with its time and connection. The `replay` subcommand sends the messages the
+//! extension sent back to a running instance, at their recorded pace or right away with `--fast`,
+//! and prints the answers, to reproduce a protocol problem without a browser:
+//!
+//! ```sh
+//! cg-local-app --no-gui --record session.jsonl -t main.rs
+//! # later, against an instance accepting other clients than the extension
+//! cg-local-app --no-gui --no-origin-check -t main.rs
+//! cg-local-app replay session.jsonl
 //! ```
 //!
-//! ## Status
+//! The recording holds the code exchanged, but not the `--auth-token`.
+//!
+//! To report a problem with the extension, `--trace-protocol <file>` writes every WebSocket frame
+//! as it was exchanged, with its time and direction, including those which aren't valid messages,
+//! whatever the log level.
 //!
-//! Missing features:
-//! * Two-way synchronization
+//! With `--mock`, the app connects a fake extension to itself instead of waiting for the browser.
+//! It identifies the question given by `--mock-question-id`, keeps the uploaded code and sends it
+//! back when the IDE code is requested, and logs what it receives, to try options like `--two-way`
+//! or `--map` from the command line:
+//!
+//! ```sh
+//! cg-local-app --no-gui --mock -t main.rs
+//! ```
 
 #![recursion_limit = "512"]
+#![allow(clippy::result_large_err)]
 
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use error_chain::error_chain;
+use error_chain::{bail, error_chain};
 
 use structopt::StructOpt;
 
-use hotwatch::{Event, Hotwatch};
-
 use futures_util::future::FutureExt;
 use futures_util::select;
 use futures_util::sink::SinkExt;
 
 use async_std::{
-    net::{TcpListener, TcpStream, ToSocketAddrs},
-    path::PathBuf,
+    path::{Path, PathBuf},
     prelude::*,
     sync::{Arc, Mutex},
-    task,
 };
 
 use async_tungstenite::tungstenite;
 
-#[derive(Debug, StructOpt)]
-#[structopt(author, about)]
-pub struct Opts {
-    /// Address to bind to for the extension. Shouldn't need to be changed.
-    #[structopt(short, long, default_value = "127.0.0.1:53135")]
-    bind: String,
+mod admission;
+use admission::{IpNetwork, WsStream};
 
-    /// Path to the target file to synchronize with the IDE.
-    #[structopt(short, long)]
-    target: PathBuf,
+mod clipboard;
 
-    /// Download the file from the IDE before synchronizing.
-    #[structopt(short, long)]
-    download: bool,
+mod channel_stats;
 
-    /// Auto-play questions on upload.
-    #[structopt(short, long)]
-    play: bool,
+mod clock_monitor;
 
-    /// Disable text user interface
-    #[structopt(long)]
-    no_gui: bool,
-}
+mod bind;
 
-error_chain! {
-    foreign_links {
-        Io(std::io::Error);
-        Hotwatch(hotwatch::Error);
-        WebSocket(tungstenite::Error);
-        WorkerNotificationChannel(std::sync::mpsc::SendError<WorkerNotification>);
-        ConnectedNotificationChannel(async_std::channel::SendError<ConnectedNotification>);
-        WorkerMessageChannel(async_std::channel::SendError<WorkerMessage>);
-        ConnectedMessageChannel(async_std::channel::SendError<ConnectedMessage>);
-        ListenMessageChannel(async_std::channel::SendError<ListenMessage>);
-    }
-}
+mod bundle;
+use bundle::Bundle;
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "action", content = "payload", rename_all = "kebab-case")]
-pub enum ServerMessage {
-    SendDetails,
-    #[serde(rename_all = "camelCase")]
-    Details {
-        title: String,
-        question_id: i32,
-    },
-    AppReady,
-    AlreadyConnected,
-    UpdateCode {
-        code: String,
-        play: bool,
-    },
-    SendCode,
-    Code {
-        code: String,
-    },
-    SetReadOnly {
-        state: bool,
-    },
-    Error {
-        message: String,
-    },
-}
+mod checksum;
 
-impl Into<tungstenite::Message> for ServerMessage {
-    fn into(self) -> tungstenite::Message {
-        tungstenite::Message::Text(serde_json::to_string(&self).unwrap())
-    }
-}
+mod commands;
+use commands::Command;
 
-pub struct State {
-    opts: Arc<Mutex<Opts>>,
-}
+mod config;
+use config::Config;
 
-impl State {
-    pub fn new(opts: Arc<Mutex<Opts>>) -> Self {
-        Self { opts }
-    }
-}
+mod connections;
+use connections::{Connection, ConnectionId, Routed, Router};
 
-async fn handle_accept(
-    peer: SocketAddr,
-    stream: TcpStream,
-    rx_connected: Arc<Mutex<async_std::channel::Receiver<ConnectedMessage>>>,
-    tx_conn_notification: async_std::channel::Sender<ConnectedNotification>,
-) -> Result<()> {
-    let mut ws_stream = async_tungstenite::accept_async(stream).await?;
+mod dead_code;
 
-    info!("accepting connection from {}", peer);
+mod file_browser;
 
-    ws_stream.send(ServerMessage::SendDetails.into()).await?;
+mod file_lock;
+use file_lock::{Access, FileLock};
 
-    loop {
-        let mut rx_ws_lock = rx_connected.lock().await;
+mod flap;
+use flap::FlapDetector;
 
-        select! {
-            msg = ws_stream.next().fuse() => {
-                if let Some(msg) = msg {
-                    let msg = msg?;
-                    debug!("msg: {:?}", msg);
+mod hooks;
+use hooks::{HookEnv, HookEvent};
 
-                    if let tungstenite::Message::Text(msg) = msg {
-                        let parsed: std::result::Result<ServerMessage, _> = serde_json::from_str(&msg);
+mod dedup;
+use dedup::DedupWindow;
 
-                        match parsed {
-                            Ok(msg) => match msg {
-                                ServerMessage::Details { title, question_id } => {
-                                    tx_conn_notification.send(ConnectedNotification::Details { title, question_id }).await?
-                                }
-                                ServerMessage::Code { code } => {
-                                    tx_conn_notification.send(ConnectedNotification::Code { code }).await?
-                                }
-                                other => {
-                                    warn!("unexpected message: {:?}", other);
-                                    ws_stream.send(ServerMessage::Error { message: format!("unexpected message") }.into()).await?
-                                }
-                            },
-                            Err(err) => {
-                                error!("failed to parse message: {}", err);
-                                ws_stream.send(ServerMessage::Error { message: err.to_string() }.into()).await?
-                            }
-                        }
-                    }
-                } else {
-                    break;
-                }
-            }
+mod diff;
+use diff::{Diff, DiffOptions};
 
-            msg = rx_ws_lock.next().fuse() => {
-                drop(rx_ws_lock);
+mod language;
+use language::Language;
 
-                if let Some(msg) = msg {
-                    match msg {
-                        ConnectedMessage::AppReady => {
-                            ws_stream.send(ServerMessage::AppReady.into()).await?;
-                        }
-                        ConnectedMessage::UpdateCode { code, play } => {
-                            ws_stream.send(ServerMessage::UpdateCode { code, play }.into()).await?;
-                        }
-                        ConnectedMessage::SendCode => {
-                            ws_stream.send(ServerMessage::SendCode.into()).await?;
-                        }
-                        ConnectedMessage::Terminate => { break; }
-                    }
-                } else {
-                    break;
-                }
-            }
-        }
-    }
+mod listener;
+use listener::{Listener, Peer, Stream};
 
-    Ok(())
-}
+mod messages;
 
-async fn accept_connection(
-    peer: SocketAddr,
-    stream: TcpStream,
-    rx_connected: Arc<Mutex<async_std::channel::Receiver<ConnectedMessage>>>,
-    tx_conn_notification: async_std::channel::Sender<ConnectedNotification>,
-) -> Result<()> {
-    if let Err(e) = handle_accept(peer, stream, rx_connected, tx_conn_notification).await {
-        match e {
-            Error(ErrorKind::WebSocket(tungstenite::Error::ConnectionClosed), _)
-            | Error(ErrorKind::WebSocket(tungstenite::Error::Protocol(_)), _)
-            | Error(ErrorKind::WebSocket(tungstenite::Error::Utf8), _) => (),
-            err => error!("error processing connection: {:?}", err),
-        }
-    }
+mod mock;
 
-    Ok(())
-}
+#[cfg(unix)]
+mod listen_fd;
 
-async fn handle_deny(peer: SocketAddr, stream: TcpStream) -> Result<()> {
-    let mut ws_stream = async_tungstenite::accept_async(stream).await?;
+mod main_file;
 
-    info!("denying connection from {}", peer);
-    ws_stream
-        .send(ServerMessage::AlreadyConnected.into())
-        .await?;
+mod metadata;
 
-    ws_stream.close(None).await?;
+mod metrics;
 
-    Ok(())
-}
+mod notes;
 
-async fn deny_connection(peer: SocketAddr, stream: TcpStream) -> Result<()> {
-    if let Err(e) = handle_deny(peer, stream).await {
-        match e {
-            Error(ErrorKind::WebSocket(tungstenite::Error::ConnectionClosed), _)
-            | Error(ErrorKind::WebSocket(tungstenite::Error::Protocol(_)), _)
-            | Error(ErrorKind::WebSocket(tungstenite::Error::Utf8), _) => (),
-            err => error!("error processing connection: {:?}", err),
-        }
-    }
+mod own_writes;
 
-    Ok(())
-}
+mod payload;
+use payload::Code;
 
-#[derive(Debug)]
-pub enum ConnectedMessage {
-    AppReady,
-    UpdateCode { code: String, play: bool },
-    SendCode,
-    Terminate,
-}
+mod pipeline;
+use pipeline::Pipeline;
 
-#[derive(Debug)]
-pub enum WorkerMessage {
-    FileChanged { code: String },
-    WatchError { error: std::io::Error },
-    Start { download: bool },
-    Stop,
-    Terminate,
-}
+mod play_policy;
+use play_policy::{PlayGate, PlayPolicy, PlayReason};
 
-#[derive(Debug)]
-pub enum WorkerNotification {
-    Details { title: String, question_id: i32 },
-    Initialized,
-    Stopped,
-    Terminate,
-}
+mod protocol_trace;
 
-#[derive(Debug)]
-pub enum ConnectedNotification {
-    Details { title: String, question_id: i32 },
-    Code { code: String },
-}
+mod provenance;
+use provenance::{Provenance, Side, SyncState, Tracker};
 
-#[derive(Debug)]
-pub enum ListenMessage {
-    Terminate,
-}
+mod question_map;
+use question_map::QuestionMap;
 
-async fn run_accept(
-    rx_connected: async_std::channel::Receiver<ConnectedMessage>,
-    mut rx_listen: async_std::channel::Receiver<ListenMessage>,
-    tx_conn_notification: async_std::channel::Sender<ConnectedNotification>,
-    addr: impl ToSocketAddrs + std::fmt::Display,
-) -> Result<()> {
-    let listener = TcpListener::bind(&addr).await?;
-    info!("listening on {}", addr);
+mod read_only;
+use read_only::ReadOnly;
 
-    let res = semaphore::Semaphore::new(1, ());
-    let rx_connected = Arc::new(Mutex::new(rx_connected));
+mod rebroadcast;
+use rebroadcast::Rebroadcaster;
 
-    loop {
-        select! {
-            accepted = listener.accept().fuse() => {
-                if let Ok((stream, _)) = accepted {
-                    let peer = stream.peer_addr()?;
-
-                    match res.try_access() {
-                        Ok(_) => {
-                            task::spawn(accept_connection(
-                                peer,
-                                stream,
-                                rx_connected.clone(),
-                                tx_conn_notification.clone(),
-                            ));
-                        }
-                        Err(semaphore::TryAccessError::NoCapacity) => {
-                            task::spawn(deny_connection(peer, stream));
-                        }
-                        Err(_) => break,
-                    }
-                } else {
-                    break;
-                }
-            },
+mod recording;
 
-            terminated = rx_listen.next().fuse() => {
-                match terminated {
-                    None | Some(ListenMessage::Terminate) => { break; }
-                }
-            }
-        }
-    }
+mod retention;
+use retention::{Retained, RetentionPolicy};
 
-    Ok(())
-}
+mod runtime;
+use runtime::net::TcpStream;
+use runtime::{channel, task};
 
-async fn run_controller(
-    state: State,
-    tx_connected: async_std::channel::Sender<ConnectedMessage>,
-    tx_listen: async_std::channel::Sender<ListenMessage>,
-    mut rx_controller: async_std::channel::Receiver<WorkerMessage>,
-    tx_notification: std::sync::mpsc::Sender<WorkerNotification>,
-    mut rx_conn_notification: async_std::channel::Receiver<ConnectedNotification>,
-) -> Result<()> {
-    let mut send_code_pending = false;
+mod shutdown;
 
-    loop {
-        select! {
-            msg = rx_controller.next().fuse() => {
-                trace!("msg: {:?}", msg);
+mod send_progress;
+use send_progress::SendProgress;
 
-                if let Some(msg) = msg {
-                    match msg {
-                        WorkerMessage::FileChanged { code } => {
-                            trace!("controller: file changed");
+mod session_context;
+use session_context::SessionContext;
 
-                            tx_connected.send(ConnectedMessage::UpdateCode { code, play: state.opts.lock().await.play }).await?;
+mod sessions;
+use sessions::SessionConfig;
 
-                            trace!("controller: file changed end");
-                        }
-                        WorkerMessage::WatchError { error } => {
-                            warn!("file watcher error: {}", error);
-                        }
-                        WorkerMessage::Start { download } => {
-                            trace!("controller: start");
+mod size_cap;
+use size_cap::CappedRead;
 
-                            // Update local file if download was requested
-                            send_code_pending = download;
+mod slug;
 
-                            // We are now ready
-                            tx_connected.send(ConnectedMessage::AppReady).await?;
+mod stats;
+use stats::SessionSummary;
 
-                            // Notify UI
-                            tx_notification.send(WorkerNotification::Initialized)?;
+mod status_file;
+use status_file::StatusFile;
 
-                            trace!("controller: start end");
-                        }
-                        WorkerMessage::Stop => {
-                            trace!("controller: stop");
+mod target_lock;
+use target_lock::TargetLock;
 
-                            // Discard any notifications from IDE
-                            send_code_pending = false;
+mod test_input;
 
-                            // Notify UI
-                            tx_notification.send(WorkerNotification::Stopped)?;
+mod transform_chains;
 
-                            trace!("controller: stop end");
-                        }
-                        WorkerMessage::Terminate => {
-                            break;
-                        }
-                    }
-                } else {
-                    break;
-                }
-            },
+mod transforms;
+use transforms::{CopyrightHeader, FinalNewlineMode};
 
-            msg = rx_conn_notification.next().fuse() => {
-                if let Some(msg) = msg {
-                    match msg {
-                        ConnectedNotification::Details { title, question_id } => {
-                            trace!("controller: details");
+mod two_way;
+use two_way::{Baseline, ConflictPolicy, Resolution};
 
-                            // Notify the UI we now have a question
-                            tx_notification.send(WorkerNotification::Details { title: title.clone(), question_id })?;
+mod watch_filter;
 
-                            trace!("controller: details end");
+#[cfg(feature = "webhook-notifications")]
+mod webhook;
 
-                        },
-                        ConnectedNotification::Code { code } => {
-                            trace!("controller: code");
+mod watchdog;
+use watchdog::{Heartbeat, OccupancyProbe};
 
-                            if send_code_pending {
-                                match std::fs::write(&state.opts.lock().await.target, code) {
-                                    Ok(_) => info!("updated code from IDE"),
-                                    Err(err) => {
-                                        let message = err.to_string();
-                                        error!("{}", message);
-                                    }
-                                }
+mod watcher;
+use watcher::Watcher;
 
-                                send_code_pending = false;
-                            }
+mod wrapper;
+use wrapper::Wrapper;
 
-                            trace!("controller: code end");
-                        }
-                    }
-                }
-            }
-        }
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, StructOpt)]
+#[structopt(author, about)]
+pub struct Opts {
+    /// Address to bind to for the extension. Shouldn't need to be changed.
+    #[structopt(short, long, default_value = "127.0.0.1:53135")]
+    bind: String,
+
+    /// Number of ports after the one of --bind to try in turn when it is already in use, like by
+    /// another instance of the app. The port chosen is the one to set in the extension options.
+    /// 0 only tries the port of --bind
+    #[structopt(long, default_value = "10")]
+    port_fallback: u16,
+
+    /// Listen on this already bound socket file descriptor instead of --bind, for systemd socket
+    /// activation
+    #[cfg(unix)]
+    #[structopt(long, conflicts_with = "bind")]
+    bind_fd: Option<i32>,
+
+    /// Listen on this Unix domain socket instead of --bind, behind a proxy forwarding the
+    /// extension connections. Only the current user can connect to it, unlike a TCP port on
+    /// localhost
+    #[cfg(unix)]
+    #[structopt(long, conflicts_with_all = &["bind", "bind-fd", "allow-ip"])]
+    bind_unix: Option<PathBuf>,
+
+    /// Send a copy of the question details and every upload to the monitoring clients connected
+    /// to this port, on the same host as --bind. Monitoring clients only receive messages.
+    #[structopt(long)]
+    rebroadcast_port: Option<u16>,
+
+    /// Accept up to this number of read-only observers alongside the extension, connecting to
+    /// --bind with an observer query parameter (ws://host:port/?observer). They receive the
+    /// question details and every upload, and anything they send is ignored.
+    #[structopt(long, default_value = "0")]
+    observers: usize,
+
+    /// Path to the target file to synchronize with the IDE.
+    // Startup value only, read `DynamicOpts::target` once synchronizing
+    #[structopt(short, long)]
+    target: Option<PathBuf>,
+
+    /// Start in this session of the configuration file, taking its target and auto-play setting.
+    /// Other sessions are switched to from the interface.
+    // Startup value only, read `DynamicOpts::session` once synchronizing
+    #[structopt(long, conflicts_with = "target")]
+    session: Option<String>,
+
+    /// Synchronize this question with its own target, as QUESTION_ID=FILE, e.g. 1234=bot.rs. The
+    /// target switches to the file when a tab identifies the question, before synchronization
+    /// starts. Can be repeated
+    #[structopt(long, number_of_values = 1)]
+    map: Vec<QuestionMap>,
+
+    /// Only synchronize this question, refusing to start with a warning when the extension
+    /// identifies another one, e.g. to never upload an arena bot to a clash. Can be repeated
+    #[structopt(long, number_of_values = 1, conflicts_with = "allow-unknown-question")]
+    expect_question: Vec<i32>,
+
+    /// Infer the CodinGame language from the extension of the target (.rs is Rust, .py is
+    /// Python3...) and show it in the title of the interface
+    #[structopt(long)]
+    auto_detect_language: bool,
+
+    /// Download the file from the IDE before synchronizing.
+    // Startup value only, read `DynamicOpts::download` once synchronizing
+    #[structopt(short, long)]
+    download: bool,
+
+    /// Auto-play questions on upload.
+    // Startup value only, read `DynamicOpts::play` once synchronizing
+    #[structopt(short, long)]
+    play: bool,
+
+    /// Also pull the edits made in the IDE into the target while synchronizing. The IDE code is
+    /// requested every --two-way-interval seconds.
+    #[structopt(long, conflicts_with_all = &["no-server", "input-from-clipboard", "watch-all-in-dir"])]
+    two_way: bool,
+
+    /// Seconds between requests of the IDE code with --two-way
+    #[structopt(long, default_value = "2")]
+    two_way_interval: u64,
+
+    /// Request the IDE code every this number of seconds while synchronizing, warning when it
+    /// changed since it was last synchronized, e.g. after edits made in the browser which the
+    /// next save would overwrite. --two-way pulls these edits instead
+    #[structopt(long, conflicts_with_all = &["two-way", "no-server", "input-from-clipboard"])]
+    ide_poll_interval: Option<u64>,
+
+    /// Side kept with --two-way when both the target and the IDE code changed: prefer-local
+    /// uploads the target, prefer-ide writes the IDE code to it, ask lets the interface choose and
+    /// keeps the local changes without it
+    #[structopt(long, default_value = "ask")]
+    conflict_policy: ConflictPolicy,
+
+    /// Make the IDE editor read-only while synchronizing, so accidental edits in the browser can't
+    /// diverge from the target
+    #[structopt(long, conflicts_with_all = &["no-server", "two-way"])]
+    lock_ide: bool,
+
+    /// Disable text user interface
+    #[structopt(long)]
+    no_gui: bool,
+
+    /// Upload the clipboard contents when they change, instead of watching the target. With
+    /// --target, the clipboard contents are also written to it.
+    #[structopt(long)]
+    input_from_clipboard: bool,
+
+    /// Interval in milliseconds at which the clipboard is polled with --input-from-clipboard
+    #[structopt(long, default_value = "500")]
+    clipboard_poll_ms: u64,
+
+    /// Use the text user interface even where it doesn't look supported, instead of falling back
+    /// to --no-gui
+    #[structopt(long, conflicts_with = "no-gui")]
+    force_tui: bool,
+
+    /// Gitignore-style pattern for files to ignore in the watched directory, in addition to
+    /// the contents of .cg-localignore. Can be repeated.
+    #[structopt(long, number_of_values = 1)]
+    ignore: Vec<String>,
+
+    /// Name of the notes file shown next to the target, {stem} being the target name without
+    /// extension and {name} the full target name
+    #[structopt(long, default_value = "{stem}.notes.md")]
+    notes_pattern: String,
+
+    /// When quitting the text user interface, offer to upload the target if it changed since the
+    /// last upload
+    #[structopt(long)]
+    upload_before_quit: bool,
+
+    /// Interval in milliseconds at which the text user interface polls for updates
+    #[structopt(long, default_value = "50")]
+    tui_refresh_rate: u64,
+
+    /// Seconds without controller progress while messages are pending before the watchdog
+    /// reports a stall. 0 disables the watchdog.
+    #[structopt(long, default_value = "10")]
+    watchdog_timeout: u64,
+
+    /// Accept up to this number of extension connections at once, e.g. one per browser tab,
+    /// denying the others. The messages for the extension go to the connection which identified
+    /// the question last.
+    #[structopt(long, default_value = "1")]
+    max_connections: usize,
+
+    /// Accept any number of extension connections, as with an unlimited --max-connections
+    #[structopt(long)]
+    no_semaphore: bool,
+
+    /// Close the oldest extension connection instead of denying a new one, e.g. when a
+    /// refreshed browser tab connects before the old socket is gone. Without it, the text user
+    /// interface asks
+    #[structopt(long)]
+    takeover: bool,
+
+    /// Accept connections whatever their Origin and User-Agent headers, e.g. from other clients
+    /// than the browser extension. Otherwise only the CodinGame extension is accepted.
+    #[structopt(long)]
+    no_origin_check: bool,
+
+    /// Accept only the browser extension of this ID, the host of its chrome-extension:// or
+    /// moz-extension:// origin as logged when it connects. Can be repeated. Without it, any
+    /// browser extension is accepted
+    #[structopt(long, number_of_values = 1)]
+    extension_id: Vec<String>,
+
+    /// Accept only the connections giving this token, as the `token` query parameter of their
+    /// request or as their first message. For a listener exposed beyond localhost
+    #[structopt(long)]
+    auth_token: Option<String>,
+
+    /// Accept connections only from this address or network, e.g. 192.168.1.10 or
+    /// 192.168.1.0/24, when binding beyond localhost. Can be repeated
+    #[structopt(long, number_of_values = 1)]
+    allow_ip: Vec<IpNetwork>,
+
+    /// Close the connection when the extension sends no message for this number of seconds, to
+    /// detect frozen browsers. 0 disables the timeout.
+    #[structopt(long, default_value = "0")]
+    session_timeout: u64,
+
+    /// Ping the extension after this number of seconds without messages, closing the connection
+    /// when it doesn't answer, so half-open connections left by a sleeping laptop or a crashed
+    /// browser free their slot. 0 disables the pings.
+    #[structopt(long, default_value = "30")]
+    keepalive_interval: u64,
+
+    /// Seconds to wait for the extension to identify the question once connected, before offering
+    /// to synchronize anyway. 0 waits forever.
+    #[structopt(long, default_value = "10")]
+    details_timeout: u64,
+
+    /// Start synchronizing without asking when the extension doesn't identify the question within
+    /// --details-timeout
+    #[structopt(long)]
+    allow_unknown_question: bool,
+
+    /// Start on this question as if the extension had identified it, for workflows without the
+    /// extension. Details sent by the extension later take over.
+    #[structopt(long, requires = "question-title-override")]
+    question_id_override: Option<i32>,
+
+    /// Title of the question of --question-id-override
+    #[structopt(long, requires = "question-id-override")]
+    question_title_override: Option<String>,
+
+    /// Consider the extension connection flapping when it closes more than this number of times
+    /// within --flap-window seconds. Prompts and automatic starts are then suspended until a
+    /// connection stays up for --flap-stabilization seconds. 0 disables the detection.
+    #[structopt(long, default_value = "5")]
+    flap_threshold: usize,
+
+    /// Seconds over which connection cycles are counted for --flap-threshold
+    #[structopt(long, default_value = "60")]
+    flap_window: u64,
+
+    /// Seconds a connection must stay up for a flapping connection to be considered stable again
+    #[structopt(long, default_value = "30")]
+    flap_stabilization: u64,
+
+    /// Exit with code 70 when the watchdog detects a stall, so a supervisor can restart the app
+    #[structopt(long)]
+    watchdog_abort: bool,
+
+    /// Seconds given to the worker to stop when exiting, after which the app exits anyway
+    #[structopt(long, default_value = "5")]
+    shutdown_timeout: u64,
+
+    /// Don't read nor upload the target when it is larger than this number of bytes. 0 means
+    /// unlimited.
+    #[structopt(long, default_value = "4194304")]
+    max_file_size: u64,
+
+    /// Refuse to upload code with more than this number of lines. 0 means unlimited.
+    #[structopt(long, default_value = "0")]
+    code_max_lines: usize,
+
+    /// Skip uploads of code already uploaded within this number of seconds, e.g. after undoing an
+    /// edit. 0 disables the check.
+    #[structopt(long, default_value = "0")]
+    upload_dedup_window: u64,
+
+    /// Command run through the shell when an upload starts and once it is sent or stalls, for
+    /// status bar indicators. It gets CGLOCAL_STATUS (uploading or idle), CGLOCAL_BYTES and the
+    /// question in CGLOCAL_QUESTION_ID and CGLOCAL_QUESTION_TITLE.
+    #[structopt(long)]
+    upload_progress_command: Option<String>,
+
+    /// Text substitution applied before uploading, as FIND:REPLACE. Can be repeated, rules are
+    /// applied in order.
+    #[structopt(long, number_of_values = 1, parse(try_from_str = transforms::parse_replacement))]
+    upload_replace: Vec<(String, String)>,
+
+    /// Refuse to upload .json targets which are not valid JSON
+    #[structopt(long)]
+    upload_validate_json: bool,
+
+    /// End the uploaded code with exactly one newline
+    #[structopt(long, conflicts_with = "upload-strip-final-newline")]
+    upload_ensure_final_newline: bool,
+
+    /// Remove all trailing newlines from the uploaded code
+    #[structopt(long)]
+    upload_strip_final_newline: bool,
+
+    /// Only upload when the extension asks for the code with a request-code message, instead of
+    /// on every change of the target
+    #[structopt(long)]
+    upload_on_ide_request: bool,
+
+    /// Truncate the uploaded code to this number of characters (Unicode codepoints), the limit
+    /// of the IDE, marking the cut with a "// TRUNCATED" line
+    #[structopt(long)]
+    upload_force_codepoint_limit: Option<usize>,
+
+    /// Send a checksum of the code with each upload, for extensions able to verify it
+    #[structopt(long)]
+    upload_checksum: bool,
+
+    /// Write the preprocessed code to this file on every change, in addition to uploading it.
+    /// {slug} in the path is replaced with a file name derived from the question title.
+    #[structopt(long)]
+    emit: Option<PathBuf>,
+
+    /// Maximum length of the file names derived from question titles
+    #[structopt(long, default_value = "64")]
+    slug_max_length: usize,
+
+    /// Don't listen for the extension, only write the preprocessed code to the --emit file.
+    /// Runs without the text user interface.
+    #[structopt(long, requires = "emit")]
+    no_server: bool,
+
+    /// Prepend the contents of this template file to the uploaded code, unless the code already
+    /// starts with it. {YEAR} in the template is replaced with the current year.
+    #[structopt(long)]
+    upload_add_copyright: Option<PathBuf>,
+
+    /// Surround the uploaded code with the contents of these two files, for puzzles expecting the
+    /// code in a class or a function. The wrapper is removed from downloaded code.
+    #[structopt(long, number_of_values = 2, value_names = &["before-path", "after-path"])]
+    upload_wrapper: Vec<PathBuf>,
+
+    /// Year substituted in the --upload-add-copyright template instead of the current year, for
+    /// reproducible uploads
+    #[structopt(long, requires = "upload-add-copyright")]
+    copyright_year_override: Option<i32>,
+
+    /// Prepend a comment with the question, upload time and app version to the uploaded code
+    #[structopt(long)]
+    upload_inject_metadata: bool,
+
+    /// Author name added to the metadata comment of --upload-inject-metadata
+    #[structopt(long)]
+    metadata_author: Option<String>,
+
+    /// Remove the metadata comment from code downloaded from the IDE
+    #[structopt(long)]
+    strip_metadata_on_download: bool,
+
+    /// Write downloaded code even when the target was saved after the download was requested.
+    /// Without the interface such downloads are refused otherwise, the interface asks.
+    #[structopt(long)]
+    force_download: bool,
+
+    /// Append the contents of this file to the uploaded code as a commented-out test input block,
+    /// which the judge ignores
+    #[structopt(long)]
+    upload_inject_test_input: Option<PathBuf>,
+
+    /// Remove the test input block from code downloaded from the IDE
+    #[structopt(long)]
+    strip_test_input: bool,
+
+    /// Comment out the entry point before uploading, for puzzles expecting a module without one:
+    /// fn main in Rust, the if __name__ == "__main__": block in Python
+    #[structopt(long)]
+    upload_comment_out_main: bool,
+
+    /// Uncomment the entry point commented out by --upload-comment-out-main in code downloaded
+    /// from the IDE
+    #[structopt(long)]
+    restore_main_on_download: bool,
+
+    /// Print the settings to enter in the browser extension once the listener is bound. The text
+    /// user interface always shows them on the waiting screen.
+    #[structopt(long)]
+    print_extension_settings: bool,
+
+    /// Log the full code carried by messages at debug and trace level, instead of only its length
+    /// and fingerprint, and write it to the --trace-protocol file
+    #[structopt(long)]
+    log_include_code: bool,
+
+    /// Show the first lines of the target file in the text user interface while synchronizing
+    #[structopt(long)]
+    tui_show_code_preview: bool,
+
+    /// Ask for confirmation in the text user interface before every upload, showing what changed
+    #[structopt(long)]
+    tui_confirm_upload: bool,
+
+    /// Number of lines shown by --tui-show-code-preview
+    #[structopt(long, default_value = "10")]
+    tui_preview_lines: usize,
+
+    /// Show a diff of the target against the last uploaded code in the text user interface
+    #[structopt(long)]
+    show_diff_in_tui: bool,
+
+    /// Show a status bar at the bottom of the text user interface with the connection state,
+    /// current question and transfer counters
+    #[structopt(long)]
+    ui_status_bar: bool,
+
+    /// Upload when any file of the target directory matching --watch-pattern changes, sending
+    /// all matching files concatenated in name order
+    #[structopt(long)]
+    watch_all_in_dir: bool,
+
+    /// Glob pattern of the file names uploaded with --watch-all-in-dir. Defaults to the files with
+    /// the extension of the target.
+    #[structopt(long, requires = "watch-all-in-dir", parse(try_from_str = globset::Glob::new))]
+    watch_pattern: Option<globset::Glob>,
+
+    /// Report the deletion of the target file, and stop uploading until it reappears
+    #[structopt(long)]
+    watch_delete: bool,
+
+    /// Re-create the target file with the last uploaded contents when it is deleted. Implies
+    /// --watch-delete.
+    #[structopt(long)]
+    recreate_target_on_delete: bool,
+
+    /// Handle saves done by renaming a new file over the target, and report renames of the target
+    #[structopt(long)]
+    watch_on_rename: bool,
+
+    /// Switch to the new path when the target is renamed. Implies --watch-on-rename.
+    #[structopt(long)]
+    follow_renames: bool,
+
+    /// Run even if another instance is already synchronizing the same target
+    #[structopt(long)]
+    allow_shared_target: bool,
+
+    /// Hold an advisory lock on the target while reading it for an upload (shared) and while
+    /// writing a download to it (exclusive), so editors honoring the lock don't write it at the
+    /// same time. The operation is skipped if the lock can't be taken within 2 seconds.
+    #[structopt(long)]
+    target_file_lock: bool,
+
+    /// Remove dead code before uploading, using a language-specific tool (rust, python or js).
+    /// The local file is left untouched.
+    #[structopt(long, parse(try_from_str = dead_code::parse_language))]
+    upload_remove_dead_code: Option<Language>,
+
+    /// Maximum number of code payloads kept in memory during the session
+    #[structopt(long, default_value = "10")]
+    retain_max_payloads: usize,
+
+    /// Maximum total size in bytes of the code payloads kept in memory during the session. The
+    /// most recent payload is always kept.
+    #[structopt(long, default_value = "4194304")]
+    retain_max_bytes: usize,
+
+    /// Don't record per-question statistics
+    #[structopt(long)]
+    no_stats: bool,
+
+    /// Keep the synchronization state in this file as a line of JSON, for shell prompts and status
+    /// bars. The file is removed on exit.
+    #[structopt(long)]
+    status_file: Option<PathBuf>,
+
+    /// Record every message exchanged with the extension to this file, as lines of JSON with
+    /// their time, to reproduce the session with the replay subcommand. The file is overwritten.
+    /// It holds the code in full, whatever --log-include-code says
+    #[structopt(long)]
+    record: Option<PathBuf>,
+
+    /// Write every WebSocket text frame exchanged with the extension to this file, with its time
+    /// and direction, whatever the log level. The file is overwritten. The code is redacted
+    /// unless --log-include-code is given
+    #[structopt(long)]
+    trace_protocol: Option<PathBuf>,
+
+    /// Connect a fake extension to the listener, which identifies --mock-question-id, keeps the
+    /// uploaded code and sends it back when the IDE code is requested, to try the synchronization
+    /// without a browser
+    #[structopt(long, conflicts_with = "no-server")]
+    mock: bool,
+
+    /// Question id identified by the --mock extension
+    #[structopt(long, default_value = "1")]
+    mock_question_id: i32,
+
+    /// Address to serve Prometheus metrics on, at /metrics
+    #[cfg(feature = "prometheus-metrics")]
+    #[structopt(long)]
+    metrics_prometheus: Option<String>,
+
+    /// URL to POST a JSON notification to on each upload and download. Can be repeated.
+    #[cfg(feature = "webhook-notifications")]
+    #[structopt(long, number_of_values = 1)]
+    notify_webhook: Vec<String>,
+
+    /// Header added to the --notify-webhook requests for authentication, as 'Name: value'
+    #[cfg(feature = "webhook-notifications")]
+    #[structopt(long, parse(try_from_str = webhook::parse_header))]
+    notify_webhook_auth_header: Option<(String, String)>,
+
+    /// Configuration file. Defaults to cg-local.toml in the current directory, then config.toml in
+    /// the cg-local-app directory of the platform configuration directory.
+    #[structopt(long)]
+    config: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+/// Options changed while synchronizing: play from the interface, the target when it is renamed
+/// or switched, the session. This is the only copy to read once synchronizing: the `play`,
+/// `download`, `target` and `session` fields of `Opts` keep their startup values and are never
+/// updated. The rest of `Opts` is set once at startup and shared without a lock.
+#[derive(Debug, Clone)]
+pub struct DynamicOpts {
+    play: bool,
+    download: bool,
+    target: Option<PathBuf>,
+    /// --auto-detect-language, the language following the target
+    detect_language: bool,
+    /// Session of the configuration file, switched from the interface
+    session: Option<String>,
+}
+
+impl DynamicOpts {
+    fn new(opts: &Opts) -> Self {
+        Self {
+            play: opts.play,
+            download: opts.download,
+            target: opts.target.clone(),
+            detect_language: opts.auto_detect_language,
+            session: opts.session.clone(),
+        }
+    }
+
+    /// CodinGame language of the target, with --auto-detect-language
+    fn language(&self) -> Option<&'static str> {
+        if !self.detect_language {
+            return None;
+        }
+
+        language::codingame_language(self.target.as_ref()?.as_ref())
+    }
+
+    /// Title of the main screens of the interface, with the detected language
+    fn title(&self) -> String {
+        match self.language() {
+            Some(language) => format!("{} - {}", messages::APP_TITLE, language),
+            None => messages::APP_TITLE.to_owned(),
+        }
+    }
+
+    /// Path of the notes of the target, whether they exist or not
+    fn notes_path(&self, notes_pattern: &str) -> Option<PathBuf> {
+        let target: &std::path::Path = self.target.as_ref()?.as_ref();
+        notes::path(target, notes_pattern).map(PathBuf::from)
+    }
+
+    /// Path to the target file. Only valid once `run` checked it was given, which it may not be
+    /// with --input-from-clipboard.
+    fn target(&self) -> &PathBuf {
+        self.target
+            .as_ref()
+            .expect("target is checked before synchronizing")
+    }
+
+    /// Target shown to the user: its path, or the clipboard when there is no target
+    fn source_label(&self) -> String {
+        match &self.target {
+            Some(target) => target.display().to_string(),
+            None => messages::clipboard_source().to_string(),
+        }
+    }
+}
+
+impl Opts {
+    /// Whether deletions of the target are reported
+    fn watches_delete(&self) -> bool {
+        self.watch_delete || self.recreate_target_on_delete
+    }
+
+    /// Whether renames involving the target are handled
+    fn watches_rename(&self) -> bool {
+        self.watch_on_rename || self.follow_renames
+    }
+
+    /// Trailing newline handling of uploaded code
+    fn final_newline_mode(&self) -> FinalNewlineMode {
+        if self.upload_ensure_final_newline {
+            FinalNewlineMode::Ensure
+        } else if self.upload_strip_final_newline {
+            FinalNewlineMode::Strip
+        } else {
+            FinalNewlineMode::Passthrough
+        }
+    }
+
+    /// Where to listen for the extension
+    fn listen(&self) -> Listen {
+        #[cfg(unix)]
+        if let Some(fd) = self.bind_fd {
+            return Listen::Fd(fd);
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &self.bind_unix {
+            return Listen::Unix(path.clone());
+        }
+
+        Listen::Addr(self.bind.clone())
+    }
+
+    /// Where to listen for monitoring clients, with --rebroadcast-port
+    fn rebroadcast_addr(&self) -> Option<String> {
+        let host = self
+            .bind
+            .rsplit_once(':')
+            .map(|(host, _)| host)
+            .unwrap_or("127.0.0.1");
+
+        self.rebroadcast_port
+            .map(|port| format!("{}:{}", host, port))
+    }
+
+    /// Handling of extension connections
+    fn connection_settings(&self) -> ConnectionSettings {
+        ConnectionSettings {
+            session_timeout: Some(self.session_timeout)
+                .filter(|secs| *secs > 0)
+                .map(std::time::Duration::from_secs),
+            details_timeout: Some(self.details_timeout)
+                .filter(|secs| *secs > 0)
+                .map(std::time::Duration::from_secs),
+            keepalive_interval: Some(self.keepalive_interval)
+                .filter(|secs| *secs > 0)
+                .map(std::time::Duration::from_secs),
+            max_connections: if self.no_semaphore {
+                usize::MAX
+            } else {
+                self.max_connections
+            },
+        }
+    }
+
+    /// Limits of the code payloads kept in memory
+    fn retention_policy(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            max_entries: self.retain_max_payloads,
+            max_bytes: self.retain_max_bytes,
+        }
+    }
+
+    /// Detector of the extension reconnecting in a loop
+    fn flap_detector(&self) -> FlapDetector {
+        FlapDetector::new(
+            self.flap_threshold,
+            std::time::Duration::from_secs(self.flap_window),
+            std::time::Duration::from_secs(self.flap_stabilization),
+        )
+    }
+
+    /// Question given by --question-id-override and --question-title-override
+    fn question_override(&self) -> Option<(i32, String)> {
+        match (self.question_id_override, &self.question_title_override) {
+            (Some(question_id), Some(title)) => Some((question_id, title.clone())),
+            _ => None,
+        }
+    }
+
+    /// Interval of the requests of the IDE code while synchronizing, with --two-way or
+    /// --ide-poll-interval
+    fn ide_poll_interval(&self) -> Option<std::time::Duration> {
+        let secs = match (self.two_way, self.ide_poll_interval) {
+            (true, _) => self.two_way_interval.max(1),
+            (false, None) | (false, Some(0)) => return None,
+            (false, Some(secs)) => secs,
+        };
+
+        Some(std::time::Duration::from_secs(secs))
+    }
+
+    /// Whether the question may be synchronized with --expect-question, an unknown question never
+    /// being expected
+    fn expects_question(&self, question_id: Option<i32>) -> bool {
+        self.expect_question.is_empty()
+            || question_id.is_some_and(|question_id| self.expect_question.contains(&question_id))
+    }
+
+    /// Header of --upload-add-copyright, read once
+    fn copyright(&self) -> Result<Option<CopyrightHeader>> {
+        match &self.upload_add_copyright {
+            Some(path) => Ok(Some(
+                CopyrightHeader::load(path.as_ref(), self.copyright_year_override).chain_err(
+                    || ErrorKind::InvalidCopyrightTemplate(path.display().to_string()),
+                )?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Wrapper of --upload-wrapper, read once
+    fn wrapper(&self) -> Result<Option<Wrapper>> {
+        match self.upload_wrapper.as_slice() {
+            [before, after] => Ok(Some(Wrapper::load(before.as_ref(), after.as_ref())?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether the text user interface shows the changes being uploaded
+    fn shows_diff(&self) -> bool {
+        self.show_diff_in_tui && !self.no_gui
+    }
+
+    /// Number of lines to show in the code preview, if enabled
+    fn preview_lines(&self) -> Option<usize> {
+        if self.tui_show_code_preview && !self.no_gui {
+            Some(self.tui_preview_lines)
+        } else {
+            None
+        }
+    }
+}
+
+/// Port the browser extension connects to unless configured otherwise
+pub const EXTENSION_DEFAULT_PORT: u16 = 53135;
+
+/// Question id shown while synchronizing a question the extension didn't identify
+const UNKNOWN_QUESTION_ID: i32 = 0;
+
+/// Replaced with the slug of the question title in the --emit path
+const SLUG_PLACEHOLDER: &str = "{slug}";
+
+/// Name of the question header of the question screens
+const HEADER_VIEW: &str = "header";
+
+/// Name of the text view of the notes dialog
+const NOTES_VIEW: &str = "notes_view";
+
+/// Diff lines shown by --show-diff-in-tui, the others are only counted
+const DIFF_PANE_LINES: usize = 30;
+
+/// Name of the text view of --show-diff-in-tui
+const PENDING_DIFF_VIEW: &str = "pending_diff";
+
+/// Changed lines shown when confirming an upload with --tui-confirm-upload
+const CONFIRM_DIFF_LINES: usize = 5;
+
+/// Name of the checkbox writing a restored version to the target
+const RESTORE_LOCAL_CHECKBOX: &str = "restore_local";
+
+/// Name of the dialog confirming an upload
+const CONFIRM_DIALOG: &str = "confirm_dialog";
+
+/// Name of the warning shown while the connection is flapping
+const FLAPPING_DIALOG: &str = "flapping_dialog";
+
+/// Name of the text of the waiting screen
+const WAITING_VIEW: &str = "waiting";
+
+/// Name of the main screen: waiting, question or running
+const MAIN_DIALOG: &str = "main_dialog";
+
+/// Name of the dialog asking whether to upload before quitting
+const QUIT_DIALOG: &str = "quit_dialog";
+
+/// Period of the stability checks while the connection is flapping without being up
+const FLAP_POLL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Delay for the extension to answer a ping before its connection is considered dead
+const PONG_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+error_chain! {
+    foreign_links {
+        Io(std::io::Error);
+        Hotwatch(hotwatch::Error);
+        WebSocket(tungstenite::Error);
+        WorkerNotificationChannel(std::sync::mpsc::SendError<WorkerNotification>);
+        ConnectedNotificationChannel(channel::SendError<ConnectedNotification>);
+        WorkerMessageChannel(channel::SendError<WorkerMessage>);
+        ConnectedMessageChannel(channel::SendError<Routed>);
+        ListenMessageChannel(channel::SendError<ListenMessage>);
+    }
+
+    errors {
+        TargetLocked(target: String, pid: u32, bind: String) {
+            description("target is already synchronized by another instance")
+            display("{} is already synchronized by process {} listening on {}, pass --allow-shared-target to run anyway", target, pid, bind)
+        }
+
+        TargetLockFailed(path: String) {
+            description("failed to acquire target lock")
+            display("failed to acquire target lock {}", path)
+        }
+
+        InvalidConfig(path: String) {
+            description("invalid configuration file")
+            display("failed to load configuration file {}", path)
+        }
+
+        IncompleteTransformChain(stage: String, option: String) {
+            description("transform chain stage without its options")
+            display("the [transforms] configuration lists the {} stage, which needs {}", stage, option)
+        }
+
+        InvalidCopyrightTemplate(path: String) {
+            description("invalid copyright template")
+            display("failed to read copyright template {}", path)
+        }
+
+        InvalidUploadWrapper(path: String) {
+            description("invalid upload wrapper")
+            display("failed to read upload wrapper {}", path)
+        }
+
+        InvalidBind(bind: String, reason: String) {
+            description("invalid address to listen on")
+            display("invalid --bind address '{}': {}. Expected host:port, e.g. 127.0.0.1:53135", bind, reason)
+        }
+
+        PortsInUse(bind: String, first: u16, last: u16) {
+            description("no free port to listen on")
+            display("can't listen on {}: ports {} to {} are all in use, raise --port-fallback or pick another port", bind, first, last)
+        }
+
+        InvalidBindFd(fd: i32, reason: String) {
+            description("invalid socket file descriptor")
+            display("can't listen on file descriptor {}: {}", fd, reason)
+        }
+
+        InvalidBindUnix(path: String, reason: String) {
+            description("invalid Unix domain socket")
+            display("can't listen on the Unix domain socket {}: {}", path, reason)
+        }
+
+        UnknownSession(name: String, known: Vec<String>) {
+            description("no such session in the configuration")
+            display("no session named '{}' in the configuration{}", name, if known.is_empty() { String::new() } else { format!(", expected one of {}", known.join(", ")) })
+        }
+
+        AmbiguousTarget(dir: String, candidates: Vec<String>) {
+            description("no obvious main file in the target directory")
+            display("{} is a directory with several candidate files: {}. Pass the one to synchronize, e.g. --target {}", dir, candidates.join(", "), candidates[0])
+        }
+
+        NoMainFile(dir: String) {
+            description("no source file in the target directory")
+            display("{} is a directory without source files, pass the file to synchronize with --target", dir)
+        }
+
+        InvalidTarget(path: String) {
+            description("target is not a readable file")
+            display("{} is not a readable file", path)
+        }
+
+        ShutdownTimeout(secs: u64, stage: String) {
+            description("worker did not stop in time")
+            display("worker did not stop within {}s (last step: {}), exiting anyway", secs, stage)
+        }
+
+        WorkerPanicked {
+            description("worker thread panicked")
+            display("worker thread panicked")
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "action", content = "payload", rename_all = "kebab-case")]
+pub enum ServerMessage {
+    SendDetails,
+    #[serde(rename_all = "camelCase")]
+    Details {
+        title: String,
+        question_id: i32,
+    },
+    AppReady,
+    AlreadyConnected,
+    /// Sent by the extension to get the current code, see --upload-on-ide-request
+    RequestCode,
+    UpdateCode {
+        code: Code,
+        play: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checksum: Option<String>,
+    },
+    SendCode,
+    Code {
+        code: Code,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checksum: Option<String>,
+    },
+    SetReadOnly {
+        state: bool,
+    },
+    Error {
+        message: String,
+    },
+    /// Sent first by the extension with --auth-token, when its request didn't have the token
+    Auth {
+        token: String,
+    },
+    /// Action of a newer extension release, only ever parsed, see `ServerMessage::parse`
+    #[serde(skip)]
+    Unknown {
+        action: String,
+        payload: serde_json::Value,
+    },
+}
+
+/// Action and payload of any message, to keep those of unknown actions
+#[derive(Deserialize)]
+struct Envelope {
+    action: String,
+    #[serde(default)]
+    payload: serde_json::Value,
+}
+
+impl ServerMessage {
+    /// Actions of the variants, others being `Unknown`
+    const ACTIONS: &'static [&'static str] = &[
+        "send-details",
+        "details",
+        "app-ready",
+        "already-connected",
+        "request-code",
+        "update-code",
+        "send-code",
+        "code",
+        "set-read-only",
+        "error",
+        "auth",
+    ];
+
+    /// Parse a message of the extension. Unknown actions, sent by newer extension releases, give
+    /// `Unknown` instead of an error, like unknown payload fields are already ignored.
+    pub fn parse(text: &str) -> serde_json::Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+        let Envelope { action, payload } = serde::Deserialize::deserialize(&value)?;
+
+        if Self::ACTIONS.contains(&action.as_str()) {
+            serde_json::from_value(value)
+        } else {
+            Ok(ServerMessage::Unknown { action, payload })
+        }
+    }
+}
+
+impl From<ServerMessage> for tungstenite::Message {
+    fn from(msg: ServerMessage) -> Self {
+        tungstenite::Message::Text(serde_json::to_string(&msg).unwrap())
+    }
+}
+
+pub struct State {
+    opts: Arc<Opts>,
+    dynamic: Arc<Mutex<DynamicOpts>>,
+    config: Arc<Config>,
+    heartbeat: Arc<Heartbeat>,
+    watcher: Watcher,
+    target_lock: Option<TargetLock>,
+    copyright: Option<CopyrightHeader>,
+    wrapper: Option<Wrapper>,
+    /// Files concatenated into the last upload with --watch-all-in-dir
+    bundle_files: Vec<PathBuf>,
+    rebroadcaster: Option<Rebroadcaster>,
+    /// Slugs used for {slug} in --emit, by question title
+    emit_slugs: HashMap<String, String>,
+    /// Set once the target being over --max-file-size was reported, until it gets under it
+    oversized: bool,
+    /// Target made read-only while the IDE editor is locked
+    read_only: ReadOnly,
+    /// Connection cycles of the extension, recorded by the accepted connections
+    flap: Arc<Mutex<FlapDetector>>,
+    /// State exposed with --status-file
+    status: StatusFile,
+    /// Question, target and session, replaced as a whole on every change
+    context: Arc<SessionContext>,
+}
+
+impl State {
+    pub fn new(
+        opts: Arc<Opts>,
+        dynamic: Arc<Mutex<DynamicOpts>>,
+        config: Arc<Config>,
+        watcher: Watcher,
+        target_lock: Option<TargetLock>,
+        copyright: Option<CopyrightHeader>,
+        wrapper: Option<Wrapper>,
+    ) -> Self {
+        let flap = Arc::new(Mutex::new(opts.flap_detector()));
+        let status = opts
+            .status_file
+            .as_ref()
+            .map(|path| StatusFile::spawn(path.as_ref()))
+            .unwrap_or_default();
+        // Started in a session with --session
+        status.session(opts.session.as_deref());
+        let context = SessionContext::new(&DynamicOpts::new(&opts));
+
+        Self {
+            opts,
+            dynamic,
+            config,
+            heartbeat: Arc::new(Heartbeat::new()),
+            watcher,
+            target_lock,
+            copyright,
+            wrapper,
+            bundle_files: Vec::new(),
+            rebroadcaster: None,
+            emit_slugs: HashMap::new(),
+            oversized: false,
+            read_only: ReadOnly::new(),
+            flap,
+            status,
+            context,
+        }
+    }
+
+    /// Hook environment describing a snapshot of the session context
+    async fn hook_env(&self, context: &SessionContext) -> HookEnv {
+        let mut env = HookEnv::new().var(
+            "CGLOCAL_SESSION_STARTED",
+            context
+                .started
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        );
+
+        if let Some(target) = &context.target {
+            env = env.var("CGLOCAL_TARGET", target.display());
+
+            if let Some(notes) = notes::path(target.as_ref(), &self.opts.notes_pattern) {
+                if notes.is_file() {
+                    env = env.var("CGLOCAL_NOTES", notes.display());
+                }
+            }
+        }
+
+        if let Some(language) = context.language {
+            env = env.var("CGLOCAL_LANGUAGE", language);
+        }
+
+        if let Some(session) = &context.session {
+            env = env.var("CGLOCAL_SESSION", session);
+        }
+
+        env.question(context.question.as_ref(), self.opts.slug_max_length)
+    }
+
+    /// Replace the question of the session context
+    fn set_question(&mut self, question: Option<(i32, String)>) {
+        self.context = self.context.with_question(question);
+    }
+
+    /// Path to write the code to with --emit, {slug} being replaced with the slug of the title.
+    /// Titles sharing a slug get distinct files for the whole session.
+    fn emit_path(&mut self, emit: &Path, title: Option<&str>, slug_max_length: usize) -> PathBuf {
+        let template = emit.to_string_lossy();
+        if !template.contains(SLUG_PLACEHOLDER) {
+            return emit.to_owned();
+        }
+
+        let title = title.unwrap_or_default();
+        let slug = match self.emit_slugs.get(title) {
+            Some(slug) => slug.clone(),
+            None => {
+                let slug = slug::unique(&slug::slug(title, slug_max_length), |candidate| {
+                    self.emit_slugs.values().any(|slug| slug == candidate)
+                });
+
+                debug!(
+                    "writing code for '{}' to {}",
+                    title,
+                    template.replace(SLUG_PLACEHOLDER, &slug)
+                );
+                self.emit_slugs.insert(title.to_owned(), slug.clone());
+                slug
+            }
+        };
+
+        PathBuf::from(template.replace(SLUG_PLACEHOLDER, &slug))
+    }
+
+    /// POST the event to the --notify-webhook URLs, `event` being `upload` or `download`
+    #[cfg(feature = "webhook-notifications")]
+    fn notify_webhooks(&self, event: &'static str, question: Option<&(i32, String)>, bytes: usize) {
+        webhook::notify(
+            self.opts.notify_webhook.clone(),
+            self.opts.notify_webhook_auth_header.clone(),
+            webhook::Event::now(event, question.map(|(question_id, _)| *question_id), bytes),
+        );
+    }
+
+    /// Send the notes of the target to the interface
+    async fn notify_notes(
+        &self,
+        tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+    ) -> Result<()> {
+        let path = self
+            .dynamic
+            .lock()
+            .await
+            .notes_path(&self.opts.notes_pattern);
+
+        if let Some(path) = path {
+            let contents = async_std::fs::read_to_string(&path).await.ok();
+            tx_notification.send(WorkerNotification::Notes { path, contents })?;
+        }
+
+        Ok(())
+    }
+
+    /// Code the watcher would upload now: the target, or the bundle with --watch-all-in-dir
+    async fn current_code(&self) -> Option<String> {
+        let target = self.dynamic.lock().await.target.clone()?;
+
+        if self.opts.watch_all_in_dir {
+            let matcher = bundle::matcher(self.opts.watch_pattern.as_ref(), target.as_ref());
+            let dir = watcher::target_dir(target.as_ref()).to_owned();
+            task::spawn_blocking(move || bundle::read(&dir, &matcher))
+                .await
+                .ok()
+                .map(|(code, _)| code)
+        } else {
+            match size_cap::read(&target, self.opts.max_file_size).await {
+                Ok(CappedRead::Contents(code)) => Some(code),
+                _ => None,
+            }
+        }
+    }
+
+    /// Switch to another target and notify the UI, reporting the failure if it is refused.
+    /// Returns whether the path is the target now.
+    async fn change_target(
+        &mut self,
+        tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+        path: PathBuf,
+    ) -> Result<bool> {
+        let path = match self.set_target(path).await {
+            Ok(Some(path)) => path,
+            Ok(None) => return Ok(true),
+            Err(err) => {
+                self.report_error(tx_notification, err.to_string())?;
+                return Ok(false);
+            }
+        };
+
+        info!("{}", messages::target_changed(&path.display().to_string()));
+        if let Some(language) = self.dynamic.lock().await.language() {
+            info!("{}", messages::language_detected(language));
+        }
+        self.notify_notes(tx_notification).await?;
+        tx_notification.send(WorkerNotification::TargetChanged { path: path.clone() })?;
+
+        // The new target gets its own warning
+        self.oversized = false;
+
+        if let Some(preview_lines) = self.opts.preview_lines() {
+            if let Some(code) = self.read_target(tx_notification, &path).await? {
+                tx_notification.send(WorkerNotification::CodePreview {
+                    snippet: code_preview(&code, preview_lines).into(),
+                })?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Apply a session of the configuration file: its target, watched instead of the current one,
+    /// and its auto-play setting. Returns the session, or `None` if it doesn't exist or its target
+    /// is refused, which is reported.
+    async fn switch_session(
+        &mut self,
+        tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+        name: String,
+    ) -> Result<Option<SessionConfig>> {
+        let session = match self.config.session(&name) {
+            Ok(session) => session.clone(),
+            Err(err) => {
+                self.report_error(tx_notification, err.to_string())?;
+                return Ok(None);
+            }
+        };
+
+        // The current session goes on if the target of the new one is refused
+        if !self
+            .change_target(tx_notification, session.target.clone().into())
+            .await?
+        {
+            return Ok(None);
+        }
+
+        info!("{}", messages::session_switched(&name));
+        {
+            let mut dynamic = self.dynamic.lock().await;
+            dynamic.session = Some(name.clone());
+            if let Some(play) = session.play {
+                dynamic.play = play;
+            }
+            self.context = self.context.with_target(&dynamic);
+        }
+        self.status.session(Some(&name));
+        tx_notification.send(WorkerNotification::SessionSwitched { name })?;
+
+        Ok(Some(session))
+    }
+
+    /// Report the target being over --max-file-size, once until it gets under it again
+    async fn file_too_large(
+        &mut self,
+        tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+        size: u64,
+    ) -> Result<()> {
+        if self.oversized {
+            debug!("target still too large ({} bytes)", size);
+            return Ok(());
+        }
+
+        self.oversized = true;
+        let target = self.dynamic.lock().await.target().display().to_string();
+
+        self.report_error(
+            tx_notification,
+            messages::file_too_large(&target, size, self.opts.max_file_size),
+        )
+    }
+
+    /// Read the target, reporting it instead if it is over --max-file-size
+    async fn read_target(
+        &mut self,
+        tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+        path: &Path,
+    ) -> Result<Option<String>> {
+        match size_cap::read(path, self.opts.max_file_size).await {
+            Ok(CappedRead::Contents(code)) => Ok(Some(code)),
+            Ok(CappedRead::TooLarge { size }) => {
+                self.file_too_large(tx_notification, size).await?;
+                Ok(None)
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Lock the target with --target-file-lock. Returns `None`, after reporting it, if another
+    /// process keeps it locked: the operation is skipped.
+    async fn lock_target(
+        &self,
+        tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+        target: &Path,
+        access: Access,
+    ) -> Result<Option<FileLock>> {
+        if !self.opts.target_file_lock {
+            return Ok(Some(FileLock::none(target.as_ref())));
+        }
+
+        match FileLock::acquire(target.as_ref(), access).await {
+            Ok(Some(lock)) => Ok(Some(lock)),
+            Ok(None) => {
+                self.report_error(tx_notification, messages::target_file_locked())?;
+                Ok(None)
+            }
+            Err(err) => {
+                // The access itself reports the failure, if any
+                debug!("not locking {}: {}", target.display(), err);
+                Ok(Some(FileLock::none(target.as_ref())))
+            }
+        }
+    }
+
+    /// Make the target read-only or writable again, following the IDE editor
+    async fn set_read_only(
+        &mut self,
+        tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+        state: bool,
+    ) -> Result<()> {
+        if state == self.read_only.is_set() {
+            return Ok(());
+        }
+
+        let locked = match (state, self.dynamic.lock().await.target.clone()) {
+            (true, Some(target)) => self.read_only.lock(target.as_ref()).await,
+            (true, None) => return Ok(()),
+            (false, _) => self.read_only.unlock().await,
+        };
+
+        match locked {
+            Ok(()) => tx_notification.send(WorkerNotification::ReadOnly { state })?,
+            Err(err) => self.report_error(
+                tx_notification,
+                messages::read_only_failed(&err.to_string()),
+            )?,
+        }
+
+        Ok(())
+    }
+
+    /// Make the target read-only again after the app wrote it, if the IDE editor is locked
+    async fn relock_target(&mut self) {
+        if let Err(err) = self.read_only.relock().await {
+            warn!("{}", messages::read_only_failed(&err.to_string()));
+        }
+    }
+
+    /// Report an error to the UI and the error hook
+    fn report_error(
+        &self,
+        tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+        message: String,
+    ) -> Result<()> {
+        self.status.error(&message);
+        self.config.hooks.fire(
+            HookEvent::Error,
+            HookEnv::new().var("CGLOCAL_ERROR", &message),
+        );
+        tx_notification.send(WorkerNotification::Error { message })?;
+        Ok(())
+    }
+
+    /// Switch synchronization to another target: validate it, lock it and watch its directory.
+    /// Returns the new target, or `None` if it is already the current one.
+    async fn set_target(&mut self, path: PathBuf) -> Result<Option<PathBuf>> {
+        let current = self.dynamic.lock().await.target.clone();
+
+        let metadata = async_std::fs::metadata(&path)
+            .await
+            .chain_err(|| ErrorKind::InvalidTarget(path.display().to_string()))?;
+        if !metadata.is_file() {
+            bail!(ErrorKind::InvalidTarget(path.display().to_string()));
+        }
+
+        // A target over the size cap is reported when it is first read instead
+        size_cap::read(&path, self.opts.max_file_size)
+            .await
+            .chain_err(|| ErrorKind::InvalidTarget(path.display().to_string()))?;
+
+        let canonical = path.canonicalize().await?;
+        if let Some(current) = current {
+            if current.canonicalize().await.ok().as_ref() == Some(&canonical) {
+                return Ok(None);
+            }
+        }
+
+        let target_lock = TargetLock::acquire(
+            path.as_ref(),
+            &self.opts.bind,
+            self.opts.allow_shared_target,
+        )?;
+
+        // The clipboard replaces the watcher, the target only receives its contents
+        if !self.opts.input_from_clipboard {
+            self.watcher.watch(path.as_ref())?;
+        }
+
+        // The previous lock is released once the new target is watched
+        self.target_lock = target_lock;
+        {
+            let mut dynamic = self.dynamic.lock().await;
+            dynamic.target = Some(path.clone());
+            self.context = self.context.with_target(&dynamic);
+        }
+
+        // The locked IDE editor now shows the new target
+        if let Err(err) = self.read_only.retarget(path.as_ref()).await {
+            warn!("{}", messages::read_only_failed(&err.to_string()));
+        }
+
+        Ok(Some(path))
+    }
+}
+
+/// Runs the disconnect hook when a connection handler exits, however it exits
+struct DisconnectHook {
+    config: Arc<Config>,
+    peer: Peer,
+}
+
+impl Drop for DisconnectHook {
+    fn drop(&mut self) {
+        self.config.hooks.fire(
+            HookEvent::Disconnect,
+            HookEnv::new().var("CGLOCAL_PEER", self.peer),
+        );
+    }
+}
+
+/// Message for the extension, recorded with --record and traced with --trace-protocol
+fn outgoing(connection: ConnectionId, msg: ServerMessage) -> tungstenite::Message {
+    recording::sent(connection, &msg);
+
+    let msg = msg.into();
+    if let tungstenite::Message::Text(text) = &msg {
+        protocol_trace::sent(connection, text);
+    }
+    msg
+}
+
+/// Complete at the deadline, or never without one
+async fn until(deadline: Option<std::time::Instant>) {
+    match deadline {
+        Some(deadline) => {
+            task::sleep(deadline.saturating_duration_since(std::time::Instant::now())).await
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+async fn handle_accept(
+    peer: Peer,
+    mut ws_stream: WsStream,
+    connection: Connection,
+    tx_conn_notification: channel_stats::Sender<ConnectedNotification>,
+    tx_notification: std::sync::mpsc::Sender<WorkerNotification>,
+    config: Arc<Config>,
+    settings: ConnectionSettings,
+) -> Result<()> {
+    let ConnectionSettings {
+        session_timeout,
+        details_timeout,
+        keepalive_interval,
+        ..
+    } = settings;
+
+    let Connection {
+        id: connection,
+        rx: mut rx_connected,
+    } = connection;
+    debug!("accepting connection {} from {}", connection, peer);
+    let _connection = metrics::ConnectionGuard::new();
+
+    config
+        .hooks
+        .fire(HookEvent::Connect, HookEnv::new().var("CGLOCAL_PEER", peer));
+    let _disconnect = DisconnectHook { config, peer };
+
+    ws_stream
+        .send(outgoing(connection, ServerMessage::SendDetails))
+        .await?;
+
+    // Deadline for the answer to the last ping
+    let mut pong_deadline: Option<std::time::Instant> = None;
+    let mut last_message_from_ide = std::time::Instant::now();
+    // Deadline for the extension to identify the question, cleared once it did
+    let mut details_deadline = details_timeout.map(|timeout| std::time::Instant::now() + timeout);
+
+    loop {
+        let pong_timeout = until(pong_deadline);
+        let session_timeout_elapsed =
+            until(session_timeout.map(|timeout| last_message_from_ide + timeout));
+
+        // Pinged once quiet for long enough, unless a ping is already waiting for its answer
+        let keepalive_deadline = keepalive_interval
+            .filter(|_| pong_deadline.is_none())
+            .map(|interval| last_message_from_ide + interval);
+        let keepalive_elapsed = until(keepalive_deadline);
+        let details_timeout_elapsed = until(details_deadline);
+
+        select! {
+            msg = ws_stream.next().fuse() => {
+                if let Some(msg) = msg {
+                    let msg = msg?;
+                    last_message_from_ide = std::time::Instant::now();
+
+                    if let tungstenite::Message::Pong(_) = msg {
+                        debug!("connection to {} is alive", peer);
+                        pong_deadline = None;
+                    } else if let tungstenite::Message::Text(msg) = msg {
+                        protocol_trace::received(connection, &msg);
+
+                        // Logged once parsed, so the code it carries is redacted
+                        let parsed = ServerMessage::parse(&msg);
+                        if let Ok(msg) = &parsed {
+                            debug!("msg: {:?}", msg);
+                            recording::received(connection, msg);
+                        }
+
+                        match parsed {
+                            Ok(msg) => match msg {
+                                ServerMessage::Details { title, question_id } => {
+                                    details_deadline = None;
+                                    tx_conn_notification.send(ConnectedNotification::Details { title, question_id, connection: Some(connection) }).await?
+                                }
+                                ServerMessage::Code { code, checksum } => {
+                                    tx_conn_notification.send(ConnectedNotification::Code { code, checksum, connection }).await?
+                                }
+                                ServerMessage::RequestCode => {
+                                    tx_conn_notification.send(ConnectedNotification::RequestCode { connection }).await?
+                                }
+                                ServerMessage::SetReadOnly { state } => {
+                                    tx_conn_notification.send(ConnectedNotification::SetReadOnly { state, connection }).await?
+                                }
+                                ServerMessage::Auth { .. } => {
+                                    debug!("{} is already authenticated", peer);
+                                }
+                                ServerMessage::Unknown { action, .. } => {
+                                    warn!("ignoring unknown action '{}' from {}, the extension may be newer than this app", action, peer);
+                                }
+                                other => {
+                                    warn!("unexpected message: {:?}", other);
+                                    ws_stream.send(outgoing(connection, ServerMessage::Error { message: messages::unexpected_message() })).await?
+                                }
+                            },
+                            Err(err) => {
+                                error!("failed to parse message: {}", err);
+                                ws_stream.send(outgoing(connection, ServerMessage::Error { message: err.to_string() })).await?
+                            }
+                        }
+                    } else {
+                        debug!("msg: {:?}", msg);
+                    }
+                } else {
+                    break;
+                }
+            }
+
+            msg = rx_connected.next().fuse() => {
+                if let Some(msg) = msg {
+                    match msg {
+                        ConnectedMessage::AppReady => {
+                            ws_stream.send(outgoing(connection, ServerMessage::AppReady)).await?;
+                        }
+                        ConnectedMessage::UpdateCode { code, play, checksum, mut progress_command } => {
+                            let bytes = code.len();
+                            let send = ws_stream.send(outgoing(connection, ServerMessage::UpdateCode { code, play, checksum }));
+                            let sent = send_progress::timed(send, bytes, |progress| {
+                                // A stalled upload is no longer shown as in progress
+                                if let SendProgress::Stalled { .. } = progress {
+                                    if let Some(command) = progress_command.take() {
+                                        command.idle();
+                                    }
+                                }
+                                let _ = tx_notification.send(WorkerNotification::UploadProgress { progress });
+                            })
+                            .await;
+                            if let Some(command) = progress_command {
+                                command.idle();
+                            }
+                            sent?;
+                        }
+                        ConnectedMessage::SendCode => {
+                            ws_stream.send(outgoing(connection, ServerMessage::SendCode)).await?;
+                        }
+                        ConnectedMessage::Ping => {
+                            ws_stream.send(tungstenite::Message::Ping(Vec::new())).await?;
+                            pong_deadline = Some(std::time::Instant::now() + PONG_TIMEOUT);
+                        }
+                        ConnectedMessage::SetReadOnly { state } => {
+                            ws_stream.send(outgoing(connection, ServerMessage::SetReadOnly { state })).await?;
+                        }
+                        ConnectedMessage::Close => {
+                            ws_stream.close(None).await?;
+                            break;
+                        }
+                        ConnectedMessage::Terminate => { break; }
+                    }
+                } else {
+                    break;
+                }
+            }
+
+            _ = pong_timeout.fuse() => {
+                warn!("no answer to ping from {}, dropping connection", peer);
+                break;
+            }
+
+            _ = keepalive_elapsed.fuse() => {
+                trace!("no message from {} in {}s, pinging", peer, last_message_from_ide.elapsed().as_secs());
+                ws_stream.send(tungstenite::Message::Ping(Vec::new())).await?;
+                pong_deadline = Some(std::time::Instant::now() + PONG_TIMEOUT);
+            }
+
+            _ = details_timeout_elapsed.fuse() => {
+                warn!("{} didn't identify the question in {}s", peer, details_timeout.unwrap_or_default().as_secs());
+                details_deadline = None;
+                tx_conn_notification.send(ConnectedNotification::DetailsTimeout { connection }).await?;
+            }
+
+            _ = session_timeout_elapsed.fuse() => {
+                warn!("no message from {} in {}s, closing connection", peer, last_message_from_ide.elapsed().as_secs());
+                tx_conn_notification.send(ConnectedNotification::SessionTimeout { connection }).await?;
+                ws_stream.close(None).await?;
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn accept_connection(
+    peer: Peer,
+    ws_stream: WsStream,
+    connection: Connection,
+    tx_conn_notification: channel_stats::Sender<ConnectedNotification>,
+    tx_notification: std::sync::mpsc::Sender<WorkerNotification>,
+    config: Arc<Config>,
+    settings: ConnectionSettings,
+) -> Result<()> {
+    if let Err(e) = handle_accept(
+        peer,
+        ws_stream,
+        connection,
+        tx_conn_notification,
+        tx_notification,
+        config,
+        settings,
+    )
+    .await
+    {
+        match e {
+            Error(ErrorKind::WebSocket(tungstenite::Error::ConnectionClosed), _)
+            | Error(ErrorKind::WebSocket(tungstenite::Error::Protocol(_)), _)
+            | Error(ErrorKind::WebSocket(tungstenite::Error::Utf8), _) => (),
+            err => error!("error processing connection: {:?}", err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Run an accepted connection, recording its cycle for the flapping detection and the status file,
+/// and telling the interface
+async fn track_cycle(
+    peer: Peer,
+    flap: Arc<Mutex<FlapDetector>>,
+    status: StatusFile,
+    tx_notification: std::sync::mpsc::Sender<WorkerNotification>,
+    connection: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    flap.lock().await.connected(std::time::Instant::now());
+    status.connected();
+    tx_notification.send(WorkerNotification::Connected { peer })?;
+    let result = connection.await;
+    status.disconnected();
+    tx_notification.send(WorkerNotification::Disconnected { peer })?;
+
+    // The controller resumes once a connection stays up
+    if flap.lock().await.disconnected(std::time::Instant::now()) {
+        warn!("{}", messages::connection_flapping());
+        metrics::record_flapping(true);
+        tx_notification.send(WorkerNotification::Flapping { flapping: true })?;
+    }
+
+    result
+}
+
+async fn handle_deny(peer: Peer, mut ws_stream: WsStream) -> Result<()> {
+    info!("denying connection from {}", peer);
+    ws_stream
+        .send(ServerMessage::AlreadyConnected.into())
+        .await?;
+
+    ws_stream.close(None).await?;
+
+    Ok(())
+}
+
+async fn deny_connection(peer: Peer, ws_stream: WsStream) -> Result<()> {
+    if let Err(e) = handle_deny(peer, ws_stream).await {
+        match e {
+            Error(ErrorKind::WebSocket(tungstenite::Error::ConnectionClosed), _)
+            | Error(ErrorKind::WebSocket(tungstenite::Error::Protocol(_)), _)
+            | Error(ErrorKind::WebSocket(tungstenite::Error::Utf8), _) => (),
+            err => error!("error processing connection: {:?}", err),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub enum ConnectedMessage {
+    AppReady,
+    UpdateCode {
+        code: Code,
+        play: bool,
+        checksum: Option<String>,
+        /// Told when the code was sent to the extension
+        progress_command: Option<hooks::UploadProgress>,
+    },
+    SendCode,
+    /// Check the connection is still alive, dropping it if it isn't
+    Ping,
+    /// Make the IDE editor read-only or editable again
+    SetReadOnly {
+        state: bool,
+    },
+    /// Close the connection, replaced by a newer one with --takeover
+    Close,
+    Terminate,
+}
+
+#[derive(Debug)]
+pub enum WorkerMessage {
+    FileChanged {
+        code: Code,
+        /// Files making up the code with --watch-all-in-dir
+        bundle: Option<Bundle>,
+    },
+    /// The target is over --max-file-size and was not read
+    FileTooLarge {
+        size: u64,
+    },
+    FileDeleted,
+    FileRenamed {
+        new_path: PathBuf,
+    },
+    WatchError {
+        error: std::io::Error,
+    },
+    Start {
+        download: bool,
+    },
+    Stop,
+    /// Stop synchronizing and forget the question, handing the editing back to the IDE
+    Detach,
+    SetTarget {
+        path: PathBuf,
+    },
+    /// Switch to a session of the configuration file
+    SwitchSession {
+        name: String,
+    },
+    /// Auto-play was toggled in the interface
+    PlayToggled {
+        play: bool,
+    },
+    Resumed {
+        gap: std::time::Duration,
+    },
+    /// The notes of the target were written or removed
+    NotesChanged,
+    /// The watcher discarded events using the exclusion rules, sent again only once the count
+    /// was taken
+    EventsFiltered,
+    /// The user wants to quit, answered with `WorkerNotification::QuitCheck`
+    QuitRequested,
+    /// The user opened the history, answered with `WorkerNotification::History`
+    HistoryRequested,
+    /// Upload a version from the history again. Unlike changes of the target, it is always sent.
+    Restore {
+        checksum: String,
+        play: bool,
+        /// Also write the version to the target
+        also_local: bool,
+    },
+    /// The user answered the confirmation of the pending upload. When confirmed, the interface
+    /// sends the code again with `FileChanged`.
+    UploadConfirmed {
+        upload: bool,
+        /// Don't ask again for this session
+        always: bool,
+    },
+    /// The user chose to synchronize although the extension didn't identify the question
+    AcceptUnknownQuestion,
+    /// The user chose between the local changes and the IDE code of a conflicting download
+    DownloadConflictResolved {
+        overwrite: bool,
+    },
+    /// The user chose whether a new connection replaces the open one
+    TakeoverAnswered {
+        takeover: bool,
+    },
+    /// The extension asked for the code, sent by the controller to itself
+    CodeRequested,
+    /// The download hook exited, sent by the controller to itself
+    TargetReleased,
+    Terminate,
+}
+
+impl WorkerMessage {
+    /// Short name of the message, for diagnostics
+    pub fn label(&self) -> &'static str {
+        match self {
+            WorkerMessage::FileChanged { .. } => "FileChanged",
+            WorkerMessage::FileTooLarge { .. } => "FileTooLarge",
+            WorkerMessage::FileDeleted => "FileDeleted",
+            WorkerMessage::FileRenamed { .. } => "FileRenamed",
+            WorkerMessage::WatchError { .. } => "WatchError",
+            WorkerMessage::Start { .. } => "Start",
+            WorkerMessage::Stop => "Stop",
+            WorkerMessage::Detach => "Detach",
+            WorkerMessage::SetTarget { .. } => "SetTarget",
+            WorkerMessage::SwitchSession { .. } => "SwitchSession",
+            WorkerMessage::PlayToggled { .. } => "PlayToggled",
+            WorkerMessage::Resumed { .. } => "Resumed",
+            WorkerMessage::NotesChanged => "NotesChanged",
+            WorkerMessage::EventsFiltered => "EventsFiltered",
+            WorkerMessage::QuitRequested => "QuitRequested",
+            WorkerMessage::HistoryRequested => "HistoryRequested",
+            WorkerMessage::Restore { .. } => "Restore",
+            WorkerMessage::UploadConfirmed { .. } => "UploadConfirmed",
+            WorkerMessage::AcceptUnknownQuestion => "AcceptUnknownQuestion",
+            WorkerMessage::DownloadConflictResolved { .. } => "DownloadConflictResolved",
+            WorkerMessage::TakeoverAnswered { .. } => "TakeoverAnswered",
+            WorkerMessage::CodeRequested => "CodeRequested",
+            WorkerMessage::TargetReleased => "TargetReleased",
+            WorkerMessage::Terminate => "Terminate",
+        }
+    }
+}
+
+/// Download requested from the IDE, until its code arrives
+#[derive(Debug)]
+struct PendingDownload {
+    /// Checksum of the target when the download was requested, `None` if it couldn't be read
+    snapshot: Option<String>,
+}
+
+impl PendingDownload {
+    async fn new(target: Option<&PathBuf>) -> Self {
+        let local = match target {
+            Some(target) => async_std::fs::read_to_string(target).await.ok(),
+            None => None,
+        };
+
+        Self {
+            snapshot: local.as_deref().map(checksum::checksum),
+        }
+    }
+
+    /// Download of IDE edits pulled with --two-way, the target being compared to its contents
+    /// when it was last synchronized
+    fn pull(baseline: &Baseline) -> Self {
+        Self {
+            snapshot: baseline.local_checksum(),
+        }
+    }
+
+    /// Whether the target was saved since the download was requested
+    fn target_changed(&self, local: Option<&str>) -> bool {
+        self.snapshot != local.map(checksum::checksum)
+    }
+}
+
+/// Version of the target retained during the session
+#[derive(Debug)]
+pub struct HistoryEntry {
+    /// Time the version was seen, as HH:MM:SS
+    at: String,
+    bytes: usize,
+    checksum: String,
+}
+
+#[derive(Debug)]
+pub enum WorkerNotification {
+    Listening {
+        addrs: Vec<SocketAddr>,
+        /// Unix domain socket listened on instead, with --bind-unix
+        socket: Option<PathBuf>,
+    },
+    Details {
+        title: String,
+        question_id: i32,
+    },
+    /// The extension moved to another question than the one the target was synchronized with,
+    /// synchronization being stopped
+    QuestionChanged {
+        title: String,
+        question_id: i32,
+    },
+    /// The question of a session started as unknown was identified
+    DetailsUpdated {
+        title: String,
+        question_id: i32,
+    },
+    /// The extension didn't identify the question, synchronizing requires the user's consent
+    DetailsMissing,
+    Initialized,
+    Stopped,
+    /// The session was detached, waiting for a question again
+    Detached,
+    /// Another session of the configuration file is active, its target and auto-play setting
+    /// applied
+    SessionSwitched {
+        name: String,
+    },
+    Error {
+        message: String,
+    },
+    Warning {
+        message: String,
+    },
+    CodePreview {
+        snippet: Code,
+    },
+    /// Versions retained during the session, newest first
+    History {
+        versions: Vec<HistoryEntry>,
+    },
+    /// Whether the upload being processed plays, and why
+    PlayDecided {
+        reason: PlayReason,
+    },
+    /// Changes of the target since the last upload, with --show-diff-in-tui
+    PendingDiff {
+        diff: Option<Diff>,
+    },
+    TargetChanged {
+        path: PathBuf,
+    },
+    Uploaded,
+    /// An upload waits for the confirmation of the user, with --tui-confirm-upload
+    PendingUpload {
+        /// Code as read, sent back with `FileChanged` once confirmed
+        code: Code,
+        /// Size of the code to upload
+        bytes: usize,
+        /// Size change from the previous code
+        delta: i64,
+        /// First changed lines
+        diff: Option<Diff>,
+    },
+    /// The clipboard changed with --input-from-clipboard
+    ClipboardChanged {
+        bytes: usize,
+    },
+    Downloaded {
+        /// Changes made to the local file, if it existed
+        diff: Option<Diff>,
+    },
+    /// The target was saved after the download was requested, the user chooses which code to keep
+    DownloadConflict {
+        /// Changes the IDE code would make to the local file
+        diff: Option<Diff>,
+    },
+    /// Both the target and the IDE code changed with --two-way, the user chooses which code to
+    /// keep
+    SyncConflict {
+        /// Changes the IDE code would make to the local file
+        diff: Option<Diff>,
+    },
+    /// The connection was closed after the extension stayed silent for too long
+    SessionTimeout,
+    /// The target was made read-only or writable again, following the IDE editor
+    ReadOnly {
+        state: bool,
+    },
+    /// A new connection would be denied, the user chooses whether it replaces the open one
+    TakeoverRequested {
+        peer: Peer,
+    },
+    /// A connection of the extension was denied, e.g. from another tab
+    ConnectionDenied {
+        peer: Peer,
+        reason: String,
+    },
+    /// The extension connected
+    Connected {
+        peer: Peer,
+    },
+    /// A connection of the extension ended, e.g. when its tab was closed
+    Disconnected {
+        peer: Peer,
+    },
+    /// The extension connection started or stopped flapping, prompts and starts being suspended
+    /// meanwhile
+    Flapping {
+        flapping: bool,
+    },
+    /// An upload is taking long to reach the extension
+    UploadProgress {
+        progress: SendProgress,
+    },
+    /// Events discarded by the exclusion rules of the watcher so far
+    EventsFiltered {
+        count: u64,
+    },
+    /// Notes of the target, `None` if the file doesn't exist
+    Notes {
+        path: PathBuf,
+        contents: Option<String>,
+    },
+    /// Answer to `WorkerMessage::QuitRequested`: the code to upload before quitting, if it
+    /// changed since the last upload
+    QuitCheck {
+        code: Option<Code>,
+    },
+    Provenance {
+        state: SyncState,
+        last: Option<Provenance>,
+    },
+    Terminate,
+}
+
+#[derive(Debug)]
+pub enum ConnectedNotification {
+    Details {
+        title: String,
+        question_id: i32,
+        /// Connection which identified the question, `None` for --question-id-override
+        connection: Option<ConnectionId>,
+    },
+    Code {
+        code: Code,
+        checksum: Option<String>,
+        connection: ConnectionId,
+    },
+    /// The connection was closed after the extension stayed silent for too long
+    SessionTimeout { connection: ConnectionId },
+    /// The extension didn't identify the question within --details-timeout
+    DetailsTimeout { connection: ConnectionId },
+    /// The extension asked for the current code
+    RequestCode { connection: ConnectionId },
+    /// The IDE editor was locked or unlocked
+    SetReadOnly {
+        state: bool,
+        connection: ConnectionId,
+    },
+}
+
+impl ConnectedNotification {
+    /// Short name of the notification, for diagnostics
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConnectedNotification::Details { .. } => "Details",
+            ConnectedNotification::Code { .. } => "Code",
+            ConnectedNotification::SessionTimeout { .. } => "SessionTimeout",
+            ConnectedNotification::DetailsTimeout { .. } => "DetailsTimeout",
+            ConnectedNotification::RequestCode { .. } => "RequestCode",
+            ConnectedNotification::SetReadOnly { .. } => "SetReadOnly",
+        }
+    }
+}
+
+/// Handling of extension connections
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionSettings {
+    /// Delay without messages after which a connection is closed
+    session_timeout: Option<std::time::Duration>,
+    /// Delay after which a connection that didn't identify the question is reported
+    details_timeout: Option<std::time::Duration>,
+    /// Delay without messages after which the connection is pinged
+    keepalive_interval: Option<std::time::Duration>,
+    /// Connections accepted at once, the others being denied
+    max_connections: usize,
+}
+
+/// Handles shared by `run_accept` with the controller
+pub struct ConnectionHandles {
+    config: Arc<Config>,
+    /// Connection cycles of the extension
+    flap: Arc<Mutex<FlapDetector>>,
+    status: StatusFile,
+    /// Fan-out to the --observers connections
+    observers: Option<Rebroadcaster>,
+}
+
+/// Socket to listen on for the extension
+#[derive(Debug)]
+pub enum Listen {
+    Addr(String),
+    /// Socket inherited from the parent process
+    #[cfg(unix)]
+    Fd(i32),
+    /// Unix domain socket, behind a proxy
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum ListenMessage {
+    /// Answer for the connection held until the user chose whether it takes over
+    Takeover {
+        accept: bool,
+    },
+    Terminate,
+}
+
+async fn run_accept(
+    rx_connected: channel_stats::Receiver<Routed>,
+    mut rx_listen: channel_stats::Receiver<ListenMessage>,
+    tx_conn_notification: channel_stats::Sender<ConnectedNotification>,
+    tx_notification: std::sync::mpsc::Sender<WorkerNotification>,
+    opts: Arc<Opts>,
+    handles: ConnectionHandles,
+) -> Result<()> {
+    let ConnectionHandles {
+        config,
+        flap,
+        status,
+        observers,
+    } = handles;
+    let settings = opts.connection_settings();
+
+    let mut listener = match opts.listen() {
+        Listen::Addr(addr) => Listener::Tcp(bind::listen(&addr, opts.port_fallback).await?),
+        #[cfg(unix)]
+        Listen::Fd(fd) => Listener::Tcp(listen_fd::listener(fd)?),
+        #[cfg(unix)]
+        Listen::Unix(path) => Listener::bind_unix(&path).await?,
+    };
+
+    let addrs: Vec<SocketAddr> = listener.local_addr()?.into_iter().collect();
+    let socket = listener.socket_path();
+    match (&addrs[..], &socket) {
+        (_, Some(path)) => info!("listening on {}", path.display()),
+        (addrs, None) => addrs.iter().for_each(|addr| info!("listening on {}", addr)),
+    }
+
+    if opts.mock {
+        let code = match &opts.target {
+            Some(target) => async_std::fs::read_to_string(target)
+                .await
+                .unwrap_or_default(),
+            None => String::new(),
+        };
+
+        let endpoint = mock::Endpoint {
+            addrs: addrs.clone(),
+            socket: socket.clone(),
+            token: opts.auth_token.clone(),
+        };
+        task::spawn(mock::run(endpoint, opts.mock_question_id, code));
+    }
+
+    tx_notification.send(WorkerNotification::Listening { addrs, socket })?;
+
+    // Each connection gets the messages of the controller addressed to it
+    let router = Arc::new(Mutex::new(Router::new()));
+    task::spawn(connections::run(rx_connected, router.clone()));
+
+    // Connection that would be denied, held until the user chose whether it takes over
+    let mut held: Option<(Peer, WsStream)> = None;
+
+    // Connections upgraded and authenticated, to accept, deny or hold
+    let (tx_admitted, mut rx_admitted) = channel_stats::bounded::<(Peer, WsStream)>("admitted", 1);
+
+    // Connections that couldn't tell the interface, ending the accept loop like the listener
+    let (tx_interface_gone, mut rx_interface_gone) =
+        channel_stats::bounded::<Error>("interface gone", 1);
+
+    let spawn_connection = |peer: Peer, ws_stream: WsStream, connection: Connection| {
+        let cycle = track_cycle(
+            peer,
+            flap.clone(),
+            status.clone(),
+            tx_notification.clone(),
+            accept_connection(
+                peer,
+                ws_stream,
+                connection,
+                tx_conn_notification.clone(),
+                tx_notification.clone(),
+                config.clone(),
+                settings,
+            ),
+        );
+
+        let tx_interface_gone = tx_interface_gone.clone();
+        task::spawn(async move {
+            if let Err(err @ Error(ErrorKind::WorkerNotificationChannel(_), _)) = cycle.await {
+                let _ = tx_interface_gone.send(err).await;
+            }
+        });
+    };
+
+    loop {
+        select! {
+            accepted = listener.accept().fuse() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(_) => break,
+                };
+
+                let (stream, token) = match (stream, peer) {
+                    (Stream::Tcp(stream), Peer::Tcp(addr)) => {
+                        match screen_tcp(addr, stream, &opts, observers.as_ref()).await {
+                            Some((stream, token)) => (Stream::Tcp(stream), token),
+                            None => continue,
+                        }
+                    }
+                    // Only the owner of the socket can connect, through its proxy: the token is
+                    // still required, from the first message
+                    #[cfg(unix)]
+                    (stream, _) => (stream, opts.auth_token.clone()),
+                };
+
+                let tx_admitted = tx_admitted.clone();
+                task::spawn(async move {
+                    match admission::upgrade(peer, stream, token.as_deref()).await {
+                        Ok(Some(ws_stream)) => {
+                            let _ = tx_admitted.send((peer, ws_stream)).await;
+                        }
+                        Ok(None) => {}
+                        Err(err) => debug!("failed to upgrade the connection from {}: {}", peer, err),
+                    }
+                });
+            },
+
+            admitted = rx_admitted.next().fuse() => {
+                let (peer, ws_stream) = match admitted {
+                    Some(admitted) => admitted,
+                    None => break,
+                };
+
+                let registered = {
+                    let mut router = router.lock().await;
+                    if opts.takeover {
+                        Some(take_over(&mut router, peer, settings.max_connections))
+                    } else if router.len() < settings.max_connections {
+                        Some(router.register(peer))
+                    } else {
+                        None
+                    }
+                };
+
+                match registered {
+                    Some(connection) => {
+                        if settings.max_connections > 1 {
+                            info!("{} active extension connection(s)", router.lock().await.len());
+                        }
+
+                        spawn_connection(peer, ws_stream, connection);
+                    }
+                    None if !opts.no_gui && held.is_none() => {
+                        info!("holding connection from {} until the user chose whether it takes over", peer);
+                        held = Some((peer, ws_stream));
+                        tx_notification.send(WorkerNotification::TakeoverRequested { peer })?;
+                    }
+                    None => {
+                        let reason = match held {
+                            Some(_) => messages::denied_takeover_pending(),
+                            None => messages::denied_connections_full(settings.max_connections),
+                        };
+                        tx_notification.send(WorkerNotification::ConnectionDenied { peer, reason })?;
+                        task::spawn(deny_connection(peer, ws_stream));
+                    }
+                }
+            },
+
+            listen = rx_listen.next().fuse() => {
+                match listen {
+                    Some(ListenMessage::Takeover { accept }) => {
+                        if let Some((peer, ws_stream)) = held.take() {
+                            if accept {
+                                let connection = take_over(&mut *router.lock().await, peer, settings.max_connections);
+                                spawn_connection(peer, ws_stream, connection);
+                            } else {
+                                task::spawn(deny_connection(peer, ws_stream));
+                            }
+                        }
+                    }
+                    None | Some(ListenMessage::Terminate) => { break; }
+                }
+            },
+
+            gone = rx_interface_gone.next().fuse() => {
+                if let Some(err) = gone {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the extension should connect, to the listener or to the proxy of the Unix domain socket
+fn extension_settings(addrs: &[SocketAddr], socket: Option<&Path>) -> String {
+    match socket {
+        Some(path) => messages::extension_settings_unix(&path.display().to_string()),
+        None => messages::extension_settings(addrs, EXTENSION_DEFAULT_PORT),
+    }
+}
+
+/// Check a TCP connection before upgrading it: its address, token and origin, handing observers
+/// over to the rebroadcaster. Returns the stream to upgrade with the token expected as its first
+/// message, or `None` if it was handled.
+async fn screen_tcp(
+    peer: SocketAddr,
+    stream: TcpStream,
+    opts: &Opts,
+    observers: Option<&Rebroadcaster>,
+) -> Option<(TcpStream, Option<String>)> {
+    // Closed before anything is read from it
+    if !admission::is_allowed(&opts.allow_ip, &peer) {
+        warn!("{}", messages::peer_not_allowed(&peer.to_string()));
+        return None;
+    }
+
+    let request = if !opts.no_origin_check || opts.auth_token.is_some() {
+        admission::peek_request(&stream).await
+    } else {
+        admission::PeekedRequest::default()
+    };
+
+    // Given in the request, or expected as the first message
+    let token = match (&opts.auth_token, &request.token) {
+        (Some(expected), Some(token)) if !admission::token_matches(expected, token) => {
+            warn!("{}", messages::auth_failed(&peer.to_string()));
+            task::spawn(admission::reject(peer, stream, "invalid token".to_owned()));
+            return None;
+        }
+        (Some(expected), None) => Some(expected.clone()),
+        _ => None,
+    };
+
+    if let (true, Some(observers)) = (opts.observers > 0, observers) {
+        if rebroadcast::is_observer_request(&stream).await {
+            if token.is_some() {
+                warn!("{}", messages::auth_failed(&peer.to_string()));
+                task::spawn(admission::reject(peer, stream, "missing token".to_owned()));
+            } else if let Err(stream) = observers.observe(peer, stream, opts.observers).await {
+                info!(
+                    "refusing observer {}, {} already connected",
+                    peer, opts.observers
+                );
+                task::spawn(async move {
+                    let ws_stream = async_tungstenite::accept_async(Stream::Tcp(stream)).await?;
+                    deny_connection(Peer::Tcp(peer), ws_stream).await
+                });
+            }
+            return None;
+        }
+    }
+
+    if !opts.no_origin_check {
+        let mut extension_ids: Vec<&str> = opts.extension_id.iter().map(String::as_str).collect();
+        // The fake extension is always let through
+        if opts.mock && !extension_ids.is_empty() {
+            extension_ids.push(mock::EXTENSION_ID);
+        }
+
+        if let Some(reason) = request.rejection(&extension_ids) {
+            warn!(
+                "{}",
+                messages::connection_rejected(&peer.to_string(), &reason)
+            );
+            task::spawn(admission::reject(peer, stream, reason));
+            return None;
+        }
+
+        if let (true, Some(id)) = (
+            extension_ids.is_empty(),
+            request.origin.as_deref().and_then(admission::extension_id),
+        ) {
+            info!("{}", messages::extension_not_pinned(id));
+        }
+    }
+
+    Some((stream, token))
+}
+
+/// Register the connection from `peer`, closing the oldest open connections to keep at most
+/// `max_connections` of them
+fn take_over(router: &mut Router, peer: Peer, max_connections: usize) -> Connection {
+    while router.len() >= max_connections {
+        match router.take_over() {
+            Some((id, old)) => info!(
+                "closing connection {} from {}, taken over by {}",
+                id, old, peer
+            ),
+            None => break,
+        }
+    }
+
+    router.register(peer)
+}
+
+/// Handle the messages of the watcher, the interface and the connections until terminated.
+///
+/// Operations on the target run one at a time: a download writes it and its hook, which may
+/// rewrite it, runs to completion before any upload. Changes seen meanwhile are folded into a
+/// single upload once the hook exited, and an upload always sends the contents of the target
+/// when it is processed rather than when the change was seen. The last upload therefore carries
+/// the final contents of the target, whatever the interleaving of saves, downloads and hooks.
+async fn run_controller(
+    mut state: State,
+    tx_connected: channel_stats::Sender<Routed>,
+    tx_listen: channel_stats::Sender<ListenMessage>,
+    rx_controller: channel_stats::Receiver<WorkerMessage>,
+    tx_notification: std::sync::mpsc::Sender<WorkerNotification>,
+    mut rx_conn_notification: channel_stats::Receiver<ConnectedNotification>,
+) -> Result<()> {
+    let mut pending_download: Option<PendingDownload> = None;
+    // Cleared when the target is deleted, uploads are rejected until it reappears
+    let mut file_exists = true;
+    // Contents of the target at the last uploads, before transformations
+    let mut uploaded = Retained::new(state.opts.retention_policy());
+    let mut provenance = Tracker::new();
+    // Set once the extension sent a checksum along with its code
+    let mut echoes_checksums = false;
+    // Set while synchronizing a question the extension didn't identify, the question of the
+    // context staying empty so nothing is recorded against it
+    let mut unknown_question = false;
+    let mut play_gate = PlayGate::default();
+    // Cleared when the user chooses to always upload
+    let mut confirm_uploads = state.opts.tui_confirm_upload && !state.opts.no_gui;
+    // Code waiting for the user to confirm its upload, and code confirmed but not uploaded yet
+    let mut pending_upload: Option<String> = None;
+    let mut confirmed_upload: Option<String> = None;
+    // Play flag of the version being restored
+    let mut restore: Option<bool> = None;
+    let mut dedup = Some(state.opts.upload_dedup_window)
+        .filter(|secs| *secs > 0)
+        .map(|secs| DedupWindow::new(std::time::Duration::from_secs(secs)));
+    let mut summary = if state.opts.no_stats {
+        None
+    } else {
+        Some(SessionSummary::new())
+    };
+    // Question identified while the connection was flapping, prompted for once it is stable
+    let mut deferred_details: Option<(i32, String)> = None;
+    // Messages of the controller to itself, unbounded as it can't wait for itself to receive them
+    let (tx_internal, rx_internal) = channel::unbounded();
+    let mut rx_controller = futures::stream::select(rx_controller, rx_internal);
+    // Set while the download hook runs, and when the target changed meanwhile
+    let mut target_busy = false;
+    let mut folded_change = false;
+    // Set when the extension asked for the code, until it is uploaded
+    let mut code_requested = false;
+    // Set once the session is detached, until the extension identifies a question again
+    let mut detached = false;
+    // The first details come from --question-id-override, the question is overridden until the
+    // extension identifies one
+    let mut override_pending = state.opts.question_override().is_some();
+    let mut overridden = false;
+    // Connection which identified the question, receiving the messages for the extension
+    let mut connection: Option<ConnectionId> = None;
+    // Set while synchronizing, from Start until stopped
+    let mut syncing = false;
+    // Question the target was last synchronized with, until another target is watched
+    let mut target_question: Option<i32> = None;
+    // Target and IDE code when they were last synchronized, and the next request of the IDE code,
+    // with --two-way
+    let mut baseline = Baseline::new();
+    let mut next_pull: Option<std::time::Instant> = None;
+    let pull_interval = state.opts.ide_poll_interval();
+
+    // A closed notification channel means the interface is gone, which ends the session like
+    // Terminate so the worker doesn't go on without anyone to report to
+    let result: Result<()> = async {
+        loop {
+            // While flapping, wait for the connection to stay up, checking again after reconnections
+            let flap_check = {
+                let flap = state.flap.lock().await;
+                until(match flap.stable_at() {
+                    Some(at) => Some(at),
+                    None if flap.is_flapping() => Some(std::time::Instant::now() + FLAP_POLL),
+                    None => None,
+                })
+            };
+
+            let pull_check = until(next_pull);
+
+            select! {
+                msg = rx_controller.next().fuse() => {
+                    trace!("msg: {:?}", msg);
+
+                    if let Some(msg) = msg {
+                        let label = msg.label();
+
+                        // A restored version goes through the same checks as a change of the target
+                        let msg = match msg {
+                            WorkerMessage::Restore { checksum, play, also_local } => match uploaded.find(&checksum).map(str::to_owned) {
+                                Some(code) => {
+                                    info!("{}", messages::restoring_version(&checksum[..8]));
+
+                                    let target = state.dynamic.lock().await.target.clone();
+                                    if let (true, Some(target)) = (also_local, target) {
+                                        // Its change events must not upload it again
+                                        state.watcher.own_writes().record(&code);
+                                        if let Err(err) = write_atomically(&target, &code).await {
+                                            state.report_error(&tx_notification, messages::write_failed(&target.display().to_string(), &err.to_string()))?;
+                                        }
+                                        state.relock_target().await;
+                                    }
+
+                                    restore = Some(play);
+                                    WorkerMessage::FileChanged { code: code.into(), bundle: None }
+                                }
+                                None => {
+                                    state.report_error(&tx_notification, messages::version_not_retained())?;
+                                    state.heartbeat.beat(label);
+                                    continue;
+                                }
+                            },
+                            WorkerMessage::CodeRequested => match state.current_code().await {
+                                Some(code) => {
+                                    code_requested = true;
+                                    WorkerMessage::FileChanged { code: code.into(), bundle: None }
+                                }
+                                None => {
+                                    state.report_error(&tx_notification, messages::requested_code_unavailable())?;
+                                    state.heartbeat.beat(label);
+                                    continue;
+                                }
+                            },
+                            WorkerMessage::TargetReleased => {
+                                target_busy = false;
+                                let code = match folded_change {
+                                    true => state.current_code().await,
+                                    false => None,
+                                };
+                                folded_change = false;
+
+                                match code {
+                                    Some(code) => {
+                                        debug!("download hook done, uploading the changes made meanwhile");
+                                        WorkerMessage::FileChanged { code: code.into(), bundle: None }
+                                    }
+                                    None => {
+                                        state.heartbeat.beat(label);
+                                        continue;
+                                    }
+                                }
+                            }
+                            msg => msg,
+                        };
+
+                        match msg {
+                            WorkerMessage::FileChanged { code, bundle } => {
+                                trace!("controller: file changed");
+
+                                let code = code.into_string();
+                                let restore = restore.take();
+                                if detached {
+                                    debug!("detached, not uploading");
+                                    state.heartbeat.beat(label);
+                                    continue;
+                                }
+
+                                // Until synchronization starts on the question the extension moved to
+                                let question_id = state.context.question.as_ref().map(|(question_id, _)| *question_id);
+                                if target_question.is_some() && target_question != question_id {
+                                    debug!("the target is for another question, not uploading");
+                                    state.heartbeat.beat(label);
+                                    continue;
+                                }
+
+                                if !state.opts.expects_question(question_id) {
+                                    debug!("not an expected question, not uploading");
+                                    state.heartbeat.beat(label);
+                                    continue;
+                                }
+
+                                if state.oversized {
+                                    info!("{}", messages::file_size_ok(&state.dynamic.lock().await.source_label()));
+                                    state.oversized = false;
+                                }
+
+                                if let Some(Bundle { trigger, files }) = bundle {
+                                    info!("{} changed, uploading {} files", trigger.display(), files.len());
+                                    let files: Vec<PathBuf> = files.into_iter().map(PathBuf::from).collect();
+                                    if files != state.bundle_files {
+                                        debug!("bundling {}", files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>().join(", "));
+                                        state.bundle_files = files;
+                                    }
+                                }
+
+                                if target_busy && restore.is_none() {
+                                    debug!("download hook running, uploading the change once it exits");
+                                    folded_change = true;
+                                    state.heartbeat.beat(label);
+                                    continue;
+                                }
+
+                                if !file_exists {
+                                    // Events queued before the deletion are stale
+                                    let target = state.dynamic.lock().await.target().clone();
+                                    if !target.exists().await {
+                                        debug!("ignoring change of deleted target");
+                                        state.heartbeat.beat(label);
+                                        continue;
+                                    }
+
+                                    info!("{}", messages::target_reappeared());
+                                    file_exists = true;
+                                }
+
+                                // Queued events may predate a download or a later save
+                                let target = state.dynamic.lock().await.target.clone();
+                                let current = match (restore, target) {
+                                    (Some(_), _) | (None, None) => None,
+                                    (None, Some(target)) => {
+                                        let lock = match state.lock_target(&tx_notification, &target, Access::Shared).await? {
+                                            Some(lock) => lock,
+                                            None => {
+                                                state.heartbeat.beat(label);
+                                                continue;
+                                            }
+                                        };
+
+                                        let current = state.current_code().await;
+                                        drop(lock);
+                                        current
+                                    }
+                                };
+                                let code = match current {
+                                    Some(current) if current != code => {
+                                        if !state.opts.watch_all_in_dir && state.watcher.own_writes().check(&current) {
+                                            debug!("the target was written by the app since the event, not uploading it back");
+                                            state.heartbeat.beat(label);
+                                            continue;
+                                        }
+
+                                        debug!("the target changed since the event, uploading its current contents");
+                                        current
+                                    }
+                                    _ => code,
+                                };
+
+                                // Don't hold the options lock while waiting on the connection
+                                let dynamic = state.dynamic.lock().await.clone();
+                                // Every output of this upload describes the same question and target
+                                let context = state.context.clone();
+                                let (play, target) = (dynamic.play, dynamic.target.clone());
+                                let opts = state.opts.clone();
+                                let pipeline = Pipeline::new(&opts, &dynamic, &state.config.transforms, state.copyright.as_ref(), state.wrapper.as_ref());
+
+                                if let Some(preview_lines) = opts.preview_lines() {
+                                    tx_notification.send(WorkerNotification::CodePreview { snippet: code_preview(&code, preview_lines).into() })?;
+                                }
+
+                                // Repeated events of a single save would replace the diff with an empty one
+                                if opts.shows_diff() && uploaded.latest() != Some(code.as_str()) {
+                                    let diff = diff::diff(uploaded.latest().unwrap_or_default(), &code, DiffOptions { max_lines: DIFF_PANE_LINES, ..DiffOptions::default() });
+                                    tx_notification.send(WorkerNotification::PendingDiff { diff })?;
+                                }
+
+                                if opts.upload_on_ide_request && restore.is_none() && !code_requested {
+                                    debug!("not uploading until the extension requests the code");
+                                    provenance.changed(Side::Local);
+                                    notify_provenance(&tx_notification, &provenance)?;
+                                    state.heartbeat.beat(label);
+                                    continue;
+                                }
+                                let requested = std::mem::take(&mut code_requested);
+
+                                let decision = play_gate.decide(restore.unwrap_or(play));
+                                debug!("{}", messages::play_reason(decision.reason));
+                                if let (PlayReason::Flagged, Some((_, title))) = (decision.reason, &context.question) {
+                                    tx_notification.send(WorkerNotification::Warning { message: messages::play_policy_warning(title) })?;
+                                }
+                                tx_notification.send(WorkerNotification::PlayDecided { reason: decision.reason })?;
+                                let play = decision.play;
+
+                                // Code to confirm the upload of, with the previous code to show the changes
+                                let confirm = if restore.is_none() && !requested && confirm_uploads && confirmed_upload.as_deref() != Some(code.as_str()) {
+                                    Some((code.clone(), uploaded.latest().unwrap_or_default().to_owned()))
+                                } else {
+                                    None
+                                };
+                                confirmed_upload = None;
+
+                                let preprocessed = pipeline.run(&code, context.question.as_ref()).await;
+                                for error in preprocessed.errors {
+                                    state.report_error(&tx_notification, error)?;
+                                }
+                                // The code of the target is retained once uploaded, not what the pipeline made of it
+                                let (original, code) = (code, preprocessed.code);
+
+                                if let Some(emit) = &opts.emit {
+                                    let emit = state.emit_path(emit, context.question.as_ref().map(|(_, title)| title.as_str()), opts.slug_max_length);
+                                    match write_atomically(&emit, &code).await {
+                                        Ok(()) => debug!("wrote preprocessed code to {}", emit.display()),
+                                        Err(err) => state.report_error(&tx_notification, messages::write_failed(&emit.display().to_string(), &err.to_string()))?,
+                                    }
+                                }
+
+                                let invalid_json = if opts.upload_validate_json && target.as_ref().map(|target| is_json_file(target.as_ref())).unwrap_or(false) {
+                                    let started = std::time::Instant::now();
+                                    let result = serde_json::from_str::<serde_json::Value>(&code);
+                                    trace!("validated JSON in {:?}", started.elapsed());
+                                    result.err()
+                                } else {
+                                    None
+                                };
+
+                                let lines = code.lines().count();
+                                if let Some(err) = invalid_json {
+                                    metrics::record_upload_error();
+                                    // The location is reported separately
+                                    let error = err.to_string();
+                                    let error = error.trim_end_matches(&format!(" at line {} column {}", err.line(), err.column()));
+                                    state.report_error(&tx_notification, messages::invalid_json(err.line(), err.column(), error))?;
+
+                                    provenance.changed(Side::Local);
+                                    notify_provenance(&tx_notification, &provenance)?;
+                                } else if opts.no_server {
+                                    trace!("controller: no server, not uploading");
+                                } else if opts.code_max_lines > 0 && lines > opts.code_max_lines {
+                                    metrics::record_upload_error();
+                                    state.report_error(&tx_notification, messages::too_many_lines(lines, opts.code_max_lines))?;
+
+                                    provenance.changed(Side::Local);
+                                    notify_provenance(&tx_notification, &provenance)?;
+                                } else if let Some((original, previous)) = confirm {
+                                    debug!("waiting for the user to confirm the upload");
+                                    tx_notification.send(WorkerNotification::PendingUpload {
+                                        code: original.clone().into(),
+                                        bytes: code.len(),
+                                        delta: original.len() as i64 - previous.len() as i64,
+                                        diff: diff::diff(&previous, &original, DiffOptions { context: 0, max_lines: CONFIRM_DIFF_LINES }),
+                                    })?;
+                                    pending_upload = Some(original);
+
+                                    provenance.changed(Side::Local);
+                                    notify_provenance(&tx_notification, &provenance)?;
+                                } else if restore.is_none() && !requested && dedup.as_mut().map(|dedup| dedup.contains(&original)).unwrap_or(false) {
+                                    debug!("not uploading, the same code was uploaded less than {}s ago", state.opts.upload_dedup_window);
+
+                                    // The IDE may have received other code since
+                                    provenance.changed(Side::Local);
+                                    notify_provenance(&tx_notification, &provenance)?;
+                                } else {
+                                    let bytes = code.len();
+                                    let started = std::time::Instant::now();
+                                    let code_checksum = checksum::checksum(&code);
+                                    let checksum = if opts.upload_checksum { Some(code_checksum.clone()) } else { None };
+                                    if let Some(rebroadcaster) = &state.rebroadcaster {
+                                        rebroadcaster.send(&ServerMessage::UpdateCode { code: code.clone().into(), play, checksum: checksum.clone() }).await;
+                                    }
+
+                                    let progress_command = match &state.opts.upload_progress_command {
+                                        Some(command) => {
+                                            let env = state.hook_env(&context).await.var("CGLOCAL_BYTES", bytes);
+                                            Some(hooks::UploadProgress::start(command, env))
+                                        }
+                                        None => None,
+                                    };
+
+                                    tx_connected.send(Routed::to(connection, ConnectedMessage::UpdateCode { code: code.into(), play, checksum, progress_command })).await?;
+                                    metrics::record_upload(bytes, started.elapsed());
+                                    state.watcher.own_writes().uploaded(&original);
+                                    state.status.uploaded();
+                                    tx_notification.send(WorkerNotification::Uploaded)?;
+
+                                    if pull_interval.is_some() {
+                                        baseline.synchronized(Some(checksum::checksum(&original)), code_checksum.clone());
+                                    }
+
+                                    if let Some(dedup) = &mut dedup {
+                                        dedup.record(&original);
+                                    }
+
+                                    // Only code the extension received is compared against, offered again or restored
+                                    let evicted = uploaded.push(original);
+                                    if !evicted.is_empty() {
+                                        debug!("dropped {} old payload(s), retaining {} ({} bytes)", evicted.len(), uploaded.len(), uploaded.retained_bytes());
+                                    }
+                                    metrics::record_retained_bytes(uploaded.retained_bytes());
+                                    provenance.synchronized(Side::Local, code_checksum);
+                                    notify_provenance(&tx_notification, &provenance)?;
+
+                                    #[cfg(feature = "webhook-notifications")]
+                                    state.notify_webhooks("upload", context.question.as_ref(), bytes);
+
+                                    let env = state.hook_env(&context).await.var("CGLOCAL_BYTES", bytes);
+                                    if play {
+                                        state.config.hooks.fire(HookEvent::Play, env.clone());
+                                    }
+                                    state.config.hooks.fire(HookEvent::Upload, env);
+
+                                    if let (Some(summary), Some((question_id, title))) = (&mut summary, &context.question) {
+                                        summary.record_upload(*question_id, title, bytes, play);
+                                        summary.maybe_flush();
+                                    }
+                                }
+
+                                trace!("controller: file changed end");
+                            }
+                            WorkerMessage::FileTooLarge { size } => {
+                                trace!("controller: file too large");
+
+                                state.file_too_large(&tx_notification, size).await?;
+
+                                trace!("controller: file too large end");
+                            }
+                            WorkerMessage::FileDeleted => {
+                                trace!("controller: file deleted");
+
+                                let target = state.dynamic.lock().await.target().clone();
+
+                                match (uploaded.latest(), state.opts.recreate_target_on_delete) {
+                                    (Some(code), true) => {
+                                        state.watcher.own_writes().record(code);
+                                        match async_std::fs::write(&target, code).await {
+                                            Ok(()) => {
+                                                info!("{}", messages::target_recreated());
+                                                state.relock_target().await;
+                                            }
+                                            Err(err) => {
+                                                file_exists = false;
+                                                state.report_error(&tx_notification, format!("{}: {}", messages::target_deleted(), err))?;
+                                            }
+                                        }
+                                    }
+                                    _ => {
+                                        file_exists = false;
+                                        state.report_error(&tx_notification, messages::target_deleted())?;
+                                    }
+                                }
+
+                                trace!("controller: file deleted end");
+                            }
+                            WorkerMessage::FileRenamed { new_path } => {
+                                trace!("controller: file renamed");
+
+                                if state.opts.follow_renames {
+                                    if state.change_target(&tx_notification, new_path).await? {
+                                        baseline = Baseline::new();
+                                    }
+                                } else {
+                                    warn!("{}", messages::target_renamed(&new_path.display().to_string()));
+                                }
+
+                                trace!("controller: file renamed end");
+                            }
+                            WorkerMessage::WatchError { error } => {
+                                warn!("file watcher error: {}", error);
+                            }
+                            WorkerMessage::Start { .. } if !state.opts.expects_question(state.context.question.as_ref().map(|(question_id, _)| *question_id)) => {
+                                tx_notification.send(WorkerNotification::Warning { message: unexpected_question(&state.opts, state.context.question.as_ref()) })?;
+                            }
+                            WorkerMessage::Start { download } => {
+                                trace!("controller: start");
+
+                                // Update local file if download was requested, unless it is saved before the
+                                // IDE code arrives
+                                pending_download = match download {
+                                    true => Some(PendingDownload::new(state.dynamic.lock().await.target.as_ref()).await),
+                                    false => None,
+                                };
+
+                                // We are now ready
+                                tx_connected.send(Routed::to(connection, ConnectedMessage::AppReady)).await?;
+                                state.status.started();
+
+                                // Edits go through the target only
+                                if state.opts.lock_ide {
+                                    set_ide_read_only(&tx_connected, connection, true).await?;
+                                }
+
+                                // Without a download, the first IDE code received is the reference
+                                syncing = true;
+                                target_question = state.context.question.as_ref().map(|(question_id, _)| *question_id);
+                                baseline = Baseline::new();
+                                next_pull = pull_interval.map(|interval| std::time::Instant::now() + interval);
+
+                                // Notify UI
+                                tx_notification.send(WorkerNotification::Initialized)?;
+
+                                let target = state.dynamic.lock().await.target.clone();
+                                let (preview_lines, shows_diff) = (state.opts.preview_lines(), state.opts.shows_diff());
+
+                                if let Some(target) = target {
+                                    // Also reports a target over --max-file-size before its first save
+                                    let code = state.read_target(&tx_notification, &target).await?;
+                                    if let (Some(preview_lines), Some(code)) = (preview_lines, &code) {
+                                        tx_notification.send(WorkerNotification::CodePreview { snippet: code_preview(code, preview_lines).into() })?;
+                                    }
+
+                                    // Changes made while synchronization was stopped
+                                    if let (true, Some(latest), Some(code)) = (shows_diff, uploaded.latest(), &code) {
+                                        tx_notification.send(WorkerNotification::PendingDiff { diff: diff::diff(latest, code, DiffOptions { max_lines: DIFF_PANE_LINES, ..DiffOptions::default() }) })?;
+                                    }
+                                }
+
+                                trace!("controller: start end");
+                            }
+                            WorkerMessage::Stop => {
+                                trace!("controller: stop");
+
+                                // Discard any notifications from IDE
+                                pending_download = None;
+                                syncing = false;
+                                next_pull = None;
+
+                                state.status.stopped();
+
+                                if state.opts.lock_ide {
+                                    set_ide_read_only(&tx_connected, connection, false).await?;
+                                }
+
+                                // Notify UI
+                                tx_notification.send(WorkerNotification::Stopped)?;
+
+                                trace!("controller: stop end");
+                            }
+                            WorkerMessage::Detach => {
+                                trace!("controller: detach");
+
+                                set_ide_read_only(&tx_connected, connection, false).await?;
+                                state.set_read_only(&tx_notification, false).await?;
+
+                                // The next question starts from scratch, even if it is the same one
+                                pending_download = None;
+                                state.set_question(None);
+                                unknown_question = false;
+                                deferred_details = None;
+                                play_gate = PlayGate::default();
+                                confirm_uploads = state.opts.tui_confirm_upload && !state.opts.no_gui;
+                                pending_upload = None;
+                                confirmed_upload = None;
+                                restore = None;
+                                state.watcher.own_writes().clear();
+                                uploaded = Retained::new(state.opts.retention_policy());
+                                metrics::record_retained_bytes(0);
+                                provenance = Tracker::new();
+                                notify_provenance(&tx_notification, &provenance)?;
+                                if let Some(dedup) = &mut dedup {
+                                    *dedup = DedupWindow::new(std::time::Duration::from_secs(state.opts.upload_dedup_window));
+                                }
+                                folded_change = false;
+                                code_requested = false;
+                                syncing = false;
+                                baseline = Baseline::new();
+                                next_pull = None;
+                                detached = true;
+                                overridden = false;
+                                state.status.detached();
+
+                                info!("{}", messages::session_detached());
+                                tx_notification.send(WorkerNotification::Detached)?;
+
+                                trace!("controller: detach end");
+                            }
+                            WorkerMessage::SetTarget { path } => {
+                                trace!("controller: set target");
+
+                                if state.change_target(&tx_notification, path).await? {
+                                    baseline = Baseline::new();
+                                    target_question = None;
+                                }
+
+                                trace!("controller: set target end");
+                            }
+                            WorkerMessage::SwitchSession { name } => {
+                                trace!("controller: switch session");
+
+                                if let Some(session) = state.switch_session(&tx_notification, name).await? {
+                                    // Synchronization stops, resuming from the question screen
+                                    if state.opts.lock_ide && syncing {
+                                        set_ide_read_only(&tx_connected, connection, false).await?;
+                                    }
+                                    pending_download = None;
+                                    syncing = false;
+                                    baseline = Baseline::new();
+                                    next_pull = None;
+                                    target_question = None;
+                                    state.status.stopped();
+
+                                    match &state.context.question {
+                                        Some((question_id, title)) if session.matches(*question_id) => {
+                                            debug!("already on the question of the session");
+                                            tx_notification.send(WorkerNotification::Details { title: title.clone(), question_id: *question_id })?;
+                                        }
+                                        _ if state.context.question.is_some() || unknown_question => {
+                                            // Nothing is uploaded to the previous question meanwhile
+                                            detached = true;
+                                            tx_internal.send(WorkerMessage::Detach).await?;
+                                        }
+                                        _ => {}
+                                    }
+                                }
+
+                                trace!("controller: switch session end");
+                            }
+                            WorkerMessage::PlayToggled { play } => {
+                                if let (true, PlayPolicy::Deny, Some((_, title))) = (play, play_gate.policy(), &state.context.question) {
+                                    tx_notification.send(WorkerNotification::Warning { message: messages::play_policy_denied(title) })?;
+                                }
+                            }
+                            WorkerMessage::Resumed { gap } => {
+                                trace!("controller: resumed after {:?}", gap);
+
+                                // Only reaches a live connection, otherwise waits for the next one
+                                // which is just as harmless
+                                if let Err(err) = tx_connected.try_send(Routed::all(ConnectedMessage::Ping)) {
+                                    debug!("not pinging connection: {}", err);
+                                }
+
+                                trace!("controller: resumed end");
+                            }
+                            WorkerMessage::NotesChanged => {
+                                state.notify_notes(&tx_notification).await?;
+                            }
+                            WorkerMessage::EventsFiltered => {
+                                let count = state.watcher.take_filtered_count();
+                                tx_notification.send(WorkerNotification::EventsFiltered { count })?;
+                            }
+                            WorkerMessage::QuitRequested => {
+                                trace!("controller: quit requested");
+
+                                // Only an ongoing synchronization has changes to upload
+                                let code = match (state.context.question.is_some() || unknown_question, state.current_code().await) {
+                                    (true, Some(code)) if uploaded.latest() != Some(code.as_str()) => Some(code.into()),
+                                    _ => None,
+                                };
+
+                                tx_notification.send(WorkerNotification::QuitCheck { code })?;
+
+                                trace!("controller: quit requested end");
+                            }
+                            WorkerMessage::HistoryRequested => {
+                                let versions = uploaded
+                                    .versions()
+                                    .into_iter()
+                                    .map(|(at, code)| HistoryEntry { at: at.format("%H:%M:%S").to_string(), bytes: code.len(), checksum: checksum::checksum(code) })
+                                    .collect();
+                                tx_notification.send(WorkerNotification::History { versions })?;
+                            }
+                            WorkerMessage::Restore { .. } | WorkerMessage::CodeRequested | WorkerMessage::TargetReleased => unreachable!("handled as file changes"),
+                            WorkerMessage::UploadConfirmed { upload, always } => {
+                                if always {
+                                    info!("uploading without confirmation from now on");
+                                    confirm_uploads = false;
+                                }
+
+                                // The interface sends the code again, it only goes through if it is
+                                // still the confirmed one
+                                if upload {
+                                    confirmed_upload = pending_upload.take();
+                                } else {
+                                    debug!("upload skipped by the user");
+                                    pending_upload = None;
+                                }
+                            }
+                            WorkerMessage::DownloadConflictResolved { overwrite } => {
+                                if overwrite {
+                                    // The IDE code may have changed too, ask for it again with the target as
+                                    // it is now as reference
+                                    debug!("overwriting the local changes, requesting the IDE code again");
+                                    pending_download = Some(PendingDownload::new(state.dynamic.lock().await.target.as_ref()).await);
+                                    tx_connected.send(Routed::to(connection, ConnectedMessage::SendCode)).await?;
+                                } else {
+                                    info!("{}", messages::download_conflict_kept_local());
+
+                                    // The IDE gets the local changes, so both sides agree again
+                                    if let (true, Some(code)) = (state.opts.two_way && syncing, state.current_code().await) {
+                                        tx_internal.send(WorkerMessage::FileChanged { code: code.into(), bundle: None }).await?;
+                                    }
+                                }
+                            }
+                            WorkerMessage::AcceptUnknownQuestion if !state.opts.expects_question(None) => {
+                                tx_notification.send(WorkerNotification::Warning { message: unexpected_question(&state.opts, None) })?;
+                            }
+                            WorkerMessage::AcceptUnknownQuestion => {
+                                if state.context.question.is_none() && !unknown_question {
+                                    unknown_question = true;
+                                    notify_unknown_question(&tx_notification)?;
+                                }
+                            }
+                            WorkerMessage::TakeoverAnswered { takeover } => {
+                                tx_listen.send(ListenMessage::Takeover { accept: takeover }).await?;
+                            }
+                            WorkerMessage::Terminate => {
+                                break;
+                            }
+                        }
+
+                        state.heartbeat.beat(label);
+                    } else {
+                        break;
+                    }
+                },
+
+                msg = rx_conn_notification.next().fuse() => {
+                    if let Some(msg) = msg {
+                        let label = msg.label();
+
+                        match msg {
+                            ConnectedNotification::Details { title, question_id, connection: from } => {
+                                trace!("controller: details");
+
+                                if let Some(from) = from {
+                                    if connection.is_some_and(|connection| connection != from) {
+                                        info!("{}", messages::connection_switched(&from.to_string()));
+                                    }
+                                    connection = Some(from);
+                                }
+
+                                detached = false;
+                                state.status.question(Some(question_id));
+
+                                if override_pending {
+                                    override_pending = false;
+                                    overridden = true;
+                                } else if overridden {
+                                    overridden = false;
+                                    match &state.context.question {
+                                        Some((override_id, override_title)) if *override_id != question_id || *override_title != title => {
+                                            info!("{}", messages::question_override_superseded(override_title, *override_id, &title, question_id));
+                                        }
+                                        _ => {}
+                                    }
+                                }
+
+                                // The extension moved to another question in the same tab: the target is
+                                // not synchronized with it blindly
+                                let changed = match &state.context.question {
+                                    Some((previous_id, previous_title)) if !overridden && *previous_id != question_id => {
+                                        info!("{}", messages::question_changed(previous_title, *previous_id, &title, question_id));
+                                        true
+                                    }
+                                    _ => false,
+                                };
+
+                                if changed && syncing {
+                                    info!("{}", messages::sync_stopped());
+                                    if state.opts.lock_ide {
+                                        set_ide_read_only(&tx_connected, connection, false).await?;
+                                    }
+                                    pending_download = None;
+                                    syncing = false;
+                                    baseline = Baseline::new();
+                                    next_pull = None;
+                                    state.status.stopped();
+                                }
+
+                                // Its own target with --map, or if a session is for it
+                                let session = state.dynamic.lock().await.session.clone();
+                                if let (false, Some(target)) = (syncing, question_map::target_for(&state.opts.map, question_id)) {
+                                    if state.change_target(&tx_notification, target.to_owned().into()).await? {
+                                        baseline = Baseline::new();
+                                        target_question = None;
+                                    }
+                                } else if let (true, Some(name)) = (changed, state.config.session_for(question_id).map(str::to_owned)) {
+                                    if session.as_ref() != Some(&name) && state.switch_session(&tx_notification, name).await?.is_some() {
+                                        target_question = None;
+                                    }
+                                }
+
+                                // Notify the UI we now have a question, with the notes first so its
+                                // question screen offers them. A session started on an unknown question
+                                // goes on with the identified one.
+                                state.notify_notes(&tx_notification).await?;
+                                if unknown_question {
+                                    unknown_question = false;
+                                    tx_notification.send(WorkerNotification::DetailsUpdated { title: title.clone(), question_id })?;
+                                } else if state.flap.lock().await.is_flapping() {
+                                    // Each reconnection would prompt or start synchronizing again
+                                    debug!("connection flapping, not prompting for {}", title);
+                                    deferred_details = Some((question_id, title.clone()));
+                                } else if !state.opts.expects_question(Some(question_id)) {
+                                    // Neither offered nor started
+                                    tx_notification.send(WorkerNotification::Warning { message: unexpected_question(&state.opts, Some(&(question_id, title.clone()))) })?;
+                                } else if target_question.is_some_and(|id| id != question_id) {
+                                    tx_notification.send(WorkerNotification::QuestionChanged { title: title.clone(), question_id })?;
+                                } else {
+                                    tx_notification.send(WorkerNotification::Details { title: title.clone(), question_id })?;
+                                }
+
+                                play_gate.set_question(&state.config.play_policy, question_id, &title);
+                                if play_gate.policy() == PlayPolicy::Deny {
+                                    info!("{}", messages::play_policy_denied(&title));
+                                }
+
+                                if let Some(rebroadcaster) = &state.rebroadcaster {
+                                    rebroadcaster.send(&ServerMessage::Details { title: title.clone(), question_id }).await;
+                                }
+
+                                let session = state.dynamic.lock().await.session.clone();
+                                if let Some(name) = session {
+                                    match state.config.session(&name).map(|session| session.question_id) {
+                                        Ok(Some(expected)) if expected != question_id => {
+                                            tx_notification.send(WorkerNotification::Warning { message: messages::session_question_mismatch(&name, expected, &title, question_id) })?;
+                                        }
+                                        _ => {}
+                                    }
+                                }
+
+                                state.set_question(Some((question_id, title)));
+                                state.config.hooks.fire(HookEvent::Details, state.hook_env(&state.context).await);
+
+                                trace!("controller: details end");
+
+                            },
+                            ConnectedNotification::SessionTimeout { connection: from } if connection.is_some_and(|connection| connection != from) => {
+                                debug!("ignoring the session timeout of connection {}, not on the question", from);
+                            }
+                            ConnectedNotification::SessionTimeout { .. } => {
+                                tx_notification.send(WorkerNotification::SessionTimeout)?;
+                            }
+                            ConnectedNotification::RequestCode { connection: from } if connection.is_some_and(|connection| connection != from) => {
+                                debug!("ignoring the code request of connection {}, not on the question", from);
+                            }
+                            ConnectedNotification::RequestCode { .. } => {
+                                debug!("the extension requested the code");
+                                tx_internal.send(WorkerMessage::CodeRequested).await?;
+                            }
+                            ConnectedNotification::SetReadOnly { connection: from, .. } if connection.is_some_and(|connection| connection != from) => {
+                                debug!("ignoring the read-only state of connection {}, not on the question", from);
+                            }
+                            // The app locked the IDE editor itself, the target stays editable
+                            ConnectedNotification::SetReadOnly { .. } if state.opts.lock_ide => {
+                                debug!("ignoring the read-only state of the IDE editor with --lock-ide");
+                            }
+                            ConnectedNotification::SetReadOnly { state: read_only, .. } => {
+                                state.set_read_only(&tx_notification, read_only).await?;
+                            }
+                            ConnectedNotification::DetailsTimeout { connection: from } if connection.is_some_and(|connection| connection != from) => {
+                                debug!("ignoring the details timeout of connection {}, not on the question", from);
+                            }
+                            ConnectedNotification::DetailsTimeout { .. } => {
+                                state.flap.lock().await.without_details();
+
+                                if state.context.question.is_none() && !unknown_question {
+                                    if state.opts.allow_unknown_question {
+                                        unknown_question = true;
+                                        notify_unknown_question(&tx_notification)?;
+                                    } else {
+                                        tx_notification.send(WorkerNotification::DetailsMissing)?;
+                                    }
+                                }
+                            }
+                            ConnectedNotification::Code { connection: from, .. } if connection.is_some_and(|connection| connection != from) => {
+                                debug!("ignoring the code of connection {}, not on the question", from);
+                            }
+                            ConnectedNotification::Code { code, checksum, .. } => {
+                                trace!("controller: code");
+
+                                // Every output of this download describes the same question and target
+                                let context = state.context.clone();
+
+                                let code = code.into_string();
+                                if checksum.is_some() && !echoes_checksums {
+                                    debug!("extension echoes checksums, using them to compare code");
+                                    echoes_checksums = true;
+                                }
+
+                                let target = context.target.clone();
+
+                                // With --two-way, IDE code changed since the last synchronization is
+                                // pulled like a download
+                                let ide_checksum = checksum::checksum(&code);
+                                let pull = match (&target, &pending_download) {
+                                    (Some(target), None) if state.opts.two_way && syncing => {
+                                        if !baseline.is_set() {
+                                            debug!("first IDE code since starting, comparing the next ones to it");
+                                            let local = async_std::fs::read_to_string(target).await.ok();
+                                            baseline.synchronized(local.as_deref().map(checksum::checksum), ide_checksum);
+                                            state.heartbeat.beat(label);
+                                            continue;
+                                        }
+
+                                        if !baseline.ide_changed(&ide_checksum) {
+                                            trace!("IDE code unchanged");
+                                            state.heartbeat.beat(label);
+                                            continue;
+                                        }
+
+                                        debug!("the IDE code changed, pulling it");
+                                        Some(PendingDownload::pull(&baseline))
+                                    }
+                                    _ => None,
+                                };
+                                let pulled = pull.is_some();
+
+                                // With --ide-poll-interval, IDE code changed since the last
+                                // synchronization is only reported, once until it changes again
+                                let diverged = match (&target, &pending_download) {
+                                    (Some(target), None) if !state.opts.two_way && pull_interval.is_some() && syncing => {
+                                        if !baseline.is_set() {
+                                            debug!("first IDE code since starting, comparing the next ones to it");
+                                            let local = async_std::fs::read_to_string(target).await.ok();
+                                            baseline.synchronized(local.as_deref().map(checksum::checksum), ide_checksum.clone());
+                                            false
+                                        } else if baseline.ide_changed(&ide_checksum) {
+                                            baseline.ide_seen(ide_checksum.clone());
+                                            true
+                                        } else {
+                                            false
+                                        }
+                                    }
+                                    _ => false,
+                                };
+
+                                if let (Some(_), None) = (&pending_download, &target) {
+                                    state.report_error(&tx_notification, messages::no_download_target())?;
+                                    pending_download = None;
+                                } else if let (Some(download), Some(target)) = (pending_download.take().or(pull), &target) {
+                                    let (strip_metadata, strip_test_input) = (state.opts.strip_metadata_on_download, state.opts.strip_test_input);
+
+                                    // The IDE checksum covers the metadata, compare contents once it is stripped
+                                    let stripped_len = metadata::strip(&code).len();
+                                    let (code, checksum) = if strip_metadata && stripped_len < code.len() {
+                                        (code[code.len() - stripped_len..].to_owned(), None)
+                                    } else {
+                                        (code, checksum)
+                                    };
+
+                                    // Same for the test input block, at the end of the code
+                                    let kept_len = test_input::strip(&code).len();
+                                    let (code, checksum) = if strip_test_input && kept_len < code.len() {
+                                        (code[..kept_len].to_owned(), None)
+                                    } else {
+                                        (code, checksum)
+                                    };
+
+                                    // The wrapper is inside the metadata and the test input block
+                                    let (code, checksum) = match state.wrapper.as_ref().map(|wrapper| wrapper.unwrap(&code)) {
+                                        Some(Some(unwrapped)) => (unwrapped, None),
+                                        Some(None) => {
+                                            warn!("{}", messages::download_wrapper_missing());
+                                            (code, checksum)
+                                        }
+                                        None => (code, checksum),
+                                    };
+
+                                    // The entry point was commented out before the wrapper was added
+                                    let restored = match (state.opts.restore_main_on_download, Language::from_path(target.as_ref())) {
+                                        (true, Some(language)) => transforms::restore_main(&code, language).map(|code| code.into_owned()),
+                                        _ => None,
+                                    };
+                                    let (code, checksum) = match restored {
+                                        Some(restored) if restored != code => (restored, None),
+                                        _ => (code, checksum),
+                                    };
+
+                                    let local = async_std::fs::read_to_string(&target).await.ok();
+                                    let unchanged = match &local {
+                                        Some(local) => checksum::same_code(local, &code, checksum.as_deref()),
+                                        None => false,
+                                    };
+
+                                    let bytes = code.len();
+                                    let local_state = if unchanged {
+                                        two_way::Local::Same
+                                    } else if download.target_changed(local.as_deref()) {
+                                        two_way::Local::Edited
+                                    } else {
+                                        two_way::Local::Untouched
+                                    };
+                                    // Pulled IDE code conflicting with local changes is resolved by the policy
+                                    let policy = if state.opts.force_download {
+                                        ConflictPolicy::PreferIde
+                                    } else if pulled {
+                                        state.opts.conflict_policy
+                                    } else {
+                                        ConflictPolicy::Ask
+                                    };
+                                    match two_way::resolve(local_state, policy, !state.opts.no_gui) {
+                                        Resolution::Unchanged => {
+                                            info!("local file already matches the IDE code");
+                                            provenance.matched();
+                                            if pull_interval.is_some() {
+                                                baseline.synchronized(local.as_deref().map(checksum::checksum), ide_checksum);
+                                            }
+                                        }
+                                        Resolution::KeepLocal => {
+                                            info!("{}", messages::sync_conflict_kept_local());
+                                            baseline.ide_seen(ide_checksum);
+
+                                            // Uploading the target makes both sides agree again
+                                            if let Some(code) = state.current_code().await {
+                                                tx_internal.send(WorkerMessage::FileChanged { code: code.into(), bundle: None }).await?;
+                                            }
+                                        }
+                                        resolution @ (Resolution::Ask | Resolution::Refuse) => {
+                                            provenance.changed(Side::Ide);
+                                            if pulled {
+                                                // Asked once, until the IDE code changes again
+                                                baseline.ide_seen(ide_checksum);
+                                            }
+
+                                            if resolution == Resolution::Refuse {
+                                                let message = if pulled { messages::sync_conflict_refused() } else { messages::download_conflict_refused() };
+                                                state.report_error(&tx_notification, message)?;
+                                            } else {
+                                                let diff = local.and_then(|local| diff::diff(&local, &code, DiffOptions::default()));
+                                                if pulled {
+                                                    warn!("{}", messages::sync_conflict());
+                                                    tx_notification.send(WorkerNotification::SyncConflict { diff })?;
+                                                } else {
+                                                    warn!("{}", messages::download_conflict());
+                                                    tx_notification.send(WorkerNotification::DownloadConflict { diff })?;
+                                                }
+                                            }
+                                        }
+                                        Resolution::Write => {
+                                            let code_checksum = checksum::checksum(&code);
+                                            let diff = local.and_then(|local| diff::diff(&local, &code, DiffOptions::default()));
+                                            // Written even though the IDE editor is locked, the IDE being the source
+                                            if let Err(err) = state.read_only.lift().await {
+                                                debug!("not lifting the read-only state of the target: {}", err);
+                                            }
+                                            let mut lock = match state.lock_target(&tx_notification, target, Access::Exclusive).await? {
+                                                Some(lock) => lock,
+                                                None => {
+                                                    state.relock_target().await;
+                                                    notify_provenance(&tx_notification, &provenance)?;
+                                                    state.heartbeat.beat(label);
+                                                    continue;
+                                                }
+                                            };
+
+                                            // The watcher would upload the downloaded code right back
+                                            state.watcher.own_writes().record(&code);
+                                            let written = lock.write(&code);
+                                            drop(lock);
+                                            state.relock_target().await;
+                                            match written {
+                                                Ok(_) => {
+                                                    if pull_interval.is_some() {
+                                                        baseline.synchronized(Some(code_checksum.clone()), ide_checksum);
+                                                    }
+                                                    if pulled {
+                                                        info!("{}", messages::ide_edits_pulled());
+                                                    } else {
+                                                        info!("{}", messages::code_downloaded());
+                                                    }
+                                                    metrics::record_download(bytes);
+                                                    tx_notification.send(WorkerNotification::Downloaded { diff })?;
+                                                    provenance.synchronized(Side::Ide, code_checksum);
+
+                                                    #[cfg(feature = "webhook-notifications")]
+                                                    state.notify_webhooks("download", context.question.as_ref(), bytes);
+
+                                                    // The hook may rewrite the target, uploads wait for it
+                                                    let tx_internal = tx_internal.clone();
+                                                    target_busy = state.config.hooks.fire_then(
+                                                        HookEvent::Download,
+                                                        state.hook_env(&context).await.var("CGLOCAL_BYTES", bytes),
+                                                        move || {
+                                                            let _ = task::block_on(tx_internal.send(WorkerMessage::TargetReleased));
+                                                        },
+                                                    );
+
+                                                    if let (Some(summary), Some((question_id, title))) = (&mut summary, &context.question) {
+                                                        summary.record_download(*question_id, title, bytes);
+                                                        summary.maybe_flush();
+                                                    }
+                                                }
+                                                Err(err) => {
+                                                    let message = err.to_string();
+                                                    error!("{}", message);
+                                                }
+                                            }
+                                        }
+                                    }
+                                } else {
+                                    // Code pushed by the IDE without a download request
+                                    let local = match &target {
+                                        Some(target) => async_std::fs::read_to_string(target).await.ok(),
+                                        None => None,
+                                    };
+
+                                    match local {
+                                        Some(local) if checksum::same_code(&local, &code, checksum.as_deref()) => provenance.matched(),
+                                        _ => provenance.changed(Side::Ide),
+                                    }
+
+                                    if diverged {
+                                        tx_notification.send(WorkerNotification::Warning { message: messages::ide_diverged() })?;
+                                    }
+                                }
+
+                                notify_provenance(&tx_notification, &provenance)?;
+
+                                trace!("controller: code end");
+                            }
+                        }
+
+                        state.heartbeat.beat(label);
+                    }
+                }
+
+                _ = flap_check.fuse() => {
+                    if state.flap.lock().await.check_stable(std::time::Instant::now()) {
+                        info!("{}", messages::connection_stable());
+                        metrics::record_flapping(false);
+                        tx_notification.send(WorkerNotification::Flapping { flapping: false })?;
+
+                        if let Some((question_id, title)) = deferred_details.take() {
+                            tx_notification.send(WorkerNotification::Details { title, question_id })?;
+                        }
+                    }
+
+                    state.heartbeat.beat("FlapCheck");
+                }
+
+                _ = pull_check.fuse() => {
+                    next_pull = pull_interval.map(|interval| std::time::Instant::now() + interval);
+
+                    // A download in progress brings the IDE code already, and its hook may still
+                    // be writing the target
+                    if pending_download.is_none() && !target_busy {
+                        if let Err(err) = tx_connected.try_send(Routed::to(connection, ConnectedMessage::SendCode)) {
+                            debug!("not requesting the IDE code: {}", err);
+                        }
+                    }
+
+                    state.heartbeat.beat("PullCheck");
+                }
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Err(Error(ErrorKind::WorkerNotificationChannel(_), _)) => {
+            warn!("{}", messages::notification_receiver_dropped());
+        }
+        result => result?,
     }
 
     info!("controller terminating");
+    state.status.remove();
+
+    debug!(
+        "retaining {} payload(s), {} bytes",
+        uploaded.len(),
+        uploaded.retained_bytes()
+    );
+
+    info!(
+        "filtered {} watch events using exclusion rules",
+        state.watcher.filtered_count()
+    );
+
+    info!("{}", state.flap.lock().await.stats());
+
+    if let Some(summary) = &mut summary {
+        summary.flush();
+    }
+
+    for stats in channel_stats::snapshot() {
+        debug!("{}", stats);
+    }
+
+    // The target is left as the user made it
+    if let Err(err) = state.read_only.unlock().await {
+        warn!("{}", messages::read_only_failed(&err.to_string()));
+    }
+
+    let no_server = state.opts.no_server;
+    let shutdown_timeout = std::time::Duration::from_secs(state.opts.shutdown_timeout);
+
+    // Without a server, nothing consumes these messages
+    if !no_server {
+        // Handed back editable, the connections process it before terminating
+        if state.opts.lock_ide && syncing {
+            if let Err(err) = set_ide_read_only(&tx_connected, connection, false).await {
+                debug!("not unlocking the IDE editor: {}", err);
+            }
+        }
 
-    // Terminate connected
-    tx_connected.send(ConnectedMessage::Terminate).await?;
+        // Terminate connected
+        state.heartbeat.beat("shutdown: connection");
+        shutdown::terminate(
+            "connection",
+            &tx_connected,
+            Routed::all(ConnectedMessage::Terminate),
+            shutdown_timeout,
+        )
+        .await;
 
-    // Terminate listener
-    tx_listen.send(ListenMessage::Terminate).await?;
+        // Terminate listener
+        state.heartbeat.beat("shutdown: listener");
+        shutdown::terminate(
+            "listener",
+            &tx_listen,
+            ListenMessage::Terminate,
+            shutdown_timeout,
+        )
+        .await;
+    }
+
+    // Terminate notification, the interface may already be gone
+    state.heartbeat.beat("shutdown: notification");
+    if tx_notification.send(WorkerNotification::Terminate).is_err() {
+        debug!("shutdown: notification already stopped");
+    }
 
-    // Terminate notification
-    tx_notification.send(WorkerNotification::Terminate)?;
+    state.heartbeat.beat("shutdown: done");
 
     Ok(())
 }
 
-fn spawn_worker(
-    opts: Arc<Mutex<Opts>>,
-) -> Result<(
+/// Warning for a question refused by --expect-question, `None` if it is unknown
+fn unexpected_question(opts: &Opts, question: Option<&(i32, String)>) -> String {
+    let expected = opts
+        .expect_question
+        .iter()
+        .map(i32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match question {
+        Some((question_id, title)) => messages::unexpected_question(title, *question_id, &expected),
+        None => messages::unknown_question_not_expected(&expected),
+    }
+}
+
+/// Start a session on a question the extension didn't identify, shown with a placeholder title
+fn notify_unknown_question(
+    tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+) -> Result<()> {
+    info!("{}", messages::unknown_question_started());
+    tx_notification.send(WorkerNotification::Details {
+        title: messages::unknown_question(),
+        question_id: UNKNOWN_QUESTION_ID,
+    })?;
+    Ok(())
+}
+
+/// Lock or unlock the IDE editor. Connections that ended don't receive it, a new one starts
+/// editable anyway.
+async fn set_ide_read_only(
+    tx_connected: &channel_stats::Sender<Routed>,
+    connection: Option<ConnectionId>,
+    state: bool,
+) -> Result<()> {
+    tx_connected
+        .send(Routed::to(
+            connection,
+            ConnectedMessage::SetReadOnly { state },
+        ))
+        .await?;
+    Ok(())
+}
+
+fn notify_provenance(
+    tx_notification: &std::sync::mpsc::Sender<WorkerNotification>,
+    tracker: &Tracker,
+) -> Result<()> {
+    tx_notification.send(WorkerNotification::Provenance {
+        state: tracker.state(),
+        last: tracker.last().cloned(),
+    })?;
+    Ok(())
+}
+
+/// Session state shown in the status bar of the text user interface
+#[derive(Debug)]
+struct StatusBar {
+    question: Option<String>,
+    /// Active session of the configuration file
+    session: Option<String>,
+    uploads: u64,
+    downloads: u64,
+    sync_state: SyncState,
+    last_change: Option<Provenance>,
+    /// Why the last upload played or not
+    play: Option<PlayReason>,
+    flapping: bool,
+    /// The target is read-only, following the IDE editor
+    read_only: bool,
+    /// Open connections of the extension, `None` until the first one
+    connections: Option<usize>,
+    /// Upload taking long to reach the extension, until it completes
+    sending: Option<SendProgress>,
+    /// Watch events discarded by the exclusion rules
+    filtered: u64,
+}
+
+impl Default for StatusBar {
+    fn default() -> Self {
+        Self {
+            question: None,
+            session: None,
+            uploads: 0,
+            downloads: 0,
+            sync_state: SyncState::InSync,
+            last_change: None,
+            play: None,
+            flapping: false,
+            read_only: false,
+            connections: None,
+            sending: None,
+            filtered: 0,
+        }
+    }
+}
+
+impl StatusBar {
+    fn render(&self) -> String {
+        let mut text = format!(
+            "{} | {}",
+            messages::status_bar(
+                self.question.as_deref(),
+                self.uploads,
+                self.downloads,
+                &chrono::Local::now().format("%H:%M:%S").to_string(),
+            ),
+            messages::sync_state(self.sync_state, self.last_change.as_ref())
+        );
+
+        if let Some(session) = &self.session {
+            text.push_str(" | ");
+            text.push_str(&messages::status_session(session));
+        }
+
+        if let Some(play) = self.play {
+            text.push_str(" | ");
+            text.push_str(&messages::play_reason(play));
+        }
+
+        if self.flapping {
+            text.push_str(" | ");
+            text.push_str(&messages::status_flapping());
+        }
+
+        if self.read_only {
+            text.push_str(" | ");
+            text.push_str(&messages::status_read_only());
+        }
+
+        // A single connection is the usual case
+        match self.connections {
+            Some(0) => {
+                text.push_str(" | ");
+                text.push_str(&messages::status_disconnected());
+            }
+            Some(n) if n > 1 => {
+                text.push_str(" | ");
+                text.push_str(&messages::status_connections(n));
+            }
+            _ => {}
+        }
+
+        match self.sending {
+            Some(SendProgress::Slow { bytes }) => {
+                text.push_str(" | ");
+                text.push_str(&messages::upload_in_progress(send_progress::kilobytes(
+                    bytes,
+                )));
+            }
+            Some(SendProgress::Stalled { bytes }) => {
+                text.push_str(" | ");
+                text.push_str(&messages::upload_stalled(
+                    send_progress::kilobytes(bytes),
+                    send_progress::SEND_STALL.as_secs(),
+                ));
+            }
+            Some(SendProgress::Done { .. }) | None => {}
+        }
+
+        if self.filtered > 0 {
+            text.push_str(" | ");
+            text.push_str(&messages::status_filtered(self.filtered));
+        }
+
+        text
+    }
+}
+
+fn is_json_file(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("json"))
+        .unwrap_or(false)
+}
+
+/// Write the file through a temporary file in the same directory, so readers never see it
+/// partially written
+async fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    async_std::fs::write(&tmp, contents).await?;
+    async_std::fs::rename(&tmp, path).await
+}
+
+/// First lines of the code, for previews
+fn code_preview(code: &str, lines: usize) -> String {
+    code.lines().take(lines).collect::<Vec<_>>().join("\n")
+}
+
+/// Handles to a running worker: its thread, its command channel, its notification channel and
+/// its controller heartbeat
+type Worker = (
     std::thread::JoinHandle<Result<()>>,
-    async_std::channel::Sender<WorkerMessage>,
+    channel_stats::Sender<WorkerMessage>,
     std::sync::mpsc::Receiver<WorkerNotification>,
-)> {
-    let state = State::new(opts.clone());
+    Arc<Heartbeat>,
+);
 
-    let mut hotwatch = Hotwatch::new()?;
-    let path: PathBuf =
-        task::block_on(async { opts.lock().await.target.parent().unwrap().to_owned() });
-
-    let (tx_controller, rx_controller) = async_std::channel::bounded(1);
-    let (tx_listen, rx_listen) = async_std::channel::bounded(1);
-    let (tx_connected, rx_connected) = async_std::channel::bounded(1);
+fn spawn_worker(
+    opts: Arc<Opts>,
+    dynamic: Arc<Mutex<DynamicOpts>>,
+    config: Arc<Config>,
+    target_lock: Option<TargetLock>,
+    copyright: Option<CopyrightHeader>,
+    wrapper: Option<Wrapper>,
+) -> Result<Worker> {
+    let (tx_controller, rx_controller) = channel_stats::bounded("controller", 1);
+    let (tx_listen, rx_listen) = channel_stats::bounded("listen", 1);
+    let (tx_connected, rx_connected) = channel_stats::bounded("connected", 1);
     let (tx_notification, rx_notification) = std::sync::mpsc::channel();
-    let (tx_conn_notification, rx_conn_notification) = async_std::channel::bounded(1);
+    let (tx_conn_notification, rx_conn_notification) =
+        channel_stats::bounded("conn-notification", 1);
 
-    {
-        let opts = opts.clone();
+    let target = task::block_on(async { dynamic.lock().await.target.clone() });
+
+    let mut watcher = Watcher::new(
+        opts.clone(),
+        dynamic.clone(),
+        tx_controller.clone(),
+        opts.ignore.clone(),
+    );
+    if opts.input_from_clipboard {
+        clipboard::spawn(
+            dynamic.clone(),
+            std::time::Duration::from_millis(opts.clipboard_poll_ms),
+            tx_controller.clone(),
+            tx_notification.clone(),
+        );
+    } else if let Some(target) = target {
+        watcher.watch(target.as_ref())?;
+    }
+
+    let mut state = State::new(
+        opts.clone(),
+        dynamic,
+        config.clone(),
+        watcher,
+        target_lock,
+        copyright,
+        wrapper,
+    );
+    let heartbeat = state.heartbeat.clone();
+
+    let (watchdog_timeout, watchdog_abort) = (opts.watchdog_timeout, opts.watchdog_abort);
+
+    clock_monitor::spawn(heartbeat.clone(), {
         let tx_controller = tx_controller.clone();
+        move |gap| {
+            let _ = task::block_on(tx_controller.send(WorkerMessage::Resumed { gap }));
+        }
+    });
+
+    if watchdog_timeout > 0 {
+        let probes = vec![
+            OccupancyProbe::input(rx_controller.stats()),
+            OccupancyProbe::input(rx_conn_notification.stats()),
+            OccupancyProbe::other(rx_connected.stats()),
+            OccupancyProbe::other(rx_listen.stats()),
+        ];
+
+        watchdog::spawn(
+            heartbeat.clone(),
+            probes,
+            std::time::Duration::from_secs(watchdog_timeout),
+            watchdog_abort,
+        );
+    }
+
+    let worker_heartbeat = heartbeat.clone();
+    let tx_accept_terminate = tx_controller.clone();
+    let tx_accept_notification = tx_notification.clone();
+
+    Ok((
+        std::thread::spawn(move || {
+            let result = task::block_on(async move {
+                #[cfg(feature = "prometheus-metrics")]
+                if let Some(addr) = opts.metrics_prometheus.clone() {
+                    task::spawn(async move {
+                        if let Err(err) = metrics::serve(&addr).await {
+                            error!("metrics server on {} failed: {}", addr, err);
+                        }
+                    });
+                }
+
+                if opts.rebroadcast_port.is_some() || opts.observers > 0 {
+                    state.rebroadcaster = Some(Rebroadcaster::new());
+                }
+                if let (Some(addr), Some(rebroadcaster)) =
+                    (opts.rebroadcast_addr(), &state.rebroadcaster)
+                {
+                    rebroadcaster.listen(&addr).await?;
+                }
+
+                // Queued ahead of any connection, so the controller handles it first
+                if let Some((question_id, title)) = opts.question_override() {
+                    debug!("starting on question {} from the options", question_id);
+                    if let Err(err) =
+                        tx_conn_notification.try_send(ConnectedNotification::Details {
+                            title,
+                            question_id,
+                            connection: None,
+                        })
+                    {
+                        warn!("failed to start on the question from the options: {}", err);
+                    }
+                }
+
+                // Waited for once the controller stopped, so the listener is closed before exiting
+                let accept_task = if opts.no_server {
+                    info!("not listening for the extension, only writing preprocessed code");
+                    None
+                } else {
+                    let accept = run_accept(
+                        rx_connected,
+                        rx_listen,
+                        tx_conn_notification,
+                        tx_notification.clone(),
+                        opts,
+                        ConnectionHandles {
+                            config,
+                            flap: state.flap.clone(),
+                            status: state.status.clone(),
+                            observers: state.rebroadcaster.clone(),
+                        },
+                    );
+
+                    // Without the interface nobody would notice the listener is gone
+                    Some(task::spawn(async move {
+                        let result = accept.await;
+                        match &result {
+                            Err(Error(ErrorKind::WorkerNotificationChannel(_), _)) => {
+                                warn!("{}", messages::notification_receiver_dropped());
+                                let _ = tx_accept_terminate.send(WorkerMessage::Terminate).await;
+                            }
+                            Err(err) => {
+                                let _ = tx_accept_notification.send(WorkerNotification::Error {
+                                    message: messages::listener_failed(&err.to_string()),
+                                });
+                            }
+                            Ok(()) => {}
+                        }
+
+                        result
+                    }))
+                };
+
+                let result = run_controller(
+                    state,
+                    tx_connected,
+                    tx_listen,
+                    rx_controller,
+                    tx_notification,
+                    rx_conn_notification,
+                )
+                .await;
+
+                if let Some(accept_task) = accept_task {
+                    let _ = accept_task.await;
+                }
+
+                result
+            });
+
+            worker_heartbeat.stop();
+            result
+        }),
+        tx_controller,
+        rx_notification,
+        heartbeat,
+    ))
+}
+
+#[paw::main]
+fn main(opts: Opts) {
+    if let Err(err) = run(opts) {
+        eprintln!("error: {}", err);
+
+        for cause in err.iter().skip(1) {
+            eprintln!("caused by: {}", cause);
+        }
+
+        std::process::exit(1);
+    }
+}
+
+fn init_logger() {
+    env_logger::init_from_env(
+        env_logger::Env::new()
+            .filter_or("CG_LOCAL_LOG", "cg_local_app=debug")
+            .write_style("CG_LOCAL_LOG_STYLE"),
+    );
+}
+
+/// Why the text user interface can't work in this environment, if it obviously can't
+fn tui_unsupported() -> Option<String> {
+    use std::io::IsTerminal;
+
+    if !std::io::stdout().is_terminal() {
+        Some("standard output is not a terminal".to_owned())
+    } else if std::env::var("TERM").as_deref() == Ok("dumb") {
+        Some("TERM is set to dumb".to_owned())
+    } else {
+        None
+    }
+}
+
+fn run(mut opts: Opts) -> Result<()> {
+    // The text user interface is built around the connection to the extension
+    if opts.no_server {
+        opts.no_gui = true;
+    }
+
+    let fallback_reason = if opts.no_gui || opts.force_tui || opts.command.is_some() {
+        None
+    } else {
+        tui_unsupported()
+    };
+
+    if opts.no_gui || fallback_reason.is_some() || opts.command.is_some() {
+        init_logger();
+    }
+
+    payload::include_in_logs(opts.log_include_code);
+
+    if let Some(reason) = &fallback_reason {
+        warn!("{}", messages::tui_fallback(reason));
+        opts.no_gui = true;
+    }
+
+    if let Some(command) = &opts.command {
+        return command.run(&opts);
+    }
+
+    if let Some(path) = &opts.record {
+        recording::start(path.as_ref())?;
+    }
+
+    if let Some(path) = &opts.trace_protocol {
+        protocol_trace::start(path.as_ref())?;
+    }
+
+    let config = Arc::new(Config::load(
+        opts.config.as_ref().map(|path| path.as_ref()),
+    )?);
+    config.transforms.check(&pipeline::flagged(&opts))?;
 
-        hotwatch.watch(path, move |event: Event| match event {
-            Event::NoticeWrite(path) | Event::Create(path) | Event::Write(path) => {
-                let tx_controller = tx_controller.clone();
-                let opts = opts.clone();
+    // The session stands for --target and --play
+    if let Some(name) = &opts.session {
+        let session = config.session(name)?;
+        info!("{}", messages::session_started(name));
+        opts.target = Some(session.target.clone().into());
+        if let Some(play) = session.play {
+            opts.play = play;
+        }
+    }
 
-                task::spawn(async move {
-                    if let Ok(target) = async_std::fs::canonicalize(&opts.lock().await.target).await
-                    {
-                        if PathBuf::from(path) == target {
-                            match async_std::fs::read_to_string(&target).await {
-                                Ok(code) => {
-                                    return tx_controller
-                                        .send(WorkerMessage::FileChanged { code })
-                                        .await
-                                }
-                                Err(error) => {
-                                    return tx_controller
-                                        .send(WorkerMessage::WatchError { error })
-                                        .await
-                                }
-                            }
-                        }
-                    }
+    if opts.target.is_none() && !opts.input_from_clipboard {
+        bail!("--target is required to synchronize a file with the IDE");
+    }
 
-                    Ok(())
-                });
-            }
-            _ => {}
-        })?;
+    // Newcomers often give the project directory
+    if let Some(target) = opts.target.clone() {
+        let target: &std::path::Path = target.as_ref();
+        if target.is_dir() {
+            let main_file = main_file::resolve(target)?;
+            info!(
+                "{} is a directory, synchronizing {}",
+                target.display(),
+                main_file.display()
+            );
+            opts.target = Some(main_file.into());
+        }
     }
 
-    Ok((
-        std::thread::spawn(move || {
-            task::block_on(async move {
-                task::spawn(run_accept(
-                    rx_connected,
-                    rx_listen,
-                    tx_conn_notification,
-                    opts.lock().await.bind.clone(),
-                ));
+    // Report an unusable address or socket before starting the interface
+    match opts.listen() {
+        Listen::Addr(addr) => {
+            bind::validate(&addr)?;
+        }
+        #[cfg(unix)]
+        Listen::Fd(fd) => listen_fd::validate(fd)?,
+        #[cfg(unix)]
+        Listen::Unix(path) => listener::validate_unix(&path)?,
+    }
 
-                run_controller(
-                    state,
-                    tx_connected,
-                    tx_listen,
-                    rx_controller,
-                    tx_notification,
-                    rx_conn_notification,
-                )
-                .await
-            })?;
+    let copyright = opts.copyright()?;
+    let wrapper = opts.wrapper()?;
 
-            // Stop watching when the async worker completes
-            drop(hotwatch);
+    let print_extension_settings = opts.print_extension_settings;
+    let show_code_preview = opts.tui_show_code_preview;
+    let show_diff = opts.show_diff_in_tui;
+    let show_status_bar = opts.ui_status_bar;
+    let upload_before_quit = opts.upload_before_quit;
+    let tui_refresh_rate = std::time::Duration::from_millis(opts.tui_refresh_rate);
 
-            Ok(())
-        }),
-        tx_controller,
-        rx_notification,
-    ))
-}
+    // Owned by the worker afterwards, which releases it when it terminates or the target changes
+    let target_lock = match &opts.target {
+        Some(target) => TargetLock::acquire(target.as_ref(), &opts.bind, opts.allow_shared_target)?,
+        None => None,
+    };
 
-#[paw::main]
-fn main(opts: Opts) -> Result<()> {
-    let no_gui = opts.no_gui;
-    if no_gui {
-        env_logger::init_from_env(
-            env_logger::Env::new()
-                .filter_or("CG_LOCAL_LOG", "cg_local_app=debug")
-                .write_style("CG_LOCAL_LOG_STYLE"),
-        );
+    let tui = if opts.no_gui {
+        None
+    } else {
+        match cursive::default().try_into_runner() {
+            Ok(s) => Some(s),
+            Err(err) if !opts.force_tui => {
+                init_logger();
+                warn!("{}", messages::tui_fallback(&err.to_string()));
+                opts.no_gui = true;
+                None
+            }
+            Err(err) => bail!("failed to initialize the text user interface: {}", err),
+        }
+    };
+
+    let shutdown_timeout = std::time::Duration::from_secs(opts.shutdown_timeout);
+    let dynamic = DynamicOpts::new(&opts);
+    if let Some(language) = dynamic.language() {
+        info!("{}", messages::language_detected(language));
     }
+    let dynamic = Arc::new(Mutex::new(dynamic));
+    // Sessions offered by the interface, with their target
+    let sessions: Vec<(String, String)> = config
+        .session
+        .iter()
+        .map(|(name, session)| (name.clone(), session.target.display().to_string()))
+        .collect();
+    let (join_handle, tx_worker, rx_notification, heartbeat) = spawn_worker(
+        Arc::new(opts),
+        dynamic.clone(),
+        config,
+        target_lock,
+        copyright,
+        wrapper,
+    )?;
+
+    // Ctrl-C terminates the worker, which ends the interface loop below like any other exit
+    #[cfg(unix)]
+    shutdown::on_signal({
+        let tx_worker = tx_worker.clone();
+        move || {
+            task::block_on(shutdown::terminate(
+                "controller",
+                &tx_worker,
+                WorkerMessage::Terminate,
+                shutdown_timeout,
+            ))
+        }
+    })?;
 
-    let opts = Arc::new(Mutex::new(opts));
-    let (join_handle, tx_worker, rx_notification) = spawn_worker(opts.clone())?;
+    match tui {
+        None => loop {
+            // The worker is gone, its thread is joined below
+            let m = match rx_notification.recv() {
+                Ok(m) => m,
+                Err(std::sync::mpsc::RecvError) => {
+                    debug!("notification channel closed, shutting down");
+                    break;
+                }
+            };
 
-    if no_gui {
-        for m in rx_notification.iter() {
             match m {
+                WorkerNotification::Listening { addrs, socket } => {
+                    if print_extension_settings {
+                        println!("{}", extension_settings(&addrs, socket.as_deref()));
+                    }
+                }
                 WorkerNotification::Details { title, question_id } => {
-                    info!("working on question '{}' (id: {})", title, question_id);
+                    info!("{}", messages::working_on_question(&title, question_id));
 
-                    task::block_on(async {
+                    let sent = task::block_on(async {
                         trace!("sending Start");
 
                         tx_worker
                             .send(WorkerMessage::Start {
-                                download: opts.lock().await.download,
+                                download: dynamic.lock().await.download,
                             })
                             .await
-                    })?;
+                    });
+
+                    // The controller stopped, shut down rather than waiting for it
+                    if sent.is_err() {
+                        warn!("{}", messages::worker_stopped());
+                        break;
+                    }
+                }
+                WorkerNotification::QuestionChanged { title, question_id } => {
+                    warn!(
+                        "{}",
+                        messages::question_changed_not_started(&title, question_id)
+                    );
+                }
+                WorkerNotification::DetailsUpdated { title, question_id } => {
+                    info!("{}", messages::working_on_question(&title, question_id));
+                }
+                WorkerNotification::DetailsMissing => {
+                    warn!("{}", messages::details_missing_hint());
                 }
                 WorkerNotification::Initialized => {
-                    info!("synchronization started");
+                    info!("{}", messages::sync_started());
                 }
                 WorkerNotification::Stopped => {
-                    info!("synchronization stopped");
+                    info!("{}", messages::sync_stopped());
+                }
+                // Already logged by the controller
+                WorkerNotification::Detached => {}
+                WorkerNotification::SessionSwitched { .. } => {}
+                WorkerNotification::Error { message } => {
+                    error!("{}", message);
+                }
+                WorkerNotification::Warning { message } => {
+                    warn!("{}", message);
+                }
+                WorkerNotification::CodePreview { .. } => {}
+                WorkerNotification::PendingDiff { .. } => {}
+                WorkerNotification::PlayDecided { .. } => {}
+                WorkerNotification::Flapping { .. } => {}
+                WorkerNotification::EventsFiltered { count } => {
+                    debug!("filtered {} watch events using exclusion rules", count);
+                }
+                WorkerNotification::UploadProgress { progress } => match progress {
+                    SendProgress::Slow { bytes } => {
+                        info!(
+                            "{}",
+                            messages::upload_in_progress(send_progress::kilobytes(bytes))
+                        );
+                    }
+                    SendProgress::Stalled { bytes } => {
+                        warn!(
+                            "{}",
+                            messages::upload_stalled(
+                                send_progress::kilobytes(bytes),
+                                send_progress::SEND_STALL.as_secs()
+                            )
+                        );
+                    }
+                    SendProgress::Done { bytes, elapsed } => {
+                        info!(
+                            "{}",
+                            messages::upload_completed(
+                                send_progress::kilobytes(bytes),
+                                elapsed.as_secs_f32()
+                            )
+                        );
+                    }
+                },
+                WorkerNotification::History { .. } => {}
+                WorkerNotification::TargetChanged { .. } => {}
+                WorkerNotification::Uploaded => {}
+                WorkerNotification::PendingUpload { .. } => {}
+                // Refused by the controller without the interface
+                WorkerNotification::DownloadConflict { .. } => {}
+                WorkerNotification::SyncConflict { .. } => {}
+                WorkerNotification::ClipboardChanged { bytes } => {
+                    info!("{}", messages::clipboard_changed(bytes));
+                }
+                WorkerNotification::Downloaded { diff } => {
+                    if let Some(diff) = diff {
+                        info!(
+                            "{}\n{}",
+                            messages::download_diff_title(),
+                            diff.render_ansi()
+                        );
+                    }
+                }
+                WorkerNotification::SessionTimeout => {
+                    warn!("{}", messages::session_timeout());
+                }
+                WorkerNotification::ReadOnly { state } => {
+                    info!("{}", messages::target_read_only(state));
+                }
+                WorkerNotification::ConnectionDenied { peer, reason } => {
+                    warn!(
+                        "{}",
+                        messages::connection_denied(&peer.to_string(), &reason)
+                    );
+                }
+                WorkerNotification::Connected { peer } => {
+                    info!("{}", messages::extension_connected(&peer.to_string()));
+                }
+                WorkerNotification::Disconnected { peer } => {
+                    info!("{}", messages::extension_disconnected(&peer.to_string()));
+                }
+                // Only requested with the interface
+                WorkerNotification::TakeoverRequested { .. } => {}
+                WorkerNotification::QuitCheck { .. } => {}
+                WorkerNotification::Notes { path, contents } => {
+                    if contents.is_some() {
+                        debug!("{}", messages::notes_found(&path.display().to_string()));
+                    }
+                }
+                WorkerNotification::Provenance { state, last } => {
+                    debug!("{}", messages::sync_state(state, last.as_ref()));
                 }
                 WorkerNotification::Terminate => {
                     break;
                 }
             }
-        }
-    } else {
-        use cursive::views::{Checkbox, Dialog, LinearLayout, TextView};
-        use cursive::Cursive;
-
-        fn dialog_waiting(s: &mut Cursive) {
-            s.pop_layer();
-            s.add_layer(
-                Dialog::around(TextView::new("Waiting for IDE to connect."))
-                    .title("cg-local-app.rs")
-                    .button("Quit", |s| s.quit()),
-            );
-        }
+        },
+        Some(mut s) => {
+            use std::cell::RefCell;
+            use std::rc::Rc;
 
-        fn dialog_initial(
-            s: &mut Cursive,
-            header: &str,
-            tx_worker: async_std::channel::Sender<WorkerMessage>,
-        ) {
-            s.pop_layer();
-            s.add_layer(
-                Dialog::around(TextView::new(header))
-                    .title("cg-local-app.rs")
-                    .button("Upload", {
+            use cursive::utils::markup::StyledString;
+            use cursive::view::{Nameable, Resizable, Scrollable};
+            use cursive::views::{
+                Checkbox, Dialog, DummyView, LinearLayout, ScrollView, SelectView, TextView,
+            };
+            use cursive::{Cursive, XY};
+
+            /// Layers kept below the dialogs, i.e. the status bar
+            struct BaseLayers(usize);
+
+            fn clear_layers(s: &mut Cursive) {
+                let base = s.user_data::<BaseLayers>().map(|base| base.0).unwrap_or(0);
+                while s.screen().len() > base {
+                    s.pop_layer();
+                }
+            }
+
+            /// Open the file browser next to the current target, switching to the chosen file
+            fn browse(
+                s: &mut Cursive,
+                tx_worker: &channel_stats::Sender<WorkerMessage>,
+                dynamic: &Arc<Mutex<DynamicOpts>>,
+            ) {
+                let target = task::block_on(async { dynamic.lock().await.target.clone() });
+                let dir = target
+                    .and_then(|target| {
+                        std::fs::canonicalize(watcher::target_dir(target.as_ref())).ok()
+                    })
+                    .or_else(|| std::env::current_dir().ok())
+                    .unwrap_or_default();
+
+                let tx_worker = tx_worker.clone();
+                file_browser::show(s, dir, move |_, path| {
+                    task::block_on(tx_worker.send(WorkerMessage::SetTarget { path: path.into() }))
+                        .expect("failed to send set target message to worker")
+                });
+            }
+
+            /// Title of the main screens, naming the detected language
+            fn screen_title(dynamic: &Arc<Mutex<DynamicOpts>>) -> String {
+                task::block_on(async { dynamic.lock().await.title() })
+            }
+
+            fn target_view(target: &str) -> impl cursive::View {
+                TextView::new(messages::target_label(target)).with_name("target")
+            }
+
+            fn dialog_waiting(
+                s: &mut Cursive,
+                settings: &str,
+                target: &str,
+                tx_worker: channel_stats::Sender<WorkerMessage>,
+                dynamic: Arc<Mutex<DynamicOpts>>,
+                pending_quit: Option<Arc<AtomicBool>>,
+            ) {
+                clear_layers(s);
+                s.add_layer(
+                    Dialog::around(
+                        LinearLayout::vertical()
+                            .child(
+                                TextView::new(format!(
+                                    "{}\n\n{}\n",
+                                    messages::waiting_for_ide(),
+                                    settings
+                                ))
+                                .with_name(WAITING_VIEW),
+                            )
+                            .child(target_view(target)),
+                    )
+                    .title(screen_title(&dynamic))
+                    .button(messages::button_browse(), {
                         let tx_worker = tx_worker.clone();
-                        move |_| {
-                            task::block_on(tx_worker.send(WorkerMessage::Start { download: false }))
-                                .expect("failed to send start message to worker")
-                        }
+                        move |s| browse(s, &tx_worker, &dynamic)
                     })
-                    .button("Download", move |_| {
-                        task::block_on(tx_worker.send(WorkerMessage::Start { download: true }))
-                            .expect("failed to send start message to worker")
+                    .button(messages::button_quit(), move |s| {
+                        request_quit(s, &tx_worker, &pending_quit)
                     })
-                    .button("Quit", |s| s.quit()),
-            );
-        }
+                    .with_name(MAIN_DIALOG),
+                );
+            }
 
-        fn dialog_running(
-            s: &mut Cursive,
-            header: &str,
-            tx_worker: async_std::channel::Sender<WorkerMessage>,
-            opts: Arc<Mutex<Opts>>,
-        ) {
-            s.pop_layer();
-            s.add_layer(
-                Dialog::around(
-                    LinearLayout::vertical().child(TextView::new(header)).child(
+            fn dialog_initial(
+                s: &mut Cursive,
+                header: &str,
+                target: &str,
+                tx_worker: channel_stats::Sender<WorkerMessage>,
+                dynamic: Arc<Mutex<DynamicOpts>>,
+                pending_quit: Option<Arc<AtomicBool>>,
+                notes: &SharedNotes,
+            ) {
+                clear_layers(s);
+                s.add_layer(
+                    notes_button(
+                        Dialog::around(
+                            LinearLayout::vertical()
+                                .child(TextView::new(header).with_name(HEADER_VIEW))
+                                .child(target_view(target)),
+                        )
+                        .title(screen_title(&dynamic))
+                        .button(messages::button_upload(), {
+                            let tx_worker = tx_worker.clone();
+                            move |_| {
+                                task::block_on(
+                                    tx_worker.send(WorkerMessage::Start { download: false }),
+                                )
+                                .expect("failed to send start message to worker")
+                            }
+                        })
+                        .button(messages::button_download(), {
+                            let tx_worker = tx_worker.clone();
+                            move |_| {
+                                task::block_on(
+                                    tx_worker.send(WorkerMessage::Start { download: true }),
+                                )
+                                .expect("failed to send start message to worker")
+                            }
+                        })
+                        .button(messages::button_browse(), {
+                            let tx_worker = tx_worker.clone();
+                            move |s| browse(s, &tx_worker, &dynamic)
+                        }),
+                        notes,
+                    )
+                    .button(messages::button_quit(), move |s| {
+                        request_quit(s, &tx_worker, &pending_quit)
+                    })
+                    .with_name(MAIN_DIALOG),
+                );
+            }
+
+            fn dialog_running(
+                s: &mut Cursive,
+                header: &str,
+                tx_worker: channel_stats::Sender<WorkerMessage>,
+                dynamic: Arc<Mutex<DynamicOpts>>,
+                pending_quit: Option<Arc<AtomicBool>>,
+                notes: &SharedNotes,
+                panes: &RunningPanes,
+            ) {
+                let mut layout = LinearLayout::vertical()
+                    .child(TextView::new(header).with_name(HEADER_VIEW))
+                    .child(
                         LinearLayout::horizontal()
                             .child({
                                 let mut chk = Checkbox::new().on_change({
-                                    let opts = opts.clone();
+                                    let dynamic = dynamic.clone();
+                                    let tx_worker = tx_worker.clone();
                                     move |_s, checked| {
-                                        task::block_on(async { opts.lock().await.play = checked });
+                                        task::block_on(async {
+                                            dynamic.lock().await.play = checked
+                                        });
+                                        task::block_on(
+                                            tx_worker
+                                                .send(WorkerMessage::PlayToggled { play: checked }),
+                                        )
+                                        .expect("failed to send play message to worker");
                                     }
                                 });
 
-                                if task::block_on(async { opts.lock().await.play }) {
+                                if task::block_on(async { dynamic.lock().await.play }) {
                                     chk.check();
                                 }
 
                                 chk
                             })
-                            .child(TextView::new("Play on upload")),
-                    ),
-                )
-                .title("cg-local-app.rs")
-                .button("Stop sync", move |_| {
-                    task::block_on(tx_worker.send(WorkerMessage::Stop))
-                        .expect("failed to send stop message to worker")
-                })
-                .button("Quit", |s| s.quit()),
-            );
-        }
+                            .child(TextView::new(messages::label_play_on_upload())),
+                    );
 
-        let mut s = cursive::default().into_runner();
-        s.add_global_callback('q', |s| s.quit());
+                if let Some(preview) = &panes.preview {
+                    layout.add_child(
+                        ScrollView::new(TextView::new(preview.as_str()).with_name("code_preview"))
+                            .max_height(12),
+                    );
+                }
+
+                if let Some(pending_diff) = &panes.pending_diff {
+                    layout.add_child(
+                        ScrollView::new(
+                            TextView::new(pending_diff.clone()).with_name(PENDING_DIFF_VIEW),
+                        )
+                        .max_height(12),
+                    );
+                }
 
-        dialog_waiting(&mut s);
+                clear_layers(s);
+                s.add_layer(
+                    notes_button(
+                        Dialog::around(layout).title(screen_title(&dynamic)).button(
+                            messages::button_stop_sync(),
+                            {
+                                let tx_worker = tx_worker.clone();
+                                move |_| {
+                                    task::block_on(tx_worker.send(WorkerMessage::Stop))
+                                        .expect("failed to send stop message to worker")
+                                }
+                            },
+                        ),
+                        notes,
+                    )
+                    .button(messages::button_detach(), {
+                        let tx_worker = tx_worker.clone();
+                        move |_| {
+                            task::block_on(tx_worker.send(WorkerMessage::Detach))
+                                .expect("failed to send detach message to worker")
+                        }
+                    })
+                    .button(messages::button_history(), {
+                        let tx_worker = tx_worker.clone();
+                        move |_| {
+                            task::block_on(tx_worker.send(WorkerMessage::HistoryRequested))
+                                .expect("failed to send history message to worker")
+                        }
+                    })
+                    .button(messages::button_quit(), move |s| {
+                        request_quit(s, &tx_worker, &pending_quit)
+                    })
+                    .with_name(MAIN_DIALOG),
+                );
+            }
 
-        s.refresh();
+            /// List the versions of the session, to upload one of them again
+            fn dialog_history(
+                s: &mut Cursive,
+                versions: Vec<HistoryEntry>,
+                tx_worker: channel_stats::Sender<WorkerMessage>,
+            ) {
+                if versions.is_empty() {
+                    s.add_layer(
+                        Dialog::info(messages::history_empty()).title(messages::history_title()),
+                    );
+                    return;
+                }
 
-        let mut header = String::new();
+                let mut list =
+                    SelectView::<String>::new().on_submit(move |s, checksum: &String| {
+                        dialog_restore(s, checksum.clone(), tx_worker.clone())
+                    });
+                for version in versions {
+                    list.add_item(
+                        messages::history_entry(&version.at, version.bytes),
+                        version.checksum,
+                    );
+                }
 
-        loop {
-            s.step();
-            if !s.is_running() {
-                break;
+                s.add_layer(
+                    Dialog::around(list.scrollable().max_height(15))
+                        .title(messages::history_title())
+                        .dismiss_button(messages::button_cancel()),
+                );
             }
 
-            let mut needs_refresh = false;
-            for m in rx_notification.try_iter() {
-                match m {
-                    WorkerNotification::Details { title, question_id } => {
-                        header = format!("Working on question '{}' (id: {})", title, question_id);
+            /// Ask how to restore the chosen version
+            fn dialog_restore(
+                s: &mut Cursive,
+                checksum: String,
+                tx_worker: channel_stats::Sender<WorkerMessage>,
+            ) {
+                let restore = move |play: bool| {
+                    let checksum = checksum.clone();
+                    let tx_worker = tx_worker.clone();
+                    move |s: &mut Cursive| {
+                        let also_local = s
+                            .call_on_name(RESTORE_LOCAL_CHECKBOX, |view: &mut Checkbox| {
+                                view.is_checked()
+                            })
+                            .unwrap_or(false);
+
+                        // The restore dialog and the history
+                        s.pop_layer();
+                        s.pop_layer();
 
-                        dialog_initial(&mut s, &header, tx_worker.clone());
+                        task::block_on(tx_worker.send(WorkerMessage::Restore {
+                            checksum: checksum.clone(),
+                            play,
+                            also_local,
+                        }))
+                        .expect("failed to send restore message to worker")
                     }
-                    WorkerNotification::Initialized => {
-                        // Show running screen
-                        dialog_running(&mut s, &header, tx_worker.clone(), opts.clone());
+                };
+
+                s.add_layer(
+                    Dialog::around(
+                        LinearLayout::horizontal()
+                            .child(Checkbox::new().with_name(RESTORE_LOCAL_CHECKBOX))
+                            .child(TextView::new(messages::label_restore_local())),
+                    )
+                    .title(messages::history_title())
+                    .button(messages::button_upload(), restore(false))
+                    .button(messages::button_upload_and_play(), restore(true))
+                    .dismiss_button(messages::button_cancel()),
+                );
+            }
+
+            /// Contents of the optional views of the running dialog, kept while it is rebuilt
+            struct RunningPanes {
+                /// With --tui-show-code-preview
+                preview: Option<String>,
+                /// With --show-diff-in-tui
+                pending_diff: Option<StyledString>,
+            }
+
+            /// Notes of the target, if it has some
+            type SharedNotes = Rc<RefCell<Option<(PathBuf, String)>>>;
+
+            /// Add a button opening the notes of the target, if it has some
+            fn notes_button(dialog: Dialog, notes: &SharedNotes) -> Dialog {
+                if notes.borrow().is_none() {
+                    return dialog;
+                }
+
+                let notes = notes.clone();
+                dialog.button(messages::button_notes(), move |s| dialog_notes(s, &notes))
+            }
+
+            /// Show the notes read-only, refreshed when they change
+            fn dialog_notes(s: &mut Cursive, notes: &SharedNotes) {
+                if let Some((path, contents)) = &*notes.borrow() {
+                    let title = path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+
+                    s.add_layer(
+                        Dialog::around(
+                            TextView::new(contents.as_str())
+                                .with_name(NOTES_VIEW)
+                                .scrollable()
+                                .max_height(20)
+                                .max_width(100),
+                        )
+                        .title(title)
+                        .dismiss_button("Ok"),
+                    );
+                }
+            }
+
+            /// Quit, or with --upload-before-quit first ask the worker whether the target has
+            /// changes to upload. Quitting again while the upload is pending quits right away.
+            fn request_quit(
+                s: &mut Cursive,
+                tx_worker: &channel_stats::Sender<WorkerMessage>,
+                pending_quit: &Option<Arc<AtomicBool>>,
+            ) {
+                match pending_quit {
+                    Some(pending_quit) if !pending_quit.load(Ordering::Relaxed) => {
+                        if s.find_name::<Dialog>(QUIT_DIALOG).is_none() {
+                            task::block_on(tx_worker.send(WorkerMessage::QuitRequested))
+                                .expect("failed to send quit message to worker")
+                        }
                     }
-                    WorkerNotification::Stopped => {
-                        // Go back to question screen
-                        dialog_initial(&mut s, &header, tx_worker.clone());
+                    _ => s.quit(),
+                }
+            }
+
+            /// Ask whether to upload the changed code before quitting
+            fn dialog_quit(
+                s: &mut Cursive,
+                code: Code,
+                tx_worker: channel_stats::Sender<WorkerMessage>,
+                pending_quit: Arc<AtomicBool>,
+            ) {
+                s.add_layer(
+                    Dialog::text(messages::upload_before_quit())
+                        .title(messages::APP_TITLE)
+                        .button(messages::button_yes(), move |s| {
+                            s.pop_layer();
+
+                            // Quit once the upload is done
+                            pending_quit.store(true, Ordering::Relaxed);
+                            let code = code.clone();
+                            task::block_on(
+                                tx_worker.send(WorkerMessage::FileChanged { code, bundle: None }),
+                            )
+                            .expect("failed to send file changed message to worker")
+                        })
+                        .button(messages::button_no(), |s| s.quit())
+                        .button(messages::button_cancel(), |s| {
+                            s.pop_layer();
+                        })
+                        .with_name(QUIT_DIALOG),
+                );
+            }
+
+            /// Ask whether to upload the changed code, replacing the previous question if it wasn't
+            /// answered
+            fn dialog_confirm_upload(
+                s: &mut Cursive,
+                code: Code,
+                bytes: usize,
+                delta: i64,
+                diff: Option<Diff>,
+                tx_worker: channel_stats::Sender<WorkerMessage>,
+            ) {
+                if let Some(position) = s.screen_mut().find_layer_from_name(CONFIRM_DIALOG) {
+                    s.screen_mut().remove_layer(position);
+                }
+
+                let confirm = move |upload: bool, always: bool| {
+                    let tx_worker = tx_worker.clone();
+                    let code = code.clone();
+                    move |s: &mut Cursive| {
+                        s.pop_layer();
+
+                        task::block_on(async {
+                            tx_worker
+                                .send(WorkerMessage::UploadConfirmed { upload, always })
+                                .await?;
+                            if upload {
+                                tx_worker
+                                    .send(WorkerMessage::FileChanged {
+                                        code: code.clone(),
+                                        bundle: None,
+                                    })
+                                    .await?;
+                            }
+                            Ok::<_, channel::SendError<WorkerMessage>>(())
+                        })
+                        .expect("failed to send upload confirmation to worker")
                     }
-                    WorkerNotification::Terminate => {
-                        s.quit();
+                };
+
+                let mut layout = LinearLayout::vertical()
+                    .child(TextView::new(messages::confirm_upload(bytes, delta)));
+                if let Some(diff) = diff {
+                    layout.add_child(ScrollView::new(TextView::new(diff.render_styled())));
+                }
+
+                s.add_layer(
+                    Dialog::around(layout)
+                        .title(messages::APP_TITLE)
+                        .button(messages::button_upload(), confirm(true, false))
+                        .button(messages::button_skip(), confirm(false, false))
+                        .button(messages::button_always(), confirm(true, true))
+                        .with_name(CONFIRM_DIALOG),
+                );
+            }
+
+            /// List the sessions of the configuration file, switching to the chosen one
+            fn dialog_sessions(
+                s: &mut Cursive,
+                sessions: &[(String, String)],
+                tx_worker: &channel_stats::Sender<WorkerMessage>,
+                dynamic: &Arc<Mutex<DynamicOpts>>,
+            ) {
+                if sessions.is_empty() {
+                    s.add_layer(
+                        Dialog::info(messages::no_sessions()).title(messages::sessions_title()),
+                    );
+                    return;
+                }
+
+                let active = task::block_on(async { dynamic.lock().await.session.clone() });
+                let mut list = SelectView::new();
+                for (name, target) in sessions {
+                    let marker = if active.as_ref() == Some(name) {
+                        '*'
+                    } else {
+                        ' '
+                    };
+                    list.add_item(format!("{} {} ({})", marker, name, target), name.clone());
+                }
+
+                let tx_worker = tx_worker.clone();
+                list.set_on_submit(move |s, name: &String| {
+                    s.pop_layer();
+
+                    task::block_on(
+                        tx_worker.send(WorkerMessage::SwitchSession { name: name.clone() }),
+                    )
+                    .expect("failed to send switch session message to worker")
+                });
+
+                s.add_layer(
+                    Dialog::around(list.scrollable())
+                        .title(messages::sessions_title())
+                        .dismiss_button(messages::button_cancel()),
+                );
+            }
+
+            /// Ask whether the IDE code overwrites the target saved since the download was requested,
+            /// or since it was last synchronized with --two-way
+            fn dialog_download_conflict(
+                s: &mut Cursive,
+                question: String,
+                diff: Option<Diff>,
+                tx_worker: channel_stats::Sender<WorkerMessage>,
+            ) {
+                let resolve = move |overwrite: bool| {
+                    let tx_worker = tx_worker.clone();
+                    move |s: &mut Cursive| {
+                        s.pop_layer();
+
+                        task::block_on(
+                            tx_worker.send(WorkerMessage::DownloadConflictResolved { overwrite }),
+                        )
+                        .expect("failed to send download conflict resolution to worker")
                     }
+                };
+
+                let mut layout = LinearLayout::vertical().child(TextView::new(question));
+                if let Some(diff) = diff {
+                    layout.add_child(ScrollView::new(TextView::new(diff.render_styled())));
                 }
 
-                needs_refresh = true;
+                s.add_layer(
+                    Dialog::around(layout)
+                        .title(messages::APP_TITLE)
+                        .button(messages::button_overwrite(), resolve(true))
+                        .button(messages::button_keep_local(), resolve(false)),
+                );
+            }
+
+            let notes: SharedNotes = Rc::new(RefCell::new(None));
+
+            let pending_quit = if upload_before_quit {
+                Some(Arc::new(AtomicBool::new(false)))
+            } else {
+                None
+            };
+
+            s.add_global_callback('q', {
+                let tx_worker = tx_worker.clone();
+                let pending_quit = pending_quit.clone();
+                move |s| request_quit(s, &tx_worker, &pending_quit)
+            });
+
+            s.add_global_callback('s', {
+                let tx_worker = tx_worker.clone();
+                let dynamic = dynamic.clone();
+                move |s| dialog_sessions(s, &sessions, &tx_worker, &dynamic)
+            });
+
+            if show_status_bar {
+                s.screen_mut().add_transparent_layer_at(
+                    XY::absolute((0, 0)),
+                    LinearLayout::vertical()
+                        .child(DummyView.full_height())
+                        .child(TextView::new("").with_name("status_bar"))
+                        .full_screen(),
+                );
+                s.set_user_data(BaseLayers(1));
             }
 
-            if needs_refresh {
-                s.refresh();
+            let mut status = StatusBar {
+                session: task::block_on(async { dynamic.lock().await.session.clone() }),
+                ..StatusBar::default()
+            };
+            let mut status_text = String::new();
+
+            let mut target = task::block_on(async { dynamic.lock().await.source_label() });
+            let mut header = String::new();
+            // Extension settings shown while waiting, again once the session is detached
+            let mut settings = String::new();
+
+            dialog_waiting(
+                &mut s,
+                "",
+                &target,
+                tx_worker.clone(),
+                dynamic.clone(),
+                pending_quit.clone(),
+            );
+
+            s.refresh();
+
+            let mut panes = RunningPanes {
+                preview: if show_code_preview {
+                    Some(String::new())
+                } else {
+                    None
+                },
+                pending_diff: if show_diff {
+                    Some(StyledString::plain(messages::no_pending_diff()))
+                } else {
+                    None
+                },
+            };
+
+            loop {
+                s.step();
+                if !s.is_running() {
+                    break;
+                }
+
+                // Wait for the next notification, but no longer than the refresh rate so user input
+                // keeps being processed
+                let first = match rx_notification.recv_timeout(tui_refresh_rate) {
+                    Ok(m) => Some(m),
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => None,
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                };
+
+                let mut needs_refresh = false;
+                for m in first.into_iter().chain(rx_notification.try_iter()) {
+                    match m {
+                        WorkerNotification::Listening { addrs, socket } => {
+                            // Still waiting for the IDE, show where it should connect
+                            settings = extension_settings(&addrs, socket.as_deref());
+
+                            if header.is_empty() {
+                                dialog_waiting(
+                                    &mut s,
+                                    &settings,
+                                    &target,
+                                    tx_worker.clone(),
+                                    dynamic.clone(),
+                                    pending_quit.clone(),
+                                );
+                            }
+                        }
+                        // Back to the question screen, to choose how to go on with the target
+                        WorkerNotification::Details { title, question_id }
+                        | WorkerNotification::QuestionChanged { title, question_id } => {
+                            header = messages::working_on_question(&title, question_id);
+                            status.question = Some(title);
+
+                            dialog_initial(
+                                &mut s,
+                                &header,
+                                &target,
+                                tx_worker.clone(),
+                                dynamic.clone(),
+                                pending_quit.clone(),
+                                &notes,
+                            );
+                        }
+                        WorkerNotification::DetailsUpdated { title, question_id } => {
+                            // Keep the current screen, only name the question
+                            header = messages::working_on_question(&title, question_id);
+                            status.question = Some(title);
+
+                            s.call_on_name(HEADER_VIEW, |view: &mut TextView| {
+                                view.set_content(header.clone())
+                            });
+                        }
+                        WorkerNotification::DetailsMissing => {
+                            // Also kept on the waiting screen once the question is dismissed
+                            s.call_on_name(WAITING_VIEW, |view: &mut TextView| {
+                                view.set_content(format!(
+                                    "{}\n\n{}\n",
+                                    messages::ide_without_details(),
+                                    settings
+                                ))
+                            });
+
+                            let tx_worker = tx_worker.clone();
+                            s.add_layer(
+                                Dialog::text(messages::details_missing())
+                                    .title(messages::APP_TITLE)
+                                    .button(messages::button_yes(), move |s| {
+                                        s.pop_layer();
+                                        task::block_on(
+                                            tx_worker.send(WorkerMessage::AcceptUnknownQuestion),
+                                        )
+                                        .expect("failed to send accept message to worker")
+                                    })
+                                    .button(messages::button_no(), |s| {
+                                        s.pop_layer();
+                                    }),
+                            );
+                        }
+                        WorkerNotification::Initialized => {
+                            // Show running screen
+                            dialog_running(
+                                &mut s,
+                                &header,
+                                tx_worker.clone(),
+                                dynamic.clone(),
+                                pending_quit.clone(),
+                                &notes,
+                                &panes,
+                            );
+                        }
+                        WorkerNotification::Stopped => {
+                            // Go back to question screen
+                            dialog_initial(
+                                &mut s,
+                                &header,
+                                &target,
+                                tx_worker.clone(),
+                                dynamic.clone(),
+                                pending_quit.clone(),
+                                &notes,
+                            );
+                        }
+                        WorkerNotification::Detached => {
+                            // Back to waiting for a question
+                            header.clear();
+                            status.question = None;
+                            if panes.pending_diff.is_some() {
+                                panes.pending_diff =
+                                    Some(StyledString::plain(messages::no_pending_diff()));
+                            }
+
+                            dialog_waiting(
+                                &mut s,
+                                &settings,
+                                &target,
+                                tx_worker.clone(),
+                                dynamic.clone(),
+                                pending_quit.clone(),
+                            );
+                        }
+                        WorkerNotification::CodePreview { snippet } => {
+                            s.call_on_name("code_preview", |view: &mut TextView| {
+                                view.set_content(snippet.to_string())
+                            });
+
+                            panes.preview = Some(snippet.into_string());
+                        }
+                        WorkerNotification::PendingDiff { diff } => {
+                            let rendered = match diff {
+                                Some(diff) => diff.render_styled(),
+                                None => StyledString::plain(messages::no_pending_diff()),
+                            };
+
+                            s.call_on_name(PENDING_DIFF_VIEW, |view: &mut TextView| {
+                                view.set_content(rendered.clone())
+                            });
+
+                            panes.pending_diff = Some(rendered);
+                        }
+                        WorkerNotification::SessionSwitched { name } => {
+                            status.session = Some(name);
+                        }
+                        WorkerNotification::TargetChanged { path } => {
+                            target = path.display().to_string();
+
+                            s.call_on_name("target", |view: &mut TextView| {
+                                view.set_content(messages::target_label(&target))
+                            });
+
+                            // The language follows the target
+                            let title = screen_title(&dynamic);
+                            s.call_on_name(MAIN_DIALOG, |dialog: &mut Dialog| {
+                                dialog.set_title(title)
+                            });
+                        }
+                        WorkerNotification::Error { message } => {
+                            // Show on top of the current screen
+                            s.add_layer(Dialog::info(message).title(messages::error_title()));
+
+                            // The upload before quitting failed, let the user decide again
+                            if let Some(pending_quit) = &pending_quit {
+                                pending_quit.store(false, Ordering::Relaxed);
+                            }
+                        }
+                        WorkerNotification::Warning { message } => {
+                            s.add_layer(Dialog::info(message).title(messages::warning_title()));
+                        }
+                        WorkerNotification::EventsFiltered { count } => {
+                            status.filtered = count;
+                        }
+                        WorkerNotification::Flapping { flapping } => {
+                            status.flapping = flapping;
+
+                            if let Some(position) =
+                                s.screen_mut().find_layer_from_name(FLAPPING_DIALOG)
+                            {
+                                s.screen_mut().remove_layer(position);
+                            }
+
+                            if flapping {
+                                s.add_layer(
+                                    Dialog::info(messages::connection_flapping())
+                                        .title(messages::warning_title())
+                                        .with_name(FLAPPING_DIALOG),
+                                );
+                            }
+                        }
+                        WorkerNotification::Uploaded => {
+                            status.uploads += 1;
+
+                            if let Some(pending_quit) = &pending_quit {
+                                if pending_quit.load(Ordering::Relaxed) {
+                                    s.quit();
+                                }
+                            }
+                        }
+                        WorkerNotification::DownloadConflict { diff } => {
+                            dialog_download_conflict(
+                                &mut s,
+                                messages::download_conflict(),
+                                diff,
+                                tx_worker.clone(),
+                            );
+                        }
+                        WorkerNotification::SyncConflict { diff } => {
+                            dialog_download_conflict(
+                                &mut s,
+                                messages::sync_conflict(),
+                                diff,
+                                tx_worker.clone(),
+                            );
+                        }
+                        WorkerNotification::PendingUpload {
+                            code,
+                            bytes,
+                            delta,
+                            diff,
+                        } => {
+                            dialog_confirm_upload(
+                                &mut s,
+                                code,
+                                bytes,
+                                delta,
+                                diff,
+                                tx_worker.clone(),
+                            );
+                        }
+                        // Already visible through the code preview and the upload count
+                        WorkerNotification::ClipboardChanged { .. } => {}
+                        WorkerNotification::Downloaded { diff } => {
+                            status.downloads += 1;
+
+                            if let Some(diff) = diff {
+                                s.add_layer(
+                                    Dialog::around(ScrollView::new(TextView::new(
+                                        diff.render_styled(),
+                                    )))
+                                    .title(messages::download_diff_title())
+                                    .dismiss_button("Ok"),
+                                );
+                            }
+                        }
+                        WorkerNotification::SessionTimeout => {
+                            status.question = None;
+                            s.add_layer(Dialog::info(messages::session_timeout()));
+                        }
+                        WorkerNotification::ReadOnly { state } => {
+                            status.read_only = state;
+                        }
+                        WorkerNotification::ConnectionDenied { peer, reason } => {
+                            s.add_layer(
+                                Dialog::info(messages::connection_denied(
+                                    &peer.to_string(),
+                                    &reason,
+                                ))
+                                .title(messages::warning_title()),
+                            );
+                        }
+                        WorkerNotification::Connected { .. } => {
+                            status.connections = Some(status.connections.unwrap_or(0) + 1);
+                        }
+                        WorkerNotification::Disconnected { .. } => {
+                            status.connections = status
+                                .connections
+                                .map(|connections| connections.saturating_sub(1));
+                        }
+                        WorkerNotification::TakeoverRequested { peer } => {
+                            let answer = |takeover: bool| {
+                                let tx_worker = tx_worker.clone();
+                                move |s: &mut Cursive| {
+                                    s.pop_layer();
+                                    task::block_on(
+                                        tx_worker
+                                            .send(WorkerMessage::TakeoverAnswered { takeover }),
+                                    )
+                                    .expect("failed to send takeover answer to worker")
+                                }
+                            };
+
+                            s.add_layer(
+                                Dialog::text(messages::takeover_requested(&peer.to_string()))
+                                    .title(messages::APP_TITLE)
+                                    .button(messages::button_yes(), answer(true))
+                                    .button(messages::button_no(), answer(false)),
+                            );
+                        }
+                        WorkerNotification::Notes { path, contents } => {
+                            s.call_on_name(NOTES_VIEW, |view: &mut TextView| {
+                                view.set_content(
+                                    contents
+                                        .clone()
+                                        .unwrap_or_else(|| messages::notes_missing().to_string()),
+                                )
+                            });
+
+                            *notes.borrow_mut() = contents.map(|contents| (path, contents));
+                        }
+                        WorkerNotification::QuitCheck { code } => match (code, &pending_quit) {
+                            (Some(code), Some(pending_quit)) => {
+                                dialog_quit(&mut s, code, tx_worker.clone(), pending_quit.clone())
+                            }
+                            _ => s.quit(),
+                        },
+                        WorkerNotification::Provenance { state, last } => {
+                            status.sync_state = state;
+                            status.last_change = last;
+                        }
+                        WorkerNotification::History { versions } => {
+                            dialog_history(&mut s, versions, tx_worker.clone());
+                        }
+                        WorkerNotification::PlayDecided { reason } => {
+                            status.play = Some(reason);
+                        }
+                        WorkerNotification::UploadProgress { progress } => {
+                            status.sending = match progress {
+                                SendProgress::Done { .. } => None,
+                                progress => Some(progress),
+                            };
+                        }
+                        WorkerNotification::Terminate => {
+                            s.quit();
+                        }
+                    }
+
+                    needs_refresh = true;
+                }
+
+                if show_status_bar {
+                    // Also refreshed when only the clock changed
+                    let text = status.render();
+                    if text != status_text {
+                        s.call_on_name("status_bar", |view: &mut TextView| {
+                            view.set_content(text.clone())
+                        });
+
+                        status_text = text;
+                        needs_refresh = true;
+                    }
+                }
+
+                if needs_refresh {
+                    s.refresh();
+                }
             }
         }
     }
 
     // Terminate worker
-    task::block_on(tx_worker.send(WorkerMessage::Terminate))?;
-    join_handle.join().unwrap()?;
-
-    Ok(())
+    shutdown::shutdown(&tx_worker, join_handle, &heartbeat, shutdown_timeout)
 }
@@ -0,0 +1,177 @@
+//! Choice of the file to synchronize when a directory is given as the target.
+
+use std::path::{Path, PathBuf};
+
+use crate::language::is_source_file;
+use crate::{ErrorKind, Result, ResultExt};
+
+/// Names of the usual main files, picked over other source files
+pub const MAIN_FILE_NAMES: &[&str] = &["main.rs", "main.py", "main.cpp", "Main.java", "index.js"];
+
+/// Outcome of the main file heuristic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MainFile {
+    Found(PathBuf),
+    /// Several files are equally likely
+    Ambiguous(Vec<PathBuf>),
+    NotFound,
+}
+
+/// Pick the main file among the files of a directory: the only file with a usual main file
+/// name, or else the only source file
+pub fn pick(files: &[PathBuf]) -> MainFile {
+    let named: Vec<_> = files
+        .iter()
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| MAIN_FILE_NAMES.contains(&name))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    let candidates = if named.is_empty() {
+        files.iter().filter(|path| is_source_file(path)).collect()
+    } else {
+        named
+    };
+
+    match candidates.as_slice() {
+        [] => MainFile::NotFound,
+        [path] => MainFile::Found((*path).clone()),
+        _ => MainFile::Ambiguous(candidates.into_iter().cloned().collect()),
+    }
+}
+
+/// Files of the directory the heuristic chooses from, sorted, hidden files excluded
+pub fn files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .map(|name| name.to_string_lossy().starts_with('.'))
+            .unwrap_or(true);
+
+        if !hidden && path.is_file() {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Main file of the directory, or an error listing the candidates
+pub fn resolve(dir: &Path) -> Result<PathBuf> {
+    let files = files(dir).chain_err(|| ErrorKind::InvalidTarget(dir.display().to_string()))?;
+    choose(dir, &files)
+}
+
+/// Main file among the files of the directory, or an error listing the candidates
+pub fn choose(dir: &Path, files: &[PathBuf]) -> Result<PathBuf> {
+    match pick(files) {
+        MainFile::Found(path) => Ok(path),
+        MainFile::Ambiguous(candidates) => Err(ErrorKind::AmbiguousTarget(
+            dir.display().to_string(),
+            candidates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+        )
+        .into()),
+        MainFile::NotFound => Err(ErrorKind::NoMainFile(dir.display().to_string()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names
+            .iter()
+            .map(|name| Path::new("puzzle").join(name))
+            .collect()
+    }
+
+    #[test]
+    fn main_files() {
+        let table: &[(&[&str], MainFile)] = &[
+            // Single file
+            (&["main.py"], MainFile::Found("puzzle/main.py".into())),
+            (
+                &["solution.py"],
+                MainFile::Found("puzzle/solution.py".into()),
+            ),
+            (
+                &["notes.txt", "bot.rs"],
+                MainFile::Found("puzzle/bot.rs".into()),
+            ),
+            // Usual main file names over other source files
+            (
+                &["main.rs", "lib.rs", "utils.rs"],
+                MainFile::Found("puzzle/main.rs".into()),
+            ),
+            (
+                &["Helper.java", "Main.java"],
+                MainFile::Found("puzzle/Main.java".into()),
+            ),
+            (
+                &["index.js", "helpers.js", "README.md"],
+                MainFile::Found("puzzle/index.js".into()),
+            ),
+            // Several candidates
+            (
+                &["a.py", "b.py"],
+                MainFile::Ambiguous(paths(&["a.py", "b.py"])),
+            ),
+            (
+                &["main.py", "main.rs", "util.py"],
+                MainFile::Ambiguous(paths(&["main.py", "main.rs"])),
+            ),
+            // No candidate
+            (&[], MainFile::NotFound),
+            (&["README.md", "input.txt"], MainFile::NotFound),
+            // Only exact names are usual main files
+            (
+                &["main.py.bak", "Main.py"],
+                MainFile::Found("puzzle/Main.py".into()),
+            ),
+        ];
+
+        for (names, expected) in table {
+            assert_eq!(&pick(&paths(names)), expected, "{:?}", names);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_directory_and_the_candidates() {
+        let dir = Path::new("puzzle");
+
+        assert_eq!(
+            choose(dir, &paths(&["main.py"])).unwrap(),
+            dir.join("main.py")
+        );
+
+        match choose(dir, &paths(&["a.py", "b.py"])).unwrap_err().kind() {
+            ErrorKind::AmbiguousTarget(name, candidates) => {
+                assert_eq!(name, "puzzle");
+                assert_eq!(
+                    candidates,
+                    &vec![
+                        dir.join("a.py").display().to_string(),
+                        dir.join("b.py").display().to_string()
+                    ]
+                );
+            }
+            kind => panic!("unexpected error: {}", kind),
+        }
+
+        match choose(dir, &[]).unwrap_err().kind() {
+            ErrorKind::NoMainFile(name) => assert_eq!(name, "puzzle"),
+            kind => panic!("unexpected error: {}", kind),
+        }
+    }
+}
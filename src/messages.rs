@@ -0,0 +1,1193 @@
+//! Catalog of user-facing messages.
+//!
+//! Every string shown in the text user interface, reported by the no-gui mode or carried by
+//! worker notifications is built here, so wording stays consistent. The catalog language is
+//! picked from the `CG_LOCAL_LANG` environment variable (`en` or `fr`), defaulting to English.
+//!
+//! Every message is rendered in both languages into `tests/snapshots/messages.txt`, so wording
+//! changes show up in review. The snapshot is regenerated with `UPDATE_SNAPSHOTS=1 cargo test`.
+
+use std::sync::OnceLock;
+
+use crate::play_policy::PlayReason;
+use crate::provenance::{Provenance, SyncState};
+
+/// Available catalog languages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Fr,
+}
+
+impl Lang {
+    /// Parse a language code such as `fr` or `fr_FR.UTF-8`
+    pub fn parse(code: &str) -> Option<Self> {
+        match code.get(..2).map(str::to_ascii_lowercase).as_deref() {
+            Some("en") => Some(Lang::En),
+            Some("fr") => Some(Lang::Fr),
+            _ => None,
+        }
+    }
+
+    fn from_env() -> Self {
+        std::env::var("CG_LOCAL_LANG")
+            .ok()
+            .and_then(|code| Lang::parse(&code))
+            .unwrap_or(Lang::En)
+    }
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    /// Language of the messages built by the test running on the thread
+    static TEST_LANG: std::cell::Cell<Option<Lang>> = const { std::cell::Cell::new(None) };
+}
+
+/// Language used for all messages
+pub fn lang() -> Lang {
+    #[cfg(test)]
+    if let Some(lang) = TEST_LANG.with(std::cell::Cell::get) {
+        return lang;
+    }
+
+    *LANG.get_or_init(Lang::from_env)
+}
+
+macro_rules! catalog {
+    ($($(#[$meta:meta])* $name:ident($($arg:ident: $ty:ty),*) { en: $en:expr, fr: $fr:expr $(,)? })*) => {
+        $(
+            $(#[$meta])*
+            pub fn $name($($arg: $ty),*) -> String {
+                match lang() {
+                    Lang::En => $en.into(),
+                    Lang::Fr => $fr.into(),
+                }
+            }
+        )*
+
+        /// Every message of the catalog with its arguments named after their parameters, in the
+        /// current language
+        #[cfg(test)]
+        fn catalog_samples() -> Vec<(&'static str, String)> {
+            vec![$((stringify!($name), $name($(tests::Sample::sample(stringify!($arg))),*))),*]
+        }
+    };
+}
+
+/// Title of the text user interface dialogs
+pub const APP_TITLE: &str = "cg-local-app.rs";
+
+catalog! {
+    /// Waiting screen text
+    waiting_for_ide() {
+        en: "Waiting for IDE to connect.",
+        fr: "En attente de connexion de l'IDE.",
+    }
+
+    /// Header of the question screens
+    working_on_question(title: &str, question_id: i32) {
+        en: format!("Working on question '{}' (id: {})", title, question_id),
+        fr: format!("Question en cours : '{}' (id : {})", title, question_id),
+    }
+
+    /// Logged when the extension identifies another question than --question-id-override
+    question_override_superseded(title: &str, question_id: i32, actual_title: &str, actual_id: i32) {
+        en: format!(
+            "The extension is on question '{}' (id: {}), replacing '{}' (id: {}) from the options",
+            actual_title, actual_id, title, question_id
+        ),
+        fr: format!(
+            "L'extension est sur la question '{}' (id : {}), qui remplace '{}' (id : {}) des options",
+            actual_title, actual_id, title, question_id
+        ),
+    }
+
+    /// Reported when synchronization starts
+    sync_started() {
+        en: "Synchronization started",
+        fr: "Synchronisation démarrée",
+    }
+
+    /// Reported when synchronization stops
+    sync_stopped() {
+        en: "Synchronization stopped",
+        fr: "Synchronisation arrêtée",
+    }
+
+    /// Reported without the interface when the worker can't be reached anymore
+    worker_stopped() {
+        en: "Synchronization stopped unexpectedly, exiting",
+        fr: "La synchronisation s'est arrêtée de façon inattendue, fermeture",
+    }
+
+    /// Reported when the interface stopped receiving notifications, terminating the worker
+    notification_receiver_dropped() {
+        en: "The interface is gone, terminating",
+        fr: "L'interface s'est arrêtée, arrêt en cours",
+    }
+
+    /// Reported when the listener for the extension stopped, e.g. on an address already in use
+    listener_failed(reason: &str) {
+        en: format!("Not listening for the extension anymore: {}", reason),
+        fr: format!("Plus en écoute de l'extension : {}", reason),
+    }
+
+    /// Logged when a client connects from an address outside of --allow-ip
+    peer_not_allowed(peer: &str) {
+        en: format!("Closed connection from {}: address not allowed by --allow-ip", peer),
+        fr: format!("Connexion de {} fermée : adresse non autorisée par --allow-ip", peer),
+    }
+
+    /// Logged when the --bind port is taken and the listener fell back to another one
+    port_fallback(taken: u16, chosen: u16) {
+        en: format!("Port {} is already in use, listening on port {} instead: set the port to {} in the extension options", taken, chosen, chosen),
+        fr: format!("Le port {} est déjà utilisé, écoute sur le port {} à la place : réglez le port à {} dans les options de l'extension", taken, chosen, chosen),
+    }
+
+    /// Logged when a client doesn't give the --auth-token
+    auth_failed(peer: &str) {
+        en: format!("Rejected connection from {}: missing or invalid --auth-token", peer),
+        fr: format!("Connexion de {} refusée : --auth-token manquant ou invalide", peer),
+    }
+
+    /// Logged when a client other than the CodinGame extension connects, see --no-origin-check
+    connection_rejected(peer: &str, reason: &str) {
+        en: format!("Rejected connection from {}: {}. Pass --no-origin-check to accept other clients than the CodinGame extension", peer, reason),
+        fr: format!("Connexion de {} refusée : {}. Passez --no-origin-check pour accepter d'autres clients que l'extension CodinGame", peer, reason),
+    }
+
+    /// Logged when a browser extension connects without --extension-id
+    extension_not_pinned(id: &str) {
+        en: format!("Accepting browser extension {}. Pass --extension-id {} to accept no other extension", id, id),
+        fr: format!("Extension de navigateur {} acceptée. Passez --extension-id {} pour n'accepter aucune autre extension", id, id),
+    }
+
+    /// Asked when the extension connects again while a connection is open, e.g. from a refreshed
+    /// browser tab whose old socket lingers, see --takeover
+    takeover_requested(peer: &str) {
+        en: format!("The extension connected again from {} while another connection is open.\n\nClose the open connection and use the new one?", peer),
+        fr: format!("L'extension s'est reconnectée depuis {} alors qu'une autre connexion est ouverte.\n\nFermer la connexion ouverte et utiliser la nouvelle ?", peer),
+    }
+
+    /// Shown when a connection of the extension is denied, e.g. from another tab
+    connection_denied(peer: &str, reason: &str) {
+        en: format!("Another tab tried to connect from {} and was denied: {}. Pass --takeover to let the newest tab take over, or raise --max-connections", peer, reason),
+        fr: format!("Un autre onglet a tenté de se connecter depuis {} et a été refusé : {}. Passez --takeover pour que le dernier onglet prenne la main, ou augmentez --max-connections", peer, reason),
+    }
+
+    /// Reason of a denied connection, all the connections allowed by --max-connections being open
+    denied_connections_full(max_connections: usize) {
+        en: format!("{} connection(s) already open", max_connections),
+        fr: format!("{} connexion(s) déjà ouverte(s)", max_connections),
+    }
+
+    /// Reason of a denied connection, the user being asked about another one
+    denied_takeover_pending() {
+        en: "the takeover by another connection is being asked",
+        fr: "la prise de main par une autre connexion est en cours de confirmation",
+    }
+
+    /// Logged when another connection of the extension identified a question, the messages for
+    /// the extension going to it from now on
+    connection_switched(connection: &str) {
+        en: format!("Connection {} identified a question, synchronizing with it", connection),
+        fr: format!("La connexion {} a identifié une question, synchronisation avec elle", connection),
+    }
+
+    /// Reported when the session is detached from the question
+    session_detached() {
+        en: "Detached from the question, the IDE is editable again",
+        fr: "Détaché de la question, l'IDE est à nouveau modifiable",
+    }
+
+    /// Logged when --session selects the initial session
+    session_started(name: &str) {
+        en: format!("Starting in session '{}'", name),
+        fr: format!("Démarrage dans la session '{}'", name),
+    }
+
+    /// Logged when another session of the configuration file is active
+    session_switched(name: &str) {
+        en: format!("Switched to session '{}'", name),
+        fr: format!("Passage à la session '{}'", name),
+    }
+
+    /// Warning when the extension identifies another question than the one of the session
+    session_question_mismatch(name: &str, expected_id: i32, title: &str, question_id: i32) {
+        en: format!(
+            "The extension is on question '{}' (id: {}), not on question {} of session '{}'",
+            title, question_id, expected_id, name
+        ),
+        fr: format!(
+            "L'extension est sur la question '{}' (id : {}), pas sur la question {} de la session '{}'",
+            title, question_id, expected_id, name
+        ),
+    }
+
+    /// Logged when the extension identifies another question in the same tab
+    question_changed(previous_title: &str, previous_id: i32, title: &str, question_id: i32) {
+        en: format!(
+            "The extension moved from question '{}' (id: {}) to '{}' (id: {})",
+            previous_title, previous_id, title, question_id
+        ),
+        fr: format!(
+            "L'extension est passée de la question '{}' (id : {}) à '{}' (id : {})",
+            previous_title, previous_id, title, question_id
+        ),
+    }
+
+    /// Warning when a new question isn't synchronized with the target of the previous one, without
+    /// the interface to choose
+    question_changed_not_started(title: &str, question_id: i32) {
+        en: format!(
+            "Not synchronizing question '{}' (id: {}) with the target of another question: go back to that question, or give this one its own target with a session of the configuration file",
+            title, question_id
+        ),
+        fr: format!(
+            "Pas de synchronisation de la question '{}' (id : {}) avec la cible d'une autre question : revenez à cette question, ou donnez à celle-ci sa propre cible avec une session du fichier de configuration",
+            title, question_id
+        ),
+    }
+
+    /// Warning when the extension identifies a question --expect-question doesn't allow
+    unexpected_question(title: &str, question_id: i32, expected: &str) {
+        en: format!(
+            "Not synchronizing question '{}' (id: {}): --expect-question only allows {}",
+            title, question_id, expected
+        ),
+        fr: format!(
+            "Pas de synchronisation de la question '{}' (id : {}) : --expect-question n'autorise que {}",
+            title, question_id, expected
+        ),
+    }
+
+    /// Warning when an unknown question is accepted with --expect-question
+    unknown_question_not_expected(expected: &str) {
+        en: format!("Not synchronizing an unknown question: --expect-question only allows {}", expected),
+        fr: format!("Pas de synchronisation d'une question inconnue : --expect-question n'autorise que {}", expected),
+    }
+
+    /// Title of the sessions menu
+    sessions_title() {
+        en: "Sessions",
+        fr: "Sessions",
+    }
+
+    /// Shown by the sessions menu without sessions in the configuration file
+    no_sessions() {
+        en: "No sessions in the configuration file. Add [session.<name>] tables with a target to switch between them.",
+        fr: "Aucune session dans le fichier de configuration. Ajoutez des tables [session.<nom>] avec une cible pour passer de l'une à l'autre.",
+    }
+
+    /// Reported when the local file was updated from the IDE
+    code_downloaded() {
+        en: "Updated code from IDE",
+        fr: "Code mis à jour depuis l'IDE",
+    }
+
+    /// Upload refused because of `--code-max-lines`
+    too_many_lines(lines: usize, max_lines: usize) {
+        en: format!("Not uploading: the code has {} lines, more than the limit of {}", lines, max_lines),
+        fr: format!("Envoi annulé : le code compte {} lignes, au-delà de la limite de {}", lines, max_lines),
+    }
+
+    /// Reported when the target file is deleted
+    target_deleted() {
+        en: "Target file was deleted, uploads are paused until it reappears",
+        fr: "Le fichier cible a été supprimé, les envois reprendront à sa réapparition",
+    }
+
+    /// Reported when a deleted target file is created again
+    target_reappeared() {
+        en: "Target file reappeared, resuming uploads",
+        fr: "Le fichier cible est réapparu, reprise des envois",
+    }
+
+    /// Reported when the deleted target file was re-created from the last upload
+    target_recreated() {
+        en: "Target file was deleted, re-created it with the last uploaded code",
+        fr: "Le fichier cible a été supprimé, il a été recréé avec le dernier code envoyé",
+    }
+
+    /// Reported when running without the text user interface because it can't work
+    tui_fallback(reason: &str) {
+        en: format!("Text user interface unavailable ({}), running as with --no-gui. Use --force-tui to override", reason),
+        fr: format!("Interface texte indisponible ({}), exécution comme avec --no-gui. Utilisez --force-tui pour passer outre", reason),
+    }
+
+    /// Reported when the target was renamed and --follow-renames is off
+    target_renamed(path: &str) {
+        en: format!("Target file was renamed to {}, pass --follow-renames to keep synchronizing it", path),
+        fr: format!("Le fichier cible a été renommé en {}, utilisez --follow-renames pour continuer à le synchroniser", path),
+    }
+
+    /// Title of error dialogs
+    error_title() {
+        en: "Error",
+        fr: "Erreur",
+    }
+
+    /// Button starting synchronization by uploading the local file
+    button_upload() {
+        en: "Upload",
+        fr: "Envoyer",
+    }
+
+    /// Button starting synchronization by downloading the IDE code
+    button_download() {
+        en: "Download",
+        fr: "Télécharger",
+    }
+
+    /// Button stopping synchronization
+    button_stop_sync() {
+        en: "Stop sync",
+        fr: "Arrêter la synchro",
+    }
+
+    /// Button stopping synchronization and forgetting the question
+    button_detach() {
+        en: "Detach",
+        fr: "Détacher",
+    }
+
+    /// Button quitting the application
+    button_quit() {
+        en: "Quit",
+        fr: "Quitter",
+    }
+
+    /// Button accepting a question
+    button_yes() {
+        en: "Yes",
+        fr: "Oui",
+    }
+
+    /// Button refusing a question
+    button_no() {
+        en: "No",
+        fr: "Non",
+    }
+
+    /// Asked when quitting with --upload-before-quit and the target changed since the last upload
+    upload_before_quit() {
+        en: "The target changed since the last upload. Upload the latest version before quitting?",
+        fr: "La cible a changé depuis le dernier envoi. Envoyer la dernière version avant de quitter ?",
+    }
+
+    /// Label of the auto-play checkbox
+    label_play_on_upload() {
+        en: "Play on upload",
+        fr: "Lancer à l'envoi",
+    }
+
+    /// Button opening the target file browser
+    button_browse() {
+        en: "Browse…",
+        fr: "Parcourir…",
+    }
+
+    /// Button closing a dialog without doing anything
+    button_cancel() {
+        en: "Cancel",
+        fr: "Annuler",
+    }
+
+    /// Title of the target file browser
+    browse_title() {
+        en: "Select the target file",
+        fr: "Choisir le fichier cible",
+    }
+
+    /// Label of the checkbox showing hidden files in the file browser
+    label_show_hidden() {
+        en: "Show hidden files",
+        fr: "Afficher les fichiers cachés",
+    }
+
+    /// Target shown on the waiting and question screens
+    target_label(path: &str) {
+        en: format!("Target: {}", path),
+        fr: format!("Cible : {}", path),
+    }
+
+    /// Language inferred from the target with --auto-detect-language
+    language_detected(language: &str) {
+        en: format!("Detected language: {}", language),
+        fr: format!("Langage détecté : {}", language),
+    }
+
+    /// Reported when the target was changed at runtime
+    target_changed(path: &str) {
+        en: format!("Now synchronizing {}", path),
+        fr: format!("Synchronisation de {}", path),
+    }
+
+    /// Title of the warning dialogs
+    warning_title() {
+        en: "Warning",
+        fr: "Attention",
+    }
+
+    /// Reported when the play policy denies auto-play for the question
+    play_policy_denied(title: &str) {
+        en: format!("Auto-play is disabled for '{}' by the play policy", title),
+        fr: format!("La lecture automatique est désactivée pour '{}' par la politique de lecture", title),
+    }
+
+    /// Shown before the first played upload on a question the play policy warns about
+    play_policy_warning(title: &str) {
+        en: format!("Auto-play is enabled on '{}', which the play policy flags: uploads will be played", title),
+        fr: format!("La lecture automatique est activée pour '{}', signalée par la politique de lecture : les envois seront joués", title),
+    }
+
+    /// Reported when --upload-validate-json refuses an upload
+    invalid_json(line: usize, column: usize, error: &str) {
+        en: format!("Not uploading invalid JSON (line {}, column {}): {}", line, column, error),
+        fr: format!("JSON invalide non envoyé (ligne {}, colonne {}) : {}", line, column, error),
+    }
+
+    /// Title of the changes made to the local file by a download
+    download_diff_title() {
+        en: "Changes made by the download",
+        fr: "Modifications apportées par le téléchargement",
+    }
+
+    /// Reported when the connection is closed by --session-timeout
+    session_timeout() {
+        en: "The IDE stopped responding, connection closed",
+        fr: "L'IDE ne répond plus, connexion fermée",
+    }
+
+    /// Reported when the target has a notes file
+    notes_found(path: &str) {
+        en: format!("Notes for this target: {}", path),
+        fr: format!("Notes de cette cible : {}", path),
+    }
+
+    /// Button opening the notes of the target
+    button_notes() {
+        en: "Notes",
+        fr: "Notes",
+    }
+
+    /// Shown in the notes dialog when the notes file was removed
+    notes_missing() {
+        en: "The notes file was removed",
+        fr: "Le fichier de notes a été supprimé",
+    }
+
+    /// Source of the code shown instead of the target with --input-from-clipboard alone
+    clipboard_source() {
+        en: "clipboard",
+        fr: "presse-papiers",
+    }
+
+    /// Reported on each change of the clipboard with --input-from-clipboard
+    clipboard_changed(bytes: usize) {
+        en: format!("Clipboard changed, uploading {} bytes", bytes),
+        fr: format!("Presse-papiers modifié, envoi de {} octets", bytes),
+    }
+
+    /// Reported when downloading with --input-from-clipboard and no target
+    no_download_target() {
+        en: "Not downloading: there is no target file to write the code to",
+        fr: "Téléchargement ignoré : aucun fichier cible où écrire le code",
+    }
+
+    /// Reported when the --upload-inject-test-input file can't be read, the code being uploaded
+    /// without it
+    test_input_unreadable(path: &str, error: &str) {
+        en: format!("Uploading without test input, failed to read {}: {}", path, error),
+        fr: format!("Envoi sans entrée de test, impossible de lire {} : {}", path, error),
+    }
+
+    /// Reported once when the target is over --max-file-size, until it gets under it again
+    file_too_large(path: &str, size: u64, max_size: u64) {
+        en: format!("{} is {} bytes, over the limit of {} bytes: it is not read nor uploaded. Pass a larger --max-file-size if this file is intended", path, size, max_size),
+        fr: format!("{} fait {} octets, au-delà de la limite de {} octets : il n'est ni lu ni envoyé. Passez une valeur plus grande à --max-file-size si ce fichier est voulu", path, size, max_size),
+    }
+
+    /// Reported when the target gets under --max-file-size again
+    file_size_ok(path: &str) {
+        en: format!("{} is under the size limit again, synchronizing it", path),
+        fr: format!("{} est de nouveau sous la limite de taille, synchronisation", path),
+    }
+
+    /// Title shown for a question the extension didn't identify
+    unknown_question() {
+        en: "Unknown question",
+        fr: "Question inconnue",
+    }
+
+    /// Reported when synchronizing a question the extension didn't identify
+    unknown_question_started() {
+        en: "The IDE didn't identify the question, synchronizing anyway",
+        fr: "L'IDE n'a pas identifié la question, synchronisation quand même",
+    }
+
+    /// Hint when the extension connected but didn't identify the question within
+    /// --details-timeout: the connection settings are right, the IDE page or the extension is not
+    ide_without_details() {
+        en: "IDE connected but didn't send question details — make sure a CodinGame IDE tab is open and the extension is up to date.",
+        fr: "L'IDE est connecté mais n'a pas envoyé les détails de la question — vérifiez qu'un onglet de l'IDE CodinGame est ouvert et que l'extension est à jour.",
+    }
+
+    /// Asked when the extension didn't identify the question within --details-timeout
+    details_missing() {
+        en: format!("{}\n\nStart synchronizing anyway?", ide_without_details()),
+        fr: format!("{}\n\nSynchroniser quand même ?", ide_without_details()),
+    }
+
+    /// Reported without the text user interface when the extension didn't identify the question
+    details_missing_hint() {
+        en: format!("{} Pass --allow-unknown-question to synchronize anyway.", ide_without_details()),
+        fr: format!("{} Passez --allow-unknown-question pour synchroniser quand même.", ide_without_details()),
+    }
+
+    /// Question of the upload confirmation dialog of --tui-confirm-upload
+    confirm_upload(bytes: usize, delta: i64) {
+        en: format!("Upload {} bytes ({:+} bytes)?", bytes, delta),
+        fr: format!("Envoyer {} octets ({:+} octets) ?", bytes, delta),
+    }
+
+    /// Button skipping the pending upload
+    button_skip() {
+        en: "Skip",
+        fr: "Ignorer",
+    }
+
+    /// Button uploading without confirmation for the rest of the session
+    button_always() {
+        en: "Always",
+        fr: "Toujours",
+    }
+
+    /// Question of the dialog shown when the target was saved while downloading
+    download_conflict() {
+        en: "The target was saved after the download was requested. Overwrite the local changes with the IDE code?",
+        fr: "La cible a été enregistrée après la demande de téléchargement. Remplacer les modifications locales par le code de l'IDE ?",
+    }
+
+    /// Reported without the interface when the target was saved while downloading
+    download_conflict_refused() {
+        en: "The target was saved after the download was requested, keeping the local changes. Pass --force-download to overwrite them with the IDE code",
+        fr: "La cible a été enregistrée après la demande de téléchargement, les modifications locales sont conservées. Passez --force-download pour les remplacer par le code de l'IDE",
+    }
+
+    /// Logged when the user keeps the local changes over a conflicting download
+    download_conflict_kept_local() {
+        en: "Keeping the local changes, the IDE code was not downloaded",
+        fr: "Modifications locales conservées, le code de l'IDE n'a pas été téléchargé",
+    }
+
+    /// Question of the dialog shown when both the target and the IDE code changed with --two-way
+    sync_conflict() {
+        en: "Both the target and the IDE code changed since they were last synchronized. Overwrite the local changes with the IDE code?",
+        fr: "La cible et le code de l'IDE ont tous deux changé depuis leur dernière synchronisation. Remplacer les modifications locales par le code de l'IDE ?",
+    }
+
+    /// Reported without the interface when both sides changed with --two-way and --conflict-policy
+    /// is ask
+    sync_conflict_refused() {
+        en: "Both the target and the IDE code changed, keeping the local changes: the next save uploads them. Pass --conflict-policy prefer-ide to take the IDE code instead",
+        fr: "La cible et le code de l'IDE ont tous deux changé, les modifications locales sont conservées : le prochain enregistrement les envoie. Passez --conflict-policy prefer-ide pour prendre le code de l'IDE",
+    }
+
+    /// Logged when --conflict-policy prefer-local uploads the target over IDE edits
+    sync_conflict_kept_local() {
+        en: "Both the target and the IDE code changed, uploading the local changes",
+        fr: "La cible et le code de l'IDE ont tous deux changé, envoi des modifications locales",
+    }
+
+    /// Warning when the IDE code polled with --ide-poll-interval changed since it was last
+    /// synchronized
+    ide_diverged() {
+        en: "The IDE code changed since it was last synchronized, e.g. by edits made in the browser: the next save of the target overwrites them, --two-way pulls them instead",
+        fr: "Le code de l'IDE a changé depuis la dernière synchronisation, par exemple après des modifications dans le navigateur : la prochaine sauvegarde de la cible les écrase, --two-way les récupère à la place",
+    }
+
+    /// Reported when edits made in the IDE were written to the target with --two-way
+    ide_edits_pulled() {
+        en: "Updated code from edits made in the IDE",
+        fr: "Code mis à jour depuis les modifications faites dans l'IDE",
+    }
+
+    /// Button overwriting the local changes with the IDE code
+    button_overwrite() {
+        en: "Overwrite",
+        fr: "Remplacer",
+    }
+
+    /// Button keeping the local changes over the IDE code
+    button_keep_local() {
+        en: "Keep local",
+        fr: "Garder local",
+    }
+
+    /// Placeholder of the diff pane of --show-diff-in-tui
+    no_pending_diff() {
+        en: "No changes since the last upload",
+        fr: "Aucune modification depuis le dernier envoi",
+    }
+
+    /// Button of the running dialog listing the versions of the session
+    button_history() {
+        en: "History",
+        fr: "Historique",
+    }
+
+    /// Title of the history dialog
+    history_title() {
+        en: "Versions of this session",
+        fr: "Versions de cette session",
+    }
+
+    /// Shown instead of the history when no version was retained yet
+    history_empty() {
+        en: "No version was uploaded yet",
+        fr: "Aucune version n'a encore été envoyée",
+    }
+
+    /// Item of the history list
+    history_entry(at: &str, bytes: usize) {
+        en: format!("{} — {} bytes", at, bytes),
+        fr: format!("{} — {} octets", at, bytes),
+    }
+
+    /// Checkbox of the restore dialog
+    label_restore_local() {
+        en: "Also write this version to the local file",
+        fr: "Écrire aussi cette version dans le fichier local",
+    }
+
+    /// Button restoring a version with auto-play
+    button_upload_and_play() {
+        en: "Upload and play",
+        fr: "Envoyer et jouer",
+    }
+
+    /// Logged when a version of the history is uploaded again
+    restoring_version(fingerprint: &str) {
+        en: format!("Uploading version {} again", fingerprint),
+        fr: format!("Renvoi de la version {}", fingerprint),
+    }
+
+    /// The version to restore was dropped by the retention limits in the meantime
+    version_not_retained() {
+        en: "This version is no longer retained, see --retain-max-payloads",
+        fr: "Cette version n'est plus conservée, voir --retain-max-payloads",
+    }
+
+    /// Persistent warning while the extension keeps reconnecting
+    connection_flapping() {
+        en: "The connection is flapping, check the extension. Prompts are paused until it is stable.",
+        fr: "La connexion est instable, vérifiez l'extension. Les demandes sont suspendues jusqu'à ce qu'elle se stabilise.",
+    }
+
+    /// Logged when a flapping connection stayed up long enough
+    connection_stable() {
+        en: "The connection is stable again",
+        fr: "La connexion est de nouveau stable",
+    }
+
+    /// Status bar segment while the target is read-only, following the IDE editor
+    status_read_only() {
+        en: "read-only",
+        fr: "lecture seule",
+    }
+
+    /// Logged when the target is made read-only or writable again, following the IDE editor
+    target_read_only(state: bool) {
+        en: if state {
+            "The IDE editor was locked, the target is read-only".to_owned()
+        } else {
+            "The IDE editor was unlocked, the target is writable again".to_owned()
+        },
+        fr: if state {
+            "L'éditeur de l'IDE a été verrouillé, la cible est en lecture seule".to_owned()
+        } else {
+            "L'éditeur de l'IDE a été déverrouillé, la cible est de nouveau modifiable".to_owned()
+        },
+    }
+
+    /// Reported when the permissions of the target couldn't follow the IDE editor
+    read_only_failed(err: &str) {
+        en: format!("Failed to change the read-only state of the target: {}", err),
+        fr: format!("Échec du changement de l'état lecture seule de la cible : {}", err),
+    }
+
+    /// Status bar segment once the last connection of the extension ended
+    status_disconnected() {
+        en: "extension disconnected",
+        fr: "extension déconnectée",
+    }
+
+    /// Status bar segment with several connections of the extension, e.g. one per tab
+    status_connections(count: usize) {
+        en: format!("{} tabs connected", count),
+        fr: format!("{} onglets connectés", count),
+    }
+
+    /// Status bar segment counting the watch events discarded by the exclusion rules
+    status_filtered(count: u64) {
+        en: format!("{} events filtered", count),
+        fr: format!("{} événements filtrés", count),
+    }
+
+    /// Logged when the extension connects
+    extension_connected(peer: &str) {
+        en: format!("Extension connected from {}", peer),
+        fr: format!("Extension connectée depuis {}", peer),
+    }
+
+    /// Logged when a connection of the extension ends, e.g. when its tab is closed
+    extension_disconnected(peer: &str) {
+        en: format!("Extension disconnected from {}", peer),
+        fr: format!("Extension déconnectée de {}", peer),
+    }
+
+    /// Status bar segment while the connection is flapping
+    status_flapping() {
+        en: "connection flapping",
+        fr: "connexion instable",
+    }
+
+    /// Active session in the status bar
+    status_session(name: &str) {
+        en: format!("session: {}", name),
+        fr: format!("session : {}", name),
+    }
+
+    /// Shown while an upload takes long to reach the extension
+    upload_in_progress(kilobytes: usize) {
+        en: format!("uploading… ({} KB)", kilobytes),
+        fr: format!("envoi en cours… ({} Ko)", kilobytes),
+    }
+
+    /// Shown when an upload stopped making progress
+    upload_stalled(kilobytes: usize, secs: u64) {
+        en: format!("upload of {} KB stalled for {}s", kilobytes, secs),
+        fr: format!("envoi de {} Ko bloqué depuis {}s", kilobytes, secs),
+    }
+
+    /// Logged when an upload reported as in progress completed
+    upload_completed(kilobytes: usize, secs: f32) {
+        en: format!("uploaded {} KB in {:.1}s", kilobytes, secs),
+        fr: format!("{} Ko envoyés en {:.1}s", kilobytes, secs),
+    }
+
+    /// The uploaded code was cut by --upload-force-codepoint-limit
+    code_truncated(limit: usize) {
+        en: format!("code truncated to {} codepoints", limit),
+        fr: format!("code tronqué à {} caractères", limit),
+    }
+
+    /// The extension asked for the code but the target can't be read
+    requested_code_unavailable() {
+        en: "The IDE requested the code, but the target can't be read",
+        fr: "L'IDE a demandé le code, mais la cible ne peut pas être lue",
+    }
+
+    /// Another process held the lock on the target for longer than --target-file-lock waits
+    target_file_locked() {
+        en: "target file is locked by another process",
+        fr: "le fichier cible est verrouillé par un autre processus",
+    }
+
+    /// Reported when a version restored locally or the preprocessed code can't be written
+    write_failed(path: &str, err: &str) {
+        en: format!("failed to write {}: {}", path, err),
+        fr: format!("échec de l'écriture de {} : {}", path, err),
+    }
+
+    /// Sent to the extension for a message it shouldn't send
+    unexpected_message() {
+        en: "unexpected message",
+        fr: "message inattendu",
+    }
+
+    /// Sent to a client whose first message didn't carry the --auth-token
+    authentication_failed() {
+        en: "authentication failed",
+        fr: "échec de l'authentification",
+    }
+
+    /// Logged when the downloaded code can't be unwrapped with --upload-wrapper
+    download_wrapper_missing() {
+        en: "downloaded code doesn't have the upload wrapper, keeping it whole",
+        fr: "le code téléchargé n'a pas l'enveloppe d'envoi, il est conservé entier",
+    }
+
+    /// Logged when the dead code stage can't tell the language of the source
+    dead_code_unknown_language(source: &str) {
+        en: format!("not removing dead code: unknown language for {}", source),
+        fr: format!("code mort conservé : langage inconnu pour {}", source),
+    }
+
+    /// Logged when the entry point can't be commented out in the language
+    comment_out_main_unsupported(language: &str) {
+        en: format!("not commenting out main: not supported for {}", language),
+        fr: format!("main non commenté : non pris en charge pour {}", language),
+    }
+
+    /// Logged when the entry point stage can't tell the language of the source
+    comment_out_main_unknown_language(source: &str) {
+        en: format!("not commenting out main: unknown language for {}", source),
+        fr: format!("main non commenté : langage inconnu pour {}", source),
+    }
+
+    /// Logged when the metadata stage can't tell the language of the source
+    metadata_unknown_language(source: &str) {
+        en: format!("not injecting metadata: unknown language for {}", source),
+        fr: format!("métadonnées non ajoutées : langage inconnu pour {}", source),
+    }
+
+    /// Logged when the test input stage can't tell the language of the source
+    test_input_unknown_language(source: &str) {
+        en: format!("not injecting test input: unknown language for {}", source),
+        fr: format!("entrée de test non ajoutée : langage inconnu pour {}", source),
+    }
+
+    /// Printed by the network-interfaces command when there is nothing to list
+    no_network_interface() {
+        en: "No network interface found",
+        fr: "Aucune interface réseau trouvée",
+    }
+
+    /// Header of the table printed by the network-interfaces command
+    network_interfaces_header() {
+        en: format!("{:<16}  {:<6}  {:>5}  {}", "Interface", "Status", "MTU", "Addresses"),
+        fr: format!("{:<16}  {:<6}  {:>5}  {}", "Interface", "État", "MTU", "Adresses"),
+    }
+
+    /// Printed by the stats command before any session recorded statistics
+    no_statistics() {
+        en: "No statistics recorded yet",
+        fr: "Aucune statistique enregistrée pour l'instant",
+    }
+
+    /// Header of the table printed by the stats command
+    stats_header() {
+        en: format!(
+            "{:>10}  {:<40}  {:>7}  {:>9}  {:>5}  {:>9}",
+            "ID", "Title", "Uploads", "Downloads", "Plays", "Bytes"
+        ),
+        fr: format!(
+            "{:>10}  {:<40}  {:>7}  {:>9}  {:>5}  {:>9}",
+            "ID", "Titre", "Envois", "Téléch.", "Lect.", "Octets"
+        ),
+    }
+
+    /// Printed by config check without a configuration file
+    config_not_found() {
+        en: "No configuration file found, the defaults apply",
+        fr: "Aucun fichier de configuration trouvé, les valeurs par défaut s'appliquent",
+    }
+
+    /// Printed by config check before checking the configuration file
+    config_checking(path: &str) {
+        en: format!("Checking {}", path),
+        fr: format!("Vérification de {}", path),
+    }
+
+    /// Printed by config check after the findings
+    config_findings(errors: usize, warnings: usize) {
+        en: format!("{} error(s), {} warning(s)", errors, warnings),
+        fr: format!("{} erreur(s), {} avertissement(s)", errors, warnings),
+    }
+
+    /// Printed by the replay command when a recorded connection is opened
+    replay_connected() {
+        en: "connected",
+        fr: "connectée",
+    }
+
+    /// Printed by the replay command when the instance closed a connection
+    replay_closed() {
+        en: "closed",
+        fr: "fermée",
+    }
+
+    /// Printed by the replay command once every message was sent
+    replay_summary(sent: usize, received: usize, recorded: usize) {
+        en: format!(
+            "Replayed {} message(s), received {} message(s) from the instance, {} recorded",
+            sent, received, recorded
+        ),
+        fr: format!(
+            "{} message(s) rejoué(s), {} message(s) reçu(s) de l'instance, {} enregistré(s)",
+            sent, received, recorded
+        ),
+    }
+}
+
+/// One-line status bar of the text user interface. The extension is considered connected once
+/// it sent the question details.
+pub fn status_bar(question: Option<&str>, uploads: u64, downloads: u64, time: &str) -> String {
+    match (lang(), question) {
+        (Lang::En, Some(title)) => format!(
+            "[CONNECTED] Q: \"{}\" | Uploads: {} | Downloads: {} | {}",
+            title, uploads, downloads, time
+        ),
+        (Lang::En, None) => format!(
+            "[IDLE] Uploads: {} | Downloads: {} | {}",
+            uploads, downloads, time
+        ),
+        (Lang::Fr, Some(title)) => format!(
+            "[CONNECTÉ] Q : \"{}\" | Envois : {} | Téléchargements : {} | {}",
+            title, uploads, downloads, time
+        ),
+        (Lang::Fr, None) => format!(
+            "[INACTIF] Envois : {} | Téléchargements : {} | {}",
+            uploads, downloads, time
+        ),
+    }
+}
+
+/// Synchronization state, with the side and time of the last synchronized change
+pub fn sync_state(state: SyncState, last: Option<&Provenance>) -> String {
+    let state = match (lang(), state) {
+        (Lang::En, SyncState::InSync) => "in sync",
+        (Lang::En, SyncState::LocalAhead) => "local ahead",
+        (Lang::En, SyncState::IdeAhead) => "IDE ahead",
+        (Lang::En, SyncState::Diverged) => "diverged",
+        (Lang::Fr, SyncState::InSync) => "synchronisé",
+        (Lang::Fr, SyncState::LocalAhead) => "local en avance",
+        (Lang::Fr, SyncState::IdeAhead) => "IDE en avance",
+        (Lang::Fr, SyncState::Diverged) => "divergent",
+    };
+
+    let last = match last {
+        Some(last) => last,
+        None => return state.to_owned(),
+    };
+
+    let time = last.at.format("%H:%M");
+    match lang() {
+        Lang::En => format!("{} — last change: {}, {}", state, last.side, time),
+        Lang::Fr => format!("{} — dernier changement : {}, {}", state, last.side, time),
+    }
+}
+
+/// Whether the last upload played and why, for the logs and the status bar
+pub fn play_reason(reason: PlayReason) -> String {
+    match (lang(), reason) {
+        (Lang::En, PlayReason::Disabled) => "play off",
+        (Lang::En, PlayReason::Allowed) => "play on",
+        (Lang::En, PlayReason::Flagged) => "play on, flagged by the play policy",
+        (Lang::En, PlayReason::Denied) => "play suppressed: denied by the play policy",
+        (Lang::Fr, PlayReason::Disabled) => "lecture désactivée",
+        (Lang::Fr, PlayReason::Allowed) => "lecture activée",
+        (Lang::Fr, PlayReason::Flagged) => "lecture activée, signalée par la politique de lecture",
+        (Lang::Fr, PlayReason::Denied) => "lecture supprimée : refusée par la politique de lecture",
+    }
+    .to_owned()
+}
+
+/// Settings to enter in the browser extension to reach the given listeners. A port other than
+/// the extension default is emphasized since it must be changed in the extension options.
+pub fn extension_settings(addrs: &[std::net::SocketAddr], default_port: u16) -> String {
+    let mut text = match lang() {
+        Lang::En => "Extension settings:".to_owned(),
+        Lang::Fr => "Réglages de l'extension :".to_owned(),
+    };
+
+    for addr in addrs {
+        text.push('\n');
+        text.push_str(&match lang() {
+            Lang::En => format!("  host: {}, port: {}, TLS: no", addr.ip(), addr.port()),
+            Lang::Fr => format!("  hôte : {}, port : {}, TLS : non", addr.ip(), addr.port()),
+        });
+
+        if addr.port() != default_port {
+            text.push('\n');
+            text.push_str(&match lang() {
+                Lang::En => format!(
+                    "  /!\\ port {} is not the extension default ({}), set it in the extension options",
+                    addr.port(),
+                    default_port
+                ),
+                Lang::Fr => format!(
+                    "  /!\\ le port {} n'est pas celui par défaut de l'extension ({}), modifiez-le dans ses options",
+                    addr.port(),
+                    default_port
+                ),
+            });
+        }
+    }
+
+    text
+}
+
+/// Settings of the extension with --bind-unix, which it can't connect to directly
+pub fn extension_settings_unix(path: &str) -> String {
+    match lang() {
+        Lang::En => format!(
+            "Extension settings:\n  listening on the Unix domain socket {}, point the extension at the proxy forwarding to it",
+            path
+        ),
+        Lang::Fr => format!(
+            "Réglages de l'extension :\n  écoute sur le socket Unix {}, configurez l'extension vers le proxy qui y redirige",
+            path
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+    use crate::provenance::Side;
+
+    /// Messages of the catalog and of the other constructors, in both languages
+    const SNAPSHOT: &str = "tests/snapshots/messages.txt";
+
+    /// Messages identical in both languages
+    const SAME_IN_BOTH: &[&str] = &["sessions_title", "button_notes"];
+
+    /// Argument of a message in the snapshot, named after its parameter where it is text
+    pub trait Sample {
+        fn sample(name: &'static str) -> Self;
+    }
+
+    impl Sample for &str {
+        fn sample(name: &'static str) -> Self {
+            Box::leak(format!("<{}>", name).into_boxed_str())
+        }
+    }
+
+    macro_rules! sample {
+        ($($ty:ty = $value:expr),*) => {
+            $(impl Sample for $ty {
+                fn sample(_: &'static str) -> Self {
+                    $value
+                }
+            })*
+        };
+    }
+
+    sample!(
+        i32 = 42,
+        i64 = -120,
+        u16 = 53135,
+        u64 = 4096,
+        usize = 3,
+        f32 = 1.5,
+        bool = true
+    );
+
+    /// Build the messages in the language
+    fn in_lang<T>(lang: Lang, build: impl FnOnce() -> T) -> T {
+        TEST_LANG.with(|cell| cell.set(Some(lang)));
+        let built = build();
+        TEST_LANG.with(|cell| cell.set(None));
+        built
+    }
+
+    /// Every message, with the arguments of the constructors outside of the catalog
+    fn samples() -> Vec<(String, String)> {
+        let mut samples: Vec<(String, String)> = catalog_samples()
+            .into_iter()
+            .map(|(name, message)| (name.to_owned(), message))
+            .collect();
+
+        let at = Local.ymd(2021, 3, 14).and_hms(15, 9, 26);
+        let last = Provenance {
+            side: Side::Ide,
+            checksum: String::new(),
+            at,
+        };
+        let addrs = [
+            "127.0.0.1:53135".parse().unwrap(),
+            "[::1]:8080".parse().unwrap(),
+        ];
+
+        let others = vec![
+            (
+                "status_bar(Some)",
+                status_bar(Some("<title>"), 4, 2, "12:00"),
+            ),
+            ("status_bar(None)", status_bar(None, 4, 2, "12:00")),
+            ("sync_state(None)", sync_state(SyncState::LocalAhead, None)),
+            (
+                "sync_state(Some)",
+                sync_state(SyncState::Diverged, Some(&last)),
+            ),
+            ("play_reason(Disabled)", play_reason(PlayReason::Disabled)),
+            ("play_reason(Allowed)", play_reason(PlayReason::Allowed)),
+            ("play_reason(Flagged)", play_reason(PlayReason::Flagged)),
+            ("play_reason(Denied)", play_reason(PlayReason::Denied)),
+            ("extension_settings", extension_settings(&addrs, 53135)),
+            ("extension_settings_unix", extension_settings_unix("<path>")),
+        ];
+        samples.extend(
+            others
+                .into_iter()
+                .map(|(name, message)| (name.to_owned(), message)),
+        );
+
+        samples
+    }
+
+    fn snapshot() -> String {
+        let mut snapshot = String::new();
+
+        for (code, lang) in &[("en", Lang::En), ("fr", Lang::Fr)] {
+            writeln!(snapshot, "[{}]", code).unwrap();
+            for (name, message) in in_lang(*lang, samples) {
+                writeln!(snapshot, "{} = {:?}", name, message).unwrap();
+            }
+            writeln!(snapshot).unwrap();
+        }
+
+        snapshot
+    }
+
+    /// Compare the messages with the snapshot, or update it with `UPDATE_SNAPSHOTS=1`
+    #[test]
+    fn matches_the_snapshot() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(SNAPSHOT);
+        let snapshot = snapshot();
+
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, &snapshot).unwrap();
+            return;
+        }
+
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        for (line, expected) in snapshot.lines().zip(expected.lines()) {
+            assert_eq!(
+                line, expected,
+                "run with UPDATE_SNAPSHOTS=1 if the change is deliberate"
+            );
+        }
+        assert_eq!(
+            snapshot.lines().count(),
+            expected.lines().count(),
+            "run with UPDATE_SNAPSHOTS=1 if the change is deliberate"
+        );
+    }
+
+    #[test]
+    fn every_message_has_both_languages() {
+        let en = in_lang(Lang::En, samples);
+        let fr = in_lang(Lang::Fr, samples);
+
+        for ((name, en), (_, fr)) in en.iter().zip(&fr) {
+            assert!(!en.trim().is_empty(), "{} is empty in English", name);
+            assert!(!fr.trim().is_empty(), "{} is empty in French", name);
+            assert!(
+                en != fr || SAME_IN_BOTH.contains(&name.as_str()),
+                "{} isn't translated: {:?}",
+                name,
+                en
+            );
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Session metadata comment injected at the top of uploaded code.
+//!
+//! The block is delimited by marker lines so it can be found and removed again, whatever the
+//! comment syntax of the language:
+//!
+//! ```text
+//! // --- CG LOCAL ---
+//! // question: 42
+//! // title: Mars Lander
+//! // uploaded: 2021-04-01T12:00:00+02:00
+//! // cg-local-app: 0.1.2
+//! // --- END CG LOCAL ---
+//! ```
+
+use std::borrow::Cow;
+
+use crate::language::{language_comment, Language};
+
+const BEGIN_MARKER: &str = "--- CG LOCAL ---";
+const END_MARKER: &str = "--- END CG LOCAL ---";
+
+/// Metadata describing an upload
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub question_id: i32,
+    pub title: String,
+    pub timestamp: String,
+    pub version: &'static str,
+    pub author: Option<String>,
+}
+
+impl Metadata {
+    /// Metadata for an upload happening now
+    pub fn now(question_id: i32, title: &str, author: Option<String>) -> Self {
+        Self {
+            question_id,
+            title: title.to_owned(),
+            timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            version: env!("CARGO_PKG_VERSION"),
+            author,
+        }
+    }
+}
+
+/// Prepend the metadata block to the code, replacing the block already present if any
+pub fn inject(code: &str, lang: Language, meta: &Metadata) -> String {
+    let comment = language_comment(lang);
+
+    let mut fields = vec![
+        ("question", meta.question_id.to_string()),
+        ("title", meta.title.clone()),
+        ("uploaded", meta.timestamp.clone()),
+        ("cg-local-app", meta.version.to_owned()),
+    ];
+    if let Some(author) = &meta.author {
+        fields.push(("author", author.clone()));
+    }
+
+    let mut result = format!("{} {}\n", comment, BEGIN_MARKER);
+    for (name, value) in fields {
+        result.push_str(&format!("{} {}: {}\n", comment, name, value));
+    }
+    result.push_str(&format!("{} {}\n", comment, END_MARKER));
+    result.push_str(&strip(code));
+
+    result
+}
+
+/// Remove the metadata block at the top of the code, if any
+pub fn strip(code: &str) -> Cow<'_, str> {
+    let first_line = code.lines().next().unwrap_or_default();
+    if !first_line.trim_end().ends_with(BEGIN_MARKER) {
+        return Cow::Borrowed(code);
+    }
+
+    let mut offset = 0;
+    for line in code.split_inclusive('\n') {
+        offset += line.len();
+
+        if line.trim_end().ends_with(END_MARKER) {
+            return Cow::Borrowed(&code[offset..]);
+        }
+    }
+
+    // Without an end marker the rest may well be code, leave it alone
+    Cow::Borrowed(code)
+}
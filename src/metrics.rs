@@ -0,0 +1,300 @@
+//! Prometheus metrics, exported over HTTP with `--metrics-prometheus`.
+//!
+//! The recording functions are no-ops when the `prometheus-metrics` feature is disabled.
+
+#[cfg(feature = "prometheus-metrics")]
+mod imp {
+    use std::sync::LazyLock;
+
+    use async_std::prelude::*;
+    use prometheus::{
+        Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, IntCounter, IntGauge, IntGaugeVec,
+        Opts, Registry, TextEncoder,
+    };
+
+    use crate::channel_stats;
+    use crate::runtime::net::TcpListener;
+
+    pub struct Metrics {
+        registry: Registry,
+        pub uploads: IntCounter,
+        pub downloads: IntCounter,
+        pub upload_bytes: IntCounter,
+        pub download_bytes: IntCounter,
+        pub upload_errors: IntCounter,
+        pub connections: IntCounter,
+        pub active_connections: IntGauge,
+        pub flaps: IntCounter,
+        pub observers: IntGauge,
+        pub flapping: IntGauge,
+        pub retained_bytes: IntGauge,
+        pub upload_latency: Histogram,
+        pub channel_sends: IntGaugeVec,
+        pub channel_receives: IntGaugeVec,
+        pub channel_occupancy: IntGaugeVec,
+        pub channel_max_send_wait: GaugeVec,
+    }
+
+    fn channel_gauge(registry: &Registry, name: &str, help: &str) -> IntGaugeVec {
+        let gauge = IntGaugeVec::new(Opts::new(name, help), &["channel"]).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        gauge
+    }
+
+    fn counter(registry: &Registry, name: &str, help: &str) -> IntCounter {
+        let counter = IntCounter::new(name, help).unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        counter
+    }
+
+    pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+        let registry = Registry::new();
+
+        let active_connections =
+            IntGauge::new("cglocal_active_connections", "Connected extensions").unwrap();
+        registry
+            .register(Box::new(active_connections.clone()))
+            .unwrap();
+
+        let flapping = IntGauge::new(
+            "cglocal_connection_flapping",
+            "1 while the extension connection is flapping",
+        )
+        .unwrap();
+        registry.register(Box::new(flapping.clone())).unwrap();
+
+        let observers =
+            IntGauge::new("cglocal_active_observers", "Connected --observers clients").unwrap();
+        registry.register(Box::new(observers.clone())).unwrap();
+
+        let retained_bytes = IntGauge::new(
+            "cglocal_retained_bytes",
+            "Bytes of code payloads kept in memory",
+        )
+        .unwrap();
+        registry.register(Box::new(retained_bytes.clone())).unwrap();
+
+        let upload_latency = Histogram::with_opts(HistogramOpts::new(
+            "cglocal_upload_latency_seconds",
+            "Time taken to hand uploaded code to the connection",
+        ))
+        .unwrap();
+        registry.register(Box::new(upload_latency.clone())).unwrap();
+
+        let channel_max_send_wait = GaugeVec::new(
+            Opts::new(
+                "cglocal_channel_max_send_wait_seconds",
+                "Longest time a send waited for room in an internal channel",
+            ),
+            &["channel"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(channel_max_send_wait.clone()))
+            .unwrap();
+
+        Metrics {
+            uploads: counter(&registry, "cglocal_uploads_total", "Uploads to the IDE"),
+            downloads: counter(
+                &registry,
+                "cglocal_downloads_total",
+                "Downloads from the IDE",
+            ),
+            upload_bytes: counter(
+                &registry,
+                "cglocal_upload_bytes_total",
+                "Bytes uploaded to the IDE",
+            ),
+            download_bytes: counter(
+                &registry,
+                "cglocal_download_bytes_total",
+                "Bytes downloaded from the IDE",
+            ),
+            upload_errors: counter(
+                &registry,
+                "cglocal_upload_errors_total",
+                "Uploads refused or failed",
+            ),
+            connections: counter(
+                &registry,
+                "cglocal_connections_total",
+                "Accepted extension connections",
+            ),
+            active_connections,
+            flaps: counter(
+                &registry,
+                "cglocal_connection_flaps_total",
+                "Times the extension connection started flapping",
+            ),
+            flapping,
+            observers,
+            retained_bytes,
+            upload_latency,
+            channel_sends: channel_gauge(
+                &registry,
+                "cglocal_channel_sends",
+                "Messages sent in an internal channel",
+            ),
+            channel_receives: channel_gauge(
+                &registry,
+                "cglocal_channel_receives",
+                "Messages received from an internal channel",
+            ),
+            channel_occupancy: channel_gauge(
+                &registry,
+                "cglocal_channel_occupancy",
+                "Messages pending in an internal channel",
+            ),
+            channel_max_send_wait,
+            registry,
+        }
+    });
+
+    /// Serve the metrics on `GET /metrics` until the listener fails
+    pub async fn serve(addr: &str) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("serving metrics on http://{}/metrics", addr);
+
+        loop {
+            let (mut stream, _) = listener.accept().await?;
+
+            let mut buf = [0u8; 1024];
+            let n = match stream.read(&mut buf).await {
+                Ok(n) => n,
+                Err(_) => continue,
+            };
+
+            let response = if buf[..n].starts_with(b"GET /metrics ") {
+                record_channels();
+
+                let mut body = Vec::new();
+                let encoder = TextEncoder::new();
+                encoder
+                    .encode(&METRICS.registry.gather(), &mut body)
+                    .unwrap();
+
+                let mut response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    encoder.format_type(),
+                    body.len()
+                )
+                .into_bytes();
+                response.extend(body);
+                response
+            } else {
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+            };
+
+            if let Err(err) = stream.write_all(&response).await {
+                debug!("failed to send metrics: {}", err);
+            }
+        }
+    }
+
+    /// Copy the channel counters, which are only read when scraped
+    fn record_channels() {
+        for stats in channel_stats::snapshot() {
+            let labels = &[stats.name];
+            METRICS
+                .channel_sends
+                .with_label_values(labels)
+                .set(stats.sends as i64);
+            METRICS
+                .channel_receives
+                .with_label_values(labels)
+                .set(stats.receives as i64);
+            METRICS
+                .channel_occupancy
+                .with_label_values(labels)
+                .set(stats.occupancy as i64);
+            let max_send_wait: Gauge = METRICS.channel_max_send_wait.with_label_values(labels);
+            max_send_wait.set(stats.max_send_wait.as_secs_f64());
+        }
+    }
+}
+
+#[cfg(feature = "prometheus-metrics")]
+pub use imp::serve;
+
+#[cfg(feature = "prometheus-metrics")]
+use imp::METRICS;
+
+pub fn record_upload(bytes: usize, latency: std::time::Duration) {
+    #[cfg(feature = "prometheus-metrics")]
+    {
+        METRICS.uploads.inc();
+        METRICS.upload_bytes.inc_by(bytes as u64);
+        METRICS.upload_latency.observe(latency.as_secs_f64());
+    }
+    #[cfg(not(feature = "prometheus-metrics"))]
+    let _ = (bytes, latency);
+}
+
+pub fn record_upload_error() {
+    #[cfg(feature = "prometheus-metrics")]
+    METRICS.upload_errors.inc();
+}
+
+pub fn record_download(bytes: usize) {
+    #[cfg(feature = "prometheus-metrics")]
+    {
+        METRICS.downloads.inc();
+        METRICS.download_bytes.inc_by(bytes as u64);
+    }
+    #[cfg(not(feature = "prometheus-metrics"))]
+    let _ = bytes;
+}
+
+pub fn record_retained_bytes(bytes: usize) {
+    #[cfg(feature = "prometheus-metrics")]
+    METRICS.retained_bytes.set(bytes as i64);
+    #[cfg(not(feature = "prometheus-metrics"))]
+    let _ = bytes;
+}
+
+pub fn record_flapping(flapping: bool) {
+    #[cfg(feature = "prometheus-metrics")]
+    {
+        if flapping {
+            METRICS.flaps.inc();
+        }
+        METRICS.flapping.set(flapping as i64);
+    }
+    #[cfg(not(feature = "prometheus-metrics"))]
+    let _ = flapping;
+}
+
+pub fn record_observers(count: usize) {
+    #[cfg(feature = "prometheus-metrics")]
+    METRICS.observers.set(count as i64);
+    #[cfg(not(feature = "prometheus-metrics"))]
+    let _ = count;
+}
+
+/// Counts an extension connection as active while alive
+pub struct ConnectionGuard(());
+
+impl ConnectionGuard {
+    pub fn new() -> Self {
+        #[cfg(feature = "prometheus-metrics")]
+        {
+            METRICS.connections.inc();
+            METRICS.active_connections.inc();
+        }
+
+        Self(())
+    }
+}
+
+impl Default for ConnectionGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "prometheus-metrics")]
+        METRICS.active_connections.dec();
+    }
+}
@@ -0,0 +1,165 @@
+//! Fake extension started with `--mock`, to exercise the synchronization without a browser.
+//!
+//! Once the listener is bound, the app connects to it and plays the part of the extension and
+//! the CodinGame IDE: it identifies the question given by `--mock-question-id`, keeps the code of
+//! every upload, and answers the requests for the IDE code with the code it holds, the target as
+//! it was on startup until the first upload. What it receives is logged.
+//!
+//! It connects like the extension does, with an extension origin and the `--auth-token`, so the
+//! admission checks apply to it unchanged. Addresses not allowed by `--allow-ip` can't use it.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use async_std::path::PathBuf;
+use async_std::prelude::*;
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use async_tungstenite::tungstenite::http::HeaderValue;
+use async_tungstenite::tungstenite::{self, Message};
+use futures_util::sink::SinkExt;
+
+use crate::admission;
+use crate::listener::Stream;
+use crate::payload::Code;
+use crate::runtime::net::TcpStream;
+#[cfg(unix)]
+use crate::runtime::net::UnixStream;
+use crate::{Result, ServerMessage};
+
+/// Title of the question the fake extension identifies
+const MOCK_TITLE: &str = "Mock question";
+
+/// ID of the fake extension, accepted along the ones of --extension-id
+pub const EXTENSION_ID: &str = "cg-local-app-mock";
+
+/// Headers of the fake extension, passing the origin check like the extension
+const MOCK_ORIGIN: &str = "moz-extension://cg-local-app-mock";
+const MOCK_USER_AGENT: &str = "Mozilla/5.0 (cg-local-app mock)";
+
+/// Listener the fake extension connects to
+pub struct Endpoint {
+    /// Addresses of the TCP listener, the first one being used
+    pub addrs: Vec<SocketAddr>,
+    /// Path of the Unix domain socket, with --bind-unix
+    pub socket: Option<PathBuf>,
+    pub token: Option<String>,
+}
+
+/// Run the fake extension until the app closes its connection
+pub async fn run(endpoint: Endpoint, question_id: i32, code: String) {
+    if let Err(err) = serve(endpoint, question_id, code).await {
+        warn!("mock extension: {}", err);
+    }
+}
+
+async fn serve(endpoint: Endpoint, question_id: i32, mut code: String) -> Result<()> {
+    let (stream, host) = connect(&endpoint).await?;
+
+    let url = admission::request_url(&host, endpoint.token.as_deref());
+    let mut request = url.into_client_request()?;
+    let headers = request.headers_mut();
+    headers.insert("Origin", HeaderValue::from_static(MOCK_ORIGIN));
+    headers.insert("User-Agent", HeaderValue::from_static(MOCK_USER_AGENT));
+
+    let (mut ws_stream, _) = async_tungstenite::client_async(request, stream).await?;
+    info!("mock extension: connected to {}", host);
+
+    while let Some(msg) = ws_stream.next().await {
+        let text = match msg {
+            Ok(Message::Text(text)) => text,
+            // The app ends its connections without closing them on exit
+            Ok(Message::Close(_))
+            | Err(tungstenite::Error::ConnectionClosed)
+            | Err(tungstenite::Error::Protocol(_)) => break,
+            Ok(_) => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        let msg = match ServerMessage::parse(&text) {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!("mock extension: invalid message: {}", err);
+                continue;
+            }
+        };
+
+        match msg {
+            ServerMessage::SendDetails => {
+                info!(
+                    "mock extension: identifying question {} ({})",
+                    question_id, MOCK_TITLE
+                );
+                ws_stream
+                    .send(
+                        ServerMessage::Details {
+                            title: MOCK_TITLE.to_owned(),
+                            question_id,
+                        }
+                        .into(),
+                    )
+                    .await?;
+            }
+            ServerMessage::AppReady => info!("mock extension: synchronizing"),
+            ServerMessage::UpdateCode {
+                code: update, play, ..
+            } => {
+                info!(
+                    "mock extension: received {} bytes{}",
+                    update.len(),
+                    if play { ", playing" } else { "" }
+                );
+                code = update.into_string();
+            }
+            ServerMessage::SendCode => {
+                debug!("mock extension: sending {} bytes", code.len());
+                ws_stream
+                    .send(
+                        ServerMessage::Code {
+                            code: Code::from(code.clone()),
+                            checksum: None,
+                        }
+                        .into(),
+                    )
+                    .await?;
+            }
+            ServerMessage::SetReadOnly { state } => {
+                info!(
+                    "mock extension: editor {}",
+                    if state { "locked" } else { "unlocked" }
+                );
+            }
+            ServerMessage::AlreadyConnected => {
+                warn!("mock extension: another extension is already connected")
+            }
+            ServerMessage::Error { message } => warn!("mock extension: error: {}", message),
+            other => debug!("mock extension: ignoring {:?}", other),
+        }
+    }
+
+    info!("mock extension: disconnected");
+    Ok(())
+}
+
+/// Connect to the listener, returning the host for the request
+async fn connect(endpoint: &Endpoint) -> Result<(Stream, String)> {
+    #[cfg(unix)]
+    if let Some(path) = &endpoint.socket {
+        let stream = UnixStream::connect(path).await?;
+        return Ok((Stream::Unix(stream), "localhost".to_owned()));
+    }
+
+    let mut addr = *endpoint
+        .addrs
+        .first()
+        .ok_or("the listener has no address to connect to")?;
+
+    // Listening on every address, reachable on the loopback one
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+
+    let stream = TcpStream::connect(addr).await?;
+    Ok((Stream::Tcp(stream), addr.to_string()))
+}
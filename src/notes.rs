@@ -0,0 +1,69 @@
+//! Local notes associated with the target, shown read-only by the text user interface.
+//!
+//! The notes file is found next to the target by replacing `{stem}` (the target name without
+//! extension) and `{name}` (the full target name) in `--notes-pattern`: with the default
+//! `{stem}.notes.md`, the notes of `main.rs` are in `main.notes.md`.
+
+use std::path::{Path, PathBuf};
+
+use crate::watcher::target_dir;
+
+/// Path of the notes of the target, whether they exist or not
+pub fn path(target: &Path, pattern: &str) -> Option<PathBuf> {
+    let name = target.file_name()?.to_string_lossy();
+    let stem = target.file_stem()?.to_string_lossy();
+
+    let file_name = pattern.replace("{stem}", &stem).replace("{name}", &name);
+    if file_name.is_empty() || file_name == name {
+        return None;
+    }
+
+    Some(target_dir(target).join(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT: &str = "{stem}.notes.md";
+
+    #[test]
+    fn sidecar_paths() {
+        let table = [
+            ("puzzle/main.rs", DEFAULT, Some("puzzle/main.notes.md")),
+            (
+                "/home/me/puzzle/bot.py",
+                DEFAULT,
+                Some("/home/me/puzzle/bot.notes.md"),
+            ),
+            (
+                "puzzle/archive.tar.gz",
+                DEFAULT,
+                Some("puzzle/archive.tar.notes.md"),
+            ),
+            ("puzzle/main.rs", "{name}.md", Some("puzzle/main.rs.md")),
+            ("puzzle/main.rs", "NOTES", Some("puzzle/NOTES")),
+            // Without an extension
+            ("puzzle/Makefile", DEFAULT, Some("puzzle/Makefile.notes.md")),
+            ("puzzle/Makefile", "{name}.md", Some("puzzle/Makefile.md")),
+            // In the current directory
+            ("main.rs", DEFAULT, Some("./main.notes.md")),
+            ("Makefile", "{name}.md", Some("./Makefile.md")),
+            // Never the target itself
+            ("puzzle/main.rs", "{name}", None),
+            ("puzzle/main", "{stem}", None),
+            ("puzzle/main.rs", "", None),
+            ("/", DEFAULT, None),
+        ];
+
+        for (target, pattern, expected) in table {
+            assert_eq!(
+                path(Path::new(target), pattern),
+                expected.map(PathBuf::from),
+                "{:?} with {:?}",
+                target,
+                pattern
+            );
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Suppression of the change events caused by the app writing the target.
+//!
+//! Downloads, restores and re-creations of a deleted target write the target, and the watcher
+//! would upload the written code right back, running it with --play. The contents written are
+//! recorded here, shared by the controller and the watcher, and reading them back from the target
+//! is not a change. Events may read the target in the middle of a write, so other contents don't
+//! forget the writes: uploading other code does, getting back to the written code being a change
+//! like any other from then on.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::checksum;
+
+/// Checksums of the contents the app wrote to the target since other code was uploaded
+#[derive(Debug, Clone, Default)]
+pub struct OwnWrites {
+    written: Arc<Mutex<HashSet<String>>>,
+}
+
+impl OwnWrites {
+    /// Record contents about to be written to the target
+    pub fn record(&self, contents: &str) {
+        self.written
+            .lock()
+            .unwrap()
+            .insert(checksum::checksum(contents));
+    }
+
+    /// Whether the contents read from the target were written by the app
+    pub fn check(&self, contents: &str) -> bool {
+        let written = self.written.lock().unwrap();
+        !written.is_empty() && written.contains(&checksum::checksum(contents))
+    }
+
+    /// Record an upload. Unless it is code the app wrote, the IDE now has other code and the
+    /// writes are forgotten.
+    pub fn uploaded(&self, contents: &str) {
+        if !self.check(contents) {
+            self.clear();
+        }
+    }
+
+    /// Forget the recorded writes
+    pub fn clear(&self) {
+        self.written.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,69 @@
+//! Code carried by the messages, kept out of the logs.
+//!
+//! Messages are logged with `{:?}` at debug and trace level, which would copy the whole code into
+//! the logs. [`Code`] wraps the code in the message enums and only shows its length and a
+//! fingerprint, unless `--log-include-code` asked for the full payload. The frames of
+//! `--trace-protocol` are redacted the same way.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::checksum;
+
+/// Number of hex digits of the checksum shown as fingerprint
+const FINGERPRINT_LEN: usize = 8;
+
+static INCLUDE_IN_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// Show the full code when messages are logged, for --log-include-code
+pub fn include_in_logs(include: bool) {
+    INCLUDE_IN_LOGS.store(include, Ordering::Relaxed);
+}
+
+/// Whether --log-include-code asked for the full code
+pub fn included_in_logs() -> bool {
+    INCLUDE_IN_LOGS.load(Ordering::Relaxed)
+}
+
+/// Length and fingerprint standing for the code kept out of the logs
+pub fn redacted(code: &str) -> String {
+    format!(
+        "<{} bytes, sha1 {}>",
+        code.len(),
+        &checksum::checksum(code)[..FINGERPRINT_LEN]
+    )
+}
+
+/// Code payload of a message, redacted when formatted with `{:?}`
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Code(String);
+
+impl Code {
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl From<String> for Code {
+    fn from(code: String) -> Self {
+        Self(code)
+    }
+}
+
+impl std::ops::Deref for Code {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if included_in_logs() {
+            return self.0.fmt(f);
+        }
+
+        f.write_str(&redacted(&self.0))
+    }
+}
@@ -0,0 +1,471 @@
+//! Preprocessing of the code before it is uploaded.
+//!
+//! By default the steps enabled by the options run in a fixed order: --upload-replace,
+//! --upload-remove-dead-code, --upload-comment-out-main, --upload-add-copyright, --upload-wrapper,
+//! --upload-inject-metadata, --upload-inject-test-input, the final newline handling and
+//! --upload-force-codepoint-limit. A chain of the `[transforms]` configuration replaces that
+//! order for its target, question or language, see [`transform_chains`]. Both the controller and
+//! the `transform` subcommand go through [`Pipeline::run`], so a preview is exactly what would be
+//! uploaded.
+//!
+//! [`transform_chains`]: crate::transform_chains
+
+use async_std::path::PathBuf;
+
+use crate::dead_code;
+use crate::language::Language;
+use crate::messages;
+use crate::metadata::{self, Metadata};
+use crate::runtime::task;
+use crate::test_input;
+use crate::transform_chains::{self, Stage, TransformsConfig};
+use crate::transforms::{self, CopyrightHeader, FinalNewlineMode};
+use crate::wrapper::Wrapper;
+use crate::{DynamicOpts, Opts};
+
+/// Appended to code cut by --upload-force-codepoint-limit
+const TRUNCATED_MARKER: &str = "\n// TRUNCATED";
+
+/// Upload preprocessing settings, taken from the options and the `[transforms]` configuration
+pub struct Pipeline<'a> {
+    transforms: &'a TransformsConfig,
+    /// Stages enabled by the options, in the default order
+    flagged: Vec<Stage>,
+    replacements: Vec<(String, String)>,
+    /// Language of the dead code stripper, if not the one of the target
+    dead_code_language: Option<Language>,
+    copyright: Option<&'a CopyrightHeader>,
+    wrapper: Option<&'a Wrapper>,
+    metadata_author: Option<String>,
+    test_input: Option<PathBuf>,
+    final_newline: FinalNewlineMode,
+    codepoint_limit: Option<usize>,
+    target: Option<std::path::PathBuf>,
+    /// Target shown when a step is skipped
+    source_label: String,
+    language: Option<Language>,
+}
+
+/// Result of the preprocessing
+#[derive(Debug)]
+pub struct Preprocessed {
+    pub code: String,
+    /// Names of the steps which changed the code, in order
+    pub applied: Vec<&'static str>,
+    /// Steps which failed and were skipped, to be reported to the user
+    pub errors: Vec<String>,
+}
+
+/// Stages enabled by the options, in the default order
+pub fn flagged(opts: &Opts) -> Vec<Stage> {
+    Stage::ALL
+        .iter()
+        .copied()
+        .filter(|stage| match stage {
+            Stage::Replace => !opts.upload_replace.is_empty(),
+            Stage::DeadCode => opts.upload_remove_dead_code.is_some(),
+            Stage::CommentOutMain => opts.upload_comment_out_main,
+            Stage::Copyright => opts.upload_add_copyright.is_some(),
+            Stage::Wrapper => !opts.upload_wrapper.is_empty(),
+            Stage::Metadata => opts.upload_inject_metadata,
+            Stage::TestInput => opts.upload_inject_test_input.is_some(),
+            Stage::FinalNewline => opts.final_newline_mode() != FinalNewlineMode::Passthrough,
+            Stage::CodepointLimit => opts.upload_force_codepoint_limit.is_some(),
+        })
+        .collect()
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(
+        opts: &Opts,
+        dynamic: &DynamicOpts,
+        transforms: &'a TransformsConfig,
+        copyright: Option<&'a CopyrightHeader>,
+        wrapper: Option<&'a Wrapper>,
+    ) -> Self {
+        let options = &transforms.options;
+        let flag_final_newline = opts.final_newline_mode();
+
+        Self {
+            transforms,
+            flagged: flagged(opts),
+            replacements: options
+                .replace
+                .rules
+                .iter()
+                .chain(&opts.upload_replace)
+                .cloned()
+                .collect(),
+            dead_code_language: opts.upload_remove_dead_code.or(options.dead_code.language),
+            copyright,
+            wrapper,
+            metadata_author: opts
+                .metadata_author
+                .clone()
+                .or_else(|| options.metadata.author.clone()),
+            test_input: opts.upload_inject_test_input.clone(),
+            // A chain listing the stage ensures the final newline unless told otherwise
+            final_newline: match flag_final_newline {
+                FinalNewlineMode::Passthrough => options
+                    .final_newline
+                    .mode
+                    .unwrap_or(FinalNewlineMode::Ensure),
+                mode => mode,
+            },
+            codepoint_limit: opts
+                .upload_force_codepoint_limit
+                .or(options.codepoint_limit.limit),
+            target: dynamic.target.clone().map(Into::into),
+            source_label: dynamic.source_label(),
+            language: dynamic
+                .target
+                .as_ref()
+                .and_then(|target| Language::from_path(target.as_ref())),
+        }
+    }
+
+    /// Stages to run for the question: its configured chain with the stages enabled by the
+    /// options, or only the latter
+    pub fn stages(&self, question_id: Option<i32>) -> Vec<Stage> {
+        match self
+            .transforms
+            .chain(self.target.as_deref(), question_id, self.language)
+        {
+            Some(chain) => transform_chains::with_flagged(chain, &self.flagged),
+            None => self.flagged.clone(),
+        }
+    }
+
+    /// Preprocess the code, for the question if it is known
+    pub async fn run(&self, code: &str, question: Option<&(i32, String)>) -> Preprocessed {
+        let mut result = Preprocessed {
+            code: code.to_owned(),
+            applied: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        let stages = self.stages(question.map(|(question_id, _)| *question_id));
+        debug!(
+            "preprocessing stages: {}",
+            stages
+                .iter()
+                .map(|stage| stage.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        for stage in stages {
+            self.run_stage(stage, question, &mut result).await;
+        }
+
+        result
+    }
+
+    async fn run_stage(
+        &self,
+        stage: Stage,
+        question: Option<&(i32, String)>,
+        result: &mut Preprocessed,
+    ) {
+        let name = stage.name();
+
+        match stage {
+            Stage::Replace => {
+                result.step(name, |code| {
+                    transforms::apply_replacements(code, &self.replacements).into_owned()
+                });
+            }
+            Stage::DeadCode => match self.dead_code_language.or(self.language) {
+                Some(language) => {
+                    let code = result.code.clone();
+                    let stripped =
+                        task::spawn_blocking(move || dead_code::strip(language, &code)).await;
+                    result.step(name, |_| stripped);
+                }
+                None => warn!(
+                    "{}",
+                    messages::dead_code_unknown_language(&self.source_label)
+                ),
+            },
+            Stage::CommentOutMain => match self.language {
+                Some(language) => {
+                    let commented = transforms::comment_out_main(&result.code, language);
+                    match commented {
+                        Some(commented) => {
+                            let commented = commented.into_owned();
+                            result.step(name, |_| commented);
+                        }
+                        None => warn!(
+                            "{}",
+                            messages::comment_out_main_unsupported(&language.to_string())
+                        ),
+                    }
+                }
+                None => warn!(
+                    "{}",
+                    messages::comment_out_main_unknown_language(&self.source_label)
+                ),
+            },
+            // Chains listing the stages below without their options are refused when the
+            // configuration is loaded, so they only run disabled when not listed
+            Stage::Copyright => {
+                if let Some(copyright) = self.copyright {
+                    result.step(name, |code| copyright.apply(code).into_owned());
+                }
+            }
+            Stage::Wrapper => {
+                if let Some(wrapper) = self.wrapper {
+                    result.step(name, |code| wrapper.wrap(code));
+                }
+            }
+            Stage::Metadata => match (self.language, question) {
+                (Some(language), Some((question_id, title))) => {
+                    let meta = Metadata::now(*question_id, title, self.metadata_author.clone());
+                    result.step(name, |code| metadata::inject(code, language, &meta));
+                }
+                (None, _) => warn!(
+                    "{}",
+                    messages::metadata_unknown_language(&self.source_label)
+                ),
+                (_, None) => debug!("not injecting metadata: no question details yet"),
+            },
+            Stage::TestInput => {
+                if let Some(test_input) = &self.test_input {
+                    match (
+                        self.language,
+                        async_std::fs::read_to_string(test_input).await,
+                    ) {
+                        (Some(language), Ok(input)) => {
+                            result.step(name, |code| test_input::append(code, language, &input));
+                        }
+                        (None, _) => warn!(
+                            "{}",
+                            messages::test_input_unknown_language(&self.source_label)
+                        ),
+                        (_, Err(err)) => result.errors.push(messages::test_input_unreadable(
+                            &test_input.display().to_string(),
+                            &err.to_string(),
+                        )),
+                    }
+                }
+            }
+            Stage::FinalNewline => {
+                let final_newline = self.final_newline;
+                result.step(name, |code| {
+                    transforms::normalize_final_newline(code, final_newline).into_owned()
+                });
+            }
+            Stage::CodepointLimit => match self.codepoint_limit {
+                Some(limit) if result.code.chars().count() > limit => {
+                    result.step(name, |code| {
+                        format!(
+                            "{}{}",
+                            transforms::truncate_at_codepoints(code, limit),
+                            TRUNCATED_MARKER
+                        )
+                    });
+                    result.errors.push(messages::code_truncated(limit));
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+impl Preprocessed {
+    /// Apply a step, recording it if it changed the code
+    fn step(&mut self, name: &'static str, step: impl FnOnce(&str) -> String) {
+        let code = step(&self.code);
+        if code != self.code {
+            self.applied.push(name);
+            self.code = code;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Golden files of the pipeline: every case of `tests/golden/pipeline` holds the input, the
+    //! files its options read and the expected output, regenerated with `UPDATE_SNAPSHOTS=1`.
+
+    use std::path::{Path, PathBuf};
+
+    use structopt::StructOpt;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::ErrorKind;
+
+    const GOLDEN: &str = "tests/golden/pipeline";
+
+    /// Case name, input file and options, `{}` standing for the directory of the case
+    const CASES: &[(&str, &str, &[&str])] = &[
+        (
+            "replace",
+            "input.py",
+            &["--upload-replace", "DEBUG = True:DEBUG = False"],
+        ),
+        (
+            "comment-out-main-rust",
+            "input.rs",
+            &["--upload-comment-out-main"],
+        ),
+        (
+            "comment-out-main-python",
+            "input.py",
+            &["--upload-comment-out-main"],
+        ),
+        (
+            "copyright",
+            "input.rs",
+            &[
+                "--upload-add-copyright",
+                "{}/copyright.txt",
+                "--copyright-year-override",
+                "2021",
+            ],
+        ),
+        (
+            "wrapper",
+            "input.py",
+            &["--upload-wrapper", "{}/before.txt", "{}/after.txt"],
+        ),
+        (
+            "test-input",
+            "input.py",
+            &["--upload-inject-test-input", "{}/input.txt"],
+        ),
+        (
+            "final-newline",
+            "input.rs",
+            &["--upload-strip-final-newline"],
+        ),
+        (
+            "final-newline-crlf",
+            "input.py",
+            &["--upload-ensure-final-newline"],
+        ),
+        (
+            "codepoint-limit",
+            "input.py",
+            &["--upload-force-codepoint-limit", "20"],
+        ),
+        (
+            "chain-order",
+            "input.rs",
+            &[
+                "--config",
+                "{}/config.toml",
+                "--upload-add-copyright",
+                "{}/copyright.txt",
+                "--copyright-year-override",
+                "2021",
+            ],
+        ),
+    ];
+
+    fn opts(dir: &Path, input: &str, args: &[&str]) -> Opts {
+        let dir = dir.to_str().unwrap();
+        let target = format!("{}/{}", dir, input);
+
+        let mut argv = vec!["cg-local-app".to_owned(), "--target".to_owned(), target];
+        argv.extend(args.iter().map(|arg| arg.replace("{}", dir)));
+        Opts::from_iter(argv)
+    }
+
+    /// Configuration of the case, checked like on startup
+    fn load_config(opts: &Opts) -> crate::Result<Config> {
+        // Never the configuration of the user
+        let config = match &opts.config {
+            Some(path) => Config::load(Some(path.as_ref()))?,
+            None => Config::default(),
+        };
+        config.transforms.check(&flagged(opts))?;
+        Ok(config)
+    }
+
+    fn preprocess(opts: &Opts, config: &Config) -> Preprocessed {
+        let code = std::fs::read_to_string(opts.target.as_ref().unwrap()).unwrap();
+        let (copyright, wrapper) = (opts.copyright().unwrap(), opts.wrapper().unwrap());
+        let pipeline = Pipeline::new(
+            opts,
+            &DynamicOpts::new(opts),
+            &config.transforms,
+            copyright.as_ref(),
+            wrapper.as_ref(),
+        );
+
+        task::block_on(pipeline.run(&code, None))
+    }
+
+    /// Compare the output of every case with its golden file, or update them with
+    /// `UPDATE_SNAPSHOTS=1`
+    #[test]
+    fn matches_the_golden_files() {
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN);
+
+        for (name, input, args) in CASES {
+            let dir = golden.join(name);
+            let opts = opts(&dir, input, args);
+            let config = load_config(&opts).unwrap();
+
+            let output = preprocess(&opts, &config).code;
+            let path: PathBuf = dir.join(input.replace("input", "expected"));
+
+            if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+                std::fs::write(&path, &output).unwrap();
+                continue;
+            }
+
+            let expected = std::fs::read_to_string(&path).unwrap_or_default();
+            assert_eq!(
+                output, expected,
+                "{}: run with UPDATE_SNAPSHOTS=1 if the change is deliberate",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn chains_need_the_options_of_their_stages() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        let load = |config: &str, args: &[&str]| {
+            std::fs::write(dir.path().join("config.toml"), config).unwrap();
+            let mut argv = vec!["--config", "{}/config.toml"];
+            argv.extend_from_slice(args);
+            load_config(&opts(dir.path(), "main.rs", &argv))
+        };
+
+        let refused = [
+            ("copyright", "--upload-add-copyright"),
+            ("wrapper", "--upload-wrapper"),
+            ("test-input", "--upload-inject-test-input"),
+            ("codepoint-limit", "--upload-force-codepoint-limit"),
+        ];
+        for (stage, option) in &refused {
+            let config = format!("[transforms.question]\n42 = [\"{}\"]\n", stage);
+            match load(&config, &[]) {
+                Err(crate::Error(ErrorKind::IncompleteTransformChain(refused, needed), _)) => {
+                    assert_eq!(&refused, stage);
+                    assert!(needed.starts_with(option), "{}", needed);
+                }
+                other => panic!("{} accepted without {}: {:?}", stage, option, other.err()),
+            }
+        }
+
+        // Limit given in the configuration
+        load(
+            "[transforms.question]\n42 = [\"codepoint-limit\"]\n\n\
+             [transforms.options.codepoint-limit]\nlimit = 10\n",
+            &[],
+        )
+        .unwrap();
+        // Template given on the command line
+        load(
+            "[transforms.question]\n42 = [\"copyright\"]\n",
+            &["--upload-add-copyright", "{}/main.rs"],
+        )
+        .unwrap();
+    }
+}
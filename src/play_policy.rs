@@ -0,0 +1,312 @@
+//! Per-question control of auto-play, for IDEs where playing has side effects such as arena
+//! submissions.
+//!
+//! Rules are read from the `[[play_policy]]` tables of the configuration file. A rule matches a
+//! question by id, or by title with a case-insensitive pattern where `*` matches any text:
+//!
+//! ```toml
+//! [[play_policy]]
+//! title = "*Arena*"
+//! policy = "deny"
+//!
+//! [[play_policy]]
+//! question_id = 42
+//! policy = "allow"
+//! ```
+//!
+//! Rules matching the question id take precedence over title patterns, then the first matching
+//! rule wins. Questions matched by no rule allow auto-play.
+//!
+//! Whether an upload plays is only decided by [`PlayGate::decide`], from the auto-play setting
+//! and the policy of the question:
+//!
+//! | Auto-play | Policy | Plays | Reason                                   |
+//! |-----------|--------|-------|------------------------------------------|
+//! | off       | any    | no    | `Disabled`                               |
+//! | on        | allow  | yes   | `Allowed`                                |
+//! | on        | warn   | yes   | `Flagged`, the first time for a question |
+//! | on        | warn   | yes   | `Allowed`, afterwards                    |
+//! | on        | deny   | no    | `Denied`                                 |
+
+/// What to do when auto-play is enabled for a question
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayPolicy {
+    #[default]
+    Allow,
+    /// Warn once before the first played upload
+    Warn,
+    /// Never play, whatever the options
+    Deny,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlayPolicyRule {
+    pub question_id: Option<i32>,
+    /// Title pattern, `*` matching any text
+    pub title: Option<String>,
+    pub policy: PlayPolicy,
+}
+
+/// Why an upload plays or not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayReason {
+    /// Auto-play is off
+    Disabled,
+    Allowed,
+    /// First play of a question flagged by the Warn policy, which the user should be warned of
+    Flagged,
+    /// Auto-play is on but the Deny policy suppresses it
+    Denied,
+}
+
+/// Outcome of [`PlayGate::decide`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayDecision {
+    pub play: bool,
+    pub reason: PlayReason,
+}
+
+/// Play decisions for the current question, remembering whether its Warn policy was shown
+#[derive(Debug, Default)]
+pub struct PlayGate {
+    policy: PlayPolicy,
+    warned: bool,
+}
+
+impl PlayGate {
+    /// Apply the policy of a new question
+    pub fn set_question(&mut self, rules: &[PlayPolicyRule], question_id: i32, title: &str) {
+        self.policy = evaluate(rules, question_id, title);
+        self.warned = false;
+    }
+
+    pub fn policy(&self) -> PlayPolicy {
+        self.policy
+    }
+
+    /// Whether an upload plays, given the auto-play setting
+    pub fn decide(&mut self, play: bool) -> PlayDecision {
+        let reason = match (play, self.policy) {
+            (false, _) => PlayReason::Disabled,
+            (true, PlayPolicy::Allow) => PlayReason::Allowed,
+            (true, PlayPolicy::Warn) if !self.warned => {
+                self.warned = true;
+                PlayReason::Flagged
+            }
+            (true, PlayPolicy::Warn) => PlayReason::Allowed,
+            (true, PlayPolicy::Deny) => PlayReason::Denied,
+        };
+
+        PlayDecision {
+            play: matches!(reason, PlayReason::Allowed | PlayReason::Flagged),
+            reason,
+        }
+    }
+}
+
+/// Policy applying to the question
+pub fn evaluate(rules: &[PlayPolicyRule], question_id: i32, title: &str) -> PlayPolicy {
+    rules
+        .iter()
+        .find(|rule| rule.question_id == Some(question_id))
+        .or_else(|| {
+            rules.iter().find(|rule| {
+                rule.question_id.is_none()
+                    && rule
+                        .title
+                        .as_deref()
+                        .map(|pattern| matches_pattern(pattern, title))
+                        .unwrap_or(false)
+            })
+        })
+        .map(|rule| rule.policy)
+        .unwrap_or_default()
+}
+
+/// Case-insensitive match of the whole text against a pattern where `*` matches any text
+pub fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    // Without any star, the prefix must be the whole text
+    let parts: Vec<_> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        None => return rest.is_empty(),
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(question_id: Option<i32>, title: Option<&str>, policy: PlayPolicy) -> PlayPolicyRule {
+        PlayPolicyRule {
+            question_id,
+            title: title.map(str::to_owned),
+            policy,
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        let table = [
+            ("Onboarding", "Onboarding", true),
+            ("onboarding", "ONBOARDING", true),
+            ("Onboarding", "Onboarding 2", false),
+            ("*", "", true),
+            ("*", "Spring Challenge", true),
+            ("*Arena*", "Bot Arena Battle", true),
+            ("*arena*", "ARENA", true),
+            ("*Arena*", "Onboarding", false),
+            ("Spring*", "Spring Challenge 2021", true),
+            ("Spring*", "The Spring Challenge", false),
+            ("*2021", "Spring Challenge 2021", true),
+            ("*2021", "Spring Challenge 2022", false),
+            ("Spring*2021", "Spring Challenge 2021", true),
+            ("Spring*Fall*2021", "Spring and Fall of 2021", true),
+            ("Spring*Fall*2021", "Fall and Spring of 2021", false),
+            // The parts don't overlap
+            ("ab*ba", "aba", false),
+            ("a*a", "a", false),
+            ("", "", true),
+            ("", "Onboarding", false),
+        ];
+
+        for (pattern, title, expected) in table {
+            assert_eq!(
+                matches_pattern(pattern, title),
+                expected,
+                "{:?} against {:?}",
+                pattern,
+                title
+            );
+        }
+    }
+
+    #[test]
+    fn question_ids_take_precedence_over_titles() {
+        let rules = [
+            rule(None, Some("*Arena*"), PlayPolicy::Deny),
+            rule(Some(42), None, PlayPolicy::Warn),
+            rule(None, Some("Bot*"), PlayPolicy::Allow),
+            // Only the id of a rule with both is matched
+            rule(Some(7), Some("Onboarding"), PlayPolicy::Deny),
+        ];
+
+        let table = [
+            (42, "Bot Arena", PlayPolicy::Warn),
+            (1, "Bot Arena", PlayPolicy::Deny),
+            (1, "Bot Programming", PlayPolicy::Allow),
+            (7, "Anything", PlayPolicy::Deny),
+            (1, "Onboarding", PlayPolicy::Allow),
+        ];
+
+        for (question_id, title, expected) in table {
+            assert_eq!(
+                evaluate(&rules, question_id, title),
+                expected,
+                "{} {:?}",
+                question_id,
+                title
+            );
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = [
+            rule(Some(42), None, PlayPolicy::Warn),
+            rule(Some(42), None, PlayPolicy::Deny),
+            rule(None, Some("*Arena*"), PlayPolicy::Deny),
+            rule(None, Some("*"), PlayPolicy::Warn),
+        ];
+
+        assert_eq!(evaluate(&rules, 42, "Onboarding"), PlayPolicy::Warn);
+        assert_eq!(evaluate(&rules, 1, "Bot Arena"), PlayPolicy::Deny);
+        assert_eq!(evaluate(&rules, 1, "Onboarding"), PlayPolicy::Warn);
+    }
+
+    #[test]
+    fn questions_without_rules_allow_play() {
+        assert_eq!(evaluate(&[], 1, "Onboarding"), PlayPolicy::Allow);
+
+        let rules = [
+            rule(Some(42), None, PlayPolicy::Deny),
+            rule(None, Some("*Arena*"), PlayPolicy::Deny),
+            // Matches nothing
+            rule(None, None, PlayPolicy::Deny),
+        ];
+        assert_eq!(evaluate(&rules, 1, "Onboarding"), PlayPolicy::Allow);
+    }
+
+    #[test]
+    fn decisions() {
+        use PlayPolicy::*;
+        use PlayReason::*;
+
+        // Auto-play, policy, warned before: reason, plays, warned after
+        let table = [
+            (false, Allow, false, Disabled, false, false),
+            (false, Allow, true, Disabled, false, true),
+            (false, Warn, false, Disabled, false, false),
+            (false, Warn, true, Disabled, false, true),
+            (false, Deny, false, Disabled, false, false),
+            (false, Deny, true, Disabled, false, true),
+            (true, Allow, false, Allowed, true, false),
+            (true, Allow, true, Allowed, true, true),
+            (true, Warn, false, Flagged, true, true),
+            (true, Warn, true, Allowed, true, true),
+            (true, Deny, false, Denied, false, false),
+            (true, Deny, true, Denied, false, true),
+        ];
+
+        for (play, policy, warned, reason, plays, warned_after) in table {
+            let mut gate = PlayGate { policy, warned };
+            let decision = gate.decide(play);
+
+            let case = format!("{} {:?} {}", play, policy, warned);
+            assert_eq!(
+                decision,
+                PlayDecision {
+                    play: plays,
+                    reason
+                },
+                "{}",
+                case
+            );
+            assert_eq!(gate.warned, warned_after, "{}", case);
+        }
+    }
+
+    #[test]
+    fn questions_are_flagged_once_each() {
+        let rules = [rule(None, Some("*"), PlayPolicy::Warn)];
+        let mut gate = PlayGate::default();
+
+        gate.set_question(&rules, 1, "Onboarding");
+        assert_eq!(gate.decide(false).reason, PlayReason::Disabled);
+        assert_eq!(gate.decide(true).reason, PlayReason::Flagged);
+        assert_eq!(gate.decide(true).reason, PlayReason::Allowed);
+
+        gate.set_question(&rules, 2, "The Descent");
+        assert_eq!(gate.decide(true).reason, PlayReason::Flagged);
+    }
+}
@@ -0,0 +1,117 @@
+//! Trace of the WebSocket frames exchanged with the extension, for `--trace-protocol`.
+//!
+//! Every text frame is appended to the file as it was received or sent, whatever the log level,
+//! on a line with its time, connection and direction:
+//!
+//! ```text
+//! 2021-03-14T15:09:26.535+01:00 #1 > {"action":"send-details"}
+//! 2021-03-14T15:09:26.541+01:00 #1 < {"action":"details","payload":{"title":"Onboarding","questionId":1}}
+//! ```
+//!
+//! Unlike `--record`, frames which aren't valid messages are traced too. Line breaks in a frame
+//! are escaped to keep one frame per line. The token sent as first message with `--auth-token`
+//! is checked before the connection is traced, so it doesn't appear. The code carried by
+//! messages is redacted as in the logs unless `--log-include-code` is given, the message being
+//! written back as JSON without it.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::connections::ConnectionId;
+use crate::{payload, Result};
+
+static TRACE: OnceLock<Mutex<LineWriter<File>>> = OnceLock::new();
+
+/// Trace the frames to the file, truncating it
+pub fn start(path: &Path) -> Result<()> {
+    let file = File::create(path)?;
+    let _ = TRACE.set(Mutex::new(LineWriter::new(file)));
+    Ok(())
+}
+
+/// Trace a frame received from the extension
+pub fn received(connection: ConnectionId, text: &str) {
+    trace_frame(connection, '<', text);
+}
+
+/// Trace a frame sent to the extension
+pub fn sent(connection: ConnectionId, text: &str) {
+    trace_frame(connection, '>', text);
+}
+
+fn trace_frame(connection: ConnectionId, direction: char, text: &str) {
+    let file = match TRACE.get() {
+        Some(file) => file,
+        None => return,
+    };
+
+    let at = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
+    let text = redact(text).replace('\r', "\\r").replace('\n', "\\n");
+
+    let mut file = file.lock().unwrap();
+    if let Err(err) = writeln!(file, "{} {} {} {}", at, connection, direction, text) {
+        warn!("failed to trace frame: {}", err);
+    }
+}
+
+/// Frame with the code of its message redacted, unless --log-include-code asked for it
+fn redact(text: &str) -> Cow<'_, str> {
+    if payload::included_in_logs() {
+        return Cow::Borrowed(text);
+    }
+
+    let mut message: serde_json::Value = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(_) => return Cow::Borrowed(text),
+    };
+
+    match message.pointer_mut("/payload/code") {
+        Some(serde_json::Value::String(code)) => *code = payload::redacted(code),
+        _ => return Cow::Borrowed(text),
+    }
+
+    Cow::Owned(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_is_redacted() {
+        let code = "print(1)\n";
+        let redacted = payload::redacted(code);
+        let table = [
+            (
+                r#"{"action":"update-code","payload":{"code":"print(1)\n","play":false}}"#,
+                format!(
+                    r#"{{"action":"update-code","payload":{{"code":"{}","play":false}}}}"#,
+                    redacted
+                ),
+            ),
+            (
+                r#"{"action":"code","payload":{"code":"print(1)\n"}}"#,
+                format!(r#"{{"action":"code","payload":{{"code":"{}"}}}}"#, redacted),
+            ),
+            // Without code
+            (
+                r#"{"action":"send-details"}"#,
+                r#"{"action":"send-details"}"#.to_owned(),
+            ),
+            (
+                r#"{"action":"details","payload":{"title":"Onboarding","questionId":1}}"#,
+                r#"{"action":"details","payload":{"title":"Onboarding","questionId":1}}"#
+                    .to_owned(),
+            ),
+            // Not a message
+            ("print(1)", "print(1)".to_owned()),
+        ];
+
+        for (frame, expected) in table {
+            assert_eq!(redact(frame), expected);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Tracking of which side last modified the synchronized content.
+
+use chrono::{DateTime, Local};
+
+/// Side of the synchronization
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Local,
+    Ide,
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Side::Local => "local",
+            Side::Ide => "IDE",
+        })
+    }
+}
+
+/// Relation between the local and IDE contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    /// Both sides have the same content, or nothing was synchronized yet
+    InSync,
+    /// The local file changed since it was last synchronized
+    LocalAhead,
+    /// The IDE code changed since it was last synchronized
+    IdeAhead,
+    /// Both sides changed since they were last synchronized
+    Diverged,
+}
+
+/// Origin of the last synchronized content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub side: Side,
+    /// Checksum of the synchronized content
+    pub checksum: String,
+    pub at: DateTime<Local>,
+}
+
+/// Synchronization state of the controller
+#[derive(Debug, Clone)]
+pub struct Tracker {
+    state: SyncState,
+    last: Option<Provenance>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self {
+            state: SyncState::InSync,
+            last: None,
+        }
+    }
+
+    pub fn state(&self) -> SyncState {
+        self.state
+    }
+
+    /// Origin of the last synchronized content, if anything was synchronized
+    pub fn last(&self) -> Option<&Provenance> {
+        self.last.as_ref()
+    }
+
+    /// Content from `side` was synchronized to the other side
+    pub fn synchronized(&mut self, side: Side, checksum: String) {
+        self.last = Some(Provenance {
+            side,
+            checksum,
+            at: Local::now(),
+        });
+        self.state = SyncState::InSync;
+    }
+
+    /// Content changed on `side` without being synchronized
+    pub fn changed(&mut self, side: Side) {
+        self.state = match (self.state, side) {
+            (SyncState::InSync, Side::Local) | (SyncState::LocalAhead, Side::Local) => {
+                SyncState::LocalAhead
+            }
+            (SyncState::InSync, Side::Ide) | (SyncState::IdeAhead, Side::Ide) => {
+                SyncState::IdeAhead
+            }
+            _ => SyncState::Diverged,
+        };
+    }
+
+    /// Both sides were found to have the same content
+    pub fn matched(&mut self) {
+        self.state = SyncState::InSync;
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tracker in the given state, reached through changes
+    fn tracker(state: SyncState) -> Tracker {
+        let mut tracker = Tracker::new();
+        match state {
+            SyncState::InSync => {}
+            SyncState::LocalAhead => tracker.changed(Side::Local),
+            SyncState::IdeAhead => tracker.changed(Side::Ide),
+            SyncState::Diverged => {
+                tracker.changed(Side::Local);
+                tracker.changed(Side::Ide);
+            }
+        }
+
+        assert_eq!(tracker.state(), state);
+        tracker
+    }
+
+    #[test]
+    fn changes() {
+        use SyncState::*;
+
+        let table = [
+            (InSync, Side::Local, LocalAhead),
+            (InSync, Side::Ide, IdeAhead),
+            (LocalAhead, Side::Local, LocalAhead),
+            (LocalAhead, Side::Ide, Diverged),
+            (IdeAhead, Side::Local, Diverged),
+            (IdeAhead, Side::Ide, IdeAhead),
+            (Diverged, Side::Local, Diverged),
+            (Diverged, Side::Ide, Diverged),
+        ];
+
+        for (from, side, to) in table {
+            let mut tracker = tracker(from);
+            tracker.changed(side);
+            assert_eq!(tracker.state(), to, "{:?} changed on {}", from, side);
+        }
+    }
+
+    #[test]
+    fn synchronizations() {
+        use SyncState::*;
+
+        for from in [InSync, LocalAhead, IdeAhead, Diverged] {
+            for side in [Side::Local, Side::Ide] {
+                let mut tracker = tracker(from);
+                tracker.synchronized(side, "checksum".to_owned());
+
+                assert_eq!(
+                    tracker.state(),
+                    InSync,
+                    "{:?} synchronized from {}",
+                    from,
+                    side
+                );
+                let last = tracker.last().unwrap();
+                assert_eq!((last.side, last.checksum.as_str()), (side, "checksum"));
+            }
+        }
+    }
+
+    #[test]
+    fn matches() {
+        use SyncState::*;
+
+        for from in [InSync, LocalAhead, IdeAhead, Diverged] {
+            let mut tracker = tracker(from);
+            tracker.matched();
+
+            assert_eq!(tracker.state(), InSync, "{:?} matched", from);
+            assert_eq!(tracker.last(), None, "matching synchronized nothing");
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Targets of the questions given with `--map`.
+//!
+//! `--map 1234=bot.rs` synchronizes question 1234 with `bot.rs`: when the extension identifies a
+//! mapped question, in a new tab or after moving to it in the same tab, the target switches to its
+//! file before synchronization starts. Other questions keep the current target. One target is
+//! synchronized at a time, the one of the question identified last, so the tab which identified it
+//! receives the uploads.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Target of a question, as `QUESTION_ID=FILE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuestionMap {
+    pub question_id: i32,
+    pub target: PathBuf,
+}
+
+impl FromStr for QuestionMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (question_id, target) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected QUESTION_ID=FILE, got '{}'", s))?;
+
+        let question_id = question_id
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid question id '{}' in '{}'", question_id, s))?;
+
+        if target.is_empty() {
+            return Err(format!("the file is missing in '{}'", s));
+        }
+
+        Ok(Self {
+            question_id,
+            target: PathBuf::from(target),
+        })
+    }
+}
+
+/// Target of the question, the last mapping given winning
+pub fn target_for(maps: &[QuestionMap], question_id: i32) -> Option<&Path> {
+    maps.iter()
+        .rev()
+        .find(|map| map.question_id == question_id)
+        .map(|map| map.target.as_path())
+}
@@ -0,0 +1,89 @@
+//! Read-only state of the target, following the IDE editor.
+//!
+//! The extension sends `SetReadOnly` when the IDE editor is locked or unlocked. The target is
+//! then made read-only on disk as well, and given its previous permissions back once unlocked.
+//! The writes of the app itself, like downloads, lift the permission for their duration. A
+//! download replacing the file takes the permissions of the new file as the ones to restore.
+
+use std::fs::Permissions;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Target made read-only, with its permissions before
+#[derive(Debug, Default)]
+pub struct ReadOnly {
+    locked: Option<(PathBuf, Permissions)>,
+}
+
+impl ReadOnly {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.locked.is_some()
+    }
+
+    /// Make `target` read-only, giving its permissions back to a previously locked target
+    pub async fn lock(&mut self, target: &Path) -> io::Result<()> {
+        match &self.locked {
+            Some((path, _)) if path == target => return Ok(()),
+            Some(_) => self.unlock().await?,
+            None => {}
+        }
+
+        let permissions = set_readonly(target).await?;
+        debug!("made {} read-only", target.display());
+        self.locked = Some((target.to_owned(), permissions));
+        Ok(())
+    }
+
+    /// Give the locked target its permissions back
+    pub async fn unlock(&mut self) -> io::Result<()> {
+        if let Some((path, permissions)) = self.locked.take() {
+            async_std::fs::set_permissions(&path, permissions).await?;
+            debug!("made {} writable again", path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Move the lock to the new target, if the editor is locked
+    pub async fn retarget(&mut self, target: &Path) -> io::Result<()> {
+        if self.is_set() {
+            self.lock(target).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Give the locked target its permissions back for a write of the app, until `relock`
+    pub async fn lift(&self) -> io::Result<()> {
+        match &self.locked {
+            Some((path, permissions)) => {
+                async_std::fs::set_permissions(path, permissions.clone()).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Make the target read-only again once written, replaced or re-created
+    pub async fn relock(&mut self) -> io::Result<()> {
+        if let Some((path, permissions)) = &mut self.locked {
+            *permissions = set_readonly(path).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Make the file read-only, returning its permissions before
+async fn set_readonly(path: &Path) -> io::Result<Permissions> {
+    let permissions = async_std::fs::metadata(path).await?.permissions();
+
+    let mut readonly = permissions.clone();
+    readonly.set_readonly(true);
+    async_std::fs::set_permissions(path, readonly).await?;
+
+    Ok(permissions)
+}
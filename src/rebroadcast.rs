@@ -0,0 +1,192 @@
+//! Copy of the uploads for monitoring clients, for `--rebroadcast-port` and `--observers`.
+//!
+//! Monitoring clients connect to a second WebSocket listener and receive every `Details` and
+//! `UpdateCode` message exchanged with the extension, in the same format. They are passive:
+//! anything they send is ignored, and any number of them can be connected.
+//!
+//! Observers get the same messages on the extension listener, by connecting with an `observer`
+//! query parameter (`ws://host:port/?observer`). Up to `--observers` of them are accepted
+//! alongside the extension.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::prelude::*;
+use async_std::sync::Mutex;
+use async_tungstenite::tungstenite;
+use futures_util::future::FutureExt;
+use futures_util::select;
+use futures_util::sink::SinkExt;
+
+use crate::metrics;
+use crate::runtime::channel::{self, Receiver, Sender};
+use crate::runtime::net::{TcpListener, TcpStream};
+use crate::runtime::task;
+use crate::{Result, ServerMessage};
+
+/// Delay for a new connection to send its request, to tell observers from the extension
+const REQUEST_PEEK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Fan-out of messages to the connected monitoring clients
+#[derive(Clone, Default)]
+pub struct Rebroadcaster {
+    clients: Arc<Mutex<Vec<Sender<String>>>>,
+    observers: Arc<AtomicUsize>,
+}
+
+impl Rebroadcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Listen for monitoring clients on `addr`
+    pub async fn listen(&self, addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("rebroadcasting uploads on {}", listener.local_addr()?);
+
+        task::spawn(self.clone().run_accept(listener));
+        Ok(())
+    }
+
+    /// Serve a connection of the extension listener as an observer, unless `limit` observers
+    /// are already connected. The stream is given back if it was refused.
+    pub async fn observe(
+        &self,
+        peer: SocketAddr,
+        stream: TcpStream,
+        limit: usize,
+    ) -> std::result::Result<(), TcpStream> {
+        let count = self.observers.fetch_add(1, Ordering::SeqCst) + 1;
+        if count > limit {
+            self.observers.fetch_sub(1, Ordering::SeqCst);
+            return Err(stream);
+        }
+
+        info!("observer {} connected ({}/{})", peer, count, limit);
+        metrics::record_observers(count);
+
+        let rx = self.register().await;
+        let observers = self.observers.clone();
+        task::spawn(async move {
+            if let Err(err) = handle_client(peer, stream, rx).await {
+                debug!("observer {} failed: {}", peer, err);
+            }
+
+            let count = observers.fetch_sub(1, Ordering::SeqCst) - 1;
+            metrics::record_observers(count);
+            info!("observer {} disconnected ({}/{})", peer, count, limit);
+        });
+
+        Ok(())
+    }
+
+    /// Add a client receiving the messages
+    async fn register(&self) -> Receiver<String> {
+        let (tx, rx) = channel::unbounded();
+        let mut clients = self.clients.lock().await;
+        clients.retain(|tx| !tx.is_closed());
+        clients.push(tx);
+        rx
+    }
+
+    /// Send a message to every connected monitoring client
+    pub async fn send(&self, msg: &ServerMessage) {
+        let text = serde_json::to_string(msg).unwrap();
+
+        // Clients that disconnected dropped their receiver
+        self.clients
+            .lock()
+            .await
+            .retain(|tx| tx.try_send(text.clone()).is_ok());
+    }
+
+    async fn run_accept(self, listener: TcpListener) {
+        while let Some(stream) = listener.incoming().next().await {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("failed to accept monitoring client: {}", err);
+                    continue;
+                }
+            };
+
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(err) => {
+                    warn!("failed to accept monitoring client: {}", err);
+                    continue;
+                }
+            };
+
+            let rx = self.register().await;
+            info!("{} monitoring client(s)", self.clients.lock().await.len());
+
+            task::spawn(async move {
+                if let Err(err) = handle_client(peer, stream, rx).await {
+                    debug!("monitoring client {} failed: {}", peer, err);
+                }
+
+                info!("monitoring client {} disconnected", peer);
+            });
+        }
+    }
+}
+
+/// Whether the connection asks to be an observer, from the query of its WebSocket request. The
+/// request is peeked, so the handshake can still read it.
+pub async fn is_observer_request(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 512];
+    let n = match async_std::future::timeout(REQUEST_PEEK_TIMEOUT, stream.peek(&mut buf)).await {
+        Ok(Ok(n)) => n,
+        _ => return false,
+    };
+
+    // GET /path?query HTTP/1.1
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let query = request
+        .lines()
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|target| target.split_once('?'))
+        .map(|(_, query)| query);
+
+    match query {
+        Some(query) => query
+            .split('&')
+            .any(|param| param == "observer" || param.starts_with("observer=")),
+        None => false,
+    }
+}
+
+async fn handle_client(
+    peer: SocketAddr,
+    stream: TcpStream,
+    mut rx: Receiver<String>,
+) -> Result<()> {
+    let mut ws_stream = async_tungstenite::accept_async(stream).await?;
+    info!("accepted monitoring client {}", peer);
+
+    loop {
+        select! {
+            msg = ws_stream.next().fuse() => {
+                match msg {
+                    // Monitoring clients are passive
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => return Err(err.into()),
+                    None => break,
+                }
+            }
+
+            text = rx.next().fuse() => {
+                match text {
+                    Some(text) => ws_stream.send(tungstenite::Message::Text(text)).await?,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+//! Recording of the messages exchanged with the extension, for `--record`.
+//!
+//! Every message received from or sent to the extension is appended to the file as a line of
+//! JSON, with the time it was exchanged, the seconds elapsed since the recording started, the
+//! connection it went through and its direction:
+//!
+//! ```json
+//! {"at":"2021-03-14T15:09:26.535+01:00","elapsed":0.012,"connection":1,"direction":"out","message":{"action":"send-details"}}
+//! ```
+//!
+//! The `replay` subcommand sends the received messages back to a running instance, at their
+//! recorded pace, to reproduce a session without a browser. Authentication messages are left
+//! out, so the recording doesn't carry the token. The code is recorded in full whatever
+//! `--log-include-code` says, as replaying the code of the IDE is what reproduces the session:
+//! recordings are to be shared like the code itself.
+
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use serde_json::json;
+
+use crate::connections::ConnectionId;
+use crate::{Result, ServerMessage};
+
+/// Direction of a recorded message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    /// Received from the extension
+    In,
+    /// Sent to the extension
+    Out,
+}
+
+/// Line of a recording
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub at: String,
+    /// Seconds since the recording started
+    pub elapsed: f64,
+    pub connection: ConnectionId,
+    pub direction: Direction,
+    /// Message as exchanged, kept as JSON for the actions of newer extension releases
+    pub message: serde_json::Value,
+}
+
+struct Recorder {
+    file: Mutex<LineWriter<File>>,
+    started: Instant,
+}
+
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+/// Record the messages to the file, truncating it
+pub fn start(path: &Path) -> Result<()> {
+    let file = File::create(path)?;
+
+    let _ = RECORDER.set(Recorder {
+        file: Mutex::new(LineWriter::new(file)),
+        started: Instant::now(),
+    });
+
+    Ok(())
+}
+
+/// Record a message received from the extension
+pub fn received(connection: ConnectionId, msg: &ServerMessage) {
+    record(connection, Direction::In, msg);
+}
+
+/// Record a message sent to the extension
+pub fn sent(connection: ConnectionId, msg: &ServerMessage) {
+    record(connection, Direction::Out, msg);
+}
+
+fn record(connection: ConnectionId, direction: Direction, msg: &ServerMessage) {
+    let recorder = match RECORDER.get() {
+        Some(recorder) => recorder,
+        None => return,
+    };
+
+    let message = match msg {
+        ServerMessage::Auth { .. } => return,
+        // Not serializable, as it is only ever parsed
+        ServerMessage::Unknown { action, payload } => {
+            json!({ "action": action, "payload": payload })
+        }
+        msg => match serde_json::to_value(msg) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("failed to record message: {}", err);
+                return;
+            }
+        },
+    };
+
+    let entry = Entry {
+        at: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+        elapsed: recorder.started.elapsed().as_secs_f64(),
+        connection,
+        direction,
+        message,
+    };
+
+    let line = serde_json::to_string(&entry).unwrap();
+    let mut file = recorder.file.lock().unwrap();
+    if let Err(err) = writeln!(file, "{}", line) {
+        warn!("failed to record message: {}", err);
+    }
+}
+
+/// Read the entries of a recording, reporting the line of the first invalid one
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    let contents = std::fs::read_to_string(path)?;
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|err| {
+                format!(
+                    "{}:{}: invalid recording entry: {}",
+                    path.display(),
+                    i + 1,
+                    err
+                )
+                .into()
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,176 @@
+//! Bounded retention of code payloads kept in memory.
+//!
+//! Generated solutions can weigh hundreds of kilobytes, so payloads kept around during a session
+//! go through a `Retained` buffer which drops the oldest ones once the policy limits are
+//! exceeded.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Local};
+
+use crate::checksum;
+
+/// Limits of a `Retained` buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Maximum number of payloads
+    pub max_entries: usize,
+    /// Maximum total size of the payloads, in bytes
+    pub max_bytes: usize,
+}
+
+/// Payloads retained in memory with the time they were retained, oldest first
+#[derive(Debug)]
+pub struct Retained {
+    policy: RetentionPolicy,
+    entries: VecDeque<(DateTime<Local>, String)>,
+    bytes: usize,
+}
+
+impl Retained {
+    pub fn new(policy: RetentionPolicy) -> Self {
+        Self {
+            policy,
+            entries: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Retain the payload, returning the payloads evicted to stay within the policy, oldest
+    /// first. The most recent payload is always kept, even if it exceeds the limits alone.
+    pub fn push(&mut self, payload: String) -> Vec<String> {
+        self.bytes += payload.len();
+        self.entries.push_back((Local::now(), payload));
+
+        let mut evicted = Vec::new();
+        while self.entries.len() > 1
+            && (self.entries.len() > self.policy.max_entries || self.bytes > self.policy.max_bytes)
+        {
+            if let Some((_, payload)) = self.entries.pop_front() {
+                self.bytes -= payload.len();
+                evicted.push(payload);
+            }
+        }
+
+        evicted
+    }
+
+    /// Most recently retained payload
+    pub fn latest(&self) -> Option<&str> {
+        self.entries.back().map(|(_, payload)| payload.as_str())
+    }
+
+    /// Retained payloads with their time, newest first. Payloads retained several times in a
+    /// row are only listed once, at the time they were first retained.
+    pub fn versions(&self) -> Vec<(DateTime<Local>, &str)> {
+        let mut versions: Vec<(DateTime<Local>, &str)> = Vec::new();
+        for (at, payload) in &self.entries {
+            if versions.last().map(|(_, last)| *last) != Some(payload.as_str()) {
+                versions.push((*at, payload));
+            }
+        }
+
+        versions.reverse();
+        versions
+    }
+
+    /// Most recent payload with the given checksum
+    pub fn find(&self, checksum: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .map(|(_, payload)| payload.as_str())
+            .find(|payload| checksum::checksum(payload) == checksum)
+    }
+
+    /// Number of retained payloads
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Total size of the retained payloads, in bytes
+    pub fn retained_bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retained(max_entries: usize, max_bytes: usize) -> Retained {
+        Retained::new(RetentionPolicy {
+            max_entries,
+            max_bytes,
+        })
+    }
+
+    #[test]
+    fn max_entries() {
+        let mut retained = retained(2, usize::MAX);
+
+        assert!(retained.push("a".to_owned()).is_empty());
+        assert!(retained.push("b".to_owned()).is_empty());
+        assert_eq!(retained.push("c".to_owned()), vec!["a"]);
+        assert_eq!(retained.push("d".to_owned()), vec!["b"]);
+
+        assert_eq!(retained.len(), 2);
+        assert_eq!(retained.retained_bytes(), 2);
+        assert_eq!(retained.latest(), Some("d"));
+    }
+
+    #[test]
+    fn max_bytes() {
+        let mut retained = retained(usize::MAX, 10);
+
+        assert!(retained.push("1234".to_owned()).is_empty());
+        assert!(retained.push("5678".to_owned()).is_empty());
+        assert_eq!(retained.push("90ab".to_owned()), vec!["1234"]);
+        assert_eq!(retained.retained_bytes(), 8);
+
+        // As many as needed to fit
+        assert_eq!(retained.push("cdefghij".to_owned()), vec!["5678", "90ab"]);
+        assert_eq!(retained.len(), 1);
+
+        // The most recent one is kept even alone over the limit
+        assert_eq!(retained.push("x".repeat(20)), vec!["cdefghij"]);
+        assert_eq!(retained.len(), 1);
+        assert_eq!(retained.retained_bytes(), 20);
+    }
+
+    #[test]
+    fn both_limits() {
+        let mut retained = retained(3, 10);
+
+        retained.push("12".to_owned());
+        retained.push("34".to_owned());
+        retained.push("56".to_owned());
+
+        // Over the count first
+        assert_eq!(retained.push("78".to_owned()), vec!["12"]);
+        assert_eq!(retained.retained_bytes(), 6);
+
+        // Then over the size
+        assert_eq!(retained.push("9abcdef".to_owned()), vec!["34", "56"]);
+        assert_eq!(retained.len(), 2);
+        assert_eq!(retained.retained_bytes(), 9);
+    }
+
+    #[test]
+    fn versions_and_checksums() {
+        let mut retained = retained(5, usize::MAX);
+        for payload in ["a", "b", "b", "a"] {
+            retained.push(payload.to_owned());
+        }
+
+        let versions: Vec<_> = retained
+            .versions()
+            .into_iter()
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(versions, vec!["a", "b", "a"]);
+
+        assert_eq!(retained.find(&checksum::checksum("b")), Some("b"));
+        assert_eq!(retained.find(&checksum::checksum("c")), None);
+    }
+}
@@ -0,0 +1,366 @@
+//! Runtime of the asynchronous tasks of the worker, and the sockets and channels they use.
+//!
+//! async-std is used by default. With the `runtime-tokio` feature, tasks are spawned on a tokio
+//! runtime, the listeners and streams are tokio sockets and the channels are tokio channels, so
+//! the app can share the runtime of a tokio-based host. The rest of the app only goes through
+//! [`task`], [`net`] and [`channel`], which offer the same API with either backend. Files stay
+//! those of async-std, which runs them on its own blocking threads under either executor.
+
+/// Task primitives of the selected executor: `spawn`, `spawn_blocking`, `block_on` and `sleep`
+#[cfg(not(feature = "runtime-tokio"))]
+pub mod task {
+    pub use async_std::task::{block_on, sleep, spawn, spawn_blocking};
+}
+
+/// TCP and Unix domain sockets of the selected executor
+#[cfg(not(feature = "runtime-tokio"))]
+pub mod net {
+    pub use async_std::net::{TcpListener, TcpStream};
+    #[cfg(unix)]
+    pub use async_std::os::unix::net::{UnixListener, UnixStream};
+}
+
+/// Channels of the selected executor: `bounded` and `unbounded`, whose receivers are streams
+#[cfg(not(feature = "runtime-tokio"))]
+pub mod channel {
+    pub use async_std::channel::{bounded, unbounded, Receiver, SendError, Sender, TrySendError};
+}
+
+/// Task primitives of the selected executor: `spawn`, `spawn_blocking`, `block_on` and `sleep`
+#[cfg(feature = "runtime-tokio")]
+pub mod task {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::OnceLock;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use tokio::runtime::{Handle, Runtime, RuntimeFlavor};
+    use tokio::task::JoinHandle;
+
+    /// Runtime shared by every thread of the app, including the file watcher and the text user
+    /// interface which spawn tasks or block on them from outside the runtime
+    pub(super) fn runtime() -> &'static Runtime {
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+        RUNTIME.get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_io()
+                .enable_time()
+                .thread_name("cg-local-worker")
+                .build()
+                .expect("failed to start the tokio runtime")
+        })
+    }
+
+    pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        runtime().spawn(future)
+    }
+
+    /// Result of [`spawn_blocking`], which may be awaited or dropped to detach the task
+    pub struct BlockingHandle<T>(JoinHandle<T>);
+
+    impl<T> Future for BlockingHandle<T> {
+        type Output = T;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+            Pin::new(&mut self.0)
+                .poll(cx)
+                .map(|result| result.expect("blocking task panicked"))
+        }
+    }
+
+    /// Run `f` on the blocking thread pool. Like with async-std, the closure starts right away
+    /// and the handle resolves to its result.
+    pub fn spawn_blocking<F, T>(f: F) -> BlockingHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        BlockingHandle(runtime().spawn_blocking(f))
+    }
+
+    /// Run a future to completion on the current thread. On a thread of a tokio runtime, ours or
+    /// the one of the host, the thread is handed over to the other tasks while it blocks, or the
+    /// future runs on a thread of its own for a single-threaded runtime which can't spare it.
+    pub fn block_on<F: Future + Send>(future: F) -> F::Output
+    where
+        F::Output: Send,
+    {
+        match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+            Err(_) => runtime().block_on(future),
+            Ok(RuntimeFlavor::CurrentThread) => std::thread::scope(|scope| {
+                scope
+                    .spawn(|| runtime().block_on(future))
+                    .join()
+                    .expect("blocked on task panicked")
+            }),
+            Ok(_) => tokio::task::block_in_place(|| runtime().block_on(future)),
+        }
+    }
+
+    pub async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// TCP and Unix domain sockets of the selected executor
+///
+/// The tokio sockets are wrapped to offer the API of the async-std ones, implementing the
+/// `futures` I/O traits expected by the WebSocket stack.
+#[cfg(feature = "runtime-tokio")]
+pub mod net {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::io::{AsyncRead, AsyncWrite};
+    use futures::Stream;
+    use tokio::io::ReadBuf;
+
+    pub struct TcpListener(tokio::net::TcpListener);
+
+    impl TcpListener {
+        pub async fn bind<A: tokio::net::ToSocketAddrs>(addrs: A) -> io::Result<Self> {
+            Ok(Self(tokio::net::TcpListener::bind(addrs).await?))
+        }
+
+        pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+            let (stream, addr) = self.0.accept().await?;
+            Ok((TcpStream(stream), addr))
+        }
+
+        /// Accepted connections, never ending
+        pub fn incoming(&self) -> impl Stream<Item = io::Result<TcpStream>> + Unpin + '_ {
+            futures::stream::poll_fn(move |cx| {
+                self.0
+                    .poll_accept(cx)
+                    .map(|accepted| Some(accepted.map(|(stream, _)| TcpStream(stream))))
+            })
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.local_addr()
+        }
+    }
+
+    /// Listener inherited from the parent process
+    impl From<std::net::TcpListener> for TcpListener {
+        fn from(listener: std::net::TcpListener) -> Self {
+            let _runtime = super::task::runtime().enter();
+            listener
+                .set_nonblocking(true)
+                .and_then(|()| tokio::net::TcpListener::from_std(listener))
+                .map(Self)
+                .expect("failed to register the inherited listener")
+        }
+    }
+
+    pub struct TcpStream(tokio::net::TcpStream);
+
+    impl TcpStream {
+        pub async fn connect<A: tokio::net::ToSocketAddrs>(addrs: A) -> io::Result<Self> {
+            Ok(Self(tokio::net::TcpStream::connect(addrs).await?))
+        }
+
+        pub async fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.peek(buf).await
+        }
+
+        pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.0.peer_addr()
+        }
+    }
+
+    #[cfg(unix)]
+    pub struct UnixListener(tokio::net::UnixListener);
+
+    #[cfg(unix)]
+    impl UnixListener {
+        pub async fn bind<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+            let _runtime = super::task::runtime().enter();
+            Ok(Self(tokio::net::UnixListener::bind(path)?))
+        }
+
+        pub async fn accept(&self) -> io::Result<(UnixStream, tokio::net::unix::SocketAddr)> {
+            let (stream, addr) = self.0.accept().await?;
+            Ok((UnixStream(stream), addr))
+        }
+    }
+
+    #[cfg(unix)]
+    pub struct UnixStream(tokio::net::UnixStream);
+
+    #[cfg(unix)]
+    impl UnixStream {
+        pub async fn connect<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+            Ok(Self(tokio::net::UnixStream::connect(path).await?))
+        }
+    }
+
+    /// Implement the `futures` I/O traits on a wrapper of a tokio stream
+    macro_rules! futures_io {
+        ($stream:ty) => {
+            impl AsyncRead for $stream {
+                fn poll_read(
+                    mut self: Pin<&mut Self>,
+                    cx: &mut Context<'_>,
+                    buf: &mut [u8],
+                ) -> Poll<io::Result<usize>> {
+                    let mut buf = ReadBuf::new(buf);
+                    tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, &mut buf)
+                        .map_ok(|()| buf.filled().len())
+                }
+            }
+
+            impl AsyncWrite for $stream {
+                fn poll_write(
+                    mut self: Pin<&mut Self>,
+                    cx: &mut Context<'_>,
+                    buf: &[u8],
+                ) -> Poll<io::Result<usize>> {
+                    tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+                }
+
+                fn poll_flush(
+                    mut self: Pin<&mut Self>,
+                    cx: &mut Context<'_>,
+                ) -> Poll<io::Result<()>> {
+                    tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+                }
+
+                fn poll_close(
+                    mut self: Pin<&mut Self>,
+                    cx: &mut Context<'_>,
+                ) -> Poll<io::Result<()>> {
+                    tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.0), cx)
+                }
+            }
+        };
+    }
+
+    futures_io!(TcpStream);
+    #[cfg(unix)]
+    futures_io!(UnixStream);
+}
+
+/// Channels of the selected executor: `bounded` and `unbounded`, whose receivers are streams
+///
+/// Like with async-std, both kinds of channels share the sender and receiver types.
+#[cfg(feature = "runtime-tokio")]
+pub mod channel {
+    use std::fmt;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::Stream;
+    use tokio::sync::mpsc;
+
+    pub use tokio::sync::mpsc::error::{SendError, TrySendError};
+
+    pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+        let (tx, rx) = mpsc::channel(cap);
+        (Sender::Bounded(tx), Receiver::Bounded(rx))
+    }
+
+    pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Sender::Unbounded(tx), Receiver::Unbounded(rx))
+    }
+
+    pub enum Sender<T> {
+        Bounded(mpsc::Sender<T>),
+        Unbounded(mpsc::UnboundedSender<T>),
+    }
+
+    impl<T> Sender<T> {
+        pub async fn send(&self, msg: T) -> Result<(), SendError<T>> {
+            match self {
+                Sender::Bounded(tx) => tx.send(msg).await,
+                Sender::Unbounded(tx) => tx.send(msg),
+            }
+        }
+
+        pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+            match self {
+                Sender::Bounded(tx) => tx.try_send(msg),
+                Sender::Unbounded(tx) => tx
+                    .send(msg)
+                    .map_err(|SendError(msg)| TrySendError::Closed(msg)),
+            }
+        }
+
+        pub fn is_closed(&self) -> bool {
+            match self {
+                Sender::Bounded(tx) => tx.is_closed(),
+                Sender::Unbounded(tx) => tx.is_closed(),
+            }
+        }
+    }
+
+    impl<T> fmt::Debug for Sender<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Sender")
+                .field("closed", &self.is_closed())
+                .finish()
+        }
+    }
+
+    impl<T> Clone for Sender<T> {
+        fn clone(&self) -> Self {
+            match self {
+                Sender::Bounded(tx) => Sender::Bounded(tx.clone()),
+                Sender::Unbounded(tx) => Sender::Unbounded(tx.clone()),
+            }
+        }
+    }
+
+    pub enum Receiver<T> {
+        Bounded(mpsc::Receiver<T>),
+        Unbounded(mpsc::UnboundedReceiver<T>),
+    }
+
+    impl<T> Stream for Receiver<T> {
+        type Item = T;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+            match self.get_mut() {
+                Receiver::Bounded(rx) => rx.poll_recv(cx),
+                Receiver::Unbounded(rx) => rx.poll_recv(cx),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "runtime-tokio"))]
+mod tests {
+    use super::task;
+
+    /// The host blocks on the app from its own tasks, whichever its runtime
+    #[test]
+    fn block_on_in_a_tokio_runtime() {
+        let multi_thread = tokio::runtime::Builder::new_multi_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let current_thread = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        for host in [multi_thread, current_thread] {
+            let answer = host.block_on(async {
+                task::block_on(async {
+                    task::sleep(std::time::Duration::from_millis(10)).await;
+                    task::spawn(async { 42 }).await.unwrap()
+                })
+            });
+            assert_eq!(answer, 42);
+        }
+    }
+}
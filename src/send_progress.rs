@@ -0,0 +1,206 @@
+//! Feedback on uploads that take long to reach the extension.
+//!
+//! A throttled browser tab can take seconds to receive a large payload, with nothing happening on
+//! screen meanwhile. The send to the IDE is timed: past [`SLOW_SEND`] it is reported as in
+//! progress, past [`SEND_STALL`] as stalled, and its completion clears the report. Only the timing
+//! is observed, the message itself is sent unchanged.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Time after which a send is reported as in progress
+pub const SLOW_SEND: Duration = Duration::from_secs(1);
+
+/// Time after which a send in progress is reported as stalled
+pub const SEND_STALL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendProgress {
+    /// The send has been taking longer than [`SLOW_SEND`]
+    Slow { bytes: usize },
+    /// The send has been taking longer than [`SEND_STALL`]
+    Stalled { bytes: usize },
+    /// A send reported as slow completed, successfully or not
+    Done { bytes: usize, elapsed: Duration },
+}
+
+/// Size of a payload in kilobytes, rounded up so a small payload doesn't show as 0 KB
+pub fn kilobytes(bytes: usize) -> usize {
+    bytes.div_ceil(1024)
+}
+
+/// Run `send`, a send of `bytes` to the extension, calling `report` when it gets slow, when it
+/// stalls and when a send reported as slow completes
+pub async fn timed<F: Future>(
+    send: F,
+    bytes: usize,
+    report: impl FnMut(SendProgress),
+) -> F::Output {
+    timed_after(send, bytes, SLOW_SEND, SEND_STALL, report).await
+}
+
+/// Same as [`timed`], the send being slow after `slow` and stalled after `stall`
+async fn timed_after<F: Future>(
+    send: F,
+    bytes: usize,
+    slow: Duration,
+    stall: Duration,
+    mut report: impl FnMut(SendProgress),
+) -> F::Output {
+    futures::pin_mut!(send);
+
+    let started = Instant::now();
+    let stages = [
+        (slow, SendProgress::Slow { bytes }),
+        (stall - slow, SendProgress::Stalled { bytes }),
+    ];
+
+    let mut reported = false;
+    for (delay, progress) in stages.iter().copied() {
+        match async_std::future::timeout(delay, send.as_mut()).await {
+            Ok(output) => {
+                if reported {
+                    report(SendProgress::Done {
+                        bytes,
+                        elapsed: started.elapsed(),
+                    });
+                }
+
+                return output;
+            }
+            Err(_) => {
+                report(progress);
+                reported = true;
+            }
+        }
+    }
+
+    let output = send.await;
+    report(SendProgress::Done {
+        bytes,
+        elapsed: started.elapsed(),
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::{Sink, SinkExt};
+
+    use super::*;
+    use crate::runtime::task;
+
+    const SLOW: Duration = Duration::from_millis(50);
+    const STALL: Duration = Duration::from_millis(150);
+
+    /// Sink standing for a throttled extension, accepting messages once its delay elapsed since
+    /// the first poll, i.e. after the send started being timed
+    struct SlowSink {
+        delay: Duration,
+        ready_at: Option<Instant>,
+        sent: Vec<String>,
+    }
+
+    impl SlowSink {
+        fn new(delay: Duration) -> Self {
+            Self {
+                delay,
+                ready_at: None,
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl Sink<String> for SlowSink {
+        type Error = ();
+
+        fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            let delay = self.delay;
+            let ready_at = *self.ready_at.get_or_insert_with(|| Instant::now() + delay);
+            let remaining = ready_at.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Poll::Ready(Ok(()));
+            }
+
+            let waker = cx.waker().clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(remaining);
+                waker.wake();
+            });
+            Poll::Pending
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: String) -> Result<(), ()> {
+            self.sent.push(item);
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            self.poll_ready(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            self.poll_ready(cx)
+        }
+    }
+
+    /// Reports of a send to a sink taking `delay`
+    fn reports(delay: Duration) -> Vec<SendProgress> {
+        let mut sink = SlowSink::new(delay);
+        let mut reports = Vec::new();
+
+        let sent = task::block_on(timed_after(
+            sink.send("print(1)".to_owned()),
+            2048,
+            SLOW,
+            STALL,
+            |progress| reports.push(progress),
+        ));
+        assert_eq!(sent, Ok(()));
+        assert_eq!(sink.sent, ["print(1)"]);
+
+        reports
+    }
+
+    #[test]
+    fn fast_sends_are_not_reported() {
+        assert_eq!(reports(Duration::ZERO), []);
+    }
+
+    #[test]
+    fn slow_sends_are_reported_until_done() {
+        match reports(Duration::from_millis(100))[..] {
+            [SendProgress::Slow { bytes: 2048 }, SendProgress::Done {
+                bytes: 2048,
+                elapsed,
+            }] => {
+                assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed)
+            }
+            ref reports => panic!("{:?}", reports),
+        }
+    }
+
+    #[test]
+    fn stalled_sends_are_reported_until_done() {
+        match reports(Duration::from_millis(250))[..] {
+            [SendProgress::Slow { bytes: 2048 }, SendProgress::Stalled { bytes: 2048 }, SendProgress::Done {
+                bytes: 2048,
+                elapsed,
+            }] => {
+                assert!(elapsed >= Duration::from_millis(250), "{:?}", elapsed)
+            }
+            ref reports => panic!("{:?}", reports),
+        }
+    }
+
+    #[test]
+    fn kilobytes_are_rounded_up() {
+        assert_eq!(
+            [0, 1, 1024, 1025, 512 * 1024].map(kilobytes),
+            [0, 1, 1, 2, 512]
+        );
+    }
+}
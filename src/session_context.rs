@@ -0,0 +1,64 @@
+//! What is being synchronized, as one consistent snapshot.
+//!
+//! The question comes from the extension while the target, its language and the session change
+//! from the interface, so reading them separately while a target is being switched can pair the
+//! new target with the previous question. The controller replaces the whole [`SessionContext`] on
+//! every change instead, and the outputs describing an event (hooks, webhooks) are built from the
+//! snapshot taken when the event is handled.
+
+use std::sync::Arc;
+
+use async_std::path::PathBuf;
+use chrono::{DateTime, Local};
+
+use crate::language::Language;
+use crate::DynamicOpts;
+
+#[derive(Debug, Clone)]
+pub struct SessionContext {
+    /// Question id and title, once identified by the extension
+    pub question: Option<(i32, String)>,
+    pub target: Option<PathBuf>,
+    /// Language of the target, from its extension
+    pub language: Option<Language>,
+    /// Session of the configuration file
+    pub session: Option<String>,
+    /// When the question was identified, or when the app started before that
+    pub started: DateTime<Local>,
+}
+
+impl SessionContext {
+    /// Context of a session starting on the options, before any question
+    pub fn new(dynamic: &DynamicOpts) -> Arc<Self> {
+        Arc::new(Self {
+            question: None,
+            target: None,
+            language: None,
+            session: None,
+            started: Local::now(),
+        })
+        .with_target(dynamic)
+    }
+
+    /// The same context on another question, starting now
+    pub fn with_question(&self, question: Option<(i32, String)>) -> Arc<Self> {
+        Arc::new(Self {
+            question,
+            started: Local::now(),
+            ..self.clone()
+        })
+    }
+
+    /// The same context on the target and session of the options
+    pub fn with_target(&self, dynamic: &DynamicOpts) -> Arc<Self> {
+        Arc::new(Self {
+            language: dynamic
+                .target
+                .as_ref()
+                .and_then(|target| Language::from_path(target.as_ref())),
+            target: dynamic.target.clone(),
+            session: dynamic.session.clone(),
+            ..self.clone()
+        })
+    }
+}
@@ -0,0 +1,41 @@
+//! Named sessions of the configuration file, to switch between puzzles during a contest.
+//!
+//! Each `[session.<name>]` table gives a target, optionally the question it is for and the
+//! auto-play setting:
+//!
+//! ```toml
+//! [session.fall-challenge]
+//! target = "contest/fall.rs"
+//! question_id = 9999
+//! play = true
+//! ```
+//!
+//! The app starts in a session with `--session`, and switches to another one from the Sessions
+//! menu of the interface (`s`). Switching stops synchronizing, moves the watcher to the target of
+//! the session and applies its auto-play setting. If the extension is already on the question of
+//! the session, it is offered again as if it was just identified; otherwise the question is
+//! forgotten as with Detach, until the extension identifies one. A single session is active at a
+//! time.
+//!
+//! When the extension moves to another question in the same tab, the app switches to the session
+//! of that question by itself, if there is one.
+
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionConfig {
+    pub target: PathBuf,
+    /// Question of the session, other questions identified by the extension being reported
+    pub question_id: Option<i32>,
+    /// Auto-play, left as it is if not given
+    pub play: Option<bool>,
+}
+
+impl SessionConfig {
+    /// Whether the question is the one of the session, any question matching a session without
+    /// one
+    pub fn matches(&self, question_id: i32) -> bool {
+        self.question_id.is_none_or(|id| id == question_id)
+    }
+}
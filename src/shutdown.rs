@@ -0,0 +1,148 @@
+//! Bounded shutdown of the worker.
+//!
+//! Every exit path (quitting the text user interface, Ctrl-C, a terminating controller) goes
+//! through [`shutdown`]: `Terminate` is sent to each stage with a timeout, and the worker thread
+//! is given at most `--shutdown-timeout` seconds to finish. A stage that doesn't stop in time is
+//! reported using the controller heartbeat, and the process exits anyway.
+
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::channel_stats::Sender;
+use crate::runtime::task;
+use crate::watchdog::Heartbeat;
+use crate::{ErrorKind, Result, WorkerMessage};
+
+/// Interval at which the worker thread is polled while waiting for it to finish
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Send a terminating message to a stage, giving up after `timeout` if its channel stays full.
+/// A stage whose receiver is already dropped is not an error, it just stopped earlier.
+pub async fn terminate<T>(stage: &'static str, tx: &Sender<T>, msg: T, timeout: Duration) {
+    match async_std::future::timeout(timeout, tx.send(msg)).await {
+        Ok(Ok(())) => trace!("shutdown: terminated {}", stage),
+        Ok(Err(_)) => debug!("shutdown: {} already stopped", stage),
+        Err(_) => warn!(
+            "shutdown: {} did not accept Terminate within {:.1}s, leaving it behind",
+            stage,
+            timeout.as_secs_f32()
+        ),
+    }
+}
+
+/// Stop the worker and wait for its thread, for at most `timeout`
+pub fn shutdown(
+    tx_worker: &Sender<WorkerMessage>,
+    join_handle: JoinHandle<Result<()>>,
+    heartbeat: &Heartbeat,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    task::block_on(terminate(
+        "controller",
+        tx_worker,
+        WorkerMessage::Terminate,
+        timeout,
+    ));
+
+    while !join_handle.is_finished() {
+        if Instant::now() >= deadline {
+            let stage = heartbeat.last_message();
+            error!(
+                "worker did not stop within {:.1}s, last step: {}",
+                timeout.as_secs_f32(),
+                stage
+            );
+
+            return Err(ErrorKind::ShutdownTimeout(timeout.as_secs(), stage.to_owned()).into());
+        }
+
+        std::thread::sleep(JOIN_POLL_INTERVAL);
+    }
+
+    join_handle.join().map_err(|_| ErrorKind::WorkerPanicked)?
+}
+
+/// Call `on_signal` from a dedicated thread when SIGINT or SIGTERM is received. A second signal
+/// exits immediately, in case the shutdown it triggered is stuck.
+#[cfg(unix)]
+pub fn on_signal(on_signal: impl Fn() + Send + 'static) -> std::io::Result<()> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+
+    std::thread::spawn(move || {
+        let mut received = false;
+
+        for signal in signals.forever() {
+            if received {
+                warn!("received signal {} again, exiting now", signal);
+                std::process::exit(128 + signal);
+            }
+
+            info!("received signal {}, shutting down", signal);
+            received = true;
+            on_signal();
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_stats;
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn stuck_stages_are_left_behind() {
+        // Full, and never drained
+        let (tx, rx) = channel_stats::bounded("stuck", 1);
+        tx.try_send(0).unwrap();
+
+        let started = Instant::now();
+        task::block_on(terminate("stuck", &tx, 1, TIMEOUT));
+
+        assert!(started.elapsed() >= TIMEOUT);
+        assert!(started.elapsed() < TIMEOUT * 5);
+        assert_eq!(rx.stats().snapshot().occupancy, 1);
+    }
+
+    #[test]
+    fn stopped_stages_are_skipped() {
+        let (tx, rx) = channel_stats::bounded("stopped", 1);
+        drop(rx);
+
+        let started = Instant::now();
+        task::block_on(terminate("stopped", &tx, 0, TIMEOUT));
+        assert!(started.elapsed() < TIMEOUT);
+    }
+
+    #[test]
+    fn stuck_workers_are_abandoned_within_the_timeout() {
+        let (tx_worker, rx_worker) = channel_stats::bounded("worker", 1);
+        tx_worker.try_send(WorkerMessage::Terminate).unwrap();
+        let (tx_release, rx_release) = std::sync::mpsc::channel::<()>();
+        let worker = std::thread::spawn(move || {
+            let _ = rx_release.recv();
+            Ok(())
+        });
+
+        let heartbeat = Heartbeat::new();
+        heartbeat.beat("Stuck");
+
+        let started = Instant::now();
+        let err = shutdown(&tx_worker, worker, &heartbeat, TIMEOUT).unwrap_err();
+        assert!(started.elapsed() < TIMEOUT * 5);
+        match err.kind() {
+            ErrorKind::ShutdownTimeout(_, stage) => assert_eq!(stage, "Stuck"),
+            kind => panic!("unexpected error: {}", kind),
+        }
+
+        drop((tx_release, rx_worker));
+    }
+}
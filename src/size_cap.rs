@@ -0,0 +1,36 @@
+//! Size cap on the files read for synchronization.
+//!
+//! A target pointed at a large generated file would be read into memory on every save. The size
+//! is checked from the file metadata first, and files over `--max-file-size` are not read at all.
+
+use async_std::path::Path;
+
+/// Contents of a file, unless it is over the size cap
+#[derive(Debug)]
+pub enum CappedRead {
+    Contents(String),
+    /// The file is over the cap and was not read
+    TooLarge {
+        size: u64,
+    },
+}
+
+/// Size of the file if it is over the cap. A cap of 0 disables the check.
+pub async fn oversized(path: &Path, max_size: u64) -> std::io::Result<Option<u64>> {
+    if max_size == 0 {
+        return Ok(None);
+    }
+
+    let size = async_std::fs::metadata(path).await?.len();
+    Ok(Some(size).filter(|size| *size > max_size))
+}
+
+/// Read the file as a string, unless it is over the cap
+pub async fn read(path: &Path, max_size: u64) -> std::io::Result<CappedRead> {
+    match oversized(path, max_size).await? {
+        Some(size) => Ok(CappedRead::TooLarge { size }),
+        None => Ok(CappedRead::Contents(
+            async_std::fs::read_to_string(path).await?,
+        )),
+    }
+}
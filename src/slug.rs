@@ -0,0 +1,107 @@
+//! File names derived from question titles.
+//!
+//! Every file name built from a title goes through [`slug`], so the same title always maps to
+//! the same name: non-ASCII text is transliterated (`Énigme` becomes `enigme`), anything but
+//! letters and digits becomes a single `-`, and the result is truncated to a maximum length.
+//! Names reserved on Windows are avoided on every platform so the files can be shared.
+//!
+//! Different titles can still map to the same slug, typically once truncated. [`unique`] then
+//! adds a `-2`, `-3`… suffix, checking candidates in order so the choice is deterministic.
+
+/// Slug of titles without any letter or digit
+const EMPTY_SLUG: &str = "untitled";
+
+/// Device names Windows refuses as file names, whatever the extension
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// File name stem for the title: lowercase ASCII letters, digits and dashes, at most `max_len`
+/// bytes long
+pub fn slug(title: &str, max_len: usize) -> String {
+    let mut slug = String::new();
+
+    for c in deunicode::deunicode(title).chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    // Only ASCII is left, so any length is a char boundary
+    slug.truncate(max_len.max(1));
+    let mut slug = slug.trim_end_matches('-').to_owned();
+
+    if slug.is_empty() {
+        slug = EMPTY_SLUG.to_owned();
+    }
+
+    if RESERVED_NAMES.contains(&slug.as_str()) {
+        slug.insert(0, '_');
+    }
+
+    slug
+}
+
+/// First of `slug`, `slug-2`, `slug-3`… for which `taken` is false
+pub fn unique(slug: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(slug) {
+        return slug.to_owned();
+    }
+
+    (2..)
+        .map(|n| format!("{}-{}", slug, n))
+        .find(|candidate| !taken(candidate))
+        .expect("infinite candidates")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs() {
+        let table = [
+            ("Onboarding", 64, "onboarding"),
+            ("The Descent", 64, "the-descent"),
+            (
+                "  Power of Thor - Episode 1  ",
+                64,
+                "power-of-thor-episode-1",
+            ),
+            ("Énigme à résoudre", 64, "enigme-a-resoudre"),
+            // Emoji
+            ("Code 🚀 Golf", 64, "code-rocket-golf"),
+            ("🎉", 64, "tada"),
+            // Right-to-left
+            ("سلام", 64, "slm"),
+            ("שלום world", 64, "shlvm-world"),
+            // Truncation, without a trailing dash
+            ("The Descent", 3, "the"),
+            ("The Descent", 4, "the"),
+            ("The Descent", 5, "the-d"),
+            ("Onboarding", 0, "o"),
+            // Nothing left
+            ("", 64, "untitled"),
+            ("?!", 64, "untitled"),
+            // Reserved on Windows
+            ("CON", 64, "_con"),
+            ("Com1", 64, "_com1"),
+            ("Console", 3, "_con"),
+        ];
+
+        for (title, max_len, expected) in table {
+            assert_eq!(slug(title, max_len), expected, "{:?} at {}", title, max_len);
+        }
+    }
+
+    #[test]
+    fn unique_slugs() {
+        let taken = ["puzzle", "puzzle-2"];
+
+        assert_eq!(unique("other", |slug| taken.contains(&slug)), "other");
+        assert_eq!(unique("puzzle", |slug| taken.contains(&slug)), "puzzle-3");
+    }
+}
@@ -0,0 +1,155 @@
+//! Lifetime activity counters per question, persisted in the platform data directory.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Minimum delay between two writes of the statistics file during a session
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Activity counters for one question
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct QuestionStats {
+    pub title: String,
+    pub uploads: u64,
+    pub downloads: u64,
+    pub plays: u64,
+    /// Total number of bytes uploaded and downloaded, counted in characters by earlier versions
+    #[serde(alias = "chars")]
+    pub bytes: u64,
+}
+
+impl QuestionStats {
+    /// Number of actions counted, used to sort questions by activity
+    pub fn activity(&self) -> u64 {
+        self.uploads + self.downloads + self.plays
+    }
+
+    fn add(&mut self, other: &QuestionStats) {
+        self.title = other.title.clone();
+        self.uploads += other.uploads;
+        self.downloads += other.downloads;
+        self.plays += other.plays;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Statistics of all questions, indexed by question id
+pub type Stats = BTreeMap<i32, QuestionStats>;
+
+/// Activity of the current session not yet written to the statistics file
+pub struct SessionSummary {
+    questions: Stats,
+    last_flush: Instant,
+}
+
+impl SessionSummary {
+    pub fn new() -> Self {
+        Self {
+            questions: Stats::new(),
+            last_flush: Instant::now(),
+        }
+    }
+
+    fn question(&mut self, question_id: i32, title: &str) -> &mut QuestionStats {
+        let stats = self.questions.entry(question_id).or_default();
+        stats.title = title.to_owned();
+        stats
+    }
+
+    pub fn record_upload(&mut self, question_id: i32, title: &str, bytes: usize, play: bool) {
+        let stats = self.question(question_id, title);
+        stats.uploads += 1;
+        stats.bytes += bytes as u64;
+
+        if play {
+            stats.plays += 1;
+        }
+    }
+
+    pub fn record_download(&mut self, question_id: i32, title: &str, bytes: usize) {
+        let stats = self.question(question_id, title);
+        stats.downloads += 1;
+        stats.bytes += bytes as u64;
+    }
+
+    /// Write the pending counters if the last write is old enough
+    pub fn maybe_flush(&mut self) {
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush();
+        }
+    }
+
+    /// Add the pending counters to the statistics file
+    pub fn flush(&mut self) {
+        self.last_flush = Instant::now();
+
+        if self.questions.is_empty() {
+            return;
+        }
+
+        let path = match stats_path() {
+            Some(path) => path,
+            None => {
+                warn!("no data directory available, not saving statistics");
+                return;
+            }
+        };
+
+        let mut stats = load();
+        for (question_id, session) in &self.questions {
+            stats.entry(*question_id).or_default().add(session);
+        }
+
+        match save(&path, &stats) {
+            Ok(()) => {
+                debug!("saved statistics to {}", path.display());
+                self.questions.clear();
+            }
+            Err(err) => warn!("failed to save statistics to {}: {}", path.display(), err),
+        }
+    }
+}
+
+impl Default for SessionSummary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Location of the statistics file
+pub fn stats_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cg-local-app").join("stats.json"))
+}
+
+/// Load the statistics file. A missing or corrupt file yields empty statistics, so the file is
+/// recreated on the next save.
+pub fn load() -> Stats {
+    let path = match stats_path() {
+        Some(path) => path,
+        None => return Stats::new(),
+    };
+
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!(
+                "ignoring corrupt statistics file {}: {}",
+                path.display(),
+                err
+            );
+            Stats::new()
+        }),
+        Err(_) => Stats::new(),
+    }
+}
+
+fn save(path: &std::path::Path, stats: &Stats) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // Write to a temporary file first so an interrupted save can't corrupt the statistics
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(stats)?)?;
+    std::fs::rename(&tmp, path)
+}
@@ -0,0 +1,246 @@
+//! Synchronization state written to a file for shell prompts and status bars, with
+//! `--status-file`.
+//!
+//! The file holds a single line of JSON, replaced atomically on every change of state:
+//!
+//! ```json
+//! {"state":"syncing","questionId":42,"session":null,"lastUpload":"2026-01-01T12:00:00+01:00","error":null}
+//! ```
+//!
+//! `session` is the active session of the configuration file, if any. `state` is one of `waiting` (no extension connected), `connected`, `syncing`, `paused`
+//! (synchronization stopped from the interface) and `error` (the last operation failed, until
+//! synchronization starts again or the next upload). Changes are written at most every
+//! [`MIN_INTERVAL`], the last one always making it to the file, and the file is removed when the
+//! app exits cleanly. For instance, in a bash prompt:
+//!
+//! ```sh
+//! cg_status() {
+//!     [ -f /tmp/cg-local.json ] && sed -n 's/.*"state":"\([a-z]*\)".*/[cg:\1] /p' /tmp/cg-local.json
+//! }
+//! PS1='$(cg_status)'"$PS1"
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+
+use crate::runtime::channel::{self, Receiver, Sender};
+use crate::runtime::task;
+
+/// Minimum time between two writes of the file
+pub const MIN_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum State {
+    Waiting,
+    Connected,
+    Syncing,
+    Paused,
+    Error,
+}
+
+#[derive(Debug, Default)]
+struct Status {
+    /// Open extension connections, more than one with --max-connections
+    connections: usize,
+    syncing: bool,
+    paused: bool,
+    question_id: Option<i32>,
+    session: Option<String>,
+    last_upload: Option<String>,
+    error: Option<String>,
+    /// Set once the file is removed, nothing is written anymore
+    removed: bool,
+}
+
+/// Contents of the file
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Snapshot<'a> {
+    state: State,
+    question_id: Option<i32>,
+    session: Option<&'a str>,
+    last_upload: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+impl Status {
+    fn snapshot(&self) -> Snapshot<'_> {
+        let state = if self.error.is_some() {
+            State::Error
+        } else if self.connections == 0 {
+            State::Waiting
+        } else if self.syncing {
+            State::Syncing
+        } else if self.paused {
+            State::Paused
+        } else {
+            State::Connected
+        };
+
+        Snapshot {
+            state,
+            question_id: self.question_id,
+            session: self.session.as_deref(),
+            last_upload: self.last_upload.as_deref(),
+            error: self.error.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    path: PathBuf,
+    status: Mutex<Status>,
+    tx_changed: Sender<()>,
+}
+
+/// Handle updating the status file, shared by the controller and the connections. Updates do
+/// nothing without --status-file.
+#[derive(Debug, Clone, Default)]
+pub struct StatusFile {
+    shared: Option<Arc<Shared>>,
+}
+
+impl StatusFile {
+    /// Maintain the status file at the given path, starting in the waiting state
+    pub fn spawn(path: &Path) -> Self {
+        // A single pending change is enough, the writer reads the latest status
+        let (tx_changed, rx_changed) = channel::bounded(1);
+        let shared = Arc::new(Shared {
+            path: path.to_owned(),
+            status: Mutex::new(Status::default()),
+            tx_changed,
+        });
+
+        task::spawn(run_writer(shared.clone(), rx_changed));
+
+        let status = Self {
+            shared: Some(shared),
+        };
+        status.update(|_| {});
+        status
+    }
+
+    pub fn connected(&self) {
+        self.update(|status| status.connections += 1);
+    }
+
+    pub fn disconnected(&self) {
+        self.update(|status| status.connections = status.connections.saturating_sub(1));
+    }
+
+    pub fn question(&self, question_id: Option<i32>) {
+        self.update(|status| status.question_id = question_id);
+    }
+
+    pub fn session(&self, name: Option<&str>) {
+        self.update(|status| status.session = name.map(str::to_owned));
+    }
+
+    /// Synchronization started or resumed
+    pub fn started(&self) {
+        self.update(|status| {
+            status.syncing = true;
+            status.paused = false;
+            status.error = None;
+        });
+    }
+
+    /// Synchronization stopped from the interface
+    pub fn stopped(&self) {
+        self.update(|status| {
+            status.syncing = false;
+            status.paused = true;
+        });
+    }
+
+    /// The session was detached, forgetting the question
+    pub fn detached(&self) {
+        self.update(|status| {
+            status.syncing = false;
+            status.paused = false;
+            status.question_id = None;
+            status.error = None;
+        });
+    }
+
+    pub fn uploaded(&self) {
+        self.update(|status| {
+            status.last_upload =
+                Some(chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false));
+            status.error = None;
+        });
+    }
+
+    pub fn error(&self, message: &str) {
+        self.update(|status| status.error = Some(message.to_owned()));
+    }
+
+    /// Remove the file on a clean exit
+    pub fn remove(&self) {
+        if let Some(shared) = &self.shared {
+            let mut status = shared.status.lock().unwrap();
+            status.removed = true;
+
+            if let Err(err) = std::fs::remove_file(&shared.path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    warn!(
+                        "failed to remove status file {}: {}",
+                        shared.path.display(),
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Status)) {
+        if let Some(shared) = &self.shared {
+            change(&mut shared.status.lock().unwrap());
+
+            // Already pending otherwise
+            let _ = shared.tx_changed.try_send(());
+        }
+    }
+}
+
+async fn run_writer(shared: Arc<Shared>, mut rx_changed: Receiver<()>) {
+    while rx_changed.next().await.is_some() {
+        {
+            let status = shared.status.lock().unwrap();
+            if status.removed {
+                break;
+            }
+
+            // Written while holding the status so a removal can't happen in between
+            let line = serde_json::to_string(&status.snapshot()).unwrap() + "\n";
+            if let Err(err) = write_atomically(&shared.path, &line) {
+                warn!(
+                    "failed to write status file {}: {}",
+                    shared.path.display(),
+                    err
+                );
+            }
+        }
+
+        task::sleep(MIN_INTERVAL).await;
+    }
+}
+
+/// Temporary file used by the writes, excluded from the watcher along with the file
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tmp.into()
+}
+
+/// Blocking version of `crate::write_atomically`, run while holding the status
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let tmp = temporary_path(path);
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
@@ -0,0 +1,184 @@
+//! Advisory lock preventing two instances from synchronizing the same target.
+//!
+//! The lock is a `.<name>.cg-local.lock` file next to the target, containing the pid and bind
+//! address of the owning instance. A lock left behind by a process that is no longer running is
+//! considered stale and taken over.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{ErrorKind, Result};
+
+/// Lock file owned by this instance, removed on drop
+pub struct TargetLock {
+    path: PathBuf,
+}
+
+impl TargetLock {
+    /// Acquire the lock for the given target. If another live instance holds it, fail unless
+    /// `allow_shared` is set, in which case `None` is returned and the other lock is left alone.
+    pub fn acquire(target: &Path, bind: &str, allow_shared: bool) -> Result<Option<Self>> {
+        let path = lock_path(target);
+
+        // A stale lock is removed and acquisition retried once
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    write!(file, "{}\n{}\n", std::process::id(), bind)?;
+                    debug!("acquired target lock {}", path.display());
+                    return Ok(Some(Self { path }));
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            let contents = std::fs::read_to_string(&path).unwrap_or_default();
+            let mut lines = contents.lines();
+            let pid = lines.next().and_then(|pid| pid.trim().parse::<u32>().ok());
+            let other_bind = lines.next().unwrap_or("unknown address").to_owned();
+
+            match pid {
+                Some(pid) if process_alive(pid) => {
+                    if allow_shared {
+                        warn!(
+                            "target is also synchronized by process {} listening on {}",
+                            pid, other_bind
+                        );
+                        return Ok(None);
+                    }
+
+                    return Err(ErrorKind::TargetLocked(
+                        target.display().to_string(),
+                        pid,
+                        other_bind,
+                    )
+                    .into());
+                }
+                _ => {
+                    info!("removing stale target lock {}", path.display());
+                    std::fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        Err(ErrorKind::TargetLockFailed(path.display().to_string()).into())
+    }
+}
+
+impl Drop for TargetLock {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(
+                "failed to remove target lock {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+fn lock_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    target.with_file_name(format!(".{}.cg-local.lock", name))
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return false;
+    }
+
+    // Signal 0 only checks whether the process exists
+    let res = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_alive(pid: u32) -> bool {
+    // Without a portable liveness check, assume the owner is still running
+    pid != std::process::id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    const BIND: &str = "127.0.0.1:53135";
+
+    /// Target in a temporary directory, with the lock file left by another process
+    fn locked_target(dir: &tempfile::TempDir, contents: &str) -> PathBuf {
+        let target = dir.path().join("main.py");
+        std::fs::write(lock_path(&target), contents).unwrap();
+        target
+    }
+
+    /// Pid of a process that exited
+    fn exited_pid() -> u32 {
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .arg("--help")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    #[test]
+    fn acquired_and_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("main.py");
+
+        let lock = TargetLock::acquire(&target, BIND, false).unwrap().unwrap();
+        let contents = std::fs::read_to_string(lock_path(&target)).unwrap();
+        assert_eq!(contents, format!("{}\n{}\n", std::process::id(), BIND));
+
+        drop(lock);
+        assert!(!lock_path(&target).exists());
+    }
+
+    #[test]
+    fn stale_lock_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for contents in &[
+            format!("{}\n127.0.0.1:1\n", exited_pid()),
+            "not a pid\n".to_owned(),
+            String::new(),
+        ] {
+            let target = locked_target(&dir, contents);
+
+            let lock = TargetLock::acquire(&target, BIND, false).unwrap();
+            assert!(lock.is_some(), "{:?} not taken over", contents);
+            let contents = std::fs::read_to_string(lock_path(&target)).unwrap();
+            assert!(contents.starts_with(&format!("{}\n", std::process::id())));
+        }
+    }
+
+    // The init process is always running
+    #[cfg(unix)]
+    #[test]
+    fn concurrent_start_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = locked_target(&dir, "1\n127.0.0.1:1\n");
+
+        match TargetLock::acquire(&target, BIND, false) {
+            Err(Error(ErrorKind::TargetLocked(_, pid, bind), _)) => {
+                assert_eq!((pid, bind.as_str()), (1, "127.0.0.1:1"))
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("lock acquired"),
+        }
+
+        // Shared, the other lock is left alone
+        assert!(TargetLock::acquire(&target, BIND, true).unwrap().is_none());
+        let contents = std::fs::read_to_string(lock_path(&target)).unwrap();
+        assert_eq!(contents, "1\n127.0.0.1:1\n");
+    }
+}
@@ -0,0 +1,65 @@
+//! Test input block appended to the end of uploaded code.
+//!
+//! The contents of the test input file are commented out line by line, between marker lines so
+//! the judge can ignore the block and it can be removed again on download:
+//!
+//! ```text
+//! # --- TESTINPUT ---
+//! # 3
+//! # 1 2 3
+//! # --- END TESTINPUT ---
+//! ```
+
+use std::borrow::Cow;
+
+use crate::language::{language_comment, Language};
+
+const BEGIN_MARKER: &str = "--- TESTINPUT ---";
+const END_MARKER: &str = "--- END TESTINPUT ---";
+
+/// Append the test input block to the code, replacing the block already present if any
+pub fn append(code: &str, lang: Language, input: &str) -> String {
+    let comment = language_comment(lang);
+
+    let mut result = strip(code).into_owned();
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+
+    result.push_str(&format!("{} {}\n", comment, BEGIN_MARKER));
+    for line in input.lines() {
+        if line.is_empty() {
+            result.push_str(&format!("{}\n", comment));
+        } else {
+            result.push_str(&format!("{} {}\n", comment, line));
+        }
+    }
+    result.push_str(&format!("{} {}\n", comment, END_MARKER));
+
+    result
+}
+
+/// Remove the test input block at the end of the code, if any
+pub fn strip(code: &str) -> Cow<'_, str> {
+    let mut begin = None;
+    let mut offset = 0;
+
+    for line in code.split_inclusive('\n') {
+        let marker = line.trim_end();
+
+        if marker.ends_with(BEGIN_MARKER) {
+            begin = Some(offset);
+        } else if marker.ends_with(END_MARKER) {
+            // Only a block closing the code is ours, anything after it is code
+            if let Some(begin) = begin {
+                if code[offset + line.len()..].trim().is_empty() {
+                    return Cow::Borrowed(&code[..begin]);
+                }
+            }
+        }
+
+        offset += line.len();
+    }
+
+    Cow::Borrowed(code)
+}
@@ -0,0 +1,1004 @@
+//! Tests of the worker, driven like the interface drives it and connected to like the extension.
+//!
+//! Saves are sent to the controller as the watcher would, the watcher ignoring the target so its
+//! events don't race with the ones of the test.
+
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use async_tungstenite::tungstenite::{self, Message, WebSocket};
+use structopt::StructOpt;
+
+use crate::runtime::task;
+use crate::{
+    channel_stats, checksum, messages, spawn_worker, Arc, Config, DynamicOpts, HistoryEntry, Mutex,
+    Opts, Result, ServerMessage, SessionConfig, WorkerMessage, WorkerNotification,
+    UNKNOWN_QUESTION_ID,
+};
+
+/// Longest wait for a notification or a message
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Code of the target when the worker starts
+const INITIAL_CODE: &str = "print(1)\n";
+
+/// Worker synchronizing a target in a temporary directory
+struct Session {
+    target: PathBuf,
+    thread: Option<std::thread::JoinHandle<Result<()>>>,
+    tx_worker: channel_stats::Sender<WorkerMessage>,
+    rx_notification: Option<Receiver<WorkerNotification>>,
+    addr: SocketAddr,
+    _dir: tempfile::TempDir,
+}
+
+impl Session {
+    /// Start a worker with the options added to the defaults of the tests, the test standing for
+    /// the interface
+    fn start(args: &[&str]) -> Self {
+        Self::start_with(args, Config::default())
+    }
+
+    /// Same as [`Session::start`], with a configuration file
+    fn start_with(args: &[&str], config: Config) -> Self {
+        Self::spawn(args, config, true)
+    }
+
+    /// Same as [`Session::start`], the watcher also reporting the changes of the target
+    fn watching(args: &[&str]) -> Self {
+        Self::spawn(args, Config::default(), false)
+    }
+
+    fn spawn(args: &[&str], config: Config, ignore_target: bool) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("main.py");
+        std::fs::write(&target, INITIAL_CODE).unwrap();
+
+        let mut argv = vec![
+            "cg-local-app",
+            "--no-stats",
+            "--no-origin-check",
+            "--bind",
+            "127.0.0.1:0",
+            "--target",
+            target.to_str().unwrap(),
+        ];
+        if ignore_target {
+            argv.extend_from_slice(&["--ignore", "main.py"]);
+        }
+        argv.extend_from_slice(args);
+
+        let opts = Opts::from_iter(argv);
+        let dynamic = Arc::new(Mutex::new(DynamicOpts::new(&opts)));
+        let (copyright, wrapper) = (opts.copyright().unwrap(), opts.wrapper().unwrap());
+        let (thread, tx_worker, rx_notification, _) = spawn_worker(
+            Arc::new(opts),
+            dynamic,
+            Arc::new(config),
+            None,
+            copyright,
+            wrapper,
+        )
+        .unwrap();
+
+        let mut session = Self {
+            target,
+            thread: Some(thread),
+            tx_worker,
+            rx_notification: Some(rx_notification),
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            _dir: dir,
+        };
+
+        session.addr = match session.next(|n| matches!(n, WorkerNotification::Listening { .. })) {
+            WorkerNotification::Listening { addrs, .. } => addrs[0],
+            _ => unreachable!(),
+        };
+
+        session
+    }
+
+    /// Start a worker and connect the extension on a question, synchronizing it like the
+    /// interface does once the question is identified
+    fn synchronizing(args: &[&str]) -> (Self, Extension) {
+        Self::synchronizing_with(args, Config::default())
+    }
+
+    /// Same as [`Session::synchronizing`], with a configuration file
+    fn synchronizing_with(args: &[&str], config: Config) -> (Self, Extension) {
+        Self::start_with(args, config).synchronized()
+    }
+
+    /// Connect the extension on a question and synchronize it
+    fn synchronized(self) -> (Self, Extension) {
+        let session = self;
+        let mut extension = Extension::connect(session.addr);
+
+        extension.expect(|msg| matches!(msg, ServerMessage::SendDetails));
+        extension.send(ServerMessage::Details {
+            title: "Onboarding".to_owned(),
+            question_id: 1,
+        });
+
+        session.next(|n| matches!(n, WorkerNotification::Details { .. }));
+        session.send(WorkerMessage::Start { download: false });
+        extension.expect(|msg| matches!(msg, ServerMessage::AppReady));
+
+        (session, extension)
+    }
+
+    /// Synchronize again downloading the code, like the interface does. The extension is left to
+    /// send it.
+    fn download(&self, extension: &mut Extension) {
+        self.send(WorkerMessage::Stop);
+        self.send(WorkerMessage::Start { download: true });
+        extension.expect(|msg| matches!(msg, ServerMessage::AppReady));
+    }
+
+    fn send(&self, msg: WorkerMessage) {
+        task::block_on(self.tx_worker.send(msg)).unwrap();
+    }
+
+    /// Save the target, telling the controller like the watcher would. The contents are renamed
+    /// over the target as editors do, as the controller reads it back while handling the change.
+    fn save(&self, code: &str) {
+        let saving = self.target.with_extension("saving");
+        std::fs::write(&saving, code).unwrap();
+        std::fs::rename(&saving, &self.target).unwrap();
+        self.send(WorkerMessage::FileChanged {
+            code: code.to_owned().into(),
+            bundle: None,
+        });
+    }
+
+    /// Next notification matching the predicate, skipping the others
+    fn next(&self, matches: impl Fn(&WorkerNotification) -> bool) -> WorkerNotification {
+        let rx = self.rx_notification.as_ref().unwrap();
+        let deadline = Instant::now() + TIMEOUT;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok(notification) if matches(&notification) => return notification,
+                Ok(_) => {}
+                Err(err) => panic!("no matching notification: {}", err),
+            }
+        }
+    }
+
+    /// Checksums of the versions listed by the history, newest first
+    fn history(&self) -> Vec<String> {
+        self.send(WorkerMessage::HistoryRequested);
+        match self.next(|n| matches!(n, WorkerNotification::History { .. })) {
+            WorkerNotification::History { versions } => versions
+                .into_iter()
+                .map(|HistoryEntry { checksum, .. }| checksum)
+                .collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Whether the worker thread ends within the timeout
+    fn stops(&mut self) -> bool {
+        let thread = self.thread.take().unwrap();
+        let deadline = Instant::now() + TIMEOUT;
+
+        while !thread.is_finished() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        thread.join().is_ok()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = task::block_on(self.tx_worker.send(WorkerMessage::Terminate));
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Client standing for the extension
+struct Extension {
+    ws: WebSocket<TcpStream>,
+}
+
+impl Extension {
+    fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        let (ws, _) = tungstenite::client(format!("ws://{}/", addr), stream).unwrap();
+        Self { ws }
+    }
+
+    fn send(&mut self, msg: ServerMessage) {
+        self.ws.write_message(msg.into()).unwrap();
+    }
+
+    /// Wait for the upload of the code, skipping the other messages
+    fn uploaded(&mut self, code: &str) {
+        self.expect(|msg| match msg {
+            ServerMessage::UpdateCode { code: uploaded, .. } => &**uploaded == code,
+            _ => false,
+        });
+    }
+
+    /// Messages of the app received during the given time
+    fn received_for(&mut self, duration: Duration) -> Vec<ServerMessage> {
+        let deadline = Instant::now() + duration;
+        let mut received = Vec::new();
+
+        while let Some(remaining) = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+        {
+            self.ws.get_ref().set_read_timeout(Some(remaining)).unwrap();
+            match self.ws.read_message() {
+                Ok(Message::Text(text)) => received.push(ServerMessage::parse(&text).unwrap()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err)) if timed_out(&err) => break,
+                Err(err) => panic!("extension disconnected: {}", err),
+            }
+        }
+
+        self.ws.get_ref().set_read_timeout(Some(TIMEOUT)).unwrap();
+        received
+    }
+
+    /// Code of the IDE once the app stopped sending messages for a while, starting from the code
+    /// it had, uploads replacing it
+    fn settle(&mut self, mut code: String) -> String {
+        self.ws
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+
+        loop {
+            match self.ws.read_message() {
+                Ok(Message::Text(text)) => {
+                    if let ServerMessage::UpdateCode { code: uploaded, .. } =
+                        ServerMessage::parse(&text).unwrap()
+                    {
+                        code = uploaded.to_string();
+                    }
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err)) if timed_out(&err) => break,
+                Err(err) => panic!("extension disconnected: {}", err),
+            }
+        }
+
+        self.ws.get_ref().set_read_timeout(Some(TIMEOUT)).unwrap();
+        code
+    }
+
+    /// Next message of the app matching the predicate, skipping the others
+    fn expect(&mut self, matches: impl Fn(&ServerMessage) -> bool) -> ServerMessage {
+        loop {
+            match self.ws.read_message() {
+                Ok(Message::Text(text)) => {
+                    let msg = ServerMessage::parse(&text).unwrap();
+                    if matches(&msg) {
+                        return msg;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => panic!("no matching message: {}", err),
+            }
+        }
+    }
+}
+
+/// Whether the read of the extension timed out, the error depending on the platform
+fn timed_out(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// Records logged by the app in every test, formatted as the logger would
+static LOGS: std::sync::Mutex<String> = std::sync::Mutex::new(String::new());
+
+struct CaptureLogger;
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target().starts_with("cg_local_app")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let line = format!(
+                "{} {}: {}\n",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            LOGS.lock().unwrap().push_str(&line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Capture the logs of the app at every level, from now on
+fn capture_logs() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        log::set_boxed_logger(Box::new(CaptureLogger)).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+}
+
+#[test]
+fn worker_stops_when_the_notification_receiver_is_dropped_by_a_connection() {
+    let mut session = Session::start(&[]);
+    drop(session.rx_notification.take());
+
+    // The connection of the extension is notified to the interface, which is gone
+    let _extension = Extension::connect(session.addr);
+
+    assert!(session.stops(), "the worker is still running");
+}
+
+#[test]
+fn worker_stops_when_the_notification_receiver_is_dropped_by_the_controller() {
+    let mut session = Session::start(&[]);
+    let mut extension = Extension::connect(session.addr);
+    extension.expect(|msg| matches!(msg, ServerMessage::SendDetails));
+    drop(session.rx_notification.take());
+
+    // The identified question is notified to the interface by the controller
+    extension.send(ServerMessage::Details {
+        title: "Onboarding".to_owned(),
+        question_id: 1,
+    });
+
+    assert!(session.stops(), "the worker is still running");
+}
+
+#[test]
+fn refused_uploads_are_not_retained() {
+    let (session, mut extension) = Session::synchronizing(&["--code-max-lines", "1"]);
+
+    session.save("print(2)\n");
+    extension.uploaded("print(2)\n");
+
+    session.save("print(3)\nprint(4)\n");
+    session.next(|n| matches!(n, WorkerNotification::Error { .. }));
+
+    assert_eq!(session.history(), vec![checksum::checksum("print(2)\n")]);
+}
+
+#[test]
+fn deleted_target_is_recreated_with_the_last_upload() {
+    let (session, mut extension) =
+        Session::synchronizing(&["--code-max-lines", "1", "--recreate-target-on-delete"]);
+
+    session.save("print(2)\n");
+    extension.uploaded("print(2)\n");
+
+    session.save("print(3)\nprint(4)\n");
+    session.next(|n| matches!(n, WorkerNotification::Error { .. }));
+
+    std::fs::remove_file(&session.target).unwrap();
+    session.send(WorkerMessage::FileDeleted);
+    // Answered once the deletion was handled
+    session.history();
+
+    assert_eq!(
+        std::fs::read_to_string(&session.target).unwrap(),
+        "print(2)\n"
+    );
+}
+
+#[test]
+fn quitting_offers_the_code_not_uploaded() {
+    let (session, mut extension) = Session::synchronizing(&["--code-max-lines", "1"]);
+    let quit_check = || {
+        session.send(WorkerMessage::QuitRequested);
+        match session.next(|n| matches!(n, WorkerNotification::QuitCheck { .. })) {
+            WorkerNotification::QuitCheck { code } => code.map(|code| code.into_string()),
+            _ => unreachable!(),
+        }
+    };
+
+    session.save("print(2)\n");
+    extension.uploaded("print(2)\n");
+    assert_eq!(quit_check(), None);
+
+    session.save("print(3)\nprint(4)\n");
+    session.next(|n| matches!(n, WorkerNotification::Error { .. }));
+    assert_eq!(quit_check().as_deref(), Some("print(3)\nprint(4)\n"));
+}
+
+#[test]
+fn diffs_compare_with_the_last_upload() {
+    let (session, mut extension) =
+        Session::synchronizing(&["--tui-confirm-upload", "--show-diff-in-tui"]);
+    let pending_diff =
+        || match session.next(|n| matches!(n, WorkerNotification::PendingDiff { .. })) {
+            WorkerNotification::PendingDiff { diff } => diff,
+            _ => unreachable!(),
+        };
+    let pending_upload =
+        || match session.next(|n| matches!(n, WorkerNotification::PendingUpload { .. })) {
+            WorkerNotification::PendingUpload { diff, .. } => diff,
+            _ => unreachable!(),
+        };
+
+    session.save("print(2)\n");
+    pending_upload();
+    session.send(WorkerMessage::UploadConfirmed {
+        upload: true,
+        always: false,
+    });
+    session.save("print(2)\n");
+    extension.uploaded("print(2)\n");
+
+    // Skipped, then saved again: still compared with the uploaded code
+    session.save("print(3)\n");
+    pending_upload();
+    session.send(WorkerMessage::UploadConfirmed {
+        upload: false,
+        always: false,
+    });
+    session.save("print(3)\n");
+    assert!(pending_diff().is_some(), "empty pending diff");
+    assert!(pending_upload().is_some(), "empty confirmation diff");
+}
+
+#[test]
+fn history_lists_the_versions_sent() {
+    let (session, mut extension) = Session::synchronizing(&["--code-max-lines", "1"]);
+
+    for code in &["print(2)\n", "print(3)\n"] {
+        session.save(code);
+        extension.uploaded(code);
+    }
+    session.save("print(4)\nprint(5)\n");
+    session.next(|n| matches!(n, WorkerNotification::Error { .. }));
+
+    let versions = session.history();
+    assert_eq!(
+        versions,
+        vec![
+            checksum::checksum("print(3)\n"),
+            checksum::checksum("print(2)\n"),
+        ]
+    );
+
+    session.send(WorkerMessage::Restore {
+        checksum: versions[1].clone(),
+        play: false,
+        also_local: false,
+    });
+    extension.uploaded("print(2)\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn upload_progress_goes_idle_once_sent() {
+    let dir = tempfile::tempdir().unwrap();
+    let statuses = dir.path().join("statuses");
+    let command = format!("echo $CGLOCAL_STATUS >> {}", statuses.display());
+    let (session, mut extension) = Session::synchronizing(&["--upload-progress-command", &command]);
+
+    session.save("print(2)\n");
+    extension.uploaded("print(2)\n");
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let contents = std::fs::read_to_string(&statuses).unwrap_or_default();
+        if contents == "uploading\nidle\n" {
+            break;
+        }
+        assert!(Instant::now() < deadline, "statuses: {:?}", contents);
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn filtered_events_are_counted_for_the_interface() {
+    let session = Session::start(&["--ignore", "*.log"]);
+    let dir = session.target.parent().unwrap().to_owned();
+
+    let filtered = |session: &Session| match session
+        .next(|n| matches!(n, WorkerNotification::EventsFiltered { .. }))
+    {
+        WorkerNotification::EventsFiltered { count } => count,
+        _ => unreachable!(),
+    };
+
+    std::fs::write(dir.join("build.log"), "built\n").unwrap();
+    let first = filtered(&session);
+    assert!(first > 0);
+
+    // Reported again once the interface got the previous count. The debouncer of the watcher
+    // misses events scheduled while it goes idle after firing one, until the next event.
+    std::thread::sleep(Duration::from_millis(200));
+    std::fs::write(dir.join("test.log"), "tested\n").unwrap();
+    assert!(filtered(&session) > first);
+}
+
+#[test]
+fn timeouts_of_connections_not_on_the_question_are_ignored() {
+    let (session, mut extension) =
+        Session::synchronizing(&["--max-connections", "2", "--session-timeout", "1"]);
+    let mut idle = Extension::connect(session.addr);
+
+    // The connection on the question stays active while the idle one is closed
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while Instant::now() < deadline {
+        extension
+            .ws
+            .write_message(Message::Ping(Vec::new()))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    while let Ok(msg) = idle.ws.read_message() {
+        if let Message::Close(_) = msg {
+            break;
+        }
+    }
+
+    session.save("print(2)\n");
+    extension.uploaded("print(2)\n");
+
+    let notifications = session.rx_notification.as_ref().unwrap();
+    assert!(!notifications
+        .try_iter()
+        .any(|n| matches!(n, WorkerNotification::SessionTimeout)));
+}
+
+#[test]
+fn flapping_connections_defer_the_prompts_until_stable() {
+    let session = Session::start(&["--flap-threshold", "2", "--flap-stabilization", "1"]);
+
+    for _ in 0..3 {
+        let mut extension = Extension::connect(session.addr);
+        extension.expect(|msg| matches!(msg, ServerMessage::SendDetails));
+    }
+    session.next(|n| matches!(n, WorkerNotification::Flapping { flapping: true }));
+
+    // The question is only offered once the connection stayed up
+    let mut extension = Extension::connect(session.addr);
+    extension.expect(|msg| matches!(msg, ServerMessage::SendDetails));
+    extension.send(ServerMessage::Details {
+        title: "Onboarding".to_owned(),
+        question_id: 1,
+    });
+
+    let stable_or_details = |n: &WorkerNotification| {
+        matches!(
+            n,
+            WorkerNotification::Flapping { flapping: false } | WorkerNotification::Details { .. }
+        )
+    };
+    assert!(matches!(
+        session.next(stable_or_details),
+        WorkerNotification::Flapping { flapping: false }
+    ));
+    assert!(matches!(
+        session.next(stable_or_details),
+        WorkerNotification::Details { question_id: 1, .. }
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn downloads_saves_and_slow_hooks_end_in_sync() {
+    let mut config = Config::default();
+    config.hooks.on_download = Some("sleep 0.3".to_owned());
+    let (session, mut extension) = Session::synchronizing_with(&[], config);
+
+    for round in 0..6 {
+        session.download(&mut extension);
+
+        let downloaded = format!("print('ide {}')\n", round);
+        extension.send(code(&downloaded));
+        session.next(|n| matches!(n, WorkerNotification::Downloaded { .. }));
+
+        // Saves land while the hook runs, or after it ended as the rounds go
+        for save in 0..round % 3 {
+            session.save(&format!("print('local {} {}')\n", round, save));
+            std::thread::sleep(Duration::from_millis(40 * round as u64));
+        }
+
+        let ide = extension.settle(downloaded);
+        assert_eq!(
+            ide,
+            std::fs::read_to_string(&session.target).unwrap(),
+            "round {}",
+            round
+        );
+    }
+}
+
+#[test]
+fn downloaded_code_is_not_uploaded_back() {
+    let (session, mut extension) = Session::watching(&[]).synchronized();
+
+    session.download(&mut extension);
+    extension.send(code("print(2)\n"));
+    session.next(|n| matches!(n, WorkerNotification::Downloaded { .. }));
+
+    // Longer than the debouncing of the watcher, which reports the write again
+    let received = extension.received_for(Duration::from_secs(3));
+    assert!(
+        !received
+            .iter()
+            .any(|msg| matches!(msg, ServerMessage::UpdateCode { .. })),
+        "{:?}",
+        received
+    );
+
+    // An edit of the user is uploaded
+    std::fs::write(&session.target, "print(3)\n").unwrap();
+    extension.uploaded("print(3)\n");
+}
+
+#[test]
+fn unknown_questions_are_started_after_the_details_timeout() {
+    let session = Session::start(&["--details-timeout", "1", "--allow-unknown-question"]);
+    // Before the connection, the timeout starting once it's accepted
+    let connected = Instant::now();
+    let mut extension = Extension::connect(session.addr);
+    extension.expect(|msg| matches!(msg, ServerMessage::SendDetails));
+
+    match session.next(|n| matches!(n, WorkerNotification::Details { .. })) {
+        WorkerNotification::Details { title, question_id } => {
+            assert_eq!(
+                (question_id, title),
+                (UNKNOWN_QUESTION_ID, messages::unknown_question())
+            );
+        }
+        _ => unreachable!(),
+    }
+    assert!(connected.elapsed() >= Duration::from_secs(1));
+
+    // Synchronized like a question the extension identified
+    session.send(WorkerMessage::Start { download: false });
+    extension.expect(|msg| matches!(msg, ServerMessage::AppReady));
+    session.save("print(2)\n");
+    extension.uploaded("print(2)\n");
+}
+
+#[test]
+fn unknown_questions_are_offered_without_allowing_them() {
+    let session = Session::start(&["--details-timeout", "1"]);
+    let mut extension = Extension::connect(session.addr);
+    extension.expect(|msg| matches!(msg, ServerMessage::SendDetails));
+
+    let details = |n: &WorkerNotification| {
+        matches!(
+            n,
+            WorkerNotification::Details { .. } | WorkerNotification::DetailsMissing
+        )
+    };
+    assert!(matches!(
+        session.next(details),
+        WorkerNotification::DetailsMissing
+    ));
+}
+
+/// Code sent by the extension, as an answer to a download
+fn code(code: &str) -> ServerMessage {
+    ServerMessage::Code {
+        code: code.to_owned().into(),
+        checksum: None,
+    }
+}
+
+#[test]
+fn saves_during_a_download_raise_a_conflict() {
+    let (session, mut extension) = Session::synchronizing(&[]);
+
+    session.download(&mut extension);
+    session.save("print(2)\n");
+    extension.send(code("print(3)\n"));
+    session.next(|n| matches!(n, WorkerNotification::DownloadConflict { .. }));
+    assert_eq!(
+        std::fs::read_to_string(&session.target).unwrap(),
+        "print(2)\n"
+    );
+
+    session.send(WorkerMessage::DownloadConflictResolved { overwrite: false });
+    session.save("print(4)\n");
+    extension.uploaded("print(4)\n");
+    assert_eq!(
+        std::fs::read_to_string(&session.target).unwrap(),
+        "print(4)\n"
+    );
+}
+
+#[test]
+fn conflicting_downloads_overwrite_the_target_once_confirmed() {
+    let (session, mut extension) = Session::synchronizing(&[]);
+
+    session.download(&mut extension);
+    session.save("print(2)\n");
+    extension.send(code("print(3)\n"));
+    session.next(|n| matches!(n, WorkerNotification::DownloadConflict { .. }));
+
+    // The code is requested again, the IDE may have changed it meanwhile
+    session.send(WorkerMessage::DownloadConflictResolved { overwrite: true });
+    extension.expect(|msg| matches!(msg, ServerMessage::SendCode));
+    extension.send(code("print(4)\n"));
+    session.next(|n| matches!(n, WorkerNotification::Downloaded { .. }));
+    assert_eq!(
+        std::fs::read_to_string(&session.target).unwrap(),
+        "print(4)\n"
+    );
+}
+
+#[test]
+fn conflicting_downloads_are_refused_without_the_interface() {
+    let (session, mut extension) = Session::synchronizing(&["--no-gui"]);
+
+    session.download(&mut extension);
+    session.save("print(2)\n");
+    extension.send(code("print(3)\n"));
+    match session.next(|n| matches!(n, WorkerNotification::Error { .. })) {
+        WorkerNotification::Error { message } => {
+            assert_eq!(message, messages::download_conflict_refused())
+        }
+        _ => unreachable!(),
+    }
+    assert_eq!(
+        std::fs::read_to_string(&session.target).unwrap(),
+        "print(2)\n"
+    );
+}
+
+#[test]
+fn forced_downloads_overwrite_the_saves_made_meanwhile() {
+    let (session, mut extension) = Session::synchronizing(&["--no-gui", "--force-download"]);
+
+    session.download(&mut extension);
+    session.save("print(2)\n");
+    extension.send(code("print(3)\n"));
+    session.next(|n| matches!(n, WorkerNotification::Downloaded { .. }));
+    assert_eq!(
+        std::fs::read_to_string(&session.target).unwrap(),
+        "print(3)\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn hooks_never_mix_the_question_target_and_session_of_a_switch() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("hooks.log");
+    let command = format!(
+        "echo \"$CGLOCAL_QUESTION_ID $CGLOCAL_TARGET $CGLOCAL_SESSION\" >> {}",
+        log.display()
+    );
+
+    let sessions = [
+        ("fall", 1, "Fall Challenge"),
+        ("spring", 2, "Spring Challenge"),
+    ];
+    let mut config = Config::default();
+    config.hooks.on_details = Some(command.clone());
+    config.hooks.on_upload = Some(command);
+    for (name, question_id, _) in &sessions {
+        let target = dir.path().join(format!("{}.py", name));
+        std::fs::write(&target, INITIAL_CODE).unwrap();
+        config.session.insert(
+            name.to_string(),
+            SessionConfig {
+                target,
+                question_id: Some(*question_id),
+                play: None,
+            },
+        );
+    }
+
+    let mut session = Session::start_with(&["--ignore", "*.py"], config);
+    let mut extension = Extension::connect(session.addr);
+    extension.expect(|msg| matches!(msg, ServerMessage::SendDetails));
+
+    // Each switch is requested while the upload of the previous session may still run
+    const ROUNDS: usize = 6;
+    for round in 0..ROUNDS {
+        let (name, question_id, title) = sessions[round % 2];
+        session.send(WorkerMessage::SwitchSession {
+            name: name.to_owned(),
+        });
+        session.next(|n| matches!(n, WorkerNotification::SessionSwitched { .. }));
+        if round > 0 {
+            // Off the question of the previous session
+            session.next(|n| matches!(n, WorkerNotification::Detached));
+        }
+        session.target = dir.path().join(format!("{}.py", name));
+
+        extension.send(ServerMessage::Details {
+            title: title.to_owned(),
+            question_id,
+        });
+        session.next(|n| matches!(n, WorkerNotification::Details { .. }));
+        session.send(WorkerMessage::Start { download: false });
+        extension.expect(|msg| matches!(msg, ServerMessage::AppReady));
+        session.save(&format!("print({})\n", round));
+    }
+    extension.uploaded(&format!("print({})\n", ROUNDS - 1));
+
+    // Details and upload hooks of every round
+    let deadline = Instant::now() + TIMEOUT;
+    let lines = loop {
+        let contents = std::fs::read_to_string(&log).unwrap_or_default();
+        if contents.lines().count() == 2 * ROUNDS {
+            break contents;
+        }
+        assert!(Instant::now() < deadline, "hooks run: {:?}", contents);
+        std::thread::sleep(Duration::from_millis(20));
+    };
+
+    let contexts: Vec<_> = sessions
+        .iter()
+        .map(|(name, question_id, _)| {
+            let target = dir.path().join(format!("{}.py", name));
+            format!("{} {} {}", question_id, target.display(), name)
+        })
+        .collect();
+    for line in lines.lines() {
+        assert!(contexts.iter().any(|context| context == line), "{}", line);
+    }
+}
+
+#[test]
+fn uploads_of_code_sent_within_the_dedup_window_are_skipped() {
+    let (session, mut extension) =
+        Session::synchronizing(&["--upload-dedup-window", "60", "--upload-inject-metadata"]);
+
+    let uploaded = |expected: &'static str| move |msg: &ServerMessage| matches!(msg, ServerMessage::UpdateCode { code, .. } if code.contains(expected));
+    session.save("print(2)\n");
+    extension.expect(uploaded("print(2)"));
+    session.save("print(3)\n");
+    extension.expect(uploaded("print(3)"));
+
+    // Skipped even though the metadata, timestamped to the second, makes the upload different
+    std::thread::sleep(Duration::from_millis(1100));
+    session.save("print(2)\n");
+    session.save("print(4)\n");
+    match extension.expect(|msg| matches!(msg, ServerMessage::UpdateCode { .. })) {
+        ServerMessage::UpdateCode { code, .. } => assert!(code.contains("print(4)")),
+        _ => unreachable!(),
+    }
+}
+
+/// Wait for the status file to match the predicate, returning its contents
+fn status(
+    path: &std::path::Path,
+    matches: impl Fn(&serde_json::Value) -> bool,
+) -> serde_json::Value {
+    let deadline = Instant::now() + TIMEOUT;
+
+    loop {
+        let contents = std::fs::read_to_string(path).unwrap_or_default();
+        if let Ok(status) = serde_json::from_str(&contents) {
+            if matches(&status) {
+                return status;
+            }
+        }
+        assert!(Instant::now() < deadline, "status: {:?}", contents);
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn status_file_follows_the_session() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("status.json");
+    let mut session = Session::start(&["--status-file", path.to_str().unwrap()]);
+    let state = |state: &'static str| move |status: &serde_json::Value| status["state"] == state;
+
+    status(&path, state("waiting"));
+    let mut extension = Extension::connect(session.addr);
+    extension.expect(|msg| matches!(msg, ServerMessage::SendDetails));
+    status(&path, state("connected"));
+
+    extension.send(ServerMessage::Details {
+        title: "Onboarding".to_owned(),
+        question_id: 1,
+    });
+    session.next(|n| matches!(n, WorkerNotification::Details { .. }));
+    status(&path, |status| {
+        status["state"] == "connected" && status["questionId"] == 1
+    });
+
+    session.send(WorkerMessage::Start { download: false });
+    extension.expect(|msg| matches!(msg, ServerMessage::AppReady));
+    status(&path, state("syncing"));
+    session.save("print(2)\n");
+    extension.uploaded("print(2)\n");
+    status(&path, |status| {
+        status["state"] == "syncing" && status["lastUpload"].is_string()
+    });
+
+    session.send(WorkerMessage::Stop);
+    status(&path, state("paused"));
+
+    drop(extension);
+    status(&path, state("waiting"));
+
+    // Removed on exit
+    session.send(WorkerMessage::Terminate);
+    assert!(session.stops());
+    assert!(!path.exists());
+}
+
+#[test]
+fn code_is_redacted_from_the_logs_and_the_status() {
+    const SENTINEL: &str = "sentinel_9f2c41d7";
+    capture_logs();
+    let status_dir = tempfile::tempdir().unwrap();
+    let status_file = status_dir.path().join("status.json");
+    let (session, mut extension) = Session::synchronizing(&[
+        "--code-max-lines",
+        "1",
+        "--status-file",
+        status_file.to_str().unwrap(),
+    ]);
+
+    let uploaded = format!("print('{}')\n", SENTINEL);
+    session.save(&uploaded);
+    extension.uploaded(&uploaded);
+
+    // Refused, the status reporting the error
+    session.save(&format!("{}\n{}\n", uploaded, SENTINEL));
+    session.next(|n| matches!(n, WorkerNotification::Error { .. }));
+
+    let status = status(&status_file, |status| status["state"] == "error").to_string();
+    assert!(!status.contains(SENTINEL), "{}", status);
+
+    let logs = LOGS.lock().unwrap().clone();
+    assert!(
+        logs.contains("FileChanged { code: <"),
+        "the saves weren't logged"
+    );
+    assert!(!logs.contains(SENTINEL), "{}", logs);
+}
+
+#[test]
+fn messages_of_unknown_actions_are_kept() {
+    let msg = ServerMessage::parse(r#"{"action": "run-tests", "payload": {"all": true}}"#).unwrap();
+    match msg {
+        ServerMessage::Unknown { action, payload } => {
+            assert_eq!(action, "run-tests");
+            assert_eq!(payload["all"], true);
+        }
+        other => panic!("parsed as {:?}", other),
+    }
+
+    // Known actions with an invalid payload are still errors
+    assert!(ServerMessage::parse(r#"{"action": "details", "payload": {"title": 42}}"#).is_err());
+    assert!(ServerMessage::parse(r#"{"payload": {}}"#).is_err());
+}
+
+/// The known actions are those serde expects, which it only lists in its errors
+#[test]
+fn known_actions_are_the_variants() {
+    let err = serde_json::from_str::<ServerMessage>(r#"{"action": "none"}"#).unwrap_err();
+    let err = err.to_string();
+    let expected = err
+        .split_once("expected one of ")
+        .map(|(_, variants)| variants.split(" at line").next().unwrap())
+        .unwrap_or_else(|| panic!("unexpected error {}", err));
+    let variants: Vec<_> = expected
+        .split(", ")
+        .map(|variant| variant.trim_matches('`'))
+        .collect();
+
+    assert_eq!(variants, ServerMessage::ACTIONS);
+}
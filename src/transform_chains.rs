@@ -0,0 +1,452 @@
+//! Chains of upload transforms, configured per target, question or language.
+//!
+//! Without configuration the preprocessing runs the steps enabled by the `--upload-*` flags, in a
+//! fixed order. The `[transforms]` table of the configuration file gives the ordered list of
+//! stages to run instead, and the options of the stages:
+//!
+//! ```toml
+//! [transforms.language]
+//! rust = ["dead-code", "comment-out-main", "final-newline", "codepoint-limit"]
+//!
+//! [transforms.question]
+//! 42 = ["replace", "final-newline"]
+//!
+//! [transforms.target]
+//! "contest/fall.rs" = ["copyright", "final-newline"]
+//!
+//! [transforms.options.codepoint-limit]
+//! limit = 65536
+//! ```
+//!
+//! The chain of the target takes precedence over the one of the question, then over the one of
+//! the language of the target. Stages enabled by a flag but missing from the chain are still run,
+//! at their place in the default order, and the values given on the command line override the
+//! options of the stages.
+//!
+//! The copyright, wrapper and test input stages read files only given on the command line, and
+//! the code point limit stage needs a limit. A chain listing one of them without its option is
+//! refused when the configuration is loaded.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::dead_code;
+use crate::language::Language;
+use crate::transforms::FinalNewlineMode;
+use crate::{ErrorKind, Result};
+
+/// Preprocessing stage, the variants being in the default order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    /// Text substitutions, `--upload-replace`
+    Replace,
+    /// `--upload-remove-dead-code`
+    DeadCode,
+    /// `--upload-comment-out-main`
+    CommentOutMain,
+    /// `--upload-add-copyright`
+    Copyright,
+    /// `--upload-wrapper`
+    Wrapper,
+    /// `--upload-inject-metadata`
+    Metadata,
+    /// `--upload-inject-test-input`
+    TestInput,
+    /// `--upload-ensure-final-newline` and `--upload-strip-final-newline`
+    FinalNewline,
+    /// `--upload-force-codepoint-limit`
+    CodepointLimit,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 9] = [
+        Stage::Replace,
+        Stage::DeadCode,
+        Stage::CommentOutMain,
+        Stage::Copyright,
+        Stage::Wrapper,
+        Stage::Metadata,
+        Stage::TestInput,
+        Stage::FinalNewline,
+        Stage::CodepointLimit,
+    ];
+
+    /// Name of the stage in the configuration, also reported as applied by the preprocessing
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Replace => "replace",
+            Stage::DeadCode => "dead-code",
+            Stage::CommentOutMain => "comment-out-main",
+            Stage::Copyright => "copyright",
+            Stage::Wrapper => "wrapper",
+            Stage::Metadata => "metadata",
+            Stage::TestInput => "test-input",
+            Stage::FinalNewline => "final-newline",
+            Stage::CodepointLimit => "codepoint-limit",
+        }
+    }
+}
+
+impl Stage {
+    /// Option the stage can't run without, if any
+    fn required_option(self) -> Option<&'static str> {
+        match self {
+            Stage::Copyright => Some("--upload-add-copyright"),
+            Stage::Wrapper => Some("--upload-wrapper"),
+            Stage::TestInput => Some("--upload-inject-test-input"),
+            Stage::CodepointLimit => {
+                Some("--upload-force-codepoint-limit or [transforms.options.codepoint-limit] limit")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransformsConfig {
+    /// Chains by target path
+    pub target: BTreeMap<PathBuf, Vec<Stage>>,
+    /// Chains by question id
+    #[serde(deserialize_with = "question_ids")]
+    pub question: BTreeMap<i32, Vec<Stage>>,
+    /// Chains by language of the target
+    #[serde(deserialize_with = "languages")]
+    pub language: Vec<(Language, Vec<Stage>)>,
+    pub options: StageOptions,
+}
+
+/// Options of the stages, overridden by the command line
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct StageOptions {
+    pub replace: ReplaceOptions,
+    pub dead_code: DeadCodeOptions,
+    pub metadata: MetadataOptions,
+    pub final_newline: FinalNewlineOptions,
+    pub codepoint_limit: CodepointLimitOptions,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplaceOptions {
+    /// Substitutions as `[find, replace]` pairs, applied before the ones of --upload-replace
+    pub rules: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeadCodeOptions {
+    /// Language of the dead code stripper, the one of the target if not given
+    #[serde(deserialize_with = "dead_code_language")]
+    pub language: Option<Language>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetadataOptions {
+    pub author: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FinalNewlineOptions {
+    /// `ensure` if not given
+    pub mode: Option<FinalNewlineMode>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodepointLimitOptions {
+    pub limit: Option<usize>,
+}
+
+impl TransformsConfig {
+    /// Every configured chain, for diagnostics
+    pub fn chains(&self) -> impl Iterator<Item = &[Stage]> {
+        self.target
+            .values()
+            .chain(self.question.values())
+            .chain(self.language.iter().map(|(_, chain)| chain))
+            .map(Vec::as_slice)
+    }
+
+    /// Check that the stages of every chain have their options, given in the configuration or
+    /// enabling the `flagged` stages on the command line
+    pub fn check(&self, flagged: &[Stage]) -> Result<()> {
+        let given = |stage: Stage| match stage {
+            Stage::CodepointLimit => self.options.codepoint_limit.limit.is_some(),
+            _ => false,
+        };
+
+        let missing = self
+            .chains()
+            .flatten()
+            .copied()
+            .filter(|stage| !flagged.contains(stage) && !given(*stage))
+            .find_map(|stage| stage.required_option().map(|option| (stage, option)));
+
+        match missing {
+            Some((stage, option)) => Err(ErrorKind::IncompleteTransformChain(
+                stage.to_string(),
+                option.to_owned(),
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Chain configured for the target, the question or the language, by precedence
+    pub fn chain(
+        &self,
+        target: Option<&Path>,
+        question_id: Option<i32>,
+        language: Option<Language>,
+    ) -> Option<&[Stage]> {
+        let by_target = target.and_then(|target| {
+            let canonical = target.canonicalize().ok();
+            self.target.iter().find_map(|(path, chain)| {
+                let matches = path == target
+                    || (canonical.is_some() && path.canonicalize().ok() == canonical);
+                if matches {
+                    Some(chain)
+                } else {
+                    None
+                }
+            })
+        });
+
+        by_target
+            .or_else(|| question_id.and_then(|question_id| self.question.get(&question_id)))
+            .or_else(|| {
+                language.and_then(|language| {
+                    self.language
+                        .iter()
+                        .find(|(configured, _)| *configured == language)
+                        .map(|(_, chain)| chain)
+                })
+            })
+            .map(Vec::as_slice)
+    }
+}
+
+/// Add the stages enabled by flags to a configured chain, each before the first stage coming
+/// after it in the default order
+pub fn with_flagged(chain: &[Stage], flagged: &[Stage]) -> Vec<Stage> {
+    let mut chain = chain.to_vec();
+
+    for stage in flagged {
+        if chain.contains(stage) {
+            continue;
+        }
+
+        let position = chain
+            .iter()
+            .position(|other| other > stage)
+            .unwrap_or(chain.len());
+        chain.insert(position, *stage);
+    }
+
+    chain
+}
+
+/// Question id keys of `[transforms.question]`, TOML keys being strings
+fn question_ids<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<i32, Vec<Stage>>, D::Error> {
+    BTreeMap::<String, Vec<Stage>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(id, chain)| {
+            let question_id = id
+                .parse()
+                .map_err(|_| serde::de::Error::custom(format!("'{}' is not a question id", id)))?;
+            Ok((question_id, chain))
+        })
+        .collect()
+}
+
+/// Language keys of `[transforms.language]`, by name or extension such as `rust` or `py`
+fn languages<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<(Language, Vec<Stage>)>, D::Error> {
+    BTreeMap::<String, Vec<Stage>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, chain)| {
+            let language = name.parse().map_err(serde::de::Error::custom)?;
+            Ok((language, chain))
+        })
+        .collect()
+}
+
+fn dead_code_language<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Language>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(name) => dead_code::parse_language(&name)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> std::result::Result<TransformsConfig, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    #[test]
+    fn stages_are_listed_in_the_default_order() {
+        let mut sorted = Stage::ALL;
+        sorted.sort();
+        assert_eq!(sorted, Stage::ALL);
+
+        // Configured by the name they are reported as
+        for stage in Stage::ALL {
+            let config = parse(&format!("[question]\n1 = [\"{}\"]\n", stage)).unwrap();
+            assert_eq!(config.question[&1], [stage]);
+        }
+    }
+
+    #[test]
+    fn unknown_stages_list_the_valid_names() {
+        let names = Stage::ALL
+            .iter()
+            .map(|stage| format!("`{}`", stage))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let err = parse("[language]\nrust = [\"minify\"]\n").unwrap_err();
+        assert!(
+            err.to_string().contains(&format!(
+                "unknown variant `minify`, expected one of {}",
+                names
+            )),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn chains_are_parsed() {
+        let config = parse(
+            "[target]\n\"contest/fall.rs\" = [\"copyright\"]\n\n\
+             [question]\n42 = [\"replace\", \"final-newline\"]\n\n\
+             [language]\nrust = [\"dead-code\"]\npy = [\"metadata\"]\n\n\
+             [options.codepoint-limit]\nlimit = 10\n\n\
+             [options.metadata]\nauthor = \"Ada\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.target[Path::new("contest/fall.rs")],
+            [Stage::Copyright]
+        );
+        assert_eq!(config.question[&42], [Stage::Replace, Stage::FinalNewline]);
+        assert_eq!(
+            config.language,
+            [
+                (Language::Python, vec![Stage::Metadata]),
+                (Language::Rust, vec![Stage::DeadCode]),
+            ]
+        );
+        assert_eq!(config.options.codepoint_limit.limit, Some(10));
+        assert_eq!(config.options.metadata.author.as_deref(), Some("Ada"));
+    }
+
+    #[test]
+    fn invalid_chains_are_refused() {
+        let cases = [
+            ("[question]\nfall = []\n", "'fall' is not a question id"),
+            ("[language]\ncobol = []\n", "unknown language 'cobol'"),
+            ("[languages]\nrust = []\n", "unknown field `languages`"),
+            ("[options.replace]\nrule = []\n", "unknown field `rule`"),
+            ("[question]\n42 = \"replace\"\n", "invalid type"),
+        ];
+
+        for (toml, error) in &cases {
+            match parse(toml) {
+                Err(err) => assert!(err.to_string().contains(error), "{}: {}", toml, err),
+                Ok(_) => panic!("accepted: {}", toml),
+            }
+        }
+    }
+
+    #[test]
+    fn chains_are_chosen_by_target_question_then_language() {
+        let config = parse(
+            "[target]\n\"fall.rs\" = [\"copyright\"]\n\n\
+             [question]\n42 = [\"replace\"]\n\n\
+             [language]\nrust = [\"dead-code\"]\n",
+        )
+        .unwrap();
+        let (target, other) = (Some(Path::new("fall.rs")), Some(Path::new("spring.rs")));
+
+        let cases = [
+            (
+                target,
+                Some(42),
+                Some(Language::Rust),
+                Some(Stage::Copyright),
+            ),
+            (other, Some(42), Some(Language::Rust), Some(Stage::Replace)),
+            (other, Some(7), Some(Language::Rust), Some(Stage::DeadCode)),
+            (other, None, Some(Language::Rust), Some(Stage::DeadCode)),
+            (other, Some(7), Some(Language::Python), None),
+            (None, None, None, None),
+        ];
+
+        for (target, question_id, language, stage) in cases {
+            assert_eq!(
+                config.chain(target, question_id, language),
+                stage.as_ref().map(std::slice::from_ref),
+                "{:?} {:?} {:?}",
+                target,
+                question_id,
+                language
+            );
+        }
+    }
+
+    #[test]
+    fn flagged_stages_are_added_in_the_default_order() {
+        use Stage::*;
+
+        let cases: [(&[Stage], &[Stage], &[Stage]); 4] = [
+            (&[], &[Metadata, Replace], &[Replace, Metadata]),
+            (
+                &[FinalNewline, Replace],
+                &[Metadata],
+                &[Metadata, FinalNewline, Replace],
+            ),
+            (
+                &[CodepointLimit, DeadCode],
+                &[Replace],
+                &[Replace, CodepointLimit, DeadCode],
+            ),
+            (&[Metadata, Replace], &[Replace], &[Metadata, Replace]),
+        ];
+
+        for (chain, flagged, expected) in &cases {
+            assert_eq!(
+                with_flagged(chain, flagged),
+                *expected,
+                "{:?} {:?}",
+                chain,
+                flagged
+            );
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! Transformations applied to the code before it is uploaded to the IDE.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use chrono::Datelike;
+
+use crate::language::Language;
+
+/// Parse a `FIND:REPLACE` substitution rule. The first colon separates both parts.
+pub fn parse_replacement(rule: &str) -> Result<(String, String), String> {
+    match rule.find(':') {
+        Some(0) => Err(format!("empty search string in '{}'", rule)),
+        Some(idx) => Ok((rule[..idx].to_owned(), rule[idx + 1..].to_owned())),
+        None => Err(format!("expected FIND:REPLACE, got '{}'", rule)),
+    }
+}
+
+/// Apply the substitutions in order, later rules operating on the output of earlier ones
+pub fn apply_replacements<'a>(code: &'a str, replacements: &[(String, String)]) -> Cow<'a, str> {
+    let mut code = Cow::Borrowed(code);
+
+    for (find, replace) in replacements {
+        let count = code.matches(find.as_str()).count();
+        info!(
+            "replaced {} occurrence(s) of '{}' with '{}'",
+            count, find, replace
+        );
+
+        if count > 0 {
+            code = Cow::Owned(code.replace(find.as_str(), replace));
+        }
+    }
+
+    code
+}
+
+/// Handling of the newlines at the end of the uploaded code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FinalNewlineMode {
+    /// End the code with exactly one newline
+    Ensure,
+    /// Remove all trailing newlines
+    Strip,
+    /// Leave the code as is
+    Passthrough,
+}
+
+/// Normalize the trailing newlines of the code according to `mode`, ensuring a `\r\n` one for
+/// code with Windows line endings
+pub fn normalize_final_newline(code: &str, mode: FinalNewlineMode) -> Cow<'_, str> {
+    let trimmed = code.trim_end_matches(['\n', '\r']);
+    let newline = if code.contains("\r\n") { "\r\n" } else { "\n" };
+
+    match mode {
+        FinalNewlineMode::Ensure if &code[trimmed.len()..] == newline => Cow::Borrowed(code),
+        FinalNewlineMode::Ensure => Cow::Owned(format!("{}{}", trimmed, newline)),
+        FinalNewlineMode::Strip => Cow::Borrowed(trimmed),
+        FinalNewlineMode::Passthrough => Cow::Borrowed(code),
+    }
+}
+
+/// First `n` codepoints of the code, cut on a character boundary
+pub fn truncate_at_codepoints(code: &str, n: usize) -> &str {
+    match code.char_indices().nth(n) {
+        Some((idx, _)) => &code[..idx],
+        None => code,
+    }
+}
+
+/// Comment out the entry point of the code, for puzzles expecting a module without one: the
+/// `fn main` function in Rust, the `if __name__ == "__main__":` block in Python. Returns `None`
+/// for other languages.
+pub fn comment_out_main(code: &str, language: Language) -> Option<Cow<'_, str>> {
+    let (comment, block) = match language {
+        Language::Rust => ("//", rust_main_block(code.split_inclusive('\n'))),
+        Language::Python => ("#", python_main_block(code.split_inclusive('\n'))),
+        _ => return None,
+    };
+
+    let (start, end) = match block {
+        Some(block) => block,
+        None => return Some(Cow::Borrowed(code)),
+    };
+
+    Some(Cow::Owned(
+        code.split_inclusive('\n')
+            .enumerate()
+            .map(|(idx, line)| {
+                if idx < start || idx >= end {
+                    line.to_owned()
+                } else if line.trim().is_empty() {
+                    format!("{}{}", comment, line.trim_start_matches([' ', '\t']))
+                } else {
+                    format!("{} {}", comment, line)
+                }
+            })
+            .collect(),
+    ))
+}
+
+/// Uncomment the entry point commented out by [`comment_out_main`]. Returns `None` for languages
+/// it doesn't handle.
+pub fn restore_main(code: &str, language: Language) -> Option<Cow<'_, str>> {
+    let (comment, is_main): (_, fn(&str) -> bool) = match language {
+        Language::Rust => ("//", is_rust_main),
+        Language::Python => ("#", is_python_main),
+        _ => return None,
+    };
+
+    let lines: Vec<&str> = code.split_inclusive('\n').collect();
+    let uncommented: Vec<Option<String>> = lines
+        .iter()
+        .map(|line| {
+            let rest = line.strip_prefix(comment)?;
+            Some(rest.strip_prefix(' ').unwrap_or(rest).to_owned())
+        })
+        .collect();
+
+    let header = uncommented
+        .iter()
+        .position(|line| line.as_deref().map(is_main).unwrap_or(false));
+
+    // The commented lines are parsed as they were before being commented out, up to the first
+    // line which is not commented
+    let block = header.and_then(|header| {
+        let commented = uncommented[header..]
+            .iter()
+            .map_while(|line| line.as_deref());
+        let block = match language {
+            Language::Rust => rust_main_block(commented),
+            _ => python_main_block(commented),
+        };
+
+        block.map(|(_, end)| (header, header + end))
+    });
+
+    let (start, end) = match block {
+        Some(block) => block,
+        None => return Some(Cow::Borrowed(code)),
+    };
+
+    Some(Cow::Owned(
+        lines
+            .iter()
+            .zip(uncommented)
+            .enumerate()
+            .map(|(idx, (line, uncommented))| match uncommented {
+                Some(uncommented) if idx >= start && idx < end => uncommented,
+                _ => (*line).to_owned(),
+            })
+            .collect(),
+    ))
+}
+
+fn is_rust_main(line: &str) -> bool {
+    line.trim_start().starts_with("fn main(")
+}
+
+fn is_python_main(line: &str) -> bool {
+    let condition: String = line.split_whitespace().collect();
+    condition == "if__name__==\"__main__\":" || condition == "if__name__=='__main__':"
+}
+
+/// Lexical state of Rust code at the end of a line, with the braces of the line
+#[derive(Debug, Default, Clone, Copy)]
+struct RustLine {
+    in_str: bool,
+    in_comment: bool,
+    opened: usize,
+    closed: usize,
+}
+
+/// Lines spanned by the `fn main` function, from its signature to its closing brace
+fn rust_main_block<'a>(lines: impl Iterator<Item = &'a str>) -> Option<(usize, usize)> {
+    let mut state = RustLine::default();
+    let mut start = None;
+    let mut depth = 0;
+    let mut opened = false;
+
+    for (idx, line) in lines.enumerate() {
+        if start.is_none() && !state.in_str && !state.in_comment && is_rust_main(line) {
+            start = Some(idx);
+        }
+
+        state = rust_scan(line, state);
+        if start.is_some() {
+            opened |= state.opened > 0;
+            depth = (depth + state.opened).saturating_sub(state.closed);
+            if opened && depth == 0 {
+                return Some((start?, idx + 1));
+            }
+        }
+    }
+
+    None
+}
+
+/// Scan a line of Rust code, counting the braces outside of strings, characters and comments.
+/// Strings and block comments may continue from the previous line.
+fn rust_scan(line: &str, previous: RustLine) -> RustLine {
+    let mut state = RustLine {
+        opened: 0,
+        closed: 0,
+        ..previous
+    };
+    let chars: Vec<char> = line.chars().collect();
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let next = chars.get(idx + 1).copied();
+
+        if state.in_comment {
+            if chars[idx] == '*' && next == Some('/') {
+                state.in_comment = false;
+                idx += 1;
+            }
+        } else if state.in_str {
+            match chars[idx] {
+                '\\' => idx += 1,
+                '"' => state.in_str = false,
+                _ => {}
+            }
+        } else {
+            match (chars[idx], next) {
+                ('/', Some('/')) => break,
+                ('/', Some('*')) => {
+                    state.in_comment = true;
+                    idx += 1;
+                }
+                ('"', _) => state.in_str = true,
+                // Character literals, not lifetimes
+                ('\'', Some('\\')) => {
+                    idx += 2;
+                    while idx < chars.len() && chars[idx] != '\'' {
+                        idx += 1;
+                    }
+                }
+                ('\'', Some(_)) if chars.get(idx + 2) == Some(&'\'') => idx += 2,
+                ('{', _) => state.opened += 1,
+                ('}', _) => state.closed += 1,
+                _ => {}
+            }
+        }
+
+        idx += 1;
+    }
+
+    state
+}
+
+/// Lines of the `if __name__ == "__main__":` block, from the condition to its last indented line
+fn python_main_block<'a>(lines: impl Iterator<Item = &'a str>) -> Option<(usize, usize)> {
+    let mut start = None;
+    let mut end = 0;
+
+    for (idx, line) in lines.enumerate() {
+        match start {
+            None if is_python_main(line) => {
+                start = Some(idx);
+                end = idx + 1;
+            }
+            None => {}
+            Some(_) if line.trim().is_empty() => {}
+            Some(_) if line.starts_with([' ', '\t']) => end = idx + 1,
+            Some(_) => break,
+        }
+    }
+
+    Some((start?, end))
+}
+
+/// Copyright header prepended to the uploaded code, read from a template where `{YEAR}` is
+/// replaced with the current year
+#[derive(Debug, Clone)]
+pub struct CopyrightHeader {
+    template: String,
+    year_override: Option<i32>,
+}
+
+impl CopyrightHeader {
+    /// Read the template once, so later edits of the file only apply on the next start
+    pub fn load(path: &Path, year_override: Option<i32>) -> std::io::Result<Self> {
+        Ok(Self {
+            template: std::fs::read_to_string(path)?,
+            year_override,
+        })
+    }
+
+    /// Header with the year substituted
+    pub fn header(&self) -> String {
+        let year = self
+            .year_override
+            .unwrap_or_else(|| chrono::Local::now().year());
+        self.template.replace("{YEAR}", &year.to_string())
+    }
+
+    /// Prepend the header to the code, unless the code already starts with it
+    pub fn apply<'a>(&self, code: &'a str) -> Cow<'a, str> {
+        let header = self.header();
+
+        if code.starts_with(&header) {
+            Cow::Borrowed(code)
+        } else {
+            Cow::Owned(header + code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn final_newlines() {
+        let table = [
+            ("print(1)", "print(1)\n", "print(1)"),
+            ("print(1)\n", "print(1)\n", "print(1)"),
+            ("print(1)\n\n\n", "print(1)\n", "print(1)"),
+            ("", "\n", ""),
+            ("\n", "\n", ""),
+            // Windows line endings
+            (
+                "print(1)\r\nprint(2)",
+                "print(1)\r\nprint(2)\r\n",
+                "print(1)\r\nprint(2)",
+            ),
+            ("print(1)\r\n", "print(1)\r\n", "print(1)"),
+            ("print(1)\r\n\r\n", "print(1)\r\n", "print(1)"),
+            ("print(1)\r\n\n", "print(1)\r\n", "print(1)"),
+            ("\r\n", "\r\n", ""),
+        ];
+
+        for (code, ensured, stripped) in table {
+            let normalize = |mode| normalize_final_newline(code, mode);
+            assert_eq!(normalize(FinalNewlineMode::Ensure), ensured, "{:?}", code);
+            assert_eq!(normalize(FinalNewlineMode::Strip), stripped, "{:?}", code);
+            assert_eq!(normalize(FinalNewlineMode::Passthrough), code, "{:?}", code);
+        }
+    }
+}
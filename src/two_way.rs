@@ -0,0 +1,173 @@
+//! Two-way synchronization: edits made in the IDE are pulled into the target.
+//!
+//! With `--two-way`, the controller requests the IDE code every `--two-way-interval` seconds
+//! while synchronizing. The checksums of both sides are remembered each time they are
+//! synchronized: IDE code differing from the last one synchronized was edited in the browser,
+//! and is written to the target like a download. If the target was also saved since, both sides
+//! changed and `--conflict-policy` decides which one wins.
+//!
+//! `--ide-poll-interval` requests the IDE code the same way and compares it to the same
+//! baseline, but only warns that it diverged.
+
+use std::str::FromStr;
+
+/// Side kept when both the target and the IDE code changed since they were last synchronized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Upload the target over the IDE code
+    PreferLocal,
+    /// Write the IDE code over the target
+    PreferIde,
+    /// Let the user choose in the interface, keeping the local changes without it
+    Ask,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefer-local" => Ok(ConflictPolicy::PreferLocal),
+            "prefer-ide" => Ok(ConflictPolicy::PreferIde),
+            "ask" => Ok(ConflictPolicy::Ask),
+            other => Err(format!(
+                "unknown conflict policy '{}', expected prefer-local, prefer-ide or ask",
+                other
+            )),
+        }
+    }
+}
+
+/// State of the target when IDE code arrives for a download or a pull
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Local {
+    /// The target already holds the IDE code
+    Same,
+    /// The target wasn't saved since the download was requested or since the last
+    /// synchronization
+    Untouched,
+    /// The target was saved since
+    Edited,
+}
+
+/// What to do with IDE code received for a download or a pull
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Nothing to write, the target already holds the code
+    Unchanged,
+    /// Write the IDE code over the target
+    Write,
+    /// Keep the target and upload it over the IDE code
+    KeepLocal,
+    /// Let the user choose in the interface
+    Ask,
+    /// Keep the target and report the conflict, there is no interface to ask
+    Refuse,
+}
+
+/// Resolve IDE code arriving while the target is in the `local` state. Downloads follow `Ask`,
+/// or `PreferIde` with `--force-download`, and pulls follow `--conflict-policy`. `interactive` is
+/// whether the interface can ask.
+pub fn resolve(local: Local, policy: ConflictPolicy, interactive: bool) -> Resolution {
+    match (local, policy) {
+        (Local::Same, _) => Resolution::Unchanged,
+        (Local::Untouched, _) | (Local::Edited, ConflictPolicy::PreferIde) => Resolution::Write,
+        (Local::Edited, ConflictPolicy::PreferLocal) => Resolution::KeepLocal,
+        (Local::Edited, ConflictPolicy::Ask) if interactive => Resolution::Ask,
+        (Local::Edited, ConflictPolicy::Ask) => Resolution::Refuse,
+    }
+}
+
+/// Checksums of the target and of the IDE code when they were last synchronized
+#[derive(Debug, Default)]
+pub struct Baseline {
+    sides: Option<(Option<String>, String)>,
+}
+
+impl Baseline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a synchronization was recorded since the baseline was reset
+    pub fn is_set(&self) -> bool {
+        self.sides.is_some()
+    }
+
+    /// The target, with checksum `local`, and the IDE code, with checksum `ide`, are the same
+    /// code as uploaded or downloaded. `local` is `None` if the target couldn't be read.
+    pub fn synchronized(&mut self, local: Option<String>, ide: String) {
+        self.sides = Some((local, ide));
+    }
+
+    /// The IDE code was seen without being synchronized, e.g. on a conflict the target was kept
+    /// over it. It isn't considered as edited again until it changes.
+    pub fn ide_seen(&mut self, ide: String) {
+        if let Some((_, seen)) = &mut self.sides {
+            *seen = ide;
+        }
+    }
+
+    /// Whether the IDE code with checksum `ide` changed since it was last synchronized or seen
+    pub fn ide_changed(&self, ide: &str) -> bool {
+        match &self.sides {
+            Some((_, seen)) => seen != ide,
+            None => true,
+        }
+    }
+
+    /// Checksum of the target when it was last synchronized
+    pub fn local_checksum(&self) -> Option<String> {
+        self.sides.as_ref().and_then(|(local, _)| local.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicts_are_resolved_by_the_policy() {
+        use ConflictPolicy::*;
+
+        let cases = [
+            (Local::Edited, PreferLocal, true, Resolution::KeepLocal),
+            (Local::Edited, PreferLocal, false, Resolution::KeepLocal),
+            (Local::Edited, PreferIde, true, Resolution::Write),
+            (Local::Edited, PreferIde, false, Resolution::Write),
+            (Local::Edited, Ask, true, Resolution::Ask),
+            (Local::Edited, Ask, false, Resolution::Refuse),
+        ];
+
+        for (local, policy, interactive, resolution) in cases {
+            assert_eq!(
+                resolve(local, policy, interactive),
+                resolution,
+                "{:?} {:?} interactive: {}",
+                local,
+                policy,
+                interactive
+            );
+        }
+    }
+
+    #[test]
+    fn code_without_conflict_is_written_unless_already_there() {
+        for policy in [
+            ConflictPolicy::PreferLocal,
+            ConflictPolicy::PreferIde,
+            ConflictPolicy::Ask,
+        ] {
+            for interactive in [true, false] {
+                assert_eq!(
+                    resolve(Local::Untouched, policy, interactive),
+                    Resolution::Write
+                );
+                assert_eq!(
+                    resolve(Local::Same, policy, interactive),
+                    Resolution::Unchanged
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Exclusion rules applied to file watcher events before they reach the controller.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Name of the ignore file looked up in the watched root.
+pub const IGNORE_FILE_NAME: &str = ".cg-localignore";
+
+/// Filter for watcher events, built from the ignore file in the watched root and the `--ignore`
+/// command-line patterns.
+pub struct WatchFilter {
+    root: PathBuf,
+    patterns: Vec<String>,
+    matcher: RwLock<Gitignore>,
+    filtered: AtomicU64,
+}
+
+impl WatchFilter {
+    pub fn new(root: impl AsRef<Path>, patterns: Vec<String>) -> Self {
+        let root = root
+            .as_ref()
+            .canonicalize()
+            .unwrap_or_else(|_| root.as_ref().to_owned());
+        let matcher = build_matcher(&root, &patterns);
+
+        Self {
+            root,
+            patterns,
+            matcher: RwLock::new(matcher),
+            filtered: AtomicU64::new(0),
+        }
+    }
+
+    /// Path to the ignore file for the watched root
+    pub fn ignore_file(&self) -> PathBuf {
+        self.root.join(IGNORE_FILE_NAME)
+    }
+
+    /// Rebuild the matcher from the ignore file and the command-line patterns
+    pub fn reload(&self) {
+        let matcher = build_matcher(&self.root, &self.patterns);
+        info!(
+            "reloaded watch exclusions ({} rules)",
+            matcher.num_ignores()
+        );
+        *self.matcher.write().unwrap() = matcher;
+    }
+
+    /// Returns true if the event for the given path should be dropped. Filtered events are
+    /// counted, see `filtered_count`.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+
+        let ignored = self
+            .matcher
+            .read()
+            .unwrap()
+            .matched_path_or_any_parents(relative, path.is_dir())
+            .is_ignore();
+
+        if ignored {
+            let count = self.filtered.fetch_add(1, Ordering::Relaxed) + 1;
+            trace!(
+                "ignored watch event for {} ({} so far)",
+                path.display(),
+                count
+            );
+        }
+
+        ignored
+    }
+
+    /// Number of watcher events dropped by the exclusion rules so far
+    pub fn filtered_count(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+}
+
+fn build_matcher(root: &Path, patterns: &[String]) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(root);
+
+    let ignore_file = root.join(IGNORE_FILE_NAME);
+    if ignore_file.is_file() {
+        if let Some(err) = builder.add(&ignore_file) {
+            warn!("failed to parse {}: {}", ignore_file.display(), err);
+        }
+    }
+
+    for pattern in patterns {
+        if let Err(err) = builder.add_line(None, pattern) {
+            warn!("invalid ignore pattern '{}': {}", pattern, err);
+        }
+    }
+
+    builder.build().unwrap_or_else(|err| {
+        warn!("failed to build watch exclusions: {}", err);
+        Gitignore::empty()
+    })
+}
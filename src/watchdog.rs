@@ -0,0 +1,239 @@
+//! Detection of a controller loop that stopped making progress.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::channel_stats::ChannelStats;
+
+/// Exit code used when the watchdog aborts the process
+pub const WATCHDOG_EXIT_CODE: i32 = 70;
+
+/// Probe returning the current number of messages queued in a channel
+pub struct OccupancyProbe {
+    stats: Arc<ChannelStats>,
+    /// true if the channel is consumed by the controller loop
+    input: bool,
+}
+
+impl OccupancyProbe {
+    /// Probe for a channel consumed by the controller: pending messages in it mean the
+    /// controller has work to do
+    pub fn input(stats: Arc<ChannelStats>) -> Self {
+        Self { stats, input: true }
+    }
+
+    /// Probe for any other channel, only reported in stall dumps
+    pub fn other(stats: Arc<ChannelStats>) -> Self {
+        Self {
+            stats,
+            input: false,
+        }
+    }
+}
+
+/// Progress marker updated by the controller after each processed message
+pub struct Heartbeat {
+    last: Mutex<(Instant, &'static str)>,
+    stopped: AtomicBool,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            last: Mutex::new((Instant::now(), "none")),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Record that the controller finished processing the given message
+    pub fn beat(&self, message: &'static str) {
+        *self.last.lock().unwrap() = (Instant::now(), message);
+    }
+
+    /// Signal the monitor that the controller exited normally
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> (Instant, &'static str) {
+        *self.last.lock().unwrap()
+    }
+
+    /// Last message processed by the controller, or shutdown step it reached
+    pub fn last_message(&self) -> &'static str {
+        self.last().1
+    }
+
+    /// Whether the controller exited
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stall decision from the successive occupancy checks and the heartbeat
+struct StallDetector {
+    timeout: Duration,
+    /// Last time no message was pending: a controller waiting for messages is not stalled
+    last_idle: Instant,
+}
+
+impl StallDetector {
+    fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            last_idle: now,
+        }
+    }
+
+    /// How long the controller made no progress while messages were pending for it, once it
+    /// reaches the timeout
+    fn check(&mut self, now: Instant, pending: bool, last_beat: Instant) -> Option<Duration> {
+        if !pending {
+            self.last_idle = now;
+            return None;
+        }
+
+        let stalled_for = now.duration_since(last_beat.max(self.last_idle));
+        (stalled_for >= self.timeout).then_some(stalled_for)
+    }
+}
+
+/// Spawn the monitor thread. It reports a stall when messages have been pending in any of the
+/// controller input channels for longer than `timeout` without the controller making progress, and exits
+/// the process with `WATCHDOG_EXIT_CODE` if `abort` is set.
+pub fn spawn(
+    heartbeat: Arc<Heartbeat>,
+    probes: Vec<OccupancyProbe>,
+    timeout: Duration,
+    abort: bool,
+) -> std::thread::JoinHandle<()> {
+    let period = (timeout / 4).max(Duration::from_millis(100));
+
+    std::thread::spawn(move || {
+        let mut detector = StallDetector::new(timeout, Instant::now());
+        let mut reported = false;
+
+        while !heartbeat.is_stopped() {
+            std::thread::sleep(period);
+
+            let occupancy: Vec<_> = probes
+                .iter()
+                .map(|probe| (probe, probe.stats.snapshot()))
+                .collect();
+            let pending = occupancy
+                .iter()
+                .any(|(probe, stats)| probe.input && stats.occupancy > 0);
+
+            let (last_beat, last_message) = heartbeat.last();
+            let stalled_for = match detector.check(Instant::now(), pending, last_beat) {
+                Some(stalled_for) => stalled_for,
+                None => {
+                    reported = false;
+                    continue;
+                }
+            };
+
+            if !reported {
+                error!(
+                    "controller made no progress for {:.1}s while messages are pending (last processed: {})",
+                    stalled_for.as_secs_f32(),
+                    last_message
+                );
+
+                for (_, stats) in &occupancy {
+                    error!("  {}", stats);
+                }
+
+                reported = true;
+            }
+
+            if abort {
+                error!("aborting because of stalled controller");
+                std::process::exit(WATCHDOG_EXIT_CODE);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn idle_controllers_are_not_stalled() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(TIMEOUT, start);
+
+        // Nothing pending for long, nor processed
+        for t in [5, 30, 60] {
+            assert_eq!(detector.check(start + secs(t), false, start), None);
+        }
+
+        // Messages pending since the last check only
+        assert_eq!(detector.check(start + secs(65), true, start), None);
+    }
+
+    #[test]
+    fn busy_controllers_are_not_stalled() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(TIMEOUT, start);
+
+        // Messages always pending, but processed
+        for t in [5, 30, 60] {
+            assert_eq!(
+                detector.check(start + secs(t), true, start + secs(t - 1)),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn stalled_controllers_are_reported() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(TIMEOUT, start);
+
+        assert_eq!(detector.check(start + secs(5), false, start), None);
+        assert_eq!(detector.check(start + secs(10), true, start), None);
+        assert_eq!(
+            detector.check(start + secs(15), true, start),
+            Some(secs(10))
+        );
+        assert_eq!(
+            detector.check(start + secs(20), true, start),
+            Some(secs(15))
+        );
+
+        // Until it processes a message
+        assert_eq!(
+            detector.check(start + secs(25), true, start + secs(24)),
+            None
+        );
+        assert_eq!(
+            detector.check(start + secs(40), true, start + secs(24)),
+            Some(secs(16))
+        );
+
+        // Or has nothing left to do
+        assert_eq!(
+            detector.check(start + secs(45), false, start + secs(24)),
+            None
+        );
+        assert_eq!(
+            detector.check(start + secs(50), true, start + secs(24)),
+            None
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! Watching of the directory containing the target, forwarding changes of the target to the
+//! controller.
+//!
+//! The watcher is owned by the controller so the watched directory follows target changes.
+//! Changes to contents the app wrote itself are not forwarded, see [`OwnWrites`], nor are changes to
+//! the --status-file.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_std::sync::{Arc, Mutex};
+
+use hotwatch::{Event, Hotwatch};
+
+use crate::bundle::{self, Bundle};
+use crate::channel_stats;
+use crate::own_writes::OwnWrites;
+use crate::runtime::{channel, task};
+use crate::size_cap::{self, CappedRead};
+use crate::status_file;
+use crate::watch_filter::WatchFilter;
+use crate::{DynamicOpts, Opts, Result, WorkerMessage};
+
+pub struct Watcher {
+    opts: Arc<Opts>,
+    dynamic: Arc<Mutex<DynamicOpts>>,
+    tx_controller: channel_stats::Sender<WorkerMessage>,
+    ignore: Vec<String>,
+    own_writes: OwnWrites,
+    current: Option<Watch>,
+    /// Events filtered in previously watched directories
+    filtered: u64,
+    /// A `WorkerMessage::EventsFiltered` was sent and the count wasn't taken since
+    filtered_pending: Arc<AtomicBool>,
+}
+
+/// Watch of a single directory, stopped on drop
+struct Watch {
+    dir: PathBuf,
+    filter: Arc<WatchFilter>,
+    _hotwatch: Hotwatch,
+}
+
+impl Watcher {
+    pub fn new(
+        opts: Arc<Opts>,
+        dynamic: Arc<Mutex<DynamicOpts>>,
+        tx_controller: channel_stats::Sender<WorkerMessage>,
+        ignore: Vec<String>,
+    ) -> Self {
+        Self {
+            opts,
+            dynamic,
+            tx_controller,
+            ignore,
+            own_writes: OwnWrites::default(),
+            current: None,
+            filtered: 0,
+            filtered_pending: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Watch the directory containing the given target, replacing the previous watch if it was
+    /// on another directory
+    pub fn watch(&mut self, target: &Path) -> Result<()> {
+        let dir = target_dir(target).canonicalize()?;
+
+        if self.current.as_ref().map(|watch| &watch.dir) == Some(&dir) {
+            return Ok(());
+        }
+
+        let filter = Arc::new(WatchFilter::new(&dir, self.ignore.clone()));
+
+        // A fresh watcher is used so watches on nested directories never overlap
+        let mut hotwatch = Hotwatch::new()?;
+        hotwatch.watch(&dir, self.handler(dir.clone(), filter.clone()))?;
+
+        debug!("watching {}", dir.display());
+
+        if let Some(previous) = self.current.replace(Watch {
+            dir,
+            filter,
+            _hotwatch: hotwatch,
+        }) {
+            self.filtered += previous.filter.filtered_count();
+        }
+
+        Ok(())
+    }
+
+    /// Contents written to the target by the app, whose change events are dropped
+    pub fn own_writes(&self) -> &OwnWrites {
+        &self.own_writes
+    }
+
+    /// Number of events discarded by the exclusion rules since the watcher was created
+    pub fn filtered_count(&self) -> u64 {
+        self.filtered
+            + self
+                .current
+                .as_ref()
+                .map(|watch| watch.filter.filtered_count())
+                .unwrap_or(0)
+    }
+
+    /// Number of filtered events, after a `WorkerMessage::EventsFiltered`. Until it is called,
+    /// further filtered events are not reported again.
+    pub fn take_filtered_count(&self) -> u64 {
+        self.filtered_pending.store(false, Ordering::SeqCst);
+        self.filtered_count()
+    }
+
+    fn handler(
+        &self,
+        dir: PathBuf,
+        filter: Arc<WatchFilter>,
+    ) -> impl FnMut(Event) + Send + 'static {
+        let opts = self.opts.clone();
+        let dynamic = self.dynamic.clone();
+        let tx_controller = self.tx_controller.clone();
+        let own_writes = self.own_writes.clone();
+        let filtered_pending = self.filtered_pending.clone();
+        let status_files: Vec<PathBuf> = self
+            .opts
+            .status_file
+            .iter()
+            .flat_map(|path| {
+                let path: &Path = path.as_ref();
+                vec![status_file::temporary_path(path), path.to_owned()]
+            })
+            .filter_map(|path| canonical_target(&path))
+            .collect();
+
+        move |event: Event| {
+            if let Some(path) = event_path(&event) {
+                if status_files.iter().any(|file| file == path) {
+                    return;
+                }
+
+                if path == filter.ignore_file() {
+                    filter.reload();
+                    return;
+                }
+
+                if filter.is_ignored(path) {
+                    if !filtered_pending.swap(true, Ordering::SeqCst) {
+                        let tx_controller = tx_controller.clone();
+                        task::spawn(async move {
+                            tx_controller.send(WorkerMessage::EventsFiltered).await
+                        });
+                    }
+                    return;
+                }
+            }
+
+            match event {
+                Event::NoticeWrite(path) | Event::Create(path) | Event::Write(path) => {
+                    task::spawn(file_changed(
+                        opts.clone(),
+                        dynamic.clone(),
+                        tx_controller.clone(),
+                        own_writes.clone(),
+                        dir.clone(),
+                        path,
+                    ));
+                }
+                Event::Rename(from, to) => {
+                    let tx_controller = tx_controller.clone();
+                    let opts = opts.clone();
+                    let dynamic = dynamic.clone();
+                    let own_writes = own_writes.clone();
+                    let dir = dir.clone();
+
+                    task::spawn(async move {
+                        if !opts.watches_rename() {
+                            return Ok(());
+                        }
+
+                        let target = dynamic.lock().await.target().clone();
+
+                        let target = canonical_target(target.as_ref());
+                        if target.as_ref() == Some(&from) {
+                            tx_controller
+                                .send(WorkerMessage::FileRenamed {
+                                    new_path: to.into(),
+                                })
+                                .await
+                        } else {
+                            // Saved by writing another file and renaming it over the target
+                            file_changed(opts, dynamic, tx_controller, own_writes, dir, to).await
+                        }
+                    });
+                }
+                Event::Remove(path) => {
+                    let tx_controller = tx_controller.clone();
+                    let opts = opts.clone();
+                    let dynamic = dynamic.clone();
+
+                    task::spawn(async move {
+                        let (target, notes) = {
+                            let dynamic = dynamic.lock().await;
+                            (
+                                dynamic.target().clone(),
+                                dynamic.notes_path(&opts.notes_pattern),
+                            )
+                        };
+
+                        if is_notes(notes, &path) {
+                            return tx_controller.send(WorkerMessage::NotesChanged).await;
+                        }
+
+                        if opts.watches_delete()
+                            && canonical_target(target.as_ref()).as_ref() == Some(&path)
+                        {
+                            return tx_controller.send(WorkerMessage::FileDeleted).await;
+                        }
+
+                        Ok(())
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Forward a change of `path`. With --watch-all-in-dir, the bundle of the watched directory is sent
+/// if the file is part of it, otherwise the target is sent if it is the changed file.
+async fn file_changed(
+    opts: Arc<Opts>,
+    dynamic: Arc<Mutex<DynamicOpts>>,
+    tx_controller: channel_stats::Sender<WorkerMessage>,
+    own_writes: OwnWrites,
+    dir: PathBuf,
+    path: PathBuf,
+) -> std::result::Result<(), channel::SendError<WorkerMessage>> {
+    let (target, notes) = {
+        let dynamic = dynamic.lock().await;
+        (
+            dynamic.target().clone(),
+            dynamic.notes_path(&opts.notes_pattern),
+        )
+    };
+
+    if is_notes(notes, &path) {
+        return tx_controller.send(WorkerMessage::NotesChanged).await;
+    }
+
+    if opts.watch_all_in_dir {
+        let matcher = bundle::matcher(opts.watch_pattern.as_ref(), target.as_ref());
+        if !bundle::is_bundled(&dir, &matcher, &path) {
+            return Ok(());
+        }
+
+        let message = match task::spawn_blocking(move || bundle::read(&dir, &matcher)).await {
+            Ok((code, files)) => WorkerMessage::FileChanged {
+                code: code.into(),
+                bundle: Some(Bundle {
+                    trigger: path,
+                    files,
+                }),
+            },
+            Err(error) => WorkerMessage::WatchError { error },
+        };
+
+        return tx_controller.send(message).await;
+    }
+
+    match async_std::fs::canonicalize(target).await {
+        Ok(target) if target == async_std::path::PathBuf::from(path) => {
+            match size_cap::read(&target, opts.max_file_size).await {
+                Ok(CappedRead::Contents(code)) => {
+                    if own_writes.check(&code) {
+                        debug!("ignoring the change written by the app");
+                        return Ok(());
+                    }
+
+                    tx_controller
+                        .send(WorkerMessage::FileChanged {
+                            code: code.into(),
+                            bundle: None,
+                        })
+                        .await
+                }
+                Ok(CappedRead::TooLarge { size }) => {
+                    tx_controller
+                        .send(WorkerMessage::FileTooLarge { size })
+                        .await
+                }
+                Err(error) => {
+                    tx_controller
+                        .send(WorkerMessage::WatchError { error })
+                        .await
+                }
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether the path is the one of the notes of the target
+fn is_notes(notes: Option<async_std::path::PathBuf>, path: &Path) -> bool {
+    notes
+        .and_then(|notes| canonical_target(notes.as_ref()))
+        .as_deref()
+        == Some(path)
+}
+
+/// Canonical path of the target, which may not exist anymore but whose directory does
+fn canonical_target(target: &Path) -> Option<PathBuf> {
+    let dir = target_dir(target).canonicalize().ok()?;
+    Some(dir.join(target.file_name()?))
+}
+
+/// Directory containing the target. A bare file name is in the current directory.
+pub fn target_dir(target: &Path) -> &Path {
+    match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Path affected by a watcher event, if any
+fn event_path(event: &Event) -> Option<&Path> {
+    match event {
+        Event::NoticeWrite(path)
+        | Event::NoticeRemove(path)
+        | Event::Create(path)
+        | Event::Write(path)
+        | Event::Chmod(path)
+        | Event::Remove(path)
+        | Event::Rename(_, path) => Some(path),
+        Event::Rescan | Event::Error(_, _) => None,
+    }
+}
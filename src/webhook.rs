@@ -0,0 +1,66 @@
+//! JSON notifications POSTed to the `--notify-webhook` URLs on uploads and downloads.
+//!
+//! Requests are sent from the blocking thread pool so a slow endpoint never delays the
+//! synchronization, and failures are only logged.
+
+use std::time::Duration;
+
+use crate::runtime::task;
+
+/// Delay after which a webhook request is abandoned
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parse a `Name: value` header for --notify-webhook-auth-header
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    match header.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_owned(), value.trim().to_owned()))
+        }
+        _ => Err(format!("expected 'Name: value', got '{}'", header)),
+    }
+}
+
+/// Body of a webhook request
+#[derive(Debug, Serialize)]
+pub struct Event {
+    event: &'static str,
+    question_id: Option<i32>,
+    bytes: usize,
+    ts: String,
+}
+
+impl Event {
+    /// Event happening now, `event` being `upload` or `download`
+    pub fn now(event: &'static str, question_id: Option<i32>, bytes: usize) -> Self {
+        Self {
+            event,
+            question_id,
+            bytes,
+            ts: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        }
+    }
+}
+
+/// POST the event to every URL in the background
+pub fn notify(urls: Vec<String>, auth_header: Option<(String, String)>, event: Event) {
+    if urls.is_empty() {
+        return;
+    }
+
+    task::spawn_blocking(move || {
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        let body = serde_json::to_string(&event).unwrap();
+
+        for url in &urls {
+            let mut request = agent.post(url).set("Content-Type", "application/json");
+            if let Some((name, value)) = &auth_header {
+                request = request.set(name, value);
+            }
+
+            match request.send_string(&body) {
+                Ok(_) => debug!("notified {} of {}", url, event.event),
+                Err(err) => warn!("webhook {} failed: {}", url, err),
+            }
+        }
+    });
+}
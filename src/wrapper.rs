@@ -0,0 +1,59 @@
+//! Structural boilerplate around the uploaded code, for puzzle types expecting the code inside a
+//! class or a function.
+//!
+//! With `--upload-wrapper`, the contents of the before and after files are read once at startup
+//! and surround the code on upload. Unlike code added by other options, the wrapper is removed
+//! again from downloaded code, so the local file only holds what is between.
+
+use std::path::Path;
+
+use crate::{ErrorKind, Result, ResultExt};
+
+/// Contents of the --upload-wrapper files
+#[derive(Debug, Clone)]
+pub struct Wrapper {
+    before: String,
+    after: String,
+}
+
+impl Wrapper {
+    pub fn load(before: &Path, after: &Path) -> Result<Self> {
+        let read = |path: &Path| {
+            std::fs::read_to_string(path)
+                .chain_err(|| ErrorKind::InvalidUploadWrapper(path.display().to_string()))
+        };
+
+        Ok(Self {
+            before: read(before)?,
+            after: read(after)?,
+        })
+    }
+
+    pub fn wrap(&self, code: &str) -> String {
+        wrap(code, &self.before, &self.after)
+    }
+
+    pub fn unwrap(&self, code: &str) -> Option<String> {
+        unwrap(code, &self.before, &self.after)
+    }
+}
+
+/// Surround the code with the wrapper
+pub fn wrap(code: &str, before: &str, after: &str) -> String {
+    let mut wrapped = String::with_capacity(before.len() + code.len() + after.len());
+    wrapped.push_str(before);
+    wrapped.push_str(code);
+    wrapped.push_str(after);
+    wrapped
+}
+
+/// Code between the wrapper, or `None` if the code isn't wrapped. Trailing whitespace after the
+/// wrapper is ignored since the IDE may normalize the end of the code.
+pub fn unwrap(code: &str, before: &str, after: &str) -> Option<String> {
+    let inner = code.strip_prefix(before)?;
+
+    inner
+        .strip_suffix(after)
+        .or_else(|| inner.trim_end().strip_suffix(after.trim_end()))
+        .map(str::to_owned)
+}
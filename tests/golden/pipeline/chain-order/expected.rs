@@ -0,0 +1,4 @@
+// Copyright 2021 Jane Doe
+fn main() {
+    println!("42");
+}
\ No newline at end of file
@@ -0,0 +1,2 @@
+# Ünïcödé cömmënts c
+// TRUNCATED
\ No newline at end of file
@@ -0,0 +1,2 @@
+# Ünïcödé cömmënts cöünt öncë pér chäräctér
+print(42)
@@ -0,0 +1,7 @@
+def solve(n):
+    return n * 2
+
+
+if __name__ == "__main__":
+    n = int(input())
+    print(solve(n))
@@ -0,0 +1,8 @@
+pub fn solve(n: u32) -> u32 {
+    n * 2
+}
+
+// fn main() {
+//     let n = 21;
+//     println!("{}", solve(n));
+// }
@@ -0,0 +1,5 @@
+// Copyright (c) 2021 Jane Doe
+
+fn main() {
+    println!("42");
+}
@@ -0,0 +1,3 @@
+fn main() {
+    println!("42");
+}
\ No newline at end of file
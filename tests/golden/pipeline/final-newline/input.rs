@@ -0,0 +1,5 @@
+fn main() {
+    println!("42");
+}
+
+
@@ -0,0 +1,5 @@
+DEBUG = False
+
+def log(message):
+    if DEBUG:
+        print(message, file=sys.stderr)
@@ -0,0 +1,5 @@
+DEBUG = True
+
+def log(message):
+    if DEBUG:
+        print(message, file=sys.stderr)
@@ -0,0 +1,7 @@
+n = int(input())
+print(n * 2)
+# --- TESTINPUT ---
+# 3
+#
+# 21
+# --- END TESTINPUT ---
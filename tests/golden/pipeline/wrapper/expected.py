@@ -0,0 +1,4 @@
+class Solution:
+def solve(self, n):
+    return n * 2
+# end of Solution
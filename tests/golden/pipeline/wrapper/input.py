@@ -0,0 +1,2 @@
+def solve(self, n):
+    return n * 2
@@ -0,0 +1,77 @@
+//! Synchronization through the listener of the built app, with the fake extension of `--mock`.
+//!
+//! Run with the default features and with `--no-default-features --features runtime-tokio`, it
+//! covers the sockets, channels and tasks of both runtimes.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+/// Longest wait for a line of the logs
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// App started with the fake extension, its logs read on a thread
+struct App {
+    child: Child,
+    logs: Receiver<String>,
+}
+
+impl App {
+    fn start(target: &std::path::Path) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_cg-local-app"))
+            .args(["--no-gui", "--no-stats", "--mock", "--bind", "127.0.0.1:0"])
+            .arg("--target")
+            .arg(target)
+            .env("RUST_LOG", "cg_local_app=info")
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let stderr = child.stderr.take().unwrap();
+        let (tx, logs) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                if tx.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { child, logs }
+    }
+
+    /// Wait for a line of the logs containing `text`
+    fn expect(&self, text: &str) {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.logs.recv_timeout(remaining) {
+                Ok(line) if line.contains(text) => return,
+                Ok(_) => continue,
+                Err(_) => panic!("no line containing {:?} in the logs", text),
+            }
+        }
+    }
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn saves_are_uploaded_to_the_extension() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("main.py");
+    std::fs::write(&target, "print(1)\n").unwrap();
+
+    let app = App::start(&target);
+    app.expect("mock extension: synchronizing");
+
+    std::fs::write(&target, "print(1234)\n").unwrap();
+    app.expect("mock extension: received 12 bytes");
+}